@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use crate::ethernet::{Ethernet, MacAddress};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArpError(pub String);
+
+impl fmt::Display for ArpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "arp: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArpOperation {
+    Request = 1,
+    Reply = 2,
+}
+
+// RFC 826
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    const SIZE: usize = 28;
+    // Hardware Type (Ethernet)
+    const HARDWARE_TYPE: u16 = 1;
+
+    pub fn parse(buf: &[u8]) -> Result<ArpPacket, ArpError> {
+        if buf.len() < ArpPacket::SIZE {
+            return Err(ArpError("too short".to_string()));
+        }
+
+        let hardware_type = u16::from_be_bytes([buf[0], buf[1]]);
+        let protocol_type = u16::from_be_bytes([buf[2], buf[3]]);
+        if hardware_type != ArpPacket::HARDWARE_TYPE
+            || protocol_type != Ethernet::TYPE_IPV4
+            || buf[4] != 6
+            || buf[5] != 4
+        {
+            return Err(ArpError(format!(
+                "unsupported type: hardware type={:#x?}, protocol type={:#x?}",
+                hardware_type, protocol_type
+            )));
+        }
+
+        let operation = match u16::from_be_bytes([buf[6], buf[7]]) {
+            1 => ArpOperation::Request,
+            2 => ArpOperation::Reply,
+            operation => {
+                return Err(ArpError(format!(
+                    "unsupported operation: operation={}",
+                    operation
+                )))
+            }
+        };
+        Ok(ArpPacket {
+            operation,
+            sender_mac: MacAddress::from_slice(&buf[8..14]),
+            sender_ip: Ipv4Addr::new(buf[14], buf[15], buf[16], buf[17]),
+            target_mac: MacAddress::from_slice(&buf[18..24]),
+            target_ip: Ipv4Addr::new(buf[24], buf[25], buf[26], buf[27]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&ArpPacket::HARDWARE_TYPE.to_be_bytes());
+        buf.extend_from_slice(&Ethernet::TYPE_IPV4.to_be_bytes());
+        // Hardware Address Length, Protocol Address Length
+        buf.extend_from_slice(&[6, 4]);
+        buf.extend_from_slice(&(self.operation as u16).to_be_bytes());
+        buf.extend_from_slice(&self.sender_mac.0);
+        buf.extend_from_slice(&self.sender_ip.octets());
+        buf.extend_from_slice(&self.target_mac.0);
+        buf.extend_from_slice(&self.target_ip.octets());
+        buf
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NeighborState {
+    Reachable,
+    // Being resolved
+    Incomplete,
+    // Added statically, which never expires
+    Permanent,
+}
+
+// An entry of the cache shown by `ip neigh`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Neighbor {
+    pub ip: Ipv4Addr,
+    pub mac: Option<MacAddress>,
+    pub state: NeighborState,
+}
+
+// Datagrams waiting for the resolution of the next hop
+struct Pending {
+    requested: Option<Instant>,
+    datagrams: Vec<Vec<u8>>,
+}
+
+pub struct ArpCache {
+    // None for the permanent entries instead of the time of the update
+    entries: HashMap<Ipv4Addr, (MacAddress, Option<Instant>)>,
+    pending: HashMap<Ipv4Addr, Pending>,
+}
+
+impl ArpCache {
+    // RFC 1122 2.3.2.1 leaves the timeout to the implementation
+    const TIMEOUT: Duration = Duration::from_secs(20 * 60);
+    const RETRANSMISSION: Duration = Duration::from_secs(1);
+    // RFC 1122 2.3.2.2 requires at least one datagram to be queued
+    const QUEUE_SIZE: usize = 16;
+
+    pub fn new() -> ArpCache {
+        ArpCache {
+            entries: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn lookup(&mut self, ip: Ipv4Addr, now: Instant) -> Option<MacAddress> {
+        let (mac, updated) = *self.entries.get(&ip)?;
+        if updated.is_some_and(|updated| now.duration_since(updated) >= ArpCache::TIMEOUT) {
+            self.entries.remove(&ip);
+            return None;
+        }
+        Some(mac)
+    }
+
+    // The entries which have not expired, sorted by the address
+    pub fn neighbors(&self, now: Instant) -> Vec<Neighbor> {
+        let mut neighbors: Vec<Neighbor> = self
+            .entries
+            .iter()
+            .filter(|(_, (_, updated))| {
+                updated.is_none_or(|updated| now.duration_since(updated) < ArpCache::TIMEOUT)
+            })
+            .map(|(&ip, &(mac, updated))| Neighbor {
+                ip,
+                mac: Some(mac),
+                state: match updated {
+                    Some(_) => NeighborState::Reachable,
+                    None => NeighborState::Permanent,
+                },
+            })
+            .chain(self.pending.keys().map(|&ip| Neighbor {
+                ip,
+                mac: None,
+                state: NeighborState::Incomplete,
+            }))
+            .collect();
+        neighbors.sort_by_key(|neighbor| neighbor.ip);
+        neighbors
+    }
+
+    // Update the entry only if it exists. A permanent entry is not updated.
+    pub fn update(&mut self, ip: Ipv4Addr, mac: MacAddress, now: Instant) -> bool {
+        match self.entries.get_mut(&ip) {
+            Some((_, None)) => true,
+            Some(entry) => {
+                *entry = (mac, Some(now));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddress, now: Instant) {
+        self.entries.insert(ip, (mac, Some(now)));
+    }
+
+    // A static entry. The datagrams waiting for the address are dropped.
+    pub fn insert_permanent(&mut self, ip: Ipv4Addr, mac: MacAddress) {
+        self.entries.insert(ip, (mac, None));
+        self.pending.remove(&ip);
+    }
+
+    // Returns false if the entry does not exist
+    pub fn remove(&mut self, ip: Ipv4Addr) -> bool {
+        let pending = self.pending.remove(&ip).is_some();
+        self.entries.remove(&ip).is_some() || pending
+    }
+
+    // Queue a datagram until the address is resolved. Returns true if a
+    // request should be sent.
+    pub fn queue(&mut self, ip: Ipv4Addr, datagram: Vec<u8>, now: Instant) -> bool {
+        let pending = self.pending.entry(ip).or_insert(Pending {
+            requested: None,
+            datagrams: vec![],
+        });
+        if pending.datagrams.len() >= ArpCache::QUEUE_SIZE {
+            // Drop the oldest datagram
+            pending.datagrams.remove(0);
+        }
+        pending.datagrams.push(datagram);
+
+        let requested = pending.requested;
+        if requested
+            .is_some_and(|requested| now.duration_since(requested) < ArpCache::RETRANSMISSION)
+        {
+            return false;
+        }
+        pending.requested = Some(now);
+        true
+    }
+
+    // The datagrams which can be sent now that the address is resolved
+    pub fn take_pending(&mut self, ip: Ipv4Addr) -> Vec<Vec<u8>> {
+        self.pending
+            .remove(&ip)
+            .map(|pending| pending.datagrams)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for ArpCache {
+    fn default() -> Self {
+        ArpCache::new()
+    }
+}
@@ -0,0 +1,212 @@
+use std::{future::poll_fn, io, net::SocketAddrV4, task::Poll};
+
+use nix::{errno::Errno, Error};
+use tokio::io::unix::AsyncFd;
+
+use crate::{
+    socket::{SocketHandle, SocketType, Sockets},
+    tuntap::TunTap,
+};
+
+// A TUN/TAP device registered with the Tokio reactor
+pub struct AsyncTunTap {
+    inner: AsyncFd<TunTap>,
+}
+
+impl AsyncTunTap {
+    // Must be called within a Tokio runtime
+    pub fn new(tuntap: TunTap) -> io::Result<AsyncTunTap> {
+        tuntap.set_nonblocking()?;
+        Ok(AsyncTunTap {
+            inner: AsyncFd::new(tuntap)?,
+        })
+    }
+
+    pub fn get_ref(&self) -> &TunTap {
+        self.inner.get_ref()
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            match guard.try_io(|inner| Ok(inner.get_ref().read(buf)?)) {
+                Ok(result) => return result,
+                // Spurious readiness
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.inner.writable().await?;
+            match guard.try_io(|inner| Ok(inner.get_ref().write(buf)?)) {
+                Ok(result) => return result,
+                // Spurious readiness
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// Retry a call on a non-blocking socket until it no longer would block
+async fn _retry<T>(sockets: &Sockets, mut call: impl FnMut() -> Result<T, Error>) -> io::Result<T> {
+    poll_fn(|cx| {
+        sockets.watch(cx.waker());
+        match call() {
+            Err(Errno::EAGAIN) | Err(Errno::EALREADY) => Poll::Pending,
+            result => Poll::Ready(result.map_err(io::Error::from)),
+        }
+    })
+    .await
+}
+
+fn _open(sockets: &Sockets, socket_type: SocketType) -> io::Result<SocketHandle> {
+    let handle = sockets.socket(socket_type)?;
+    sockets.set_nonblocking(handle, true)?;
+    Ok(handle)
+}
+
+// A UDP socket of the stack. The stack is driven by its own event loop, so
+// no Tokio reactor is needed to create the sockets.
+pub struct UdpSocket {
+    sockets: Sockets,
+    handle: SocketHandle,
+}
+
+impl UdpSocket {
+    pub fn bind(sockets: &Sockets, addr: SocketAddrV4) -> io::Result<UdpSocket> {
+        let socket = UdpSocket {
+            sockets: sockets.clone(),
+            handle: _open(sockets, SocketType::Datagram)?,
+        };
+        sockets.bind(socket.handle, addr)?;
+        Ok(socket)
+    }
+
+    pub fn connect(&self, addr: SocketAddrV4) -> io::Result<()> {
+        Ok(self.sockets.connect(self.handle, addr)?)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddrV4> {
+        Ok(self.sockets.local_addr(self.handle)?)
+    }
+
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> io::Result<usize> {
+        _retry(&self.sockets, || {
+            self.sockets.send_to(self.handle, buf, addr)
+        })
+        .await
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        _retry(&self.sockets, || self.sockets.send(self.handle, buf)).await
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddrV4)> {
+        _retry(&self.sockets, || self.sockets.recv_from(self.handle, buf)).await
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        _retry(&self.sockets, || self.sockets.recv(self.handle, buf)).await
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let _ = self.sockets.close(self.handle);
+    }
+}
+
+pub struct TcpListener {
+    sockets: Sockets,
+    handle: SocketHandle,
+}
+
+impl TcpListener {
+    // SOMAXCONN of Linux
+    const BACKLOG: usize = 128;
+
+    pub fn bind(sockets: &Sockets, addr: SocketAddrV4) -> io::Result<TcpListener> {
+        let listener = TcpListener {
+            sockets: sockets.clone(),
+            handle: _open(sockets, SocketType::Stream)?,
+        };
+        sockets.bind(listener.handle, addr)?;
+        sockets.listen(listener.handle, TcpListener::BACKLOG)?;
+        Ok(listener)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddrV4> {
+        Ok(self.sockets.local_addr(self.handle)?)
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddrV4)> {
+        let (handle, addr) = _retry(&self.sockets, || self.sockets.accept(self.handle)).await?;
+        self.sockets.set_nonblocking(handle, true)?;
+        let stream = TcpStream {
+            sockets: self.sockets.clone(),
+            handle,
+        };
+        Ok((stream, addr))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = self.sockets.close(self.handle);
+    }
+}
+
+pub struct TcpStream {
+    sockets: Sockets,
+    handle: SocketHandle,
+}
+
+impl TcpStream {
+    pub async fn connect(sockets: &Sockets, addr: SocketAddrV4) -> io::Result<TcpStream> {
+        let stream = TcpStream {
+            sockets: sockets.clone(),
+            handle: _open(sockets, SocketType::Stream)?,
+        };
+        match sockets.connect(stream.handle, addr) {
+            Ok(()) | Err(Errno::EINPROGRESS) => {}
+            Err(e) => return Err(e.into()),
+        }
+        // Connecting again tells whether the handshake has finished
+        _retry(sockets, || match sockets.connect(stream.handle, addr) {
+            Err(Errno::EISCONN) => Ok(()),
+            result => result,
+        })
+        .await?;
+        Ok(stream)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddrV4> {
+        Ok(self.sockets.local_addr(self.handle)?)
+    }
+
+    // Returns 0 at the end of the stream
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        _retry(&self.sockets, || self.sockets.recv(self.handle, buf)).await
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        _retry(&self.sockets, || self.sockets.send(self.handle, buf)).await
+    }
+
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = self.write(buf).await?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
+
+// The connection is closed gracefully by the stack after the data sent
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let _ = self.sockets.close(self.handle);
+    }
+}
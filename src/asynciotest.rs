@@ -0,0 +1,176 @@
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, net::SocketAddrV4};
+
+    use tokio::{runtime::Builder, task};
+
+    use crate::{
+        asyncio::{TcpListener, TcpStream, UdpSocket},
+        eventloop::EventLoop,
+        ipv4::{Datagram, IPv4Protocol},
+        protocol::ProtocolError,
+        socket::Sockets,
+        tcp::Tcp,
+        testutil::{host, stack},
+        udp::Udp,
+    };
+
+    // Control Bits
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const PSH: u8 = 0x08;
+    const ACK: u8 = 0x10;
+
+    fn host_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(host(), 5000)
+    }
+
+    fn stack_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(stack(), 80)
+    }
+
+    fn seq(buf: &[u8]) -> u32 {
+        u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]])
+    }
+
+    fn poll(protocol: &dyn IPv4Protocol) -> Vec<Datagram> {
+        let source = |_, _| Some((stack(), 1500));
+        protocol.poll(&source)
+    }
+
+    #[test]
+    fn udp() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let udp = Udp::new(sockets.clone());
+        let runtime = Builder::new_current_thread().build().unwrap();
+
+        let _socket = runtime.block_on(async {
+            let socket = UdpSocket::bind(&sockets, stack_addr()).unwrap();
+            let receiver = task::spawn(async move {
+                let mut buf = [0u8; 16];
+                let (n, addr) = socket.recv_from(&mut buf).await.unwrap();
+                socket.send_to(&buf[..n], addr).await.unwrap();
+                // Closing the socket would drop the datagram
+                socket
+            });
+            task::yield_now().await;
+            assert!(!receiver.is_finished());
+
+            // The datagram wakes up the task
+            let buf = Udp::datagram(host_addr(), stack_addr(), b"hello");
+            assert_eq!(
+                udp.input(host(), stack(), &buf),
+                Err(ProtocolError::General)
+            );
+            receiver.await.unwrap()
+        });
+
+        let datagrams = poll(&udp);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].dst, host());
+        assert_eq!(&datagrams[0].data[8..], b"hello");
+    }
+
+    #[test]
+    fn accept() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets.clone(), event_loop.timers());
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let segment = |seq, ack, flags, data: &[u8]| {
+            let buf = Tcp::segment(
+                host_addr(),
+                stack_addr(),
+                seq,
+                ack,
+                flags,
+                65535,
+                None,
+                data,
+            );
+            tcp.input(host(), stack(), &buf)
+        };
+
+        let _stream = runtime.block_on(async {
+            let listener = TcpListener::bind(&sockets, stack_addr()).unwrap();
+            let server = task::spawn(async move {
+                let (stream, addr) = listener.accept().await.unwrap();
+                assert_eq!(addr, host_addr());
+                let mut buf = [0u8; 16];
+                let n = stream.read(&mut buf).await.unwrap();
+                stream.write_all(&buf[..n]).await.unwrap();
+                stream
+            });
+            task::yield_now().await;
+            assert!(!server.is_finished());
+
+            segment(1000, 0, SYN, &[]).unwrap_err();
+            let iss = seq(&poll(&tcp)[0].data);
+            segment(1001, iss.wrapping_add(1), ACK, &[]).unwrap_err();
+            task::yield_now().await;
+            // Accepted and waiting for the data
+            assert!(!server.is_finished());
+
+            segment(1001, iss.wrapping_add(1), PSH | ACK, b"hello").unwrap_err();
+            server.await.unwrap()
+        });
+
+        let datagrams = poll(&tcp);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(&datagrams[0].data[20..], b"hello");
+    }
+
+    #[test]
+    fn connect() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets.clone(), event_loop.timers());
+        let runtime = Builder::new_current_thread().build().unwrap();
+
+        runtime.block_on(async {
+            let client = task::spawn({
+                let sockets = sockets.clone();
+                async move { TcpStream::connect(&sockets, host_addr()).await.map(|_| ()) }
+            });
+            task::yield_now().await;
+            assert!(!client.is_finished());
+
+            // SYN, ACK
+            let syn = poll(&tcp)[0].data.clone();
+            let local = u16::from_be_bytes([syn[0], syn[1]]);
+            let local = SocketAddrV4::new(stack(), local);
+            let buf = Tcp::segment(
+                host_addr(),
+                local,
+                3000,
+                seq(&syn).wrapping_add(1),
+                SYN | ACK,
+                65535,
+                None,
+                &[],
+            );
+            tcp.input(host(), stack(), &buf).unwrap_err();
+            assert!(client.await.unwrap().is_ok());
+
+            // Refused by the host
+            let client = task::spawn({
+                let sockets = sockets.clone();
+                async move { TcpStream::connect(&sockets, host_addr()).await.map(|_| ()) }
+            });
+            task::yield_now().await;
+            let syn = poll(&tcp)
+                .into_iter()
+                .map(|datagram| datagram.data)
+                .find(|data| data[13] == SYN)
+                .unwrap();
+            let local = u16::from_be_bytes([syn[0], syn[1]]);
+            let local = SocketAddrV4::new(stack(), local);
+            let ack = seq(&syn).wrapping_add(1);
+            let buf = Tcp::segment(host_addr(), local, 0, ack, RST | ACK, 0, None, &[]);
+            tcp.input(host(), stack(), &buf).unwrap_err();
+            let error = client.await.unwrap().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        });
+    }
+}
@@ -0,0 +1,19 @@
+use std::{env, path::Path, process};
+
+use pareiodon::control::Control;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("usage: pareiodon-ctl sockets|routes|neighbors|addresses|rules [add|del ARGS]|connections|nameservers|resolve NAME");
+        process::exit(2);
+    }
+    let path = env::var("PAREIODON_CONTROL").unwrap_or_else(|_| Control::DEFAULT_PATH.to_string());
+    match Control::request(Path::new(&path), &args.join(" ")) {
+        Ok(output) => print!("{}", output),
+        Err(e) => {
+            eprintln!("pareiodon-ctl: {}", e);
+            process::exit(1);
+        }
+    }
+}
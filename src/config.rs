@@ -0,0 +1,475 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    dhcpserver::DhcpServerConfig,
+    ethernet::MacAddress,
+    firewall::Rule,
+    route::{Prefix, Route, RouteError, RoutingTable},
+    router::Interface,
+    services::Service,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "config: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Tun,
+    Tap,
+}
+
+// The address of the interface of the stack
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Address {
+    Static(Prefix),
+    Dhcp,
+}
+
+impl FromStr for Address {
+    type Err = RouteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dhcp" => Ok(Address::Dhcp),
+            _ => Ok(Address::Static(s.parse()?)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub device_type: DeviceType,
+    #[serde(default = "DeviceConfig::_default_mtu")]
+    pub mtu: usize,
+    // Random if not given
+    #[serde(default, deserialize_with = "_parse_option")]
+    pub mac: Option<MacAddress>,
+    // Attach to a persistent device as it is instead of configuring the host
+    // side
+    #[serde(default = "DeviceConfig::_default_configure")]
+    pub configure: bool,
+    // The address of the host side of the device
+    #[serde(default, deserialize_with = "_parse_option")]
+    pub host: Option<Prefix>,
+    // Unconfigured if not given
+    #[serde(default, deserialize_with = "_parse_option")]
+    pub address: Option<Address>,
+}
+
+impl DeviceConfig {
+    // RFC 791 requires every module to forward a datagram of 68 octets
+    const MIN_MTU: usize = 68;
+    const MAX_MTU: usize = 65535;
+
+    fn _default_mtu() -> usize {
+        1500
+    }
+
+    fn _default_configure() -> bool {
+        true
+    }
+
+    fn _prefix(&self) -> Option<Prefix> {
+        match self.address {
+            Some(Address::Static(prefix)) => Some(prefix),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(deserialize_with = "_parse")]
+    pub destination: Prefix,
+    pub via: Option<Ipv4Addr>,
+    pub device: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolName {
+    Icmp,
+    Udp,
+    Tcp,
+    Raw,
+    Igmp,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpConfig {
+    pub device: String,
+    pub pool_start: Ipv4Addr,
+    pub pool_end: Ipv4Addr,
+    pub router: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub dns: Vec<Ipv4Addr>,
+    // In seconds
+    pub lease_time: Option<u64>,
+    // The hardware addresses to the addresses
+    #[serde(default)]
+    pub reservations: HashMap<String, Ipv4Addr>,
+    pub database: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DnsConfig {
+    pub zone: PathBuf,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServicesConfig {
+    // Echo, discard and chargen
+    #[serde(default)]
+    pub builtin: Vec<Service>,
+    pub dhcp: Option<DhcpConfig>,
+    pub dns: Option<DnsConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // The filter of env_logger, overridden by RUST_LOG
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub path: PathBuf,
+}
+
+// The nameservers of the resolver. Without this section, the nameservers of
+// the DHCP lease are used.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ResolverConfig {
+    pub nameservers: Vec<Ipv4Addr>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: SocketAddr,
+}
+
+impl MetricsConfig {
+    // The endpoint is served on a socket of the host without any
+    // authentication, so it listens only on a loopback address
+    pub fn new(listen: SocketAddr) -> Result<MetricsConfig, ConfigError> {
+        if !listen.ip().is_loopback() {
+            return Err(ConfigError(format!(
+                "metrics: not a loopback address: {}",
+                listen
+            )));
+        }
+        Ok(MetricsConfig { listen })
+    }
+}
+
+// The rules of the firewall in the order to check
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirewallConfig {
+    #[serde(deserialize_with = "_parse_vec")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ControlConfig {
+    pub path: PathBuf,
+}
+
+// The configuration of the pareiodon binary in TOML
+//
+// The interfaces of the router are the devices in the order of the file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "Config::_default_protocols")]
+    pub protocols: Vec<ProtocolName>,
+    pub devices: Vec<DeviceConfig>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub services: ServicesConfig,
+    #[serde(default)]
+    pub firewall: FirewallConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    pub resolver: Option<ResolverConfig>,
+    pub capture: Option<CaptureConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub control: Option<ControlConfig>,
+}
+
+impl Config {
+    pub const DEFAULT_PATH: &'static str = "pareiodon.toml";
+
+    fn _default_protocols() -> Vec<ProtocolName> {
+        vec![ProtocolName::Icmp, ProtocolName::Udp, ProtocolName::Tcp]
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let s = fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+        Config::parse(&s).map_err(|e| ConfigError(format!("{}: {}", path.display(), e.0)))
+    }
+
+    pub fn parse(s: &str) -> Result<Config, ConfigError> {
+        let config: Config =
+            toml::from_str(s).map_err(|e| ConfigError(e.to_string().trim_end().to_string()))?;
+        config._validate()?;
+        Ok(config)
+    }
+
+    // The index of the interface of the device
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.devices.iter().position(|device| device.name == name)
+    }
+
+    pub fn has_protocol(&self, protocol: ProtocolName) -> bool {
+        self.protocols.contains(&protocol)
+    }
+
+    pub fn interfaces(&self) -> Vec<Interface> {
+        self.devices
+            .iter()
+            .map(|device| match device._prefix() {
+                Some(prefix) => Interface::new(prefix.address, prefix.netmask, device.mtu),
+                None => Interface::unconfigured(device.mtu),
+            })
+            .collect()
+    }
+
+    // The routes other than the directly connected networks
+    pub fn routing_table(&self) -> RoutingTable {
+        let mut routes = RoutingTable::new();
+        for route in &self.routes {
+            let Prefix { address, netmask } = route.destination;
+            let index = self.index(&route.device).unwrap();
+            routes.add(match route.via {
+                Some(gateway) => Route::via(address, netmask, gateway, index),
+                None => Route::new(address, netmask, index),
+            });
+        }
+        routes
+    }
+
+    pub fn dhcp_server(&self) -> Option<DhcpServerConfig> {
+        let dhcp = self.services.dhcp.as_ref()?;
+        let index = self.index(&dhcp.device)?;
+        let prefix = self.devices[index]._prefix()?;
+        let mut config =
+            DhcpServerConfig::new(index, dhcp.pool_start, dhcp.pool_end, prefix.netmask);
+        config.router = dhcp.router;
+        config.dns = dhcp.dns.clone();
+        if let Some(lease_time) = dhcp.lease_time {
+            config.lease_time = Duration::from_secs(lease_time);
+        }
+        for (mac, address) in &dhcp.reservations {
+            config.reservations.insert(mac.parse().ok()?, *address);
+        }
+        config.database = dhcp.database.clone();
+        Some(config)
+    }
+
+    fn _validate(&self) -> Result<(), ConfigError> {
+        if self.devices.is_empty() {
+            return Err(ConfigError("no devices".to_string()));
+        }
+        for (i, device) in self.devices.iter().enumerate() {
+            self._validate_device(device)
+                .map_err(|e| ConfigError(format!("device {}: {}", device.name, e)))?;
+            if self.devices[..i].iter().any(|d| d.name == device.name) {
+                return Err(ConfigError(format!("duplicate device: {}", device.name)));
+            }
+        }
+        for route in &self.routes {
+            self._validate_route(route)
+                .map_err(|e| ConfigError(format!("route {}: {}", route.destination, e)))?;
+        }
+        if let Some(dhcp) = &self.services.dhcp {
+            self._validate_dhcp(dhcp)
+                .map_err(|e| ConfigError(format!("DHCP server: {}", e)))?;
+        }
+        if self
+            .resolver
+            .as_ref()
+            .is_some_and(|resolver| resolver.nameservers.is_empty())
+        {
+            return Err(ConfigError("resolver: no nameservers".to_string()));
+        }
+        if let Some(metrics) = &self.metrics {
+            MetricsConfig::new(metrics.listen)?;
+        }
+
+        // The protocols which the services run on
+        let dhcp_client = self
+            .devices
+            .iter()
+            .any(|device| device.address == Some(Address::Dhcp));
+        let services = [
+            (self.services.dhcp.is_some() || dhcp_client, "DHCP", false),
+            (self.services.dns.is_some(), "DNS", true),
+            (self.resolver.is_some(), "the resolver", true),
+            (
+                !self.services.builtin.is_empty(),
+                "the builtin services",
+                true,
+            ),
+        ];
+        for (enabled, service, tcp) in services {
+            let mut protocols = vec![ProtocolName::Udp];
+            if tcp {
+                protocols.push(ProtocolName::Tcp);
+            }
+            if let Some(protocol) = protocols
+                .iter()
+                .find(|p| enabled && !self.has_protocol(**p))
+            {
+                return Err(ConfigError(format!(
+                    "{} needs {} in the protocols",
+                    service,
+                    format!("{:?}", protocol).to_lowercase()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn _validate_device(&self, device: &DeviceConfig) -> Result<(), String> {
+        // The name must fit in IFNAMSIZ with a null character
+        if device.name.is_empty() || device.name.len() >= 16 {
+            return Err("invalid name".to_string());
+        }
+        if !(DeviceConfig::MIN_MTU..=DeviceConfig::MAX_MTU).contains(&device.mtu) {
+            return Err(format!("invalid MTU: {}", device.mtu));
+        }
+        if device.device_type == DeviceType::Tun {
+            if device.mac.is_some() {
+                return Err("a MAC address is only for a TAP device".to_string());
+            }
+            if device.address == Some(Address::Dhcp) {
+                return Err("DHCP is only for a TAP device".to_string());
+            }
+        }
+        if let Some(prefix) = device._prefix() {
+            if prefix.address.is_unspecified() || prefix.address.is_broadcast() {
+                return Err(format!("invalid address: {}", prefix));
+            }
+            if let Some(host) = device.host {
+                if host.netmask != prefix.netmask || !prefix.contains(host.address) {
+                    return Err("the host and the stack are on different networks".to_string());
+                }
+                if host.address == prefix.address {
+                    return Err(format!("the host and the stack have {}", prefix.address));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn _validate_route(&self, route: &RouteConfig) -> Result<(), String> {
+        let index = self
+            .index(&route.device)
+            .ok_or_else(|| format!("no such device: {}", route.device))?;
+        if let (Some(gateway), Some(prefix)) = (route.via, self.devices[index]._prefix()) {
+            if !prefix.contains(gateway) {
+                return Err(format!("{} is not on {}", gateway, route.device));
+            }
+        }
+        Ok(())
+    }
+
+    fn _validate_dhcp(&self, dhcp: &DhcpConfig) -> Result<(), String> {
+        let index = self
+            .index(&dhcp.device)
+            .ok_or_else(|| format!("no such device: {}", dhcp.device))?;
+        let prefix = self.devices[index]
+            ._prefix()
+            .ok_or_else(|| format!("{} has no static address", dhcp.device))?;
+        if u32::from(dhcp.pool_start) > u32::from(dhcp.pool_end) {
+            return Err("the pool ends before the start".to_string());
+        }
+        for address in [dhcp.pool_start, dhcp.pool_end] {
+            if !prefix.contains(address) {
+                return Err(format!("{} is not on {}", address, dhcp.device));
+            }
+        }
+        let pool = u32::from(dhcp.pool_start)..=u32::from(dhcp.pool_end);
+        if pool.contains(&u32::from(prefix.address)) {
+            return Err(format!("the pool contains {}", prefix.address));
+        }
+        for (mac, address) in &dhcp.reservations {
+            MacAddress::from_str(mac).map_err(|e| e.0)?;
+            if !prefix.contains(*address) {
+                return Err(format!("{} is not on {}", address, dhcp.device));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn _parse<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn _parse_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    _parse(deserializer).map(Some)
+}
+
+fn _parse_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect()
+}
@@ -0,0 +1,236 @@
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, path::PathBuf, time::Duration};
+
+    use crate::{
+        config::{Address, Config, ConfigError, DeviceType, MetricsConfig, ProtocolName},
+        route::{Prefix, Route},
+        router::Interface,
+        services::Service,
+    };
+
+    fn netmask() -> Ipv4Addr {
+        Ipv4Addr::new(255, 255, 255, 0)
+    }
+
+    #[test]
+    fn example() {
+        let config = Config::parse(include_str!("../pareiodon.toml")).unwrap();
+        assert_eq!(config.devices.len(), 4);
+        assert_eq!(config.devices[2].device_type, DeviceType::Tap);
+        assert_eq!(config.devices[2].address, Some(Address::Dhcp));
+        assert!(config.has_protocol(ProtocolName::Raw));
+        assert_eq!(
+            config.interfaces()[0],
+            Interface::new(Ipv4Addr::new(192, 0, 2, 2), netmask(), 1500)
+        );
+        assert_eq!(config.interfaces()[2], Interface::unconfigured(1500));
+        assert_eq!(
+            config.services.builtin,
+            vec![Service::Echo, Service::Discard, Service::Chargen]
+        );
+
+        let dhcp = config.dhcp_server().unwrap();
+        assert_eq!(dhcp.interface, 3);
+        assert_eq!(dhcp.pool_start, Ipv4Addr::new(10, 0, 0, 100));
+        assert_eq!(dhcp.netmask, netmask());
+        assert_eq!(dhcp.dns, vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(dhcp.database, Some(PathBuf::from("pareiodon.leases")));
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::parse(
+            r#"
+            [[devices]]
+            name = "tun0"
+            type = "tun"
+            address = "192.0.2.2/24"
+
+            [[routes]]
+            destination = "default"
+            via = "192.0.2.1"
+            device = "tun0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.protocols,
+            vec![ProtocolName::Icmp, ProtocolName::Udp, ProtocolName::Tcp]
+        );
+        assert_eq!(config.devices[0].mtu, 1500);
+        assert_eq!(config.devices[0].host, None);
+        assert!(config.devices[0].configure);
+        assert!(config.firewall.rules.is_empty());
+        assert_eq!(config.logging.level, "info");
+        assert_eq!(config.capture, None);
+        assert_eq!(config.resolver, None);
+        assert_eq!(config.dhcp_server().map(|dhcp| dhcp.interface), None);
+
+        let default = Ipv4Addr::UNSPECIFIED;
+        let routes = config.routing_table();
+        assert_eq!(
+            routes.routes(),
+            &[Route::via(default, default, Ipv4Addr::new(192, 0, 2, 1), 0)]
+        );
+    }
+
+    #[test]
+    fn dhcp_server() {
+        let config = Config::parse(
+            r#"
+            [[devices]]
+            name = "tap1"
+            type = "tap"
+            mac = "02:00:00:00:00:01"
+            address = "10.0.0.1/24"
+
+            [services.dhcp]
+            device = "tap1"
+            pool_start = "10.0.0.100"
+            pool_end = "10.0.0.199"
+            lease_time = 600
+
+            [services.dhcp.reservations]
+            "02:00:00:00:00:02" = "10.0.0.50"
+            "#,
+        )
+        .unwrap();
+        let dhcp = config.dhcp_server().unwrap();
+        assert_eq!(dhcp.lease_time, Duration::from_secs(600));
+        assert_eq!(dhcp.reservations.len(), 1);
+    }
+
+    #[test]
+    fn resolver() {
+        let config = Config::parse(
+            r#"
+            [[devices]]
+            name = "tun0"
+            type = "tun"
+            address = "192.0.2.2/24"
+
+            [resolver]
+            nameservers = ["192.0.2.1", "198.51.100.1"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.resolver.unwrap().nameservers,
+            vec![Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(198, 51, 100, 1)]
+        );
+    }
+
+    fn error(s: &str) -> String {
+        let tun0 = r#"
+            [[devices]]
+            name = "tun0"
+            type = "tun"
+            address = "192.0.2.2/24"
+        "#;
+        // Before the tables for the keys at the top level
+        match Config::parse(&format!("{}\n{}", s, tun0)) {
+            Err(ConfigError(e)) => e,
+            Ok(_) => panic!("no error: {}", s),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Config::parse(""),
+            Err(ConfigError(
+                "TOML parse error at line 1, column 1\n  |\n1 | \n  | ^\nmissing field `devices`"
+                    .to_string()
+            ))
+        );
+        assert!(error("protocols = [\"sctp\"]").contains("unknown variant `sctp`"));
+        assert!(error("[logging]\nlevels = \"debug\"").contains("unknown field `levels`"));
+
+        let device = |s: &str| error(&format!("[[devices]]\n{}", s));
+        assert!(
+            device("name = \"tun1\"\ntype = \"tun\"\naddress = \"192.0.2.0/33\"")
+                .contains("route: invalid prefix: 192.0.2.0/33")
+        );
+        assert_eq!(
+            device("name = \"tun0\"\ntype = \"tun\""),
+            "duplicate device: tun0"
+        );
+        assert_eq!(
+            device("name = \"tun1\"\ntype = \"tun\"\nmac = \"02:00:00:00:00:01\""),
+            "device tun1: a MAC address is only for a TAP device"
+        );
+        assert_eq!(
+            device("name = \"tun1\"\ntype = \"tun\"\naddress = \"dhcp\""),
+            "device tun1: DHCP is only for a TAP device"
+        );
+        assert_eq!(
+            device("name = \"tun1\"\ntype = \"tun\"\nmtu = 67"),
+            "device tun1: invalid MTU: 67"
+        );
+        assert_eq!(
+            device("name = \"tun1\"\ntype = \"tun\"\nhost = \"198.51.100.1/24\"\naddress = \"198.51.100.1/24\""),
+            "device tun1: the host and the stack have 198.51.100.1"
+        );
+        assert_eq!(
+            device("name = \"tun1\"\ntype = \"tun\"\nhost = \"203.0.113.1/24\"\naddress = \"198.51.100.2/24\""),
+            "device tun1: the host and the stack are on different networks"
+        );
+
+        assert_eq!(
+            error("[[routes]]\ndestination = \"default\"\ndevice = \"eth0\""),
+            "route 0.0.0.0/0: no such device: eth0"
+        );
+        assert_eq!(
+            error(
+                "[[routes]]\ndestination = \"default\"\nvia = \"198.51.100.1\"\ndevice = \"tun0\""
+            ),
+            "route 0.0.0.0/0: 198.51.100.1 is not on tun0"
+        );
+
+        let dhcp = |pool_start: &str, pool_end: &str| {
+            error(&format!(
+                "[services.dhcp]\ndevice = \"tun0\"\npool_start = \"{}\"\npool_end = \"{}\"",
+                pool_start, pool_end
+            ))
+        };
+        assert_eq!(
+            dhcp("192.0.2.100", "192.0.2.99"),
+            "DHCP server: the pool ends before the start"
+        );
+        assert_eq!(
+            dhcp("192.0.2.100", "198.51.100.1"),
+            "DHCP server: 198.51.100.1 is not on tun0"
+        );
+        assert_eq!(
+            dhcp("192.0.2.1", "192.0.2.9"),
+            "DHCP server: the pool contains 192.0.2.2"
+        );
+
+        assert!(
+            error("[firewall]\nrules = [\"input proto tcp reject\", \"input dport 22 drop\"]")
+                .contains("firewall: ports need proto udp or tcp")
+        );
+        assert_eq!(
+            error("protocols = [\"icmp\", \"udp\"]\n[services.dns]\nzone = \"lab.zone\""),
+            "DNS needs tcp in the protocols"
+        );
+        assert_eq!(
+            error("[metrics]\nlisten = \"0.0.0.0:9464\""),
+            "metrics: not a loopback address: 0.0.0.0:9464"
+        );
+        assert!(MetricsConfig::new("[::1]:9464".parse().unwrap()).is_ok());
+        assert_eq!(
+            error("[resolver]\nnameservers = []"),
+            "resolver: no nameservers"
+        );
+        assert_eq!(
+            error("protocols = [\"icmp\"]\n[resolver]\nnameservers = [\"192.0.2.1\"]"),
+            "the resolver needs udp in the protocols"
+        );
+        assert_eq!(
+            Prefix::new(Ipv4Addr::new(192, 0, 2, 2), 24).network(),
+            Ipv4Addr::new(192, 0, 2, 0)
+        );
+    }
+}
@@ -0,0 +1,685 @@
+use std::{
+    collections::HashMap,
+    fmt, iter,
+    net::{Ipv4Addr, SocketAddrV4},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{firewall::FirewallError, icmp::Icmp, protocol::update_checksum, tcp::Tcp, udp::Udp};
+
+// The state of a datagram with respect to the connections seen before, as in
+// netfilter
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    // The first datagrams of a connection before a reply
+    New,
+    // A connection which has seen datagrams in both directions
+    Established,
+    // An ICMP error about a tracked connection
+    Related,
+    // A datagram which belongs to no connection and cannot start one, e.g. a
+    // TCP segment without SYN or a later fragment
+    Invalid,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            State::New => "new",
+            State::Established => "established",
+            State::Related => "related",
+            State::Invalid => "invalid",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for State {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(State::New),
+            "established" => Ok(State::Established),
+            "related" => Ok(State::Related),
+            "invalid" => Ok(State::Invalid),
+            _ => Err(FirewallError(format!("invalid state: {}", s))),
+        }
+    }
+}
+
+// The addresses and the ports of a flow in one direction. The identifier
+// takes the place of both ports for ICMP echo, and the ports are zero for
+// the other protocols.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Tuple {
+    pub protocol: u8,
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+}
+
+impl Tuple {
+    pub fn reverse(&self) -> Tuple {
+        Tuple {
+            protocol: self.protocol,
+            src: self.dst,
+            dst: self.src,
+        }
+    }
+
+    // The tuple of a datagram, or of the datagram in an ICMP error which may
+    // be cut after 8 octets of the data
+    pub fn parse(buf: &[u8]) -> Option<Tuple> {
+        let ihl = 4 * (*buf.first()? & 0xf) as usize;
+        let header = buf.get(..20)?;
+        let data = buf.get(ihl..)?;
+        let protocol = header[9];
+        let (src_port, dst_port) = match protocol {
+            Udp::NUMBER | Tcp::NUMBER => {
+                let ports = data.get(..4)?;
+                (
+                    u16::from_be_bytes([ports[0], ports[1]]),
+                    u16::from_be_bytes([ports[2], ports[3]]),
+                )
+            }
+            Icmp::NUMBER => {
+                let identifier = data.get(4..6)?;
+                let identifier = u16::from_be_bytes([identifier[0], identifier[1]]);
+                (identifier, identifier)
+            }
+            _ => (0, 0),
+        };
+        let src = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+        let dst = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+        Some(Tuple {
+            protocol,
+            src: SocketAddrV4::new(src, src_port),
+            dst: SocketAddrV4::new(dst, dst_port),
+        })
+    }
+}
+
+// A simplified state of a TCP connection seen from the middle
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TcpTrack {
+    SynSent,
+    SynReceived,
+    Established,
+    // FIN has been seen in either direction
+    FinWait,
+    // RST has been seen
+    Close,
+}
+
+impl TcpTrack {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const ACK: u8 = 0x10;
+
+    fn _timeout(&self) -> Duration {
+        match self {
+            TcpTrack::SynSent => Duration::from_secs(120),
+            TcpTrack::SynReceived => Duration::from_secs(60),
+            TcpTrack::Established => Duration::from_secs(5 * 24 * 60 * 60),
+            TcpTrack::FinWait => Duration::from_secs(120),
+            TcpTrack::Close => Duration::from_secs(10),
+        }
+    }
+
+    fn _next(&self, reply: bool, flags: u8) -> TcpTrack {
+        if flags & TcpTrack::RST != 0 {
+            return TcpTrack::Close;
+        }
+        if flags & TcpTrack::FIN != 0 {
+            return TcpTrack::FinWait;
+        }
+        match self {
+            TcpTrack::SynSent
+                if reply && flags & TcpTrack::SYN != 0 && flags & TcpTrack::ACK != 0 =>
+            {
+                TcpTrack::SynReceived
+            }
+            TcpTrack::SynReceived if !reply && flags & TcpTrack::ACK != 0 => TcpTrack::Established,
+            state => *state,
+        }
+    }
+}
+
+impl fmt::Display for TcpTrack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TcpTrack::SynSent => "SYN_SENT",
+            TcpTrack::SynReceived => "SYN_RECV",
+            TcpTrack::Established => "ESTABLISHED",
+            TcpTrack::FinWait => "FIN_WAIT",
+            TcpTrack::Close => "CLOSE",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// A tracked connection with the tuples of both directions
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Connection {
+    pub original: Tuple,
+    pub reply: Tuple,
+    pub tcp: Option<TcpTrack>,
+    // A datagram has been seen in the reply direction
+    pub replied: bool,
+    pub expires: Instant,
+}
+
+impl Connection {
+    fn _timeout(&self) -> Duration {
+        match (self.original.protocol, self.tcp) {
+            (_, Some(tcp)) => tcp._timeout(),
+            (Udp::NUMBER, _) if self.replied => Duration::from_secs(120),
+            (Udp::NUMBER | Icmp::NUMBER, _) => Duration::from_secs(30),
+            _ => Duration::from_secs(600),
+        }
+    }
+}
+
+// A datagram tracked in a connection
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Tracked {
+    pub state: State,
+    // The original tuple of the connection and whether the datagram is in the
+    // reply direction
+    connection: Option<(Tuple, bool)>,
+    // The datagram created the connection
+    created: bool,
+}
+
+// Which end of a datagram to translate
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum End {
+    Source,
+    Destination,
+}
+
+#[derive(Debug)]
+struct Table {
+    // By the original tuple
+    connections: HashMap<Tuple, Connection>,
+    // The original tuples by the reply tuples
+    replies: HashMap<Tuple, Tuple>,
+    swept: Option<Instant>,
+}
+
+// The connection tracking table (conntrack) of the router
+//
+// Every datagram received and sent is tracked before the firewall checks the
+// rules, so a rule can match on the state. A connection is removed after the
+// timeout of its protocol and state without datagrams. The number of
+// connections is limited as by nf_conntrack_max.
+#[derive(Debug)]
+pub struct Conntrack {
+    table: Mutex<Table>,
+    max: usize,
+}
+
+impl Conntrack {
+    // Expired connections are removed at most once in the interval
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+    // The ports to translate to if the original port is in use
+    const NAT_PORT_START: u16 = 1024;
+    const MAX_CONNECTIONS: usize = 65536;
+
+    pub fn new() -> Conntrack {
+        Conntrack::with_max(Conntrack::MAX_CONNECTIONS)
+    }
+
+    pub fn with_max(max: usize) -> Conntrack {
+        Conntrack {
+            table: Mutex::new(Table {
+                connections: HashMap::new(),
+                replies: HashMap::new(),
+                swept: None,
+            }),
+            max,
+        }
+    }
+
+    // The connections in the order of the original tuples
+    pub fn connections(&self) -> Vec<Connection> {
+        let table = self.table.lock().unwrap();
+        let mut connections: Vec<Connection> = table.connections.values().cloned().collect();
+        connections.sort_by_key(|connection| connection.original);
+        connections
+    }
+
+    // The datagram has been verified by IPv4. A datagram which finds the
+    // table full is invalid.
+    pub fn track(&self, buf: &[u8], now: Instant) -> State {
+        self.follow(buf, now)
+            .map_or(State::Invalid, |tracked| tracked.state)
+    }
+
+    // Track the datagram and return the connection to translate it in, or
+    // None if the datagram would start a connection and the table is full
+    pub fn follow(&self, buf: &[u8], now: Instant) -> Option<Tracked> {
+        let untracked = |state| {
+            Some(Tracked {
+                state,
+                connection: None,
+                created: false,
+            })
+        };
+        // Fragment Offset
+        if buf[6] & 0x1f != 0 || buf[7] != 0 {
+            return untracked(State::Invalid);
+        }
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        let data = &buf[ihl..];
+        let mut table = self.table.lock().unwrap();
+        Conntrack::_sweep(&mut table, now);
+
+        let protocol = buf[9];
+        let mut flags = 0;
+        match protocol {
+            Icmp::NUMBER => match data.first() {
+                Some(&icmp_type) if Icmp::is_error(icmp_type) => {
+                    // The error travels in the other direction than the
+                    // datagram in it
+                    let inner = match data.get(8..).and_then(Tuple::parse) {
+                        Some(inner) => inner,
+                        None => return untracked(State::Invalid),
+                    };
+                    let connection =
+                        Conntrack::_lookup(&mut table, &inner.reverse(), now).or_else(|| {
+                            Conntrack::_lookup(&mut table, &inner, now)
+                                .map(|(original, reply)| (original, !reply))
+                        });
+                    return match connection {
+                        Some(connection) => Some(Tracked {
+                            state: State::Related,
+                            connection: Some(connection),
+                            created: false,
+                        }),
+                        None => untracked(State::Invalid),
+                    };
+                }
+                // Echo Reply and Echo
+                Some(0 | 8) => {}
+                // Only echo is tracked
+                Some(_) => return untracked(State::New),
+                None => return untracked(State::Invalid),
+            },
+            Tcp::NUMBER => match data.get(13) {
+                Some(&f) => flags = f,
+                None => return untracked(State::Invalid),
+            },
+            _ => {}
+        }
+        let tuple = match Tuple::parse(buf) {
+            Some(tuple) => tuple,
+            None => return untracked(State::Invalid),
+        };
+
+        let (original, reply) = match Conntrack::_lookup(&mut table, &tuple, now) {
+            Some(found) => found,
+            None => {
+                let starts = match protocol {
+                    Icmp::NUMBER => data[0] == 8,
+                    Tcp::NUMBER => flags & (TcpTrack::SYN | TcpTrack::ACK) == TcpTrack::SYN,
+                    _ => true,
+                };
+                if !starts {
+                    return untracked(State::Invalid);
+                }
+                if table.connections.len() >= self.max && !Conntrack::_evict(&mut table, now) {
+                    return None;
+                }
+                let mut connection = Connection {
+                    original: tuple,
+                    reply: tuple.reverse(),
+                    tcp: (protocol == Tcp::NUMBER).then_some(TcpTrack::SynSent),
+                    replied: false,
+                    expires: now,
+                };
+                connection.expires = now + connection._timeout();
+                table.replies.insert(connection.reply, tuple);
+                table.connections.insert(tuple, connection);
+                return Some(Tracked {
+                    state: State::New,
+                    connection: Some((tuple, false)),
+                    created: true,
+                });
+            }
+        };
+
+        let connection = table.connections.get_mut(&original).unwrap();
+        connection.replied |= reply;
+        if let Some(tcp) = connection.tcp {
+            connection.tcp = Some(tcp._next(reply, flags));
+        }
+        connection.expires = now + connection._timeout();
+        let state = match connection.replied {
+            true => State::Established,
+            false => State::New,
+        };
+        Some(Tracked {
+            state,
+            connection: Some((original, reply)),
+            created: false,
+        })
+    }
+
+    // Remove the connection created by a datagram which is dropped, so that
+    // only the accepted datagrams take entries in the table
+    pub fn discard(&self, tracked: &Tracked) {
+        if let (true, Some((original, _))) = (tracked.created, tracked.connection) {
+            let mut table = self.table.lock().unwrap();
+            Conntrack::_remove(&mut table, &original);
+        }
+    }
+
+    // Rewrite the source of a new connection to the address and a port, or
+    // an identifier of echo, which no other connection uses. The port is
+    // kept if possible. Returns false if all the ports are in use.
+    pub fn bind_source(&self, tracked: &Tracked, address: Ipv4Addr) -> bool {
+        let original = match tracked.connection {
+            Some((original, false)) => original,
+            _ => return false,
+        };
+        let mut table = self.table.lock().unwrap();
+        let old = match table.connections.get(&original) {
+            Some(connection) => connection.reply,
+            None => return false,
+        };
+        // Bound by an earlier datagram
+        if old.dst != original.src {
+            return true;
+        }
+
+        let port = original.src.port();
+        let start = match original.protocol {
+            Icmp::NUMBER => 0,
+            _ => Conntrack::NAT_PORT_START,
+        };
+        let candidates = iter::once(port).chain((start..=u16::MAX).filter(|&p| p != port));
+        for candidate in candidates {
+            // The destination may be translated already
+            let src = match original.protocol {
+                Icmp::NUMBER => SocketAddrV4::new(*old.src.ip(), candidate),
+                _ => old.src,
+            };
+            let reply = Tuple {
+                protocol: original.protocol,
+                src,
+                dst: SocketAddrV4::new(address, candidate),
+            };
+            if Conntrack::_rebind(&mut table, original, reply) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Rewrite the destination of a new connection to the address, and to
+    // the port if any. Returns false if another connection uses the tuple.
+    pub fn bind_destination(
+        &self,
+        tracked: &Tracked,
+        address: Ipv4Addr,
+        port: Option<u16>,
+    ) -> bool {
+        let original = match tracked.connection {
+            Some((original, false)) => original,
+            _ => return false,
+        };
+        let mut table = self.table.lock().unwrap();
+        let old = match table.connections.get(&original) {
+            Some(connection) => connection.reply,
+            None => return false,
+        };
+        // Bound by an earlier datagram
+        if old.src != original.dst {
+            return true;
+        }
+        let port = port.unwrap_or(original.dst.port());
+        let reply = Tuple {
+            protocol: original.protocol,
+            src: SocketAddrV4::new(address, port),
+            dst: old.dst,
+        };
+        Conntrack::_rebind(&mut table, original, reply)
+    }
+
+    // Whether the destination of the connection is translated
+    pub fn is_redirected(&self, tracked: &Tracked) -> bool {
+        let original = match tracked.connection {
+            Some((original, _)) => original,
+            None => return false,
+        };
+        let table = self.table.lock().unwrap();
+        table
+            .connections
+            .get(&original)
+            .is_some_and(|connection| connection.reply.src != original.dst)
+    }
+
+    // Replace the reply tuple of the connection unless another connection
+    // uses the new one
+    fn _rebind(table: &mut Table, original: Tuple, reply: Tuple) -> bool {
+        let old = table.connections[&original].reply;
+        if reply == old {
+            return true;
+        }
+        if table.replies.contains_key(&reply) || table.connections.contains_key(&reply) {
+            return false;
+        }
+        table.replies.remove(&old);
+        table.replies.insert(reply, original);
+        table.connections.get_mut(&original).unwrap().reply = reply;
+        true
+    }
+
+    // Rewrite the destination of the datagram for the connection, before
+    // routing
+    pub fn translate_destination(&self, tracked: &Tracked, buf: &mut [u8]) {
+        if let Some((_, dst)) = self._targets(tracked) {
+            Conntrack::_translate(tracked, buf, End::Destination, dst);
+        }
+    }
+
+    // Rewrite the source of the datagram for the connection, after routing
+    pub fn translate_source(&self, tracked: &Tracked, buf: &mut [u8]) {
+        if let Some((src, _)) = self._targets(tracked) {
+            Conntrack::_translate(tracked, buf, End::Source, src);
+        }
+    }
+
+    // The source and the destination of the datagrams in the direction
+    fn _targets(&self, tracked: &Tracked) -> Option<(SocketAddrV4, SocketAddrV4)> {
+        let (original, reply) = tracked.connection?;
+        let table = self.table.lock().unwrap();
+        let connection = table.connections.get(&original)?;
+        // Not translated
+        if connection.reply == original.reverse() {
+            return None;
+        }
+        Some(match reply {
+            false => (connection.reply.dst, connection.reply.src),
+            true => (connection.original.dst, connection.original.src),
+        })
+    }
+
+    fn _translate(tracked: &Tracked, buf: &mut [u8], end: End, to: SocketAddrV4) {
+        if tracked.state != State::Related {
+            Conntrack::_rewrite(buf, 0, end, to, None);
+            return;
+        }
+        // The address of the error and the other end of the datagram in it.
+        // Nothing is translated if the end of the datagram is already the
+        // target, e.g. in an error about a datagram before its source is
+        // translated.
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        let current = buf
+            .get(ihl + 8..)
+            .and_then(Tuple::parse)
+            .map(|inner| match end {
+                End::Source => inner.dst,
+                End::Destination => inner.src,
+            });
+        if current == Some(to) {
+            return;
+        }
+        Conntrack::_rewrite(buf, 0, end, SocketAddrV4::new(*to.ip(), 0), None);
+        let inner = match end {
+            End::Source => End::Destination,
+            End::Destination => End::Source,
+        };
+        Conntrack::_rewrite(buf, ihl + 8, inner, to, Some(ihl + 2));
+    }
+
+    // Rewrite an end of the datagram at the offset. `outer` is the checksum
+    // of the ICMP error which carries the datagram. The checksums are
+    // updated incrementally (RFC 1624).
+    fn _rewrite(buf: &mut [u8], ip: usize, end: End, to: SocketAddrV4, outer: Option<usize>) {
+        let ihl = 4 * (buf[ip] & 0xf) as usize;
+        let protocol = buf[ip + 9];
+        let l4 = ip + ihl;
+        // The checksum of the protocol if it is in the buffer. Zero means no
+        // checksum for UDP.
+        let checksum = match protocol {
+            Udp::NUMBER if buf.len() >= l4 + 8 && buf[l4 + 6..l4 + 8] != [0, 0] => Some(l4 + 6),
+            Tcp::NUMBER if buf.len() >= l4 + 18 => Some(l4 + 16),
+            _ => None,
+        };
+
+        // The checksums of TCP and UDP cover the addresses in the pseudo
+        // header
+        let address = ip
+            + match end {
+                End::Source => 12,
+                End::Destination => 16,
+            };
+        let [a, b, c, d] = to.ip().octets();
+        let checksums: Vec<usize> = [Some(ip + 10), checksum].into_iter().flatten().collect();
+        Conntrack::_replace(buf, address, u16::from_be_bytes([a, b]), &checksums, outer);
+        Conntrack::_replace(
+            buf,
+            address + 2,
+            u16::from_be_bytes([c, d]),
+            &checksums,
+            outer,
+        );
+
+        let port = match (protocol, end, buf.get(l4)) {
+            (Udp::NUMBER | Tcp::NUMBER, End::Source, _) => Some((l4, checksum)),
+            (Udp::NUMBER | Tcp::NUMBER, End::Destination, _) => Some((l4 + 2, checksum)),
+            // The identifier of Echo Reply and Echo
+            (Icmp::NUMBER, _, Some(0 | 8)) => Some((l4 + 4, Some(l4 + 2))),
+            _ => None,
+        };
+        if let Some((port, checksum)) = port {
+            if buf.len() >= port + 2 {
+                let checksums: Vec<usize> = checksum.into_iter().collect();
+                Conntrack::_replace(buf, port, to.port(), &checksums, outer);
+            }
+        }
+        // RFC 768: a computed checksum of zero is sent as all ones
+        if protocol == Udp::NUMBER && checksum.is_some() && buf[l4 + 6..l4 + 8] == [0, 0] {
+            Conntrack::_replace(buf, l4 + 6, 0xffff, &[], outer);
+        }
+    }
+
+    // Replace the 16-bit word at the offset and update the checksums which
+    // cover it. The outer checksum covers the other checksums as well.
+    fn _replace(
+        buf: &mut [u8],
+        offset: usize,
+        new: u16,
+        checksums: &[usize],
+        outer: Option<usize>,
+    ) {
+        let old = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        if old == new {
+            return;
+        }
+        buf[offset..offset + 2].copy_from_slice(&new.to_be_bytes());
+        let mut changes = vec![(old, new)];
+        for &checksum in checksums {
+            let old = u16::from_be_bytes([buf[checksum], buf[checksum + 1]]);
+            let new = update_checksum(old, changes[0].0, changes[0].1);
+            buf[checksum..checksum + 2].copy_from_slice(&new.to_be_bytes());
+            changes.push((old, new));
+        }
+        if let Some(outer) = outer {
+            for (old, new) in changes {
+                let checksum = u16::from_be_bytes([buf[outer], buf[outer + 1]]);
+                let checksum = update_checksum(checksum, old, new);
+                buf[outer..outer + 2].copy_from_slice(&checksum.to_be_bytes());
+            }
+        }
+    }
+
+    // The original tuple of the connection, and whether the tuple is in the
+    // reply direction. An expired connection is removed.
+    fn _lookup(table: &mut Table, tuple: &Tuple, now: Instant) -> Option<(Tuple, bool)> {
+        let (original, reply) = match table.connections.contains_key(tuple) {
+            true => (*tuple, false),
+            false => (*table.replies.get(tuple)?, true),
+        };
+        if table.connections[&original].expires <= now {
+            Conntrack::_remove(table, &original);
+            return None;
+        }
+        Some((original, reply))
+    }
+
+    fn _remove(table: &mut Table, original: &Tuple) {
+        if let Some(connection) = table.connections.remove(original) {
+            table.replies.remove(&connection.reply);
+        }
+    }
+
+    // Make room for a new connection by removing an expired connection or
+    // the unreplied one which expires first, as early_drop in netfilter.
+    // Returns false if all the connections have been replied.
+    fn _evict(table: &mut Table, now: Instant) -> bool {
+        let victim = table
+            .connections
+            .values()
+            .filter(|connection| !connection.replied || connection.expires <= now)
+            .min_by_key(|connection| connection.expires)
+            .map(|connection| connection.original);
+        match victim {
+            Some(original) => {
+                Conntrack::_remove(table, &original);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn _sweep(table: &mut Table, now: Instant) {
+        if table
+            .swept
+            .is_some_and(|swept| now < swept + Conntrack::SWEEP_INTERVAL)
+        {
+            return;
+        }
+        table.swept = Some(now);
+        let expired: Vec<Tuple> = table
+            .connections
+            .values()
+            .filter(|connection| connection.expires <= now)
+            .map(|connection| connection.original)
+            .collect();
+        for original in expired {
+            Conntrack::_remove(table, &original);
+        }
+    }
+}
+
+impl Default for Conntrack {
+    fn default() -> Self {
+        Conntrack::new()
+    }
+}
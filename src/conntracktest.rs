@@ -0,0 +1,501 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        conntrack::{Conntrack, State, TcpTrack, Tuple},
+        icmp::{DestinationUnreachableCode, Icmp},
+        ipv4::IPv4,
+        protocol::{get_checksum, ProtocolError},
+        tcp::Tcp,
+        testutil::{
+            assert_checksums, datagram, echo, forward, host, remote, router, router_with_mtu,
+            segment, stack, udp,
+        },
+        udp::Udp,
+    };
+
+    #[test]
+    fn echo_states() {
+        let conntrack = Conntrack::new();
+        let now = Instant::now();
+        let request = echo(host(), remote(), 8);
+        let reply = echo(remote(), host(), 0);
+        assert_eq!(conntrack.track(&reply, now), State::Invalid);
+        assert_eq!(conntrack.track(&request, now), State::New);
+        assert_eq!(conntrack.track(&request, now), State::New);
+        assert_eq!(conntrack.track(&reply, now), State::Established);
+        assert_eq!(conntrack.track(&request, now), State::Established);
+
+        let connections = conntrack.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(
+            connections[0].original,
+            Tuple {
+                protocol: Icmp::NUMBER,
+                src: SocketAddrV4::new(host(), 0x1234),
+                dst: SocketAddrV4::new(remote(), 0x1234),
+            }
+        );
+        assert_eq!(connections[0].reply, connections[0].original.reverse());
+        assert!(connections[0].replied);
+        assert_eq!(connections[0].expires, now + Duration::from_secs(30));
+
+        // Other ICMP messages are not tracked
+        let timestamp = datagram(host(), remote(), Icmp::NUMBER, &[13, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(conntrack.track(&timestamp, now), State::New);
+        assert_eq!(conntrack.connections().len(), 1);
+    }
+
+    #[test]
+    fn tcp_states() {
+        let conntrack = Conntrack::new();
+        let now = Instant::now();
+        let ports = (40000, 80);
+        let reverse = (80, 40000);
+        let tcp = |conntrack: &Conntrack| conntrack.connections()[0].tcp;
+
+        // Only SYN starts a connection
+        let ack = segment(host(), remote(), ports, 0x10);
+        assert_eq!(conntrack.track(&ack, now), State::Invalid);
+        let syn = segment(host(), remote(), ports, 0x02);
+        assert_eq!(conntrack.track(&syn, now), State::New);
+        assert_eq!(tcp(&conntrack), Some(TcpTrack::SynSent));
+        let syn_ack = segment(remote(), host(), reverse, 0x12);
+        assert_eq!(conntrack.track(&syn_ack, now), State::Established);
+        assert_eq!(tcp(&conntrack), Some(TcpTrack::SynReceived));
+        assert_eq!(conntrack.track(&ack, now), State::Established);
+        assert_eq!(tcp(&conntrack), Some(TcpTrack::Established));
+        assert_eq!(
+            conntrack.connections()[0].expires,
+            now + Duration::from_secs(5 * 24 * 60 * 60)
+        );
+
+        let fin = segment(remote(), host(), reverse, 0x11);
+        assert_eq!(conntrack.track(&fin, now), State::Established);
+        assert_eq!(tcp(&conntrack), Some(TcpTrack::FinWait));
+        let rst = segment(host(), remote(), ports, 0x04);
+        assert_eq!(conntrack.track(&rst, now), State::Established);
+        assert_eq!(tcp(&conntrack), Some(TcpTrack::Close));
+
+        // Removed after 10 seconds in CLOSE
+        let later = now + Duration::from_secs(10);
+        assert_eq!(conntrack.track(&ack, later), State::Invalid);
+        assert!(conntrack.connections().is_empty());
+    }
+
+    #[test]
+    fn timeout() {
+        let conntrack = Conntrack::new();
+        let now = Instant::now();
+        assert_eq!(conntrack.track(&udp(host(), remote()), now), State::New);
+        let reply = datagram(
+            remote(),
+            host(),
+            Udp::NUMBER,
+            &[0, 53, 0x9c, 0x40, 0, 8, 0, 0],
+        );
+        assert_eq!(
+            conntrack.track(&reply, now + Duration::from_secs(29)),
+            State::Established
+        );
+        // 120 seconds after a reply
+        let later = now + Duration::from_secs(29 + 119);
+        assert_eq!(
+            conntrack.track(&udp(host(), remote()), later),
+            State::Established
+        );
+        let later = later + Duration::from_secs(120);
+        assert_eq!(conntrack.track(&udp(host(), remote()), later), State::New);
+
+        // Swept with the other connections
+        let later = later + Duration::from_secs(30);
+        assert_eq!(
+            conntrack.track(&echo(host(), remote(), 8), later),
+            State::New
+        );
+        assert_eq!(conntrack.connections().len(), 1);
+    }
+
+    #[test]
+    fn related() {
+        let conntrack = Conntrack::new();
+        let now = Instant::now();
+        let request = udp(host(), remote());
+        let unreachable = |original: &[u8]| {
+            let icmp = Icmp::destination_unreachable(DestinationUnreachableCode::Net, original, 0);
+            datagram(Ipv4Addr::new(203, 0, 113, 1), host(), Icmp::NUMBER, &icmp)
+        };
+        assert_eq!(conntrack.track(&unreachable(&request), now), State::Invalid);
+        assert_eq!(conntrack.track(&request, now), State::New);
+        assert_eq!(conntrack.track(&unreachable(&request), now), State::Related);
+
+        // A later fragment
+        let mut fragment = udp(host(), remote());
+        fragment[7] = 1;
+        assert_eq!(conntrack.track(&fragment, now), State::Invalid);
+    }
+
+    // Only the connections from the inside are forwarded
+    #[test]
+    fn stateful() {
+        let router = router(IPv4::new(vec![]));
+        for rule in [
+            "forward state established,related accept",
+            "forward from 198.51.100.0/24 drop",
+            "forward state invalid drop",
+        ] {
+            router.firewall().add(rule.parse().unwrap());
+        }
+
+        let request = echo(remote(), host(), 8);
+        assert_eq!(router.input(1, &request), Err(ProtocolError::General));
+        let request = echo(host(), remote(), 8);
+        assert_eq!(router.input(0, &request).unwrap()[0].0, 1);
+        let reply = echo(remote(), host(), 0);
+        assert_eq!(router.input(1, &reply).unwrap()[0].0, 0);
+
+        let ack = segment(host(), remote(), (40000, 80), 0x10);
+        assert_eq!(router.input(0, &ack), Err(ProtocolError::General));
+        assert_eq!(router.ipv4_stats().in_discards.get(), 2);
+    }
+
+    #[test]
+    fn limit() {
+        let conntrack = Conntrack::with_max(2);
+        let now = Instant::now();
+        let hosts = [
+            host(),
+            Ipv4Addr::new(192, 0, 2, 3),
+            Ipv4Addr::new(192, 0, 2, 4),
+        ];
+        let reply = |src| datagram(remote(), src, Udp::NUMBER, &[0, 53, 0x9c, 0x40, 0, 8, 0, 0]);
+        assert_eq!(conntrack.track(&udp(hosts[0], remote()), now), State::New);
+        assert_eq!(conntrack.track(&reply(hosts[0]), now), State::Established);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(conntrack.track(&udp(hosts[1], remote()), later), State::New);
+
+        // The unreplied connection makes room for the new one
+        let later = now + Duration::from_secs(2);
+        assert_eq!(conntrack.track(&udp(hosts[2], remote()), later), State::New);
+        let sources: Vec<Ipv4Addr> = conntrack
+            .connections()
+            .iter()
+            .map(|connection| *connection.original.src.ip())
+            .collect();
+        assert_eq!(sources, vec![hosts[0], hosts[2]]);
+
+        // Full of replied connections
+        assert_eq!(conntrack.track(&reply(hosts[2]), later), State::Established);
+        assert!(conntrack.follow(&udp(hosts[1], remote()), later).is_none());
+        assert_eq!(conntrack.connections().len(), 2);
+        // Datagrams of the connections are still tracked
+        assert_eq!(conntrack.track(&reply(hosts[0]), later), State::Established);
+    }
+
+    #[test]
+    fn discard() {
+        let router = router(IPv4::new(vec![]));
+        router
+            .firewall()
+            .add("forward proto udp dport 53 drop".parse().unwrap());
+        router
+            .firewall()
+            .add("forward proto tcp dport 25 reject".parse().unwrap());
+
+        // Dropped datagrams take no entries
+        let query = udp(host(), remote());
+        assert_eq!(router.input(0, &query), Err(ProtocolError::General));
+        let syn = segment(host(), remote(), (40000, 25), 0x02);
+        assert_eq!(router.input(0, &syn).unwrap()[0].0, 0);
+
+        // Nor do the datagrams answered only with Destination Unreachable
+        // or Time Exceeded
+        let unrouted = echo(host(), Ipv4Addr::new(203, 0, 113, 1), 8);
+        let error = router.input(0, &unrouted).unwrap();
+        assert_eq!(error[0].1[20..22], [3, 0]);
+        let mut expiring = echo(host(), remote(), 8);
+        expiring[8] = 1;
+        expiring[10..12].copy_from_slice(&[0, 0]);
+        let checksum = get_checksum(&expiring[..20]);
+        expiring[10..12].copy_from_slice(&checksum.to_be_bytes());
+        let error = router.input(0, &expiring).unwrap();
+        assert_eq!(error[0].1[20..22], [11, 0]);
+        assert!(router.conntrack().connections().is_empty());
+
+        let request = echo(host(), remote(), 8);
+        assert_eq!(router.input(0, &request).unwrap()[0].0, 1);
+        assert_eq!(router.conntrack().connections().len(), 1);
+    }
+
+    #[test]
+    fn masquerade() {
+        let router = router(IPv4::new(vec![]));
+        let public = Ipv4Addr::new(198, 51, 100, 2);
+        router
+            .firewall()
+            .add("postrouting from 192.0.2.0/24 masquerade".parse().unwrap());
+
+        let (index, request) = forward(&router, 0, &echo(host(), remote(), 8));
+        assert_eq!(index, 1);
+        assert_eq!(&request[12..16], &public.octets());
+        assert_eq!(&request[24..26], &[0x12, 0x34]);
+        assert_checksums(&request);
+        let (index, reply) = forward(&router, 1, &echo(remote(), public, 0));
+        assert_eq!(index, 0);
+        assert_eq!(&reply[16..20], &host().octets());
+        assert_checksums(&reply);
+
+        // Another host with the same identifier
+        let other = Ipv4Addr::new(192, 0, 2, 3);
+        let (_, request) = forward(&router, 0, &echo(other, remote(), 8));
+        assert_ne!(&request[24..26], &[0x12, 0x34]);
+        assert_checksums(&request);
+        let (_, reply) = forward(&router, 1, &echo(remote(), public, 0));
+        assert_eq!(&reply[16..20], &host().octets());
+
+        let connections = router.conntrack().connections();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(
+            connections[0].reply,
+            Tuple {
+                protocol: Icmp::NUMBER,
+                src: SocketAddrV4::new(remote(), 0x1234),
+                dst: SocketAddrV4::new(public, 0x1234),
+            }
+        );
+    }
+
+    #[test]
+    fn snat() {
+        let router = router(IPv4::new(vec![]));
+        let public = Ipv4Addr::new(198, 51, 100, 10);
+        router.firewall().add(
+            "postrouting to 198.51.100.0/24 snat 198.51.100.10"
+                .parse()
+                .unwrap(),
+        );
+
+        // The port is kept unless another connection uses it
+        let udp = |src: Ipv4Addr| {
+            let data = Udp::datagram(
+                SocketAddrV4::new(src, 40000),
+                SocketAddrV4::new(remote(), 53),
+                b"query",
+            );
+            datagram(src, remote(), Udp::NUMBER, &data)
+        };
+        let (_, first) = forward(&router, 0, &udp(host()));
+        assert_eq!(&first[12..16], &public.octets());
+        assert_eq!(&first[20..22], &40000u16.to_be_bytes());
+        assert_checksums(&first);
+        let (_, second) = forward(&router, 0, &udp(Ipv4Addr::new(192, 0, 2, 3)));
+        assert_eq!(&second[20..22], &1024u16.to_be_bytes());
+        assert_checksums(&second);
+
+        let data = Udp::datagram(
+            SocketAddrV4::new(remote(), 53),
+            SocketAddrV4::new(public, 1024),
+            b"answer",
+        );
+        let answer = datagram(remote(), public, Udp::NUMBER, &data);
+        let (index, answer) = forward(&router, 1, &answer);
+        assert_eq!(index, 0);
+        assert_eq!(&answer[16..20], &[192, 0, 2, 3]);
+        assert_eq!(&answer[22..24], &40000u16.to_be_bytes());
+        assert_checksums(&answer);
+
+        let syn = Tcp::segment(
+            SocketAddrV4::new(host(), 40000),
+            SocketAddrV4::new(remote(), 80),
+            1,
+            0,
+            0x02,
+            1024,
+            Some(1460),
+            &[],
+        );
+        let (_, syn) = forward(&router, 0, &datagram(host(), remote(), Tcp::NUMBER, &syn));
+        assert_eq!(&syn[12..16], &public.octets());
+        assert_checksums(&syn);
+    }
+
+    // The datagram in an ICMP error is translated back as well
+    #[test]
+    fn translate_error() {
+        let router = router(IPv4::new(vec![]));
+        let public = Ipv4Addr::new(198, 51, 100, 2);
+        router
+            .firewall()
+            .add("postrouting masquerade".parse().unwrap());
+        let data = Udp::datagram(
+            SocketAddrV4::new(host(), 40000),
+            SocketAddrV4::new(remote(), 53),
+            b"query",
+        );
+        let original = datagram(host(), remote(), Udp::NUMBER, &data);
+        let (_, translated) = forward(&router, 0, &original);
+
+        let icmp = Icmp::destination_unreachable(DestinationUnreachableCode::Net, &translated, 0);
+        let error = datagram(remote(), public, Icmp::NUMBER, &icmp);
+        let (index, error) = forward(&router, 1, &error);
+        assert_eq!(index, 0);
+        assert_eq!(&error[16..20], &host().octets());
+        assert_checksums(&error);
+        // The original datagram as sent by the host, except for the TTL
+        let mut expected = original[..28].to_vec();
+        expected[8] -= 1;
+        expected[10..12].copy_from_slice(&[0, 0]);
+        let checksum = get_checksum(&expected[..20]);
+        expected[10..12].copy_from_slice(&checksum.to_be_bytes());
+        assert_eq!(&error[28..56], &expected[..]);
+    }
+
+    // Set Don't Fragment
+    fn dont_fragment(buf: &mut [u8]) {
+        buf[6] |= 0x40;
+        buf[10..12].copy_from_slice(&[0, 0]);
+        let checksum = get_checksum(&buf[..20]);
+        buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    #[test]
+    fn fragmentation_needed() {
+        // The hosts behind interface 1 share the address of interface 0
+        let router = router_with_mtu(IPv4::new(vec![]), 576);
+        router.firewall().add(
+            "postrouting from 198.51.100.0/24 masquerade"
+                .parse()
+                .unwrap(),
+        );
+        let inside = Ipv4Addr::new(198, 51, 100, 3);
+        for src in [remote(), inside] {
+            let data = Udp::datagram(
+                SocketAddrV4::new(src, 40000),
+                SocketAddrV4::new(host(), 53),
+                b"query",
+            );
+            forward(&router, 1, &datagram(src, host(), Udp::NUMBER, &data));
+        }
+
+        // A reply too big for interface 1 to the second host, whose port has
+        // been translated
+        let data = Udp::datagram(
+            SocketAddrV4::new(host(), 53),
+            SocketAddrV4::new(stack(), 1024),
+            &[0; 1000],
+        );
+        let mut reply = datagram(host(), stack(), Udp::NUMBER, &data);
+        dont_fragment(&mut reply);
+        let (index, error) = forward(&router, 0, &reply);
+        assert_eq!(index, 0);
+        assert_eq!(&error[12..16], &stack().octets());
+        assert_eq!(&error[16..20], &host().octets());
+        assert_eq!(&error[20..22], &[3, 4]);
+        assert_eq!(&error[26..28], &576u16.to_be_bytes());
+        assert_checksums(&error);
+        // The datagram as sent by the host
+        assert_eq!(&error[28..56], &reply[..28]);
+        assert_eq!(
+            router.conntrack().track(&error, Instant::now()),
+            State::Related
+        );
+    }
+
+    fn syn(src: SocketAddrV4, dst: SocketAddrV4) -> Vec<u8> {
+        let tcp = Tcp::segment(src, dst, 1, 0, 0x02, 1024, None, &[]);
+        datagram(*src.ip(), *dst.ip(), Tcp::NUMBER, &tcp)
+    }
+
+    fn syn_ack(src: SocketAddrV4, dst: SocketAddrV4) -> Vec<u8> {
+        let tcp = Tcp::segment(src, dst, 1, 2, 0x12, 1024, None, &[]);
+        datagram(*src.ip(), *dst.ip(), Tcp::NUMBER, &tcp)
+    }
+
+    #[test]
+    fn port_forward() {
+        let router = router(IPv4::new(vec![]));
+        let public = SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 2), 8080);
+        let server = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 5), 80);
+        router.firewall().add(
+            "prerouting to 198.51.100.2 proto tcp dport 8080 dnat 192.0.2.5:80"
+                .parse()
+                .unwrap(),
+        );
+
+        let client = SocketAddrV4::new(remote(), 40000);
+        let (index, request) = forward(&router, 1, &syn(client, public));
+        assert_eq!(index, 0);
+        assert_eq!(&request[16..20], &server.ip().octets());
+        assert_eq!(&request[22..24], &80u16.to_be_bytes());
+        assert_checksums(&request);
+
+        let (index, reply) = forward(&router, 0, &syn_ack(server, client));
+        assert_eq!(index, 1);
+        assert_eq!(&reply[12..16], &public.ip().octets());
+        assert_eq!(&reply[20..22], &8080u16.to_be_bytes());
+        assert_checksums(&reply);
+    }
+
+    #[test]
+    fn port_forward_fragmentation_needed() {
+        // The server behind interface 1 is reached on a port of interface 0
+        let router = router_with_mtu(IPv4::new(vec![]), 576);
+        let public = SocketAddrV4::new(stack(), 8080);
+        let server = SocketAddrV4::new(remote(), 80);
+        router.firewall().add(
+            "prerouting to 192.0.2.2 proto tcp dport 8080 dnat 198.51.100.1:80"
+                .parse()
+                .unwrap(),
+        );
+        let client = SocketAddrV4::new(host(), 40000);
+        forward(&router, 0, &syn(client, public));
+        forward(&router, 1, &syn_ack(server, client));
+
+        // A segment too big for interface 1
+        let tcp = Tcp::segment(client, public, 2, 3001, 0x18, 1024, None, &[0; 1000]);
+        let mut segment = datagram(host(), stack(), Tcp::NUMBER, &tcp);
+        dont_fragment(&mut segment);
+        let (index, error) = forward(&router, 0, &segment);
+        assert_eq!(index, 0);
+        assert_eq!(&error[12..16], &stack().octets());
+        assert_eq!(&error[16..20], &host().octets());
+        assert_eq!(&error[20..22], &[3, 4]);
+        assert_eq!(&error[26..28], &576u16.to_be_bytes());
+        assert_checksums(&error);
+        // The segment as sent by the client, to the public address and port
+        assert_eq!(&error[28..56], &segment[..28]);
+    }
+
+    // A host connects to the public address of a server on the same link
+    #[test]
+    fn hairpin() {
+        let router = router(IPv4::new(vec![]));
+        let public = SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 2), 8080);
+        let server = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 5), 80);
+        router.firewall().add(
+            "prerouting to 198.51.100.2 proto tcp dport 8080 dnat 192.0.2.5:80"
+                .parse()
+                .unwrap(),
+        );
+
+        let client = SocketAddrV4::new(host(), 40000);
+        let (index, request) = forward(&router, 0, &syn(client, public));
+        assert_eq!(index, 0);
+        // From the router so that the server replies through it
+        assert_eq!(&request[12..16], &[192, 0, 2, 2]);
+        assert_eq!(&request[16..20], &server.ip().octets());
+        assert_checksums(&request);
+
+        let router_side = SocketAddrV4::new(stack(), 40000);
+        let (index, reply) = forward(&router, 0, &syn_ack(server, router_side));
+        assert_eq!(index, 0);
+        assert_eq!(&reply[12..16], &public.ip().octets());
+        assert_eq!(&reply[16..20], &client.ip().octets());
+        assert_eq!(&reply[20..24], &[0x1f, 0x90, 0x9c, 0x40]);
+        assert_checksums(&reply);
+    }
+}
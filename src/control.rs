@@ -0,0 +1,487 @@
+use std::{
+    fmt,
+    fmt::Write as _,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+use log::debug;
+
+use crate::{
+    arp::NeighborState,
+    conntrack::{Connection, Tuple},
+    device::Device,
+    ethernet::MacAddress,
+    firewall::{FirewallError, Rule},
+    icmp::Icmp,
+    resolver::Resolver,
+    route::{Prefix, Route, RouteError},
+    router::{Interface, Router},
+    socket::{SocketInfo, SocketType, Sockets},
+    tcp::{Tcp, TcpState},
+    udp::Udp,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ControlError(pub String);
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "control: {}", self.0)
+    }
+}
+
+// Shows and changes the state of the running stack over a Unix domain socket
+//
+// A client sends one command in a line. The response starts with "ok" or
+// "error" and a message in the first line, followed by the output. A command
+// is applied at once under the locks of the router or the device, so the
+// stack never sees a half-applied change.
+pub struct Control {
+    sockets: Sockets,
+    router: Arc<Router>,
+    devices: Arc<[Device]>,
+    // The names of the interfaces
+    names: Vec<String>,
+    resolver: Option<Resolver>,
+}
+
+impl Control {
+    pub const DEFAULT_PATH: &'static str = "/run/pareiodon.sock";
+
+    pub fn new(
+        sockets: Sockets,
+        router: Arc<Router>,
+        devices: Arc<[Device]>,
+        names: Vec<String>,
+    ) -> Control {
+        Control {
+            sockets,
+            router,
+            devices,
+            names,
+            resolver: None,
+        }
+    }
+
+    // Answer `resolve` and `nameservers` with the resolver
+    pub fn with_resolver(mut self, resolver: Resolver) -> Control {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    // Replace the socket left by a previous run
+    pub fn bind(path: &Path) -> Result<UnixListener, ControlError> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(ControlError(format!("not a socket: {}", path.display())));
+            }
+            let _ = fs::remove_file(path);
+        }
+        UnixListener::bind(path).map_err(|e| ControlError(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn serve(self: Arc<Self>, listener: UnixListener) -> Result<(), ControlError> {
+        loop {
+            let (stream, _) = listener.accept().map_err(|e| ControlError(e.to_string()))?;
+            let control = self.clone();
+            thread::spawn(move || {
+                if let Err(e) = control._respond(stream) {
+                    debug!("{}", e);
+                }
+            });
+        }
+    }
+
+    // Send a command to the running stack and return the output
+    pub fn request(path: &Path, command: &str) -> Result<String, ControlError> {
+        let error = |e: std::io::Error| ControlError(format!("{}: {}", path.display(), e));
+        let mut stream = UnixStream::connect(path).map_err(error)?;
+        stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .map_err(error)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(error)?;
+
+        let (status, body) = response.split_once('\n').unwrap_or((&response, ""));
+        match status.split_once(' ') {
+            Some(("error", message)) => Err(ControlError(message.to_string())),
+            _ if status == "ok" => Ok(body.to_string()),
+            _ => Err(ControlError(format!("invalid response: {}", status))),
+        }
+    }
+
+    fn _respond(&self, stream: UnixStream) -> Result<(), ControlError> {
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut line)
+            .map_err(|e| ControlError(e.to_string()))?;
+        let response = match self.handle(line.trim()) {
+            Ok(body) => format!("ok\n{}", body),
+            Err(e) => format!("error {}\n", e.0),
+        };
+        (&stream)
+            .write_all(response.as_bytes())
+            .map_err(|e| ControlError(e.to_string()))
+    }
+
+    // Returns the output of the command
+    pub fn handle(&self, command: &str) -> Result<String, ControlError> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["sockets"] => Ok(Control::format_sockets(&self.sockets.list())),
+            ["routes"] => Ok(self._routes()),
+            ["neighbors"] => Ok(self._neighbors()),
+            ["addresses"] => Ok(self._addresses()),
+            ["addresses", "add", prefix, "dev", name] => self._add_address(prefix, name),
+            ["addresses", "del", prefix, "dev", name] => self._remove_address(prefix, name),
+            ["routes", "add", args @ ..] => {
+                let route = self._route(args)?;
+                match self.router.add_route(route) {
+                    true => Ok(String::new()),
+                    false => Err(ControlError("route exists".to_string())),
+                }
+            }
+            ["routes", "del", args @ ..] => {
+                let route = self._route(args)?;
+                match self.router.remove_route(&route) {
+                    true => Ok(String::new()),
+                    false => Err(ControlError("no such route".to_string())),
+                }
+            }
+            ["neighbors", "add", ip, "lladdr", mac, "dev", name] => {
+                let ip = Control::_parse::<Ipv4Addr>(ip)?;
+                let mac = Control::_parse::<MacAddress>(mac)?;
+                match self._device(name)?.add_neighbor(ip, mac) {
+                    true => Ok(String::new()),
+                    false => Err(ControlError(format!("no ARP on {}", name))),
+                }
+            }
+            ["neighbors", "del", ip, "dev", name] => {
+                let ip = Control::_parse::<Ipv4Addr>(ip)?;
+                match self._device(name)?.remove_neighbor(ip) {
+                    true => Ok(String::new()),
+                    false => Err(ControlError("no such neighbor".to_string())),
+                }
+            }
+            ["nameservers"] => Ok(self._nameservers()),
+            ["resolve", name] => self._resolve(name),
+            ["rules"] => Ok(self._rules()),
+            ["connections"] => Ok(self._connections()),
+            ["rules", "add", args @ ..] => {
+                self.router.firewall().add(Control::_rule(args)?);
+                Ok(String::new())
+            }
+            ["rules", "del", args @ ..] => {
+                match self.router.firewall().delete(&Control::_rule(args)?) {
+                    true => Ok(String::new()),
+                    false => Err(ControlError("no such rule".to_string())),
+                }
+            }
+            [] => Err(ControlError("no command".to_string())),
+            _ => Err(ControlError(format!("unknown command: {}", command))),
+        }
+    }
+
+    fn _name(&self, index: usize) -> String {
+        self.names
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| index.to_string())
+    }
+
+    fn _index(&self, name: &str) -> Result<usize, ControlError> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .filter(|&i| i < self.router.interfaces().len())
+            .ok_or_else(|| ControlError(format!("no such device: {}", name)))
+    }
+
+    fn _device(&self, name: &str) -> Result<&Device, ControlError> {
+        let i = self._index(name)?;
+        self.devices
+            .get(i)
+            .ok_or_else(|| ControlError(format!("no such device: {}", name)))
+    }
+
+    fn _parse<T: FromStr>(s: &str) -> Result<T, ControlError> {
+        s.parse()
+            .map_err(|_| ControlError(format!("invalid argument: {}", s)))
+    }
+
+    fn _parse_prefix(s: &str) -> Result<Prefix, ControlError> {
+        s.parse().map_err(|e: RouteError| ControlError(e.0))
+    }
+
+    // PREFIX [via GATEWAY] dev NAME
+    fn _route(&self, args: &[&str]) -> Result<Route, ControlError> {
+        let (prefix, gateway, name) = match args {
+            [prefix, "dev", name] => (prefix, None, name),
+            [prefix, "via", gateway, "dev", name] => (prefix, Some(gateway), name),
+            _ => {
+                return Err(ControlError(
+                    "usage: routes add|del PREFIX [via GATEWAY] dev NAME".to_string(),
+                ))
+            }
+        };
+        let prefix = Control::_parse_prefix(prefix)?;
+        let interface = self._index(name)?;
+        Ok(match gateway {
+            Some(gateway) => Route::via(
+                prefix.address,
+                prefix.netmask,
+                Control::_parse(gateway)?,
+                interface,
+            ),
+            None => Route::new(prefix.address, prefix.netmask, interface),
+        })
+    }
+
+    // An interface has one address. Like `ip addr`, only the route of the
+    // directly connected network is added or removed with it.
+    fn _add_address(&self, prefix: &str, name: &str) -> Result<String, ControlError> {
+        let prefix = Control::_parse_prefix(prefix)?;
+        let i = self._index(name)?;
+        let interface = self.router.interface(i);
+        if !interface.address.is_unspecified() {
+            return Err(ControlError(format!("{} has an address", name)));
+        }
+        self.router.set_interface(
+            i,
+            Interface::new(prefix.address, prefix.netmask, interface.mtu),
+        );
+        Ok(String::new())
+    }
+
+    fn _remove_address(&self, prefix: &str, name: &str) -> Result<String, ControlError> {
+        let prefix = Control::_parse_prefix(prefix)?;
+        let i = self._index(name)?;
+        let interface = self.router.interface(i);
+        if interface.address != prefix.address || interface.netmask != prefix.netmask {
+            return Err(ControlError("no such address".to_string()));
+        }
+        self.router
+            .set_interface(i, Interface::unconfigured(interface.mtu));
+        Ok(String::new())
+    }
+
+    // Like ss
+    pub fn format_sockets(sockets: &[SocketInfo]) -> String {
+        let mut out = format!(
+            "{:<6} {:<11} {:>6} {:>6} {:<21} {:<21}\n",
+            "Netid", "State", "Recv-Q", "Send-Q", "Local Address:Port", "Peer Address:Port"
+        );
+        for socket in sockets {
+            let (netid, local, remote) = match socket.socket_type {
+                SocketType::Datagram => ("udp", socket.local.to_string(), socket.remote),
+                SocketType::Stream => ("tcp", socket.local.to_string(), socket.remote),
+                // The protocol number in place of the port
+                SocketType::Raw(protocol) => (
+                    "raw",
+                    format!("{}:{}", socket.local.ip(), protocol),
+                    socket
+                        .remote
+                        .map(|remote| SocketAddrV4::new(*remote.ip(), protocol as u16)),
+                ),
+            };
+            let state = match socket.state {
+                Some(state) => Control::_state(state),
+                None if socket.remote.is_some() => "ESTAB",
+                None => "UNCONN",
+            };
+            let remote = remote.map_or("*:*".to_string(), |remote| remote.to_string());
+            let _ = writeln!(
+                out,
+                "{:<6} {:<11} {:>6} {:>6} {:<21} {:<21}",
+                netid, state, socket.recv_queue, socket.send_queue, local, remote
+            );
+        }
+        out
+    }
+
+    fn _state(state: TcpState) -> &'static str {
+        match state {
+            TcpState::Closed => "UNCONN",
+            TcpState::Listen => "LISTEN",
+            TcpState::SynSent => "SYN-SENT",
+            TcpState::SynReceived => "SYN-RECV",
+            TcpState::Established => "ESTAB",
+            TcpState::FinWait1 => "FIN-WAIT-1",
+            TcpState::FinWait2 => "FIN-WAIT-2",
+            TcpState::CloseWait => "CLOSE-WAIT",
+            TcpState::Closing => "CLOSING",
+            TcpState::LastAck => "LAST-ACK",
+            TcpState::TimeWait => "TIME-WAIT",
+        }
+    }
+
+    // Like ip route
+    fn _routes(&self) -> String {
+        let interfaces = self.router.interfaces();
+        let mut out = String::new();
+        for route in self.router.routes() {
+            let destination = match u32::from(route.netmask) {
+                0 => "default".to_string(),
+                _ => {
+                    let prefix = Prefix {
+                        address: route.destination,
+                        netmask: route.netmask,
+                    };
+                    format!("{}/{}", prefix.network(), prefix.length())
+                }
+            };
+            let _ = write!(out, "{}", destination);
+            if let Some(gateway) = route.gateway {
+                let _ = write!(out, " via {}", gateway);
+            }
+            let _ = write!(out, " dev {}", self._name(route.interface));
+            if route.gateway.is_none() {
+                if let Some(interface) = interfaces.get(route.interface) {
+                    let _ = write!(out, " scope link src {}", interface.address);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Like conntrack -L
+    fn _connections(&self) -> String {
+        let now = Instant::now();
+        let mut out = String::new();
+        for connection in self.router.conntrack().connections() {
+            let Connection {
+                original, reply, ..
+            } = connection;
+            let name = match original.protocol {
+                Icmp::NUMBER => "icmp",
+                Udp::NUMBER => "udp",
+                Tcp::NUMBER => "tcp",
+                _ => "unknown",
+            };
+            let timeout = connection.expires.saturating_duration_since(now).as_secs();
+            let _ = write!(out, "{:<8}{:<4}{} ", name, original.protocol, timeout);
+            if let Some(tcp) = connection.tcp {
+                let _ = write!(out, "{} ", tcp);
+            }
+            let _ = write!(out, "{} ", Control::_tuple(&original));
+            if !connection.replied {
+                out.push_str("[UNREPLIED] ");
+            }
+            let _ = writeln!(out, "{}", Control::_tuple(&reply));
+        }
+        out
+    }
+
+    fn _tuple(tuple: &Tuple) -> String {
+        let addresses = format!("src={} dst={}", tuple.src.ip(), tuple.dst.ip());
+        match tuple.protocol {
+            Icmp::NUMBER => format!("{} id={}", addresses, tuple.src.port()),
+            Udp::NUMBER | Tcp::NUMBER => format!(
+                "{} sport={} dport={}",
+                addresses,
+                tuple.src.port(),
+                tuple.dst.port()
+            ),
+            _ => addresses,
+        }
+    }
+
+    // The resolver configured or learned from DHCP
+    fn _resolver(&self) -> Result<&Resolver, ControlError> {
+        self.resolver
+            .as_ref()
+            .ok_or_else(|| ControlError("no resolver".to_string()))
+    }
+
+    // Like resolv.conf
+    fn _nameservers(&self) -> String {
+        let mut out = String::new();
+        if let Ok(resolver) = self._resolver() {
+            for nameserver in resolver.nameservers() {
+                let _ = writeln!(out, "nameserver {}", nameserver);
+            }
+        }
+        out
+    }
+
+    fn _resolve(&self, name: &str) -> Result<String, ControlError> {
+        let addresses = self
+            ._resolver()?
+            .lookup_ipv4(name)
+            .map_err(|e| ControlError(e.0))?;
+        let mut out = String::new();
+        for address in addresses {
+            let _ = writeln!(out, "{}", address);
+        }
+        Ok(out)
+    }
+
+    // The rules of the firewall in the order to check
+    fn _rules(&self) -> String {
+        let mut out = String::new();
+        for rule in self.router.firewall().rules() {
+            let _ = writeln!(out, "{}", rule);
+        }
+        out
+    }
+
+    fn _rule(args: &[&str]) -> Result<Rule, ControlError> {
+        args.join(" ")
+            .parse()
+            .map_err(|e: FirewallError| ControlError(e.0))
+    }
+
+    // Like ip neigh
+    fn _neighbors(&self) -> String {
+        let mut out = String::new();
+        for (i, device) in self.devices.iter().enumerate() {
+            for neighbor in device.neighbors() {
+                let _ = write!(out, "{} dev {}", neighbor.ip, self._name(i));
+                if let Some(mac) = neighbor.mac {
+                    let _ = write!(out, " lladdr {}", mac);
+                }
+                let state = match neighbor.state {
+                    NeighborState::Reachable => "REACHABLE",
+                    NeighborState::Incomplete => "INCOMPLETE",
+                    NeighborState::Permanent => "PERMANENT",
+                };
+                let _ = writeln!(out, " {}", state);
+            }
+        }
+        out
+    }
+
+    // Like ip -4 address
+    fn _addresses(&self) -> String {
+        let mut out = String::new();
+        for (i, interface) in self.router.interfaces().iter().enumerate() {
+            let _ = writeln!(out, "{}: {}: mtu {}", i, self._name(i), interface.mtu);
+            if let Some(mac) = self.devices.get(i).and_then(|device| device.mac()) {
+                let _ = writeln!(out, "    link/ether {}", mac);
+            }
+            if !interface.address.is_unspecified() {
+                let prefix = u32::from(interface.netmask).count_ones();
+                let _ = writeln!(
+                    out,
+                    "    inet {}/{} brd {}",
+                    interface.address,
+                    prefix,
+                    interface.broadcast()
+                );
+            }
+        }
+        out
+    }
+}
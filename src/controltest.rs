@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        net::{Ipv4Addr, SocketAddrV4},
+        process,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        control::{Control, ControlError},
+        dhcp::Lease,
+        dhcpclient::DhcpClient,
+        ethernet::MacAddress,
+        eventloop::EventLoop,
+        icmp::Icmp,
+        ipv4::IPv4,
+        protocol::get_checksum,
+        resolver::Resolver,
+        route::{Route, RoutingTable},
+        router::{Interface, Router},
+        socket::{SocketType, Sockets},
+    };
+
+    fn control() -> (Control, Sockets) {
+        let (control, sockets, _) = control_router();
+        (control, sockets)
+    }
+
+    fn control_router() -> (Control, Sockets, Arc<Router>) {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let interfaces = vec![
+            Interface::new(Ipv4Addr::new(192, 0, 2, 2), netmask, 1500),
+            Interface::unconfigured(1500),
+        ];
+        let mut routes = RoutingTable::new();
+        let default = Ipv4Addr::UNSPECIFIED;
+        routes.add(Route::via(default, default, Ipv4Addr::new(192, 0, 2, 1), 0));
+        let ipv4 = IPv4::new(vec![Box::new(Icmp::new())]);
+        let router = Arc::new(Router::new(ipv4, interfaces, routes));
+        let names = vec!["tun0".to_string(), "tap0".to_string()];
+        (
+            Control::new(sockets.clone(), router.clone(), Arc::from(vec![]), names),
+            sockets,
+            router,
+        )
+    }
+
+    #[test]
+    fn sockets() {
+        let (control, sockets) = control();
+        let udp = sockets.socket(SocketType::Datagram).unwrap();
+        sockets
+            .bind(udp, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 53))
+            .unwrap();
+        let tcp = sockets.socket(SocketType::Stream).unwrap();
+        sockets
+            .bind(tcp, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 7))
+            .unwrap();
+        sockets.listen(tcp, 8).unwrap();
+        sockets.socket(SocketType::Raw(1)).unwrap();
+
+        let output = control.handle("sockets").unwrap();
+        let lines: Vec<&str> = output.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Netid  State       Recv-Q Send-Q Local Address:Port    Peer Address:Port",
+                "udp    UNCONN           0      0 0.0.0.0:53            *:*",
+                "tcp    LISTEN           0      0 0.0.0.0:7             *:*",
+                "raw    UNCONN           0      0 0.0.0.0:1             *:*",
+            ]
+        );
+    }
+
+    #[test]
+    fn routes() {
+        let (control, _) = control();
+        assert_eq!(
+            control.handle("routes"),
+            Ok("default via 192.0.2.1 dev tun0\n\
+                192.0.2.0/24 dev tun0 scope link src 192.0.2.2\n"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        let (control, _) = control();
+        assert_eq!(
+            control.handle("addresses"),
+            Ok("0: tun0: mtu 1500\n    \
+                inet 192.0.2.2/24 brd 192.0.2.255\n\
+                1: tap0: mtu 1500\n"
+                .to_string())
+        );
+        assert_eq!(control.handle("neighbors"), Ok(String::new()));
+    }
+
+    #[test]
+    fn change() {
+        let (control, _) = control();
+        let ok = Ok(String::new());
+        assert_eq!(control.handle("addresses add 198.51.100.1/24 dev tap0"), ok);
+        assert_eq!(
+            control.handle("addresses add 198.51.100.1/24 dev tap0"),
+            Err(ControlError("tap0 has an address".to_string()))
+        );
+        assert_eq!(
+            control.handle("routes add 203.0.113.0/24 via 198.51.100.254 dev tap0"),
+            ok
+        );
+        assert_eq!(
+            control.handle("routes add 203.0.113.0/24 via 198.51.100.254 dev tap0"),
+            Err(ControlError("route exists".to_string()))
+        );
+        assert_eq!(
+            control.handle("routes"),
+            Ok("default via 192.0.2.1 dev tun0\n\
+                192.0.2.0/24 dev tun0 scope link src 192.0.2.2\n\
+                198.51.100.0/24 dev tap0 scope link src 198.51.100.1\n\
+                203.0.113.0/24 via 198.51.100.254 dev tap0\n"
+                .to_string())
+        );
+
+        assert_eq!(
+            control.handle("routes del default via 192.0.2.1 dev tun0"),
+            ok
+        );
+        assert_eq!(
+            control.handle("routes del default via 192.0.2.1 dev tun0"),
+            Err(ControlError("no such route".to_string()))
+        );
+        // Only the connected route is removed with the address
+        assert_eq!(control.handle("addresses del 198.51.100.1/24 dev tap0"), ok);
+        assert_eq!(
+            control.handle("routes"),
+            Ok("192.0.2.0/24 dev tun0 scope link src 192.0.2.2\n\
+                203.0.113.0/24 via 198.51.100.254 dev tap0\n"
+                .to_string())
+        );
+        assert_eq!(control.handle("addresses add 198.51.100.3/24 dev tap0"), ok);
+        assert_eq!(
+            control.handle("routes"),
+            Ok("192.0.2.0/24 dev tun0 scope link src 192.0.2.2\n\
+                203.0.113.0/24 via 198.51.100.254 dev tap0\n\
+                198.51.100.0/24 dev tap0 scope link src 198.51.100.3\n"
+                .to_string())
+        );
+
+        assert_eq!(
+            control.handle("routes add 10.0.0.0/33 dev tun0"),
+            Err(ControlError("invalid prefix: 10.0.0.0/33".to_string()))
+        );
+        assert_eq!(
+            control.handle("routes add 10.0.0.0/8 dev eth0"),
+            Err(ControlError("no such device: eth0".to_string()))
+        );
+        assert!(control.handle("routes add 10.0.0.0/8").is_err());
+        // No devices in the test
+        assert_eq!(
+            control.handle("neighbors add 192.0.2.1 lladdr 02:00:00:00:00:01 dev tun0"),
+            Err(ControlError("no such device: tun0".to_string()))
+        );
+    }
+
+    #[test]
+    fn rules() {
+        let (control, _) = control();
+        let ok = Ok(String::new());
+        assert_eq!(control.handle("rules"), ok);
+        assert_eq!(
+            control.handle("rules add input proto tcp dport 23 reject"),
+            ok
+        );
+        assert_eq!(
+            control.handle("rules add forward from 192.0.2.0/24  proto icmp   drop"),
+            ok
+        );
+        assert_eq!(
+            control.handle("rules"),
+            Ok("input proto tcp dport 23 reject\n\
+                forward from 192.0.2.0/24 proto icmp drop\n"
+                .to_string())
+        );
+        assert_eq!(
+            control.handle("rules del input proto tcp dport 23 reject"),
+            ok
+        );
+        assert_eq!(
+            control.handle("rules del input proto tcp dport 23 reject"),
+            Err(ControlError("no such rule".to_string()))
+        );
+        assert_eq!(
+            control.handle("rules add input dport 23 drop"),
+            Err(ControlError("ports need proto udp or tcp".to_string()))
+        );
+    }
+
+    #[test]
+    fn connections() {
+        let (control, _, router) = control_router();
+        let mut icmp = vec![8, 0, 0, 0, 0, 1, 0, 1];
+        let checksum = get_checksum(&icmp);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+        let request = IPv4::new(vec![]).datagram(
+            Ipv4Addr::new(192, 0, 2, 1),
+            Ipv4Addr::new(198, 51, 100, 1),
+            Icmp::NUMBER,
+            &icmp,
+        );
+        router.input(0, &request).unwrap();
+        // The seconds until the timeout after the protocol number
+        let output = control.handle("connections").unwrap();
+        let (timeout, output) = output[12..].split_once(' ').unwrap();
+        assert!(["29", "30"].contains(&timeout));
+        assert_eq!(
+            output,
+            "src=192.0.2.1 dst=198.51.100.1 id=1 [UNREPLIED] src=198.51.100.1 dst=192.0.2.1 id=1\n"
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        let (control, _) = control();
+        assert_eq!(
+            control.handle("reboot"),
+            Err(ControlError("unknown command: reboot".to_string()))
+        );
+        assert_eq!(
+            control.handle(""),
+            Err(ControlError("no command".to_string()))
+        );
+    }
+
+    #[test]
+    fn request() {
+        let path = env::temp_dir().join(format!("pareiodon-control-{}.sock", process::id()));
+        let listener = Control::bind(&path).unwrap();
+        let control = Arc::new(control().0);
+        thread::spawn(move || control.serve(listener));
+
+        let output = Control::request(&path, "routes").unwrap();
+        assert!(output.starts_with("default via 192.0.2.1 dev tun0\n"));
+        assert_eq!(
+            Control::request(&path, "reboot"),
+            Err(ControlError("unknown command: reboot".to_string()))
+        );
+
+        // A stale socket is replaced
+        assert!(Control::bind(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resolver() {
+        let (control, sockets, router) = control_router();
+        assert_eq!(control.handle("nameservers"), Ok(String::new()));
+        assert_eq!(
+            control.handle("resolve example.com"),
+            Err(ControlError("no resolver".to_string()))
+        );
+
+        // The nameservers follow the DHCP lease of tap0
+        let mac = MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let dhcp = DhcpClient::new(sockets.clone(), router, 1, mac);
+        let control = control.with_resolver(Resolver::from_dhcp(sockets, dhcp.clone()));
+        assert_eq!(control.handle("nameservers"), Ok(String::new()));
+        let server = Ipv4Addr::new(198, 51, 100, 1);
+        dhcp.configure(&Lease {
+            address: Ipv4Addr::new(198, 51, 100, 10),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            router: None,
+            dns: vec![server, Ipv4Addr::new(198, 51, 100, 2)],
+            mtu: None,
+            server,
+            lease_time: Duration::from_secs(3600),
+            renewal_time: Duration::from_secs(1800),
+            rebinding_time: Duration::from_secs(3150),
+        });
+        assert_eq!(
+            control.handle("nameservers"),
+            Ok("nameserver 198.51.100.1\nnameserver 198.51.100.2\n".to_string())
+        );
+        dhcp.unconfigure();
+        assert_eq!(control.handle("nameservers"), Ok(String::new()));
+    }
+}
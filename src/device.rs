@@ -0,0 +1,171 @@
+use std::{
+    net::Ipv4Addr,
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use log::debug;
+use nix::Error;
+
+use crate::{
+    arp::Neighbor,
+    ethernet::{Ethernet, MacAddress},
+    pcapng::{self, Capture, Direction},
+    router::Interface,
+    stats::DeviceStats,
+    tuntap::TunTap,
+};
+
+// A TUN device carries IP datagrams while a TAP device carries Ethernet frames
+pub struct Device {
+    tuntap: TunTap,
+    ethernet: Option<Mutex<Ethernet>>,
+    // The capture with the ID of the interface
+    capture: Option<(Arc<Capture>, u32)>,
+    stats: Arc<DeviceStats>,
+}
+
+impl Device {
+    const BUFFER_SIZE: usize = 65535;
+
+    pub fn tun(tuntap: TunTap) -> Device {
+        Device {
+            tuntap,
+            ethernet: None,
+            capture: None,
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn tap(tuntap: TunTap, mac: MacAddress) -> Device {
+        Device {
+            tuntap,
+            ethernet: Some(Mutex::new(Ethernet::new(mac))),
+            capture: None,
+            stats: Arc::default(),
+        }
+    }
+
+    // Record the packets in both directions
+    pub fn set_capture(&mut self, capture: Arc<Capture>, name: &str) {
+        let linktype = match self.ethernet {
+            Some(_) => pcapng::LINKTYPE_ETHERNET,
+            None => pcapng::LINKTYPE_RAW,
+        };
+        let id = capture.add_interface(name, linktype);
+        self.capture = Some((capture, id));
+    }
+
+    fn _capture(&self, direction: Direction, buf: &[u8]) {
+        if let Some((capture, id)) = &self.capture {
+            capture.packet(*id, direction, buf, SystemTime::now());
+        }
+    }
+
+    // The ARP cache, which a TUN device does not have
+    pub fn neighbors(&self) -> Vec<Neighbor> {
+        match &self.ethernet {
+            Some(ethernet) => ethernet.lock().unwrap().neighbors(Instant::now()),
+            None => vec![],
+        }
+    }
+
+    // Add a permanent entry to the ARP cache. Returns false for a TUN device.
+    pub fn add_neighbor(&self, ip: Ipv4Addr, mac: MacAddress) -> bool {
+        match &self.ethernet {
+            Some(ethernet) => {
+                ethernet.lock().unwrap().add_neighbor(ip, mac);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_neighbor(&self, ip: Ipv4Addr) -> bool {
+        match &self.ethernet {
+            Some(ethernet) => ethernet.lock().unwrap().remove_neighbor(ip),
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<DeviceStats> {
+        &self.stats
+    }
+
+    fn _write(&self, buf: &[u8]) -> Result<(), Error> {
+        self._capture(Direction::Outbound, buf);
+        self.tuntap
+            .write(buf)
+            .inspect_err(|_| self.stats.out_discards.increment())?;
+        self.stats.out_packets.increment();
+        self.stats.out_octets.add(buf.len() as u64);
+        Ok(())
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.tuntap.fd()
+    }
+
+    pub fn mac(&self) -> Option<MacAddress> {
+        self.ethernet
+            .as_ref()
+            .map(|ethernet| ethernet.lock().unwrap().mac())
+    }
+
+    // Returns the IP datagram read from the device if any
+    pub fn receive(&self, interface: &Interface) -> Result<Option<Vec<u8>>, Error> {
+        let mut buf = vec![0u8; Device::BUFFER_SIZE];
+        let n = self
+            .tuntap
+            .read(&mut buf)
+            .inspect_err(|_| self.stats.in_discards.increment())?;
+        buf.truncate(n);
+        self._capture(Direction::Inbound, &buf);
+        self.stats.in_packets.increment();
+        self.stats.in_octets.add(n as u64);
+
+        let ethernet = match &self.ethernet {
+            Some(ethernet) => ethernet,
+            None => return Ok(Some(buf)),
+        };
+        let result = ethernet
+            .lock()
+            .unwrap()
+            .input(&buf, interface, Instant::now());
+        match result {
+            Ok((datagram, frames)) => {
+                for frame in frames {
+                    self._write(&frame)?;
+                }
+                Ok(datagram)
+            }
+            Err(e) => {
+                debug!("dropped: {}", e);
+                self.stats.in_discards.increment();
+                Ok(None)
+            }
+        }
+    }
+
+    // Send the datagram to the next hop on the link
+    pub fn send(
+        &self,
+        next_hop: Ipv4Addr,
+        interface: &Interface,
+        datagram: &[u8],
+    ) -> Result<(), Error> {
+        let ethernet = match &self.ethernet {
+            Some(ethernet) => ethernet,
+            None => return self._write(datagram),
+        };
+        let frames = ethernet
+            .lock()
+            .unwrap()
+            .output(next_hop, interface, datagram, Instant::now());
+        for frame in frames {
+            self._write(&frame)?;
+        }
+        Ok(())
+    }
+}
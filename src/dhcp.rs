@@ -0,0 +1,276 @@
+use std::{fmt, net::Ipv4Addr, time::Duration};
+
+use crate::ethernet::MacAddress;
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct DhcpError(pub String);
+
+impl fmt::Display for DhcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dhcp: {}", self.0)
+    }
+}
+
+// RFC 2132
+pub mod option {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DOMAIN_NAME_SERVER: u8 = 6;
+    pub const INTERFACE_MTU: u8 = 26;
+    pub const REQUESTED_IP_ADDRESS: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_IDENTIFIER: u8 = 54;
+    pub const PARAMETER_REQUEST_LIST: u8 = 55;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const REBINDING_TIME: u8 = 59;
+    pub const END: u8 = 255;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = DhcpError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(MessageType::Discover),
+            2 => Ok(MessageType::Offer),
+            3 => Ok(MessageType::Request),
+            4 => Ok(MessageType::Decline),
+            5 => Ok(MessageType::Ack),
+            6 => Ok(MessageType::Nak),
+            7 => Ok(MessageType::Release),
+            8 => Ok(MessageType::Inform),
+            _ => Err(DhcpError(format!("unknown message type: type={}", value))),
+        }
+    }
+}
+
+// RFC 2131
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DhcpMessage {
+    pub op: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: MacAddress,
+    pub options: Vec<(u8, Vec<u8>)>,
+}
+
+impl DhcpMessage {
+    pub const SERVER_PORT: u16 = 67;
+    pub const CLIENT_PORT: u16 = 68;
+    pub const BOOTREQUEST: u8 = 1;
+    pub const BOOTREPLY: u8 = 2;
+    // Flags (Broadcast)
+    pub const BROADCAST: u16 = 0x8000;
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+    // The fixed fields and the magic cookie
+    const HEADER_SIZE: usize = 240;
+
+    pub fn new(op: u8, xid: u32, chaddr: MacAddress, message_type: MessageType) -> DhcpMessage {
+        DhcpMessage {
+            op,
+            xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            options: vec![(option::MESSAGE_TYPE, vec![message_type as u8])],
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<DhcpMessage, DhcpError> {
+        if buf.len() < DhcpMessage::HEADER_SIZE {
+            return Err(DhcpError("too short".to_string()));
+        }
+        // Hardware Type (Ethernet), Hardware Address Length
+        if buf[1] != 1 || buf[2] != 6 {
+            return Err(DhcpError(format!(
+                "unsupported hardware: htype={}, hlen={}",
+                buf[1], buf[2]
+            )));
+        }
+        if buf[236..240] != DhcpMessage::MAGIC_COOKIE {
+            return Err(DhcpError("magic cookie error".to_string()));
+        }
+
+        let address = |i: usize| Ipv4Addr::new(buf[i], buf[i + 1], buf[i + 2], buf[i + 3]);
+        Ok(DhcpMessage {
+            op: buf[0],
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            secs: u16::from_be_bytes([buf[8], buf[9]]),
+            flags: u16::from_be_bytes([buf[10], buf[11]]),
+            ciaddr: address(12),
+            yiaddr: address(16),
+            siaddr: address(20),
+            giaddr: address(24),
+            chaddr: MacAddress::from_slice(&buf[28..34]),
+            options: DhcpMessage::_parse_options(&buf[DhcpMessage::HEADER_SIZE..])?,
+        })
+    }
+
+    fn _parse_options(buf: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, DhcpError> {
+        let mut options = vec![];
+        let mut i = 0;
+        while i < buf.len() {
+            match buf[i] {
+                option::END => break,
+                option::PAD => i += 1,
+                code => {
+                    let length = *buf
+                        .get(i + 1)
+                        .ok_or_else(|| DhcpError("option length error".to_string()))?
+                        as usize;
+                    if i + 2 + length > buf.len() {
+                        return Err(DhcpError(format!(
+                            "option length error: code={}, length={}",
+                            code, length
+                        )));
+                    }
+                    options.push((code, buf[i + 2..i + 2 + length].to_vec()));
+                    i += 2 + length;
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Hardware Type (Ethernet), Hardware Address Length, Hops
+        let mut buf = vec![self.op, 1, 6, 0];
+        buf.extend_from_slice(&self.xid.to_be_bytes());
+        buf.extend_from_slice(&self.secs.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.ciaddr.octets());
+        buf.extend_from_slice(&self.yiaddr.octets());
+        buf.extend_from_slice(&self.siaddr.octets());
+        buf.extend_from_slice(&self.giaddr.octets());
+        buf.extend_from_slice(&self.chaddr.0);
+        // The rest of chaddr, sname and file
+        buf.resize(DhcpMessage::HEADER_SIZE - 4, 0);
+        buf.extend_from_slice(&DhcpMessage::MAGIC_COOKIE);
+
+        for (code, data) in &self.options {
+            buf.push(*code);
+            buf.push(data.len() as u8);
+            buf.extend_from_slice(data);
+        }
+        buf.push(option::END);
+        buf
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        let data = self.option(option::MESSAGE_TYPE)?;
+        MessageType::try_from(*data.first()?).ok()
+    }
+
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub fn add_option(&mut self, code: u8, data: &[u8]) {
+        self.options.push((code, data.to_vec()));
+    }
+
+    pub fn addresses(&self, code: u8) -> Vec<Ipv4Addr> {
+        self.option(code)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+            .collect()
+    }
+
+    pub fn address(&self, code: u8) -> Option<Ipv4Addr> {
+        self.addresses(code).first().copied()
+    }
+
+    // Times in seconds
+    pub fn duration(&self, code: u8) -> Option<Duration> {
+        let data: [u8; 4] = self.option(code)?.try_into().ok()?;
+        Some(Duration::from_secs(u32::from_be_bytes(data) as u64))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub router: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    pub mtu: Option<usize>,
+    pub server: Ipv4Addr,
+    pub lease_time: Duration,
+    // T1
+    pub renewal_time: Duration,
+    // T2
+    pub rebinding_time: Duration,
+}
+
+impl Lease {
+    // The smallest MTU which every host must accept (RFC 791)
+    const MIN_MTU: usize = 68;
+
+    pub fn from_ack(ack: &DhcpMessage) -> Result<Lease, DhcpError> {
+        let server = ack
+            .address(option::SERVER_IDENTIFIER)
+            .ok_or_else(|| DhcpError("no server identifier".to_string()))?;
+        let lease_time = ack
+            .duration(option::LEASE_TIME)
+            .ok_or_else(|| DhcpError("no lease time".to_string()))?;
+
+        // Guess the mask from the class if the server does not tell it
+        let netmask =
+            ack.address(option::SUBNET_MASK)
+                .unwrap_or_else(|| match ack.yiaddr.octets()[0] {
+                    0..=127 => Ipv4Addr::new(255, 0, 0, 0),
+                    128..=191 => Ipv4Addr::new(255, 255, 0, 0),
+                    _ => Ipv4Addr::new(255, 255, 255, 0),
+                });
+        let mtu = ack.option(option::INTERFACE_MTU).and_then(|data| {
+            let data: [u8; 2] = data.try_into().ok()?;
+            let mtu = u16::from_be_bytes(data) as usize;
+            (mtu >= Lease::MIN_MTU).then_some(mtu)
+        });
+
+        // RFC 2131 4.4.5
+        let renewal_time = ack.duration(option::RENEWAL_TIME).unwrap_or(lease_time / 2);
+        let rebinding_time = ack
+            .duration(option::REBINDING_TIME)
+            .unwrap_or(lease_time * 7 / 8);
+
+        Ok(Lease {
+            address: ack.yiaddr,
+            netmask,
+            router: ack.address(option::ROUTER),
+            dns: ack.addresses(option::DOMAIN_NAME_SERVER),
+            mtu,
+            server,
+            lease_time,
+            renewal_time,
+            rebinding_time,
+        })
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use nix::{errno::Errno, Error};
+
+use crate::{
+    dhcp::{option, DhcpMessage, Lease, MessageType},
+    ethernet::MacAddress,
+    route::Route,
+    router::{Interface, Router},
+    socket::{SocketHandle, SocketType, Sockets},
+};
+
+// RFC 2131 client which configures an interface with the lease
+#[derive(Clone)]
+pub struct DhcpClient {
+    sockets: Sockets,
+    router: Arc<Router>,
+    index: usize,
+    mac: MacAddress,
+    // The MTU of the interface before any lease
+    mtu: usize,
+    lease: Arc<Mutex<Option<Lease>>>,
+}
+
+impl DhcpClient {
+    // RFC 2131 4.1
+    const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+    const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+    const PARAMETERS: [u8; 7] = [
+        option::SUBNET_MASK,
+        option::ROUTER,
+        option::DOMAIN_NAME_SERVER,
+        option::INTERFACE_MTU,
+        option::LEASE_TIME,
+        option::RENEWAL_TIME,
+        option::REBINDING_TIME,
+    ];
+
+    pub fn new(sockets: Sockets, router: Arc<Router>, index: usize, mac: MacAddress) -> DhcpClient {
+        let mtu = router.interface(index).mtu;
+        DhcpClient {
+            sockets,
+            router,
+            index,
+            mac,
+            mtu,
+            lease: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // The current lease if the interface is configured
+    pub fn lease(&self) -> Option<Lease> {
+        self.lease.lock().unwrap().clone()
+    }
+
+    // Acquire and renew leases forever
+    pub fn run(&self) -> Result<(), Error> {
+        let socket = self.sockets.socket(SocketType::Datagram)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DhcpMessage::CLIENT_PORT);
+        self.sockets.bind(socket, addr)?;
+        self.sockets.bind_to_device(socket, Some(self.index))?;
+
+        let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, DhcpMessage::SERVER_PORT);
+        loop {
+            // SELECTING
+            let discover = self._discover();
+            let offer = self._exchange(socket, &discover, broadcast, None, |m| {
+                m.message_type() == Some(MessageType::Offer)
+            })?;
+            let offer = match offer {
+                Some(offer) => offer,
+                None => continue,
+            };
+            let server = match offer.address(option::SERVER_IDENTIFIER) {
+                Some(server) => server,
+                None => continue,
+            };
+
+            // REQUESTING
+            let request = self._select(discover.xid, offer.yiaddr, server);
+            let deadline = Instant::now() + DhcpClient::REQUEST_TIMEOUT;
+            let mut lease = match self._exchange_ack(socket, &request, broadcast, deadline)? {
+                Some(lease) => lease,
+                None => continue,
+            };
+
+            // BOUND
+            loop {
+                let start = Instant::now();
+                self.configure(&lease);
+                self._sleep(socket, start + lease.renewal_time)?;
+
+                // RENEWING
+                let request = self._renew(lease.address, false);
+                let server = SocketAddrV4::new(lease.server, DhcpMessage::SERVER_PORT);
+                let deadline = start + lease.rebinding_time;
+                if let Some(renewed) = self._exchange_ack(socket, &request, server, deadline)? {
+                    lease = renewed;
+                    continue;
+                }
+
+                // REBINDING
+                let request = self._renew(lease.address, true);
+                let deadline = start + lease.lease_time;
+                match self._exchange_ack(socket, &request, broadcast, deadline)? {
+                    Some(renewed) => lease = renewed,
+                    None => break,
+                }
+            }
+
+            // The lease has expired or the server refused it
+            info!("lost {}", lease.address);
+            self.unconfigure();
+        }
+    }
+
+    fn _xid() -> u32 {
+        RandomState::new().build_hasher().finish() as u32
+    }
+
+    fn _message(&self, xid: u32, message_type: MessageType) -> DhcpMessage {
+        let mut message = DhcpMessage::new(DhcpMessage::BOOTREQUEST, xid, self.mac, message_type);
+        message.add_option(option::PARAMETER_REQUEST_LIST, &DhcpClient::PARAMETERS);
+        message
+    }
+
+    fn _discover(&self) -> DhcpMessage {
+        let mut discover = self._message(DhcpClient::_xid(), MessageType::Discover);
+        // The client cannot receive unicast datagrams without an address
+        discover.flags = DhcpMessage::BROADCAST;
+        discover
+    }
+
+    fn _select(&self, xid: u32, address: Ipv4Addr, server: Ipv4Addr) -> DhcpMessage {
+        let mut request = self._message(xid, MessageType::Request);
+        request.flags = DhcpMessage::BROADCAST;
+        request.add_option(option::REQUESTED_IP_ADDRESS, &address.octets());
+        request.add_option(option::SERVER_IDENTIFIER, &server.octets());
+        request
+    }
+
+    fn _renew(&self, address: Ipv4Addr, rebinding: bool) -> DhcpMessage {
+        let mut request = self._message(DhcpClient::_xid(), MessageType::Request);
+        request.ciaddr = address;
+        if rebinding {
+            request.flags = DhcpMessage::BROADCAST;
+        }
+        request
+    }
+
+    // Returns None if the server sends DHCPNAK or does not answer
+    fn _exchange_ack(
+        &self,
+        socket: SocketHandle,
+        request: &DhcpMessage,
+        dst: SocketAddrV4,
+        deadline: Instant,
+    ) -> Result<Option<Lease>, Error> {
+        let reply = self._exchange(socket, request, dst, Some(deadline), |m| {
+            matches!(
+                m.message_type(),
+                Some(MessageType::Ack) | Some(MessageType::Nak)
+            )
+        })?;
+        Ok(reply
+            .filter(|m| m.message_type() == Some(MessageType::Ack))
+            .and_then(|ack| Lease::from_ack(&ack).ok()))
+    }
+
+    // Send the message until a reply arrives with exponential backoff
+    fn _exchange<F>(
+        &self,
+        socket: SocketHandle,
+        message: &DhcpMessage,
+        dst: SocketAddrV4,
+        deadline: Option<Instant>,
+        accept: F,
+    ) -> Result<Option<DhcpMessage>, Error>
+    where
+        F: Fn(&DhcpMessage) -> bool,
+    {
+        let mut timeout = DhcpClient::INITIAL_TIMEOUT;
+        loop {
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Ok(None);
+            }
+            self.sockets.send_to(socket, &message.to_bytes(), dst)?;
+
+            let retransmit = match deadline {
+                Some(deadline) => deadline.min(now + timeout),
+                None => now + timeout,
+            };
+            while let Some(reply) = self._receive(socket, retransmit)? {
+                if reply.op == DhcpMessage::BOOTREPLY
+                    && reply.xid == message.xid
+                    && reply.chaddr == self.mac
+                    && accept(&reply)
+                {
+                    return Ok(Some(reply));
+                }
+            }
+            timeout = (timeout * 2).min(DhcpClient::MAX_TIMEOUT);
+        }
+    }
+
+    // Returns None when the time is up
+    fn _receive(&self, socket: SocketHandle, until: Instant) -> Result<Option<DhcpMessage>, Error> {
+        let mut buf = [0u8; 1500];
+        loop {
+            let timeout = match until.checked_duration_since(Instant::now()) {
+                Some(timeout) if !timeout.is_zero() => timeout,
+                _ => return Ok(None),
+            };
+            self.sockets.set_read_timeout(socket, Some(timeout))?;
+            match self.sockets.recv_from(socket, &mut buf) {
+                Ok((n, _)) => match DhcpMessage::parse(&buf[..n]) {
+                    Ok(message) => return Ok(Some(message)),
+                    Err(e) => debug!("dropped: {}", e),
+                },
+                Err(Errno::EAGAIN) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Discard the messages until the time
+    fn _sleep(&self, socket: SocketHandle, until: Instant) -> Result<(), Error> {
+        while self._receive(socket, until)?.is_some() {}
+        Ok(())
+    }
+
+    // Apply the lease to the interface. Only the connected route and the
+    // default route of the lease change; the other routes are kept.
+    pub(crate) fn configure(&self, lease: &Lease) {
+        info!(
+            "bound to {}/{} from {} for {}s",
+            lease.address,
+            lease.netmask,
+            lease.server,
+            lease.lease_time.as_secs()
+        );
+        let mut current = self.lease.lock().unwrap();
+        let mtu = lease.mtu.map_or(self.mtu, |mtu| mtu.min(self.mtu));
+        let interface = Interface::new(lease.address, lease.netmask, mtu);
+        // Renewals usually keep the address
+        if self.router.interface(self.index) != interface {
+            self.router.set_interface(self.index, interface);
+        }
+        let gateway = current.as_ref().and_then(|lease| lease.router);
+        if gateway != lease.router {
+            if let Some(gateway) = gateway {
+                self.router.remove_route(&self._default_route(gateway));
+            }
+            if let Some(gateway) = lease.router {
+                self.router.add_route(self._default_route(gateway));
+            }
+        }
+        *current = Some(lease.clone());
+    }
+
+    pub(crate) fn unconfigure(&self) {
+        let mut current = self.lease.lock().unwrap();
+        if let Some(gateway) = current.as_ref().and_then(|lease| lease.router) {
+            self.router.remove_route(&self._default_route(gateway));
+        }
+        self.router
+            .set_interface(self.index, Interface::unconfigured(self.mtu));
+        *current = None;
+    }
+
+    fn _default_route(&self, gateway: Ipv4Addr) -> Route {
+        let default = Ipv4Addr::UNSPECIFIED;
+        Route::via(default, default, gateway, self.index)
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use nix::Error;
+
+use crate::{
+    dhcp::{option, DhcpMessage, MessageType},
+    ethernet::MacAddress,
+    router::Router,
+    socket::{SocketType, Sockets},
+};
+
+pub struct DhcpServerConfig {
+    // The interface to serve
+    pub interface: usize,
+    pub pool_start: Ipv4Addr,
+    pub pool_end: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub router: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    pub lease_time: Duration,
+    // Static addresses for the clients
+    pub reservations: HashMap<MacAddress, Ipv4Addr>,
+    // The file to keep the leases across restarts
+    pub database: Option<PathBuf>,
+}
+
+impl DhcpServerConfig {
+    const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(
+        interface: usize,
+        pool_start: Ipv4Addr,
+        pool_end: Ipv4Addr,
+        netmask: Ipv4Addr,
+    ) -> DhcpServerConfig {
+        DhcpServerConfig {
+            interface,
+            pool_start,
+            pool_end,
+            netmask,
+            router: None,
+            dns: vec![],
+            lease_time: DhcpServerConfig::DEFAULT_LEASE_TIME,
+            reservations: HashMap::new(),
+            database: None,
+        }
+    }
+}
+
+// An address bound to a client
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Binding {
+    pub mac: MacAddress,
+    pub address: Ipv4Addr,
+    pub expires: SystemTime,
+}
+
+impl Binding {
+    // A line of the database: the expiry in seconds since the epoch, the
+    // hardware address and the IP address
+    fn _parse(line: &str) -> Option<Binding> {
+        let mut fields = line.split_whitespace();
+        let expires = fields.next()?.parse::<u64>().ok()?;
+        let mac = fields.next()?.parse().ok()?;
+        let address = fields.next()?.parse().ok()?;
+        Some(Binding {
+            mac,
+            address,
+            expires: UNIX_EPOCH + Duration::from_secs(expires),
+        })
+    }
+
+    fn _format(&self) -> String {
+        let expires = self
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("{} {} {}\n", expires, self.mac, self.address)
+    }
+}
+
+struct Bindings {
+    bindings: Vec<Binding>,
+    // Offers expire quickly unless the clients request them
+    offers: Vec<Binding>,
+}
+
+// RFC 2131 server
+pub struct DhcpServer {
+    sockets: Sockets,
+    router: Arc<Router>,
+    config: DhcpServerConfig,
+    bindings: Mutex<Bindings>,
+}
+
+impl DhcpServer {
+    const OFFER_TIME: Duration = Duration::from_secs(60);
+
+    // Load the leases from the database if any
+    pub fn new(
+        sockets: Sockets,
+        router: Arc<Router>,
+        config: DhcpServerConfig,
+    ) -> io::Result<DhcpServer> {
+        let bindings = match &config.database {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(s) => s.lines().filter_map(Binding::_parse).collect(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e),
+            },
+            None => vec![],
+        };
+        Ok(DhcpServer {
+            sockets,
+            router,
+            config,
+            bindings: Mutex::new(Bindings {
+                bindings,
+                offers: vec![],
+            }),
+        })
+    }
+
+    // The leases which have not expired
+    pub fn bindings(&self) -> Vec<Binding> {
+        let now = SystemTime::now();
+        let bindings = self.bindings.lock().unwrap();
+        bindings
+            .bindings
+            .iter()
+            .filter(|b| b.expires > now && b.mac != MacAddress::UNSPECIFIED)
+            .cloned()
+            .collect()
+    }
+
+    pub fn run(&self) -> Result<(), Error> {
+        let socket = self.sockets.socket(SocketType::Datagram)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DhcpMessage::SERVER_PORT);
+        self.sockets.bind(socket, addr)?;
+        self.sockets
+            .bind_to_device(socket, Some(self.config.interface))?;
+
+        let mut buf = [0u8; 1500];
+        loop {
+            let (n, _) = self.sockets.recv_from(socket, &mut buf)?;
+            let message = match DhcpMessage::parse(&buf[..n]) {
+                Ok(message) => message,
+                Err(e) => {
+                    debug!("dropped: {}", e);
+                    continue;
+                }
+            };
+            if let Some((reply, dst)) = self.handle(&message, SystemTime::now()) {
+                self.sockets.send_to(socket, &reply.to_bytes(), dst)?;
+            }
+        }
+    }
+
+    // Returns the reply with the destination
+    pub fn handle(
+        &self,
+        message: &DhcpMessage,
+        now: SystemTime,
+    ) -> Option<(DhcpMessage, SocketAddrV4)> {
+        if message.op != DhcpMessage::BOOTREQUEST {
+            return None;
+        }
+        let server = self.router.interface(self.config.interface).address;
+        if server.is_unspecified() {
+            return None;
+        }
+
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.offers.retain(|b| b.expires > now);
+        let reply = match message.message_type()? {
+            MessageType::Discover => {
+                let address = self._allocate(&bindings, message, now)?;
+                bindings.offers.retain(|b| b.mac != message.chaddr);
+                bindings.offers.push(Binding {
+                    mac: message.chaddr,
+                    address,
+                    expires: now + DhcpServer::OFFER_TIME,
+                });
+                self._reply(message, MessageType::Offer, address, server)
+            }
+            MessageType::Request => {
+                // The client has chosen another server
+                if let Some(id) = message.address(option::SERVER_IDENTIFIER) {
+                    if id != server {
+                        bindings.offers.retain(|b| b.mac != message.chaddr);
+                        return None;
+                    }
+                }
+
+                let requested = match message.ciaddr {
+                    // SELECTING or INIT-REBOOT
+                    ciaddr if ciaddr.is_unspecified() => {
+                        message.address(option::REQUESTED_IP_ADDRESS)?
+                    }
+                    // RENEWING or REBINDING
+                    ciaddr => ciaddr,
+                };
+                if !self._is_available(&bindings, message.chaddr, requested, now) {
+                    info!("{} is not available for {}", requested, message.chaddr);
+                    self._reply(message, MessageType::Nak, Ipv4Addr::UNSPECIFIED, server)
+                } else {
+                    self._bind(&mut bindings, message.chaddr, requested, now);
+                    info!("leased {} to {}", requested, message.chaddr);
+                    self._reply(message, MessageType::Ack, requested, server)
+                }
+            }
+            MessageType::Decline => {
+                // Somebody else uses the address
+                let address = message.address(option::REQUESTED_IP_ADDRESS)?;
+                info!("{} declined {}", message.chaddr, address);
+                self._bind(&mut bindings, MacAddress::UNSPECIFIED, address, now);
+                return None;
+            }
+            MessageType::Release => {
+                let len = bindings.bindings.len();
+                bindings
+                    .bindings
+                    .retain(|b| b.mac != message.chaddr || b.address != message.ciaddr);
+                if bindings.bindings.len() != len {
+                    info!("{} released {}", message.chaddr, message.ciaddr);
+                    self._save(&bindings);
+                }
+                return None;
+            }
+            MessageType::Inform => {
+                self._reply(message, MessageType::Ack, Ipv4Addr::UNSPECIFIED, server)
+            }
+            _ => return None,
+        };
+
+        let dst = DhcpServer::_destination(message, &reply);
+        Some((reply, dst))
+    }
+
+    fn _in_pool(&self, address: Ipv4Addr) -> bool {
+        let address = u32::from(address);
+        u32::from(self.config.pool_start) <= address && address <= u32::from(self.config.pool_end)
+    }
+
+    fn _is_available(
+        &self,
+        bindings: &Bindings,
+        mac: MacAddress,
+        address: Ipv4Addr,
+        now: SystemTime,
+    ) -> bool {
+        if let Some(reserved) = self.config.reservations.get(&mac) {
+            return *reserved == address;
+        }
+        let reserved = self.config.reservations.values().any(|a| *a == address);
+        let server = self.router.interface(self.config.interface).address;
+        let in_use = bindings
+            .bindings
+            .iter()
+            .chain(bindings.offers.iter())
+            .any(|b| b.address == address && b.mac != mac && b.expires > now);
+        self._in_pool(address) && !reserved && address != server && !in_use
+    }
+
+    // Prefer the reserved address, the previous address and the requested
+    // address in this order
+    fn _allocate(
+        &self,
+        bindings: &Bindings,
+        message: &DhcpMessage,
+        now: SystemTime,
+    ) -> Option<Ipv4Addr> {
+        let mac = message.chaddr;
+        if let Some(reserved) = self.config.reservations.get(&mac) {
+            return Some(*reserved);
+        }
+
+        let previous = bindings
+            .bindings
+            .iter()
+            .rev()
+            .find(|b| b.mac == mac)
+            .map(|b| b.address);
+        let requested = message.address(option::REQUESTED_IP_ADDRESS);
+        let pool = u32::from(self.config.pool_start)..=u32::from(self.config.pool_end);
+        previous
+            .into_iter()
+            .chain(requested)
+            .chain(pool.map(Ipv4Addr::from))
+            .find(|a| self._is_available(bindings, mac, *a, now))
+    }
+
+    fn _bind(&self, bindings: &mut Bindings, mac: MacAddress, address: Ipv4Addr, now: SystemTime) {
+        bindings
+            .offers
+            .retain(|b| b.mac != mac || mac == MacAddress::UNSPECIFIED);
+        bindings.bindings.retain(|b| {
+            b.expires > now
+                && b.address != address
+                && (b.mac != mac || mac == MacAddress::UNSPECIFIED)
+        });
+        bindings.bindings.push(Binding {
+            mac,
+            address,
+            expires: now + self.config.lease_time,
+        });
+        self._save(bindings);
+    }
+
+    fn _save(&self, bindings: &Bindings) {
+        let path = match &self.config.database {
+            Some(path) => path,
+            None => return,
+        };
+        let s: String = bindings.bindings.iter().map(Binding::_format).collect();
+        // Replace the file at once so that a crash does not leave half of it
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, s).and_then(|_| fs::rename(&tmp, path)) {
+            warn!("failed to save the leases: {}", e);
+        }
+    }
+
+    fn _reply(
+        &self,
+        request: &DhcpMessage,
+        message_type: MessageType,
+        address: Ipv4Addr,
+        server: Ipv4Addr,
+    ) -> DhcpMessage {
+        let mut reply = DhcpMessage::new(
+            DhcpMessage::BOOTREPLY,
+            request.xid,
+            request.chaddr,
+            message_type,
+        );
+        reply.flags = request.flags;
+        reply.giaddr = request.giaddr;
+        reply.add_option(option::SERVER_IDENTIFIER, &server.octets());
+        if message_type == MessageType::Nak {
+            return reply;
+        }
+
+        reply.yiaddr = address;
+        // DHCPINFORM does not assign an address
+        if message_type == MessageType::Ack && address.is_unspecified() {
+            reply.ciaddr = request.ciaddr;
+        } else {
+            let lease_time = self.config.lease_time.as_secs() as u32;
+            reply.add_option(option::LEASE_TIME, &lease_time.to_be_bytes());
+        }
+        reply.add_option(option::SUBNET_MASK, &self.config.netmask.octets());
+        if let Some(router) = self.config.router {
+            reply.add_option(option::ROUTER, &router.octets());
+        }
+        if !self.config.dns.is_empty() {
+            let dns: Vec<u8> = self.config.dns.iter().flat_map(|a| a.octets()).collect();
+            reply.add_option(option::DOMAIN_NAME_SERVER, &dns);
+        }
+        reply
+    }
+
+    // RFC 2131 4.1
+    fn _destination(request: &DhcpMessage, reply: &DhcpMessage) -> SocketAddrV4 {
+        if !request.giaddr.is_unspecified() {
+            return SocketAddrV4::new(request.giaddr, DhcpMessage::SERVER_PORT);
+        }
+        // Clients without an address cannot receive unicast datagrams as the
+        // stack does not fill the ARP cache from DHCP
+        let dst = match reply.message_type() {
+            Some(MessageType::Nak) => Ipv4Addr::BROADCAST,
+            _ if !request.ciaddr.is_unspecified() => request.ciaddr,
+            _ => Ipv4Addr::BROADCAST,
+        };
+        SocketAddrV4::new(dst, DhcpMessage::CLIENT_PORT)
+    }
+}
@@ -0,0 +1,199 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{Ipv4Addr, SocketAddrV4},
+        process,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        dhcp::{option, DhcpMessage, MessageType},
+        dhcpserver::{DhcpServer, DhcpServerConfig},
+        ethernet::MacAddress,
+        eventloop::EventLoop,
+        ipv4::IPv4,
+        route::RoutingTable,
+        router::{Interface, Router},
+        socket::Sockets,
+    };
+
+    fn netmask() -> Ipv4Addr {
+        Ipv4Addr::new(255, 255, 255, 0)
+    }
+
+    fn mac(n: u8) -> MacAddress {
+        MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, n])
+    }
+
+    fn config() -> DhcpServerConfig {
+        let mut config = DhcpServerConfig::new(
+            0,
+            Ipv4Addr::new(10, 0, 0, 100),
+            Ipv4Addr::new(10, 0, 0, 101),
+            netmask(),
+        );
+        config.router = Some(Ipv4Addr::new(10, 0, 0, 1));
+        config.dns = vec![Ipv4Addr::new(10, 0, 0, 53)];
+        config
+    }
+
+    fn server(config: DhcpServerConfig) -> DhcpServer {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let interfaces = vec![Interface::new(Ipv4Addr::new(10, 0, 0, 1), netmask(), 1500)];
+        let router = Router::new(IPv4::new(vec![]), interfaces, RoutingTable::new());
+        DhcpServer::new(sockets, Arc::new(router), config).unwrap()
+    }
+
+    fn discover(mac: MacAddress) -> DhcpMessage {
+        let mut discover =
+            DhcpMessage::new(DhcpMessage::BOOTREQUEST, 1, mac, MessageType::Discover);
+        discover.flags = DhcpMessage::BROADCAST;
+        discover
+    }
+
+    fn request(mac: MacAddress, address: Ipv4Addr) -> DhcpMessage {
+        let mut request = DhcpMessage::new(DhcpMessage::BOOTREQUEST, 1, mac, MessageType::Request);
+        request.add_option(option::REQUESTED_IP_ADDRESS, &address.octets());
+        request.add_option(option::SERVER_IDENTIFIER, &[10, 0, 0, 1]);
+        request
+    }
+
+    fn broadcast() -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::BROADCAST, DhcpMessage::CLIENT_PORT)
+    }
+
+    #[test]
+    fn lease() {
+        let server = server(config());
+        let now = SystemTime::now();
+
+        let (offer, dst) = server.handle(&discover(mac(1)), now).unwrap();
+        assert_eq!(dst, broadcast());
+        assert_eq!(offer.message_type(), Some(MessageType::Offer));
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(10, 0, 0, 100));
+        assert_eq!(
+            offer.address(option::SERVER_IDENTIFIER),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            offer.address(option::ROUTER),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            offer.address(option::DOMAIN_NAME_SERVER),
+            Some(Ipv4Addr::new(10, 0, 0, 53))
+        );
+        assert_eq!(
+            offer.duration(option::LEASE_TIME),
+            Some(Duration::from_secs(3600))
+        );
+
+        // The offered address is not offered to another client
+        let (offer, _) = server.handle(&discover(mac(2)), now).unwrap();
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(10, 0, 0, 101));
+
+        let (ack, _) = server
+            .handle(&request(mac(1), Ipv4Addr::new(10, 0, 0, 100)), now)
+            .unwrap();
+        assert_eq!(ack.message_type(), Some(MessageType::Ack));
+        assert_eq!(ack.yiaddr, Ipv4Addr::new(10, 0, 0, 100));
+        assert_eq!(server.bindings().len(), 1);
+
+        // The pool is exhausted
+        assert!(server.handle(&discover(mac(3)), now).is_none());
+    }
+
+    #[test]
+    fn nak() {
+        let server = server(config());
+        let now = SystemTime::now();
+
+        let address = Ipv4Addr::new(10, 0, 0, 100);
+        server.handle(&request(mac(1), address), now).unwrap();
+
+        let (nak, dst) = server.handle(&request(mac(2), address), now).unwrap();
+        assert_eq!(nak.message_type(), Some(MessageType::Nak));
+        assert_eq!(dst, broadcast());
+
+        // Outside the pool
+        let address = Ipv4Addr::new(10, 0, 0, 50);
+        let (nak, _) = server.handle(&request(mac(2), address), now).unwrap();
+        assert_eq!(nak.message_type(), Some(MessageType::Nak));
+    }
+
+    #[test]
+    fn other_server() {
+        let server = server(config());
+        let mut request = request(mac(1), Ipv4Addr::new(10, 0, 0, 100));
+        request
+            .options
+            .retain(|(code, _)| *code != option::SERVER_IDENTIFIER);
+        request.add_option(option::SERVER_IDENTIFIER, &[10, 0, 0, 2]);
+        assert!(server.handle(&request, SystemTime::now()).is_none());
+    }
+
+    #[test]
+    fn reservation() {
+        let mut config = config();
+        let reserved = Ipv4Addr::new(10, 0, 0, 150);
+        config.reservations.insert(mac(1), reserved);
+        config
+            .reservations
+            .insert(mac(9), Ipv4Addr::new(10, 0, 0, 100));
+        let server = server(config);
+        let now = SystemTime::now();
+
+        let (offer, _) = server.handle(&discover(mac(1)), now).unwrap();
+        assert_eq!(offer.yiaddr, reserved);
+
+        // The reserved addresses are not given to the others
+        let (offer, _) = server.handle(&discover(mac(2)), now).unwrap();
+        assert_eq!(offer.yiaddr, Ipv4Addr::new(10, 0, 0, 101));
+    }
+
+    #[test]
+    fn renew() {
+        let server = server(config());
+        let now = SystemTime::now();
+        let address = Ipv4Addr::new(10, 0, 0, 100);
+        server.handle(&request(mac(1), address), now).unwrap();
+
+        let mut renew = DhcpMessage::new(DhcpMessage::BOOTREQUEST, 2, mac(1), MessageType::Request);
+        renew.ciaddr = address;
+        let (ack, dst) = server.handle(&renew, now).unwrap();
+        assert_eq!(ack.message_type(), Some(MessageType::Ack));
+        assert_eq!(dst, SocketAddrV4::new(address, DhcpMessage::CLIENT_PORT));
+    }
+
+    #[test]
+    fn database() {
+        let path = std::env::temp_dir().join(format!("pareiodon-{}.leases", process::id()));
+        let mut config = config();
+        config.database = Some(path.clone());
+        let server1 = server(config);
+
+        let address = Ipv4Addr::new(10, 0, 0, 101);
+        server1
+            .handle(&request(mac(1), address), SystemTime::now())
+            .unwrap();
+
+        let mut config = self::config();
+        config.database = Some(path.clone());
+        let server2 = server(config);
+        let bindings = server2.bindings();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].mac, mac(1));
+        assert_eq!(bindings[0].address, address);
+
+        // The client gets the same address again
+        let (offer, _) = server2
+            .handle(&discover(mac(1)), SystemTime::now())
+            .unwrap();
+        assert_eq!(offer.yiaddr, address);
+    }
+}
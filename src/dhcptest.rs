@@ -0,0 +1,146 @@
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+    use crate::{
+        dhcp::{option, DhcpError, DhcpMessage, Lease, MessageType},
+        dhcpclient::DhcpClient,
+        ethernet::MacAddress,
+        eventloop::EventLoop,
+        ipv4::IPv4,
+        route::{Route, RoutingTable},
+        router::{Interface, Router},
+        socket::Sockets,
+    };
+
+    fn mac() -> MacAddress {
+        MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01])
+    }
+
+    fn ack() -> DhcpMessage {
+        let mut ack = DhcpMessage::new(DhcpMessage::BOOTREPLY, 0x12345678, mac(), MessageType::Ack);
+        ack.yiaddr = Ipv4Addr::new(203, 0, 113, 10);
+        ack.add_option(option::SERVER_IDENTIFIER, &[203, 0, 113, 1]);
+        ack.add_option(option::LEASE_TIME, &3600u32.to_be_bytes());
+        ack
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut message = ack();
+        message.flags = DhcpMessage::BROADCAST;
+        message.add_option(
+            option::DOMAIN_NAME_SERVER,
+            &[203, 0, 113, 1, 203, 0, 113, 2],
+        );
+
+        let buf = message.to_bytes();
+        assert_eq!(buf[0], DhcpMessage::BOOTREPLY);
+        // Magic Cookie
+        assert_eq!(&buf[236..240], &[99, 130, 83, 99]);
+        assert_eq!(buf.last(), Some(&option::END));
+
+        let parsed = DhcpMessage::parse(&buf).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.message_type(), Some(MessageType::Ack));
+        assert_eq!(
+            parsed.addresses(option::DOMAIN_NAME_SERVER),
+            vec![Ipv4Addr::new(203, 0, 113, 1), Ipv4Addr::new(203, 0, 113, 2)]
+        );
+    }
+
+    #[test]
+    fn magic_cookie_error() {
+        let mut buf = ack().to_bytes();
+        buf[236] = 0;
+        assert_eq!(
+            DhcpMessage::parse(&buf),
+            Err(DhcpError("magic cookie error".to_string()))
+        );
+    }
+
+    #[test]
+    fn option_length_error() {
+        let mut buf = ack().to_bytes();
+        // Remove the end option and truncate the last option
+        buf.truncate(buf.len() - 3);
+        assert!(DhcpMessage::parse(&buf).is_err());
+    }
+
+    #[test]
+    fn lease() {
+        let mut ack = ack();
+        ack.add_option(option::SUBNET_MASK, &[255, 255, 255, 0]);
+        ack.add_option(option::ROUTER, &[203, 0, 113, 1]);
+        ack.add_option(option::INTERFACE_MTU, &1400u16.to_be_bytes());
+
+        let lease = Lease::from_ack(&ack).unwrap();
+        assert_eq!(lease.address, Ipv4Addr::new(203, 0, 113, 10));
+        assert_eq!(lease.netmask, Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(lease.router, Some(Ipv4Addr::new(203, 0, 113, 1)));
+        assert_eq!(lease.mtu, Some(1400));
+        assert_eq!(lease.server, Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(lease.lease_time, Duration::from_secs(3600));
+        // T1 and T2 default to 0.5 and 0.875 times the lease time
+        assert_eq!(lease.renewal_time, Duration::from_secs(1800));
+        assert_eq!(lease.rebinding_time, Duration::from_secs(3150));
+    }
+
+    #[test]
+    fn lease_without_server() {
+        let mut ack = ack();
+        ack.options
+            .retain(|(code, _)| *code != option::SERVER_IDENTIFIER);
+        assert_eq!(
+            Lease::from_ack(&ack),
+            Err(DhcpError("no server identifier".to_string()))
+        );
+    }
+
+    #[test]
+    fn renew() {
+        let mut ack = ack();
+        ack.add_option(option::SUBNET_MASK, &[255, 255, 255, 0]);
+        ack.add_option(option::ROUTER, &[203, 0, 113, 1]);
+        let lease = Lease::from_ack(&ack).unwrap();
+
+        // A static route on the interface which DHCP configures
+        let network = Ipv4Addr::new(198, 51, 100, 0);
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let static_route = Route::via(network, netmask, Ipv4Addr::new(203, 0, 113, 254), 0);
+        let mut routes = RoutingTable::new();
+        routes.add(static_route.clone());
+        let router = Arc::new(Router::new(
+            IPv4::new(vec![]),
+            vec![Interface::unconfigured(1500)],
+            routes,
+        ));
+        let event_loop = EventLoop::new().unwrap();
+        let client = DhcpClient::new(Sockets::new(event_loop.waker()), router.clone(), 0, mac());
+
+        let any = Ipv4Addr::UNSPECIFIED;
+        let default = Route::via(any, any, Ipv4Addr::new(203, 0, 113, 1), 0);
+        let connected = Route::new(lease.address, netmask, 0);
+        client.configure(&lease);
+        let bound = vec![static_route.clone(), connected.clone(), default.clone()];
+        assert_eq!(router.routes(), bound);
+
+        // A renewal with the same lease changes nothing
+        client.configure(&lease);
+        assert_eq!(router.routes(), bound);
+
+        // Another router replaces the default route only
+        let mut renewed = lease.clone();
+        renewed.router = Some(Ipv4Addr::new(203, 0, 113, 2));
+        client.configure(&renewed);
+        let default = Route::via(any, any, Ipv4Addr::new(203, 0, 113, 2), 0);
+        assert_eq!(
+            router.routes(),
+            vec![static_route.clone(), connected, default]
+        );
+
+        client.unconfigure();
+        assert_eq!(router.routes(), vec![static_route]);
+        assert_eq!(router.interface(0), Interface::unconfigured(1500));
+    }
+}
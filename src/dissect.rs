@@ -0,0 +1,369 @@
+use std::{fmt::Write, net::Ipv4Addr};
+
+use crate::{
+    dhcp::DhcpMessage,
+    dns::{self, DnsMessage},
+    icmp::{Icmp, IcmpType},
+    ipv4::IPv4,
+    protocol::{get_checksum, ProtocolError},
+    tcp::Tcp,
+    udp::Udp,
+};
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    // One line per datagram
+    Summary,
+    // One line per field
+    Tree,
+}
+
+// A decoded protocol header
+struct Layer {
+    name: &'static str,
+    summary: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Layer {
+    fn new(name: &'static str, summary: String) -> Layer {
+        Layer {
+            name,
+            summary,
+            fields: vec![],
+        }
+    }
+
+    fn field(&mut self, name: &'static str, value: impl ToString) {
+        self.fields.push((name, value.to_string()));
+    }
+}
+
+// Human-readable descriptions of datagrams for debugging
+pub struct Dissector {
+    // Used for verifying the headers only
+    ipv4: IPv4,
+}
+
+impl Dissector {
+    pub fn new() -> Dissector {
+        Dissector {
+            ipv4: IPv4::new(vec![]),
+        }
+    }
+
+    pub fn format(&self, buf: &[u8], verbosity: Verbosity) -> Option<String> {
+        match verbosity {
+            Verbosity::Quiet => None,
+            Verbosity::Summary => Some(self.summary(buf)),
+            Verbosity::Tree => Some(self.tree(buf)),
+        }
+    }
+
+    // e.g. "IPv4 192.0.2.1 > 192.0.2.2 ttl=64 id=0x6d6f ICMP echo id=45 seq=2 len=56"
+    pub fn summary(&self, buf: &[u8]) -> String {
+        let layers = self._layers(buf);
+        let summaries: Vec<String> = layers
+            .iter()
+            .map(|l| format!("{} {}", l.name, l.summary))
+            .collect();
+        summaries.join(" ")
+    }
+
+    pub fn tree(&self, buf: &[u8]) -> String {
+        let mut s = String::new();
+        for layer in self._layers(buf) {
+            let _ = writeln!(s, "{} {}", layer.name, layer.summary);
+            for (name, value) in &layer.fields {
+                let _ = writeln!(s, "    {}: {}", name, value);
+            }
+        }
+        s
+    }
+
+    fn _layers(&self, buf: &[u8]) -> Vec<Layer> {
+        let ihl = match self.ipv4.verify(buf) {
+            Ok(ihl) => ihl,
+            Err(ProtocolError::IPv4(e)) => {
+                return vec![Layer::new("IPv4", format!("invalid: {}", e.0))]
+            }
+            Err(_) => return vec![Layer::new("IPv4", "invalid".to_string())],
+        };
+        let total_length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        let id = u16::from_be_bytes([buf[4], buf[5]]);
+        let flags_offset = u16::from_be_bytes([buf[6], buf[7]]);
+        let offset = (flags_offset & 0x1fff) as usize * 8;
+        let protocol = buf[9];
+
+        let mut summary = format!("{} > {} ttl={} id={:#06x}", src, dst, buf[8], id);
+        if flags_offset & 0x4000 != 0 {
+            summary.push_str(" DF");
+        }
+        if flags_offset & 0x2000 != 0 || offset != 0 {
+            let _ = write!(summary, " frag offset={}", offset);
+            if flags_offset & 0x2000 != 0 {
+                summary.push('+');
+            }
+        }
+        let mut ipv4 = Layer::new("IPv4", summary);
+        ipv4.field("header length", ihl);
+        ipv4.field("tos", format!("{:#04x}", buf[1]));
+        ipv4.field("total length", total_length);
+        ipv4.field("id", format!("{:#06x}", id));
+        ipv4.field("flags", format!("{:#03x}", flags_offset >> 13));
+        ipv4.field("fragment offset", offset);
+        ipv4.field("ttl", buf[8]);
+        ipv4.field("protocol", protocol);
+        ipv4.field(
+            "checksum",
+            format!("{:#06x}", u16::from_be_bytes([buf[10], buf[11]])),
+        );
+        ipv4.field("src", src);
+        ipv4.field("dst", dst);
+
+        // Only the first fragment has the header of the upper layer
+        let data = &buf[ihl..total_length];
+        let upper = match protocol {
+            _ if offset != 0 => None,
+            Icmp::NUMBER => Some(Dissector::_icmp(data)),
+            Udp::NUMBER => Some(Dissector::_udp(data, src, dst)),
+            Tcp::NUMBER => Some(Dissector::_tcp(data, src, dst)),
+            _ => None,
+        };
+        let mut layers = vec![ipv4];
+        match upper {
+            Some(upper) => layers.extend(upper),
+            None => layers.push(Layer::new(
+                "proto",
+                format!("{} len={}", protocol, data.len()),
+            )),
+        }
+        layers
+    }
+
+    fn _truncated(name: &'static str) -> Vec<Layer> {
+        vec![Layer::new(name, "truncated".to_string())]
+    }
+
+    fn _icmp(buf: &[u8]) -> Vec<Layer> {
+        if buf.len() < 8 {
+            return Dissector::_truncated("ICMP");
+        }
+        let (icmp_type, code) = (buf[0], buf[1]);
+        let id = u16::from_be_bytes([buf[4], buf[5]]);
+        let seq = u16::from_be_bytes([buf[6], buf[7]]);
+        let len = buf.len() - 8;
+        let summary = match icmp_type {
+            t if t == IcmpType::Echo as u8 => format!("echo id={} seq={} len={}", id, seq, len),
+            t if t == IcmpType::EchoReply as u8 => {
+                format!("echo reply id={} seq={} len={}", id, seq, len)
+            }
+            t if t == IcmpType::DestinationUnreachable as u8 => {
+                format!("unreachable code={}", code)
+            }
+            t if t == IcmpType::TimeExceeded as u8 => format!("time exceeded code={}", code),
+            t => format!("type={} code={}", t, code),
+        };
+
+        let mut icmp = Layer::new("ICMP", summary);
+        icmp.field("type", icmp_type);
+        icmp.field("code", code);
+        icmp.field("checksum", Dissector::_checksum(buf, 2, &[]));
+        if Icmp::is_error(icmp_type) {
+            // The header of the original datagram
+            icmp.field("original", format!("{} bytes", len));
+        } else {
+            icmp.field("id", id);
+            icmp.field("seq", seq);
+            icmp.field("data", format!("{} bytes", len));
+        }
+        vec![icmp]
+    }
+
+    fn _udp(buf: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Vec<Layer> {
+        if buf.len() < 8 {
+            return Dissector::_truncated("UDP");
+        }
+        let src_port = u16::from_be_bytes([buf[0], buf[1]]);
+        let dst_port = u16::from_be_bytes([buf[2], buf[3]]);
+        let data = &buf[8..];
+
+        let mut udp = Layer::new(
+            "UDP",
+            format!("{} > {} len={}", src_port, dst_port, data.len()),
+        );
+        udp.field("src port", src_port);
+        udp.field("dst port", dst_port);
+        udp.field("length", u16::from_be_bytes([buf[4], buf[5]]));
+        let checksum = match [buf[6], buf[7]] {
+            // The sender has not computed the checksum
+            [0, 0] => "none".to_string(),
+            _ => {
+                let pseudo_header = IPv4::pseudo_header(src, dst, Udp::NUMBER, buf.len());
+                Dissector::_checksum(buf, 6, &pseudo_header)
+            }
+        };
+        udp.field("checksum", checksum);
+
+        let mut layers = vec![udp];
+        let ports = [src_port, dst_port];
+        if ports.contains(&DnsMessage::PORT) {
+            layers.extend(Dissector::_dns(data));
+        } else if ports.contains(&DhcpMessage::SERVER_PORT) {
+            layers.extend(Dissector::_dhcp(data));
+        }
+        layers
+    }
+
+    fn _tcp(buf: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Vec<Layer> {
+        if buf.len() < 20 {
+            return Dissector::_truncated("TCP");
+        }
+        let src_port = u16::from_be_bytes([buf[0], buf[1]]);
+        let dst_port = u16::from_be_bytes([buf[2], buf[3]]);
+        let seq = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let ack = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        let data_offset = 4 * (buf[12] >> 4) as usize;
+        let flags = buf[13];
+        let window = u16::from_be_bytes([buf[14], buf[15]]);
+        let len = buf.len().saturating_sub(data_offset);
+
+        // The same notation as tcpdump
+        let names: String = [
+            (0x02, 'S'),
+            (0x01, 'F'),
+            (0x04, 'R'),
+            (0x08, 'P'),
+            (0x10, '.'),
+        ]
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, c)| c)
+        .collect();
+        let mut tcp = Layer::new(
+            "TCP",
+            format!(
+                "{} > {} [{}] seq={} ack={} win={} len={}",
+                src_port, dst_port, names, seq, ack, window, len
+            ),
+        );
+        tcp.field("src port", src_port);
+        tcp.field("dst port", dst_port);
+        tcp.field("seq", seq);
+        tcp.field("ack", ack);
+        tcp.field("header length", data_offset);
+        tcp.field("flags", format!("{:#04x}", flags));
+        tcp.field("window", window);
+        let pseudo_header = IPv4::pseudo_header(src, dst, Tcp::NUMBER, buf.len());
+        tcp.field("checksum", Dissector::_checksum(buf, 16, &pseudo_header));
+        tcp.field("data", format!("{} bytes", len));
+        vec![tcp]
+    }
+
+    fn _dns(buf: &[u8]) -> Vec<Layer> {
+        let message = match DnsMessage::parse(buf) {
+            Ok(message) => message,
+            Err(e) => return vec![Layer::new("DNS", format!("invalid: {}", e.0))],
+        };
+        let questions: Vec<String> = message
+            .questions
+            .iter()
+            .map(|q| format!("{} {}", Dissector::_rtype(q.rtype), q.name))
+            .collect();
+        let summary = match message.is_response() {
+            true => format!(
+                "response id={:#06x} rcode={} {} answers={}",
+                message.id,
+                message.rcode(),
+                questions.join(", "),
+                message.answers.len()
+            ),
+            false => format!("query id={:#06x} {}", message.id, questions.join(", ")),
+        };
+
+        let mut layer = Layer::new("DNS", summary);
+        layer.field("id", format!("{:#06x}", message.id));
+        layer.field("flags", format!("{:#06x}", message.flags));
+        for question in questions {
+            layer.field("question", question);
+        }
+        for record in &message.answers {
+            layer.field(
+                "answer",
+                format!(
+                    "{} {} {} {:?}",
+                    record.name,
+                    record.ttl,
+                    Dissector::_rtype(record.rtype()),
+                    record.data
+                ),
+            );
+        }
+        vec![layer]
+    }
+
+    fn _rtype(rtype: u16) -> String {
+        match rtype {
+            dns::rtype::A => "A".to_string(),
+            dns::rtype::NS => "NS".to_string(),
+            dns::rtype::CNAME => "CNAME".to_string(),
+            dns::rtype::SOA => "SOA".to_string(),
+            dns::rtype::PTR => "PTR".to_string(),
+            dns::rtype::MX => "MX".to_string(),
+            dns::rtype::TXT => "TXT".to_string(),
+            dns::rtype::AAAA => "AAAA".to_string(),
+            dns::rtype::ANY => "ANY".to_string(),
+            t => format!("TYPE{}", t),
+        }
+    }
+
+    fn _dhcp(buf: &[u8]) -> Vec<Layer> {
+        let message = match DhcpMessage::parse(buf) {
+            Ok(message) => message,
+            Err(e) => return vec![Layer::new("DHCP", format!("invalid: {}", e.0))],
+        };
+        let message_type = match message.message_type() {
+            Some(t) => format!("{:?}", t).to_uppercase(),
+            None => "BOOTP".to_string(),
+        };
+        let mut layer = Layer::new(
+            "DHCP",
+            format!(
+                "{} xid={:#010x} chaddr={}",
+                message_type, message.xid, message.chaddr
+            ),
+        );
+        layer.field("op", message.op);
+        layer.field("xid", format!("{:#010x}", message.xid));
+        layer.field("flags", format!("{:#06x}", message.flags));
+        layer.field("ciaddr", message.ciaddr);
+        layer.field("yiaddr", message.yiaddr);
+        layer.field("siaddr", message.siaddr);
+        layer.field("giaddr", message.giaddr);
+        layer.field("chaddr", message.chaddr);
+        for (code, data) in &message.options {
+            layer.field("option", format!("{} {:02x?}", code, data));
+        }
+        vec![layer]
+    }
+
+    // The checksum at the offset and whether it is correct
+    fn _checksum(buf: &[u8], offset: usize, pseudo_header: &[u8]) -> String {
+        let checksum = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        let mut data = pseudo_header.to_vec();
+        data.extend_from_slice(buf);
+        match get_checksum(&data) {
+            0 => format!("{:#06x} (correct)", checksum),
+            _ => format!("{:#06x} (incorrect)", checksum),
+        }
+    }
+}
+
+impl Default for Dissector {
+    fn default() -> Self {
+        Dissector::new()
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddrV4;
+
+    use crate::{
+        dissect::{Dissector, Verbosity},
+        dns::{rtype, DnsMessage},
+        ipv4::IPv4,
+        protocol::get_checksum,
+        tcp::Tcp,
+        testutil::{host, stack},
+        udp::Udp,
+    };
+
+    fn echo() -> Vec<u8> {
+        let mut buf = vec![
+            0x45, 0x00, 0x00, 0x54, 0x6d, 0x6f, 0x40, 0x00, 0x40, 0x01, 0x00, 0x00, 0xc0, 0x00,
+            0x02, 0x01, 0xc0, 0x00, 0x02, 0x02, // IPv4
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x02, // ICMP
+        ];
+        buf.resize(84, 0);
+        let checksum = get_checksum(&buf[..20]);
+        buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+        let checksum = get_checksum(&buf[20..]);
+        buf[22..24].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn icmp() {
+        let dissector = Dissector::new();
+        assert_eq!(
+            dissector.summary(&echo()),
+            "IPv4 192.0.2.1 > 192.0.2.2 ttl=64 id=0x6d6f DF ICMP echo id=45 seq=2 len=56"
+        );
+
+        let tree = dissector.tree(&echo());
+        assert!(tree.contains("    ttl: 64\n"));
+        assert!(tree.contains("ICMP echo id=45 seq=2 len=56\n"));
+        assert!(tree.contains("    checksum: 0x"));
+        assert!(tree.contains(" (correct)\n"));
+        assert!(tree.contains("    data: 56 bytes\n"));
+    }
+
+    #[test]
+    fn udp() {
+        let query = DnsMessage::query(0x1234, "example.com", rtype::A).to_bytes();
+        let mut data = vec![0xc0, 0x00, 0x00, 0x35, 0x00, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&query);
+        let buf = IPv4::new(vec![]).datagram(host(), stack(), Udp::NUMBER, &data);
+        assert_eq!(
+            Dissector::new().summary(&buf),
+            "IPv4 192.0.2.1 > 192.0.2.2 ttl=64 id=0x0000 UDP 49152 > 53 len=29 \
+             DNS query id=0x1234 A example.com"
+        );
+    }
+
+    #[test]
+    fn tcp() {
+        let data = Tcp::segment(
+            SocketAddrV4::new(host(), 40000),
+            SocketAddrV4::new(stack(), 7),
+            1,
+            0,
+            0x02,
+            65535,
+            Some(1460),
+            &[],
+        );
+        let buf = IPv4::new(vec![]).datagram(host(), stack(), Tcp::NUMBER, &data);
+        let dissector = Dissector::new();
+        assert_eq!(
+            dissector.summary(&buf),
+            "IPv4 192.0.2.1 > 192.0.2.2 ttl=64 id=0x0000 TCP 40000 > 7 [S] seq=1 ack=0 \
+             win=65535 len=0"
+        );
+        assert!(dissector.tree(&buf).contains(" (correct)\n"));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut buf = echo();
+        buf[10] ^= 0xff;
+        assert!(Dissector::new()
+            .summary(&buf)
+            .starts_with("IPv4 invalid: header checksum error"));
+    }
+
+    #[test]
+    fn quiet() {
+        let dissector = Dissector::new();
+        assert_eq!(dissector.format(&echo(), Verbosity::Quiet), None);
+        assert_eq!(
+            dissector.format(&echo(), Verbosity::Summary),
+            Some(dissector.summary(&echo()))
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct DnsError(pub String);
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dns: {}", self.0)
+    }
+}
+
+// RFC 1035 3.2.2 and RFC 3596
+pub mod rtype {
+    pub const A: u16 = 1;
+    pub const NS: u16 = 2;
+    pub const CNAME: u16 = 5;
+    pub const SOA: u16 = 6;
+    pub const PTR: u16 = 12;
+    pub const MX: u16 = 15;
+    pub const TXT: u16 = 16;
+    pub const AAAA: u16 = 28;
+    // QTYPE for all the records
+    pub const ANY: u16 = 255;
+}
+
+// RFC 1035 4.1.1
+pub mod rcode {
+    pub const NOERROR: u8 = 0;
+    pub const FORMERR: u8 = 1;
+    pub const SERVFAIL: u8 = 2;
+    pub const NXDOMAIN: u8 = 3;
+    pub const NOTIMP: u8 = 4;
+    pub const REFUSED: u8 = 5;
+}
+
+// The Internet class
+pub const CLASS_IN: u16 = 1;
+
+// Names are kept without the trailing dot and compared case-insensitively
+pub fn name_eq(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+// RFC 1035 2.3.4
+pub fn check_name(name: &str) -> Result<(), DnsError> {
+    let name = name.trim_end_matches('.');
+    if name.len() > 253 {
+        return Err(DnsError(format!("name too long: {}", name)));
+    }
+    if !name.is_empty() && name.split('.').any(|l| l.is_empty() || l.len() > 63) {
+        return Err(DnsError(format!("invalid name: {}", name)));
+    }
+    Ok(())
+}
+
+// RFC 1035 3.5
+pub fn reverse_name(address: Ipv4Addr) -> String {
+    let [a, b, c, d] = address.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+}
+
+// RFC 1035 3.3.13
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    // The TTL of negative answers (RFC 2308 4)
+    pub minimum: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(String),
+    Cname(String),
+    Soa(Soa),
+    Ptr(String),
+    Mx { preference: u16, exchange: String },
+    Txt(Vec<String>),
+    // Types which the stack does not interpret
+    Other { rtype: u16, data: Vec<u8> },
+}
+
+impl RData {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            RData::A(_) => rtype::A,
+            RData::Aaaa(_) => rtype::AAAA,
+            RData::Ns(_) => rtype::NS,
+            RData::Cname(_) => rtype::CNAME,
+            RData::Soa(_) => rtype::SOA,
+            RData::Ptr(_) => rtype::PTR,
+            RData::Mx { .. } => rtype::MX,
+            RData::Txt(_) => rtype::TXT,
+            RData::Other { rtype, .. } => *rtype,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+impl Record {
+    pub fn rtype(&self) -> u16 {
+        self.data.rtype()
+    }
+}
+
+// RFC 1035 4.1
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+impl DnsMessage {
+    pub const PORT: u16 = 53;
+    // Flags (Response, Authoritative Answer, TrunCation, Recursion Desired,
+    // Recursion Available)
+    pub const QR: u16 = 0x8000;
+    pub const AA: u16 = 0x0400;
+    pub const TC: u16 = 0x0200;
+    pub const RD: u16 = 0x0100;
+    pub const RA: u16 = 0x0080;
+    // The largest message over UDP without EDNS (RFC 1035 4.2.1)
+    pub const UDP_SIZE: usize = 512;
+    const HEADER_SIZE: usize = 12;
+    // Compression pointers have 14 bits for the offset
+    const MAX_POINTER: usize = 0x3fff;
+
+    pub fn query(id: u16, name: &str, rtype: u16) -> DnsMessage {
+        DnsMessage {
+            id,
+            flags: DnsMessage::RD,
+            questions: vec![Question {
+                name: name.trim_end_matches('.').to_string(),
+                rtype,
+                class: CLASS_IN,
+            }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & DnsMessage::QR != 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.flags & DnsMessage::TC != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<DnsMessage, DnsError> {
+        if buf.len() < DnsMessage::HEADER_SIZE {
+            return Err(DnsError("too short".to_string()));
+        }
+        let count = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+        let mut i = DnsMessage::HEADER_SIZE;
+
+        let mut questions = vec![];
+        for _ in 0..count(4) {
+            let name = DnsMessage::_parse_name(buf, &mut i)?;
+            let fixed = DnsMessage::_take(buf, &mut i, 4)?;
+            questions.push(Question {
+                name,
+                rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+        }
+        let mut sections = [vec![], vec![], vec![]];
+        for (section, n) in sections.iter_mut().zip([count(6), count(8), count(10)]) {
+            for _ in 0..n {
+                section.push(DnsMessage::_parse_record(buf, &mut i)?);
+            }
+        }
+        let [answers, authorities, additionals] = sections;
+
+        Ok(DnsMessage {
+            id: count(0),
+            flags: count(2),
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+
+    fn _take<'a>(buf: &'a [u8], i: &mut usize, n: usize) -> Result<&'a [u8], DnsError> {
+        let data = buf
+            .get(*i..*i + n)
+            .ok_or_else(|| DnsError(format!("too short: offset={}", *i)))?;
+        *i += n;
+        Ok(data)
+    }
+
+    // RFC 1035 4.1.4
+    fn _parse_name(buf: &[u8], i: &mut usize) -> Result<String, DnsError> {
+        let mut labels: Vec<String> = vec![];
+        let mut length = 0;
+        let mut offset = *i;
+        // Where the labels being read start
+        let mut start = offset;
+        // The end of the name in the message if it has been compressed
+        let mut end = None;
+        loop {
+            let n = *buf
+                .get(offset)
+                .ok_or_else(|| DnsError(format!("name error: offset={}", offset)))?
+                as usize;
+            match n & 0xc0 {
+                0x00 if n == 0 => {
+                    offset += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = buf
+                        .get(offset + 1..offset + 1 + n)
+                        .ok_or_else(|| DnsError(format!("name error: offset={}", offset)))?;
+                    length += n + 1;
+                    if length > 255 {
+                        return Err(DnsError("name too long".to_string()));
+                    }
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    offset += 1 + n;
+                }
+                0xc0 => {
+                    let low = *buf
+                        .get(offset + 1)
+                        .ok_or_else(|| DnsError(format!("name error: offset={}", offset)))?;
+                    let pointer = ((n & 0x3f) << 8) | low as usize;
+                    // Only backward pointers so that they cannot loop
+                    if pointer >= start {
+                        return Err(DnsError(format!("pointer error: pointer={}", pointer)));
+                    }
+                    end.get_or_insert(offset + 2);
+                    offset = pointer;
+                    start = pointer;
+                }
+                _ => return Err(DnsError(format!("unknown label type: type={:#x}", n))),
+            }
+        }
+        *i = end.unwrap_or(offset);
+        Ok(labels.join("."))
+    }
+
+    fn _parse_record(buf: &[u8], i: &mut usize) -> Result<Record, DnsError> {
+        let name = DnsMessage::_parse_name(buf, i)?;
+        let fixed = DnsMessage::_take(buf, i, 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+
+        let start = *i;
+        let data = DnsMessage::_take(buf, i, length)?;
+        let length_error = || DnsError(format!("rdata length error: length={}", length));
+        // Names in the data may point to the rest of the message
+        let mut j = start;
+        let data = match rtype {
+            rtype::A => {
+                let a: [u8; 4] = data.try_into().map_err(|_| length_error())?;
+                j = *i;
+                RData::A(Ipv4Addr::from(a))
+            }
+            rtype::AAAA => {
+                let a: [u8; 16] = data.try_into().map_err(|_| length_error())?;
+                j = *i;
+                RData::Aaaa(Ipv6Addr::from(a))
+            }
+            rtype::NS => RData::Ns(DnsMessage::_parse_name(buf, &mut j)?),
+            rtype::CNAME => RData::Cname(DnsMessage::_parse_name(buf, &mut j)?),
+            rtype::PTR => RData::Ptr(DnsMessage::_parse_name(buf, &mut j)?),
+            rtype::SOA => {
+                let mname = DnsMessage::_parse_name(buf, &mut j)?;
+                let rname = DnsMessage::_parse_name(buf, &mut j)?;
+                let fixed = DnsMessage::_take(buf, &mut j, 20)?;
+                let field = |k: usize| {
+                    u32::from_be_bytes([fixed[k], fixed[k + 1], fixed[k + 2], fixed[k + 3]])
+                };
+                RData::Soa(Soa {
+                    mname,
+                    rname,
+                    serial: field(0),
+                    refresh: field(4),
+                    retry: field(8),
+                    expire: field(12),
+                    minimum: field(16),
+                })
+            }
+            rtype::MX => {
+                let preference = DnsMessage::_take(buf, &mut j, 2)?;
+                let preference = u16::from_be_bytes([preference[0], preference[1]]);
+                let exchange = DnsMessage::_parse_name(buf, &mut j)?;
+                RData::Mx {
+                    preference,
+                    exchange,
+                }
+            }
+            rtype::TXT => {
+                // One or more <character-string>s
+                let mut strings = vec![];
+                let mut k = 0;
+                while k < data.len() {
+                    let n = data[k] as usize;
+                    let string = data.get(k + 1..k + 1 + n).ok_or_else(length_error)?;
+                    strings.push(String::from_utf8_lossy(string).into_owned());
+                    k += 1 + n;
+                }
+                j = *i;
+                RData::Txt(strings)
+            }
+            _ => {
+                j = *i;
+                RData::Other {
+                    rtype,
+                    data: data.to_vec(),
+                }
+            }
+        };
+        if j != *i {
+            return Err(length_error());
+        }
+        Ok(Record {
+            name,
+            class,
+            ttl,
+            data,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        for n in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+
+        // The offsets of the names written so far
+        let mut names = HashMap::new();
+        for question in &self.questions {
+            DnsMessage::_write_name(&mut buf, &mut names, &question.name);
+            buf.extend_from_slice(&question.rtype.to_be_bytes());
+            buf.extend_from_slice(&question.class.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            DnsMessage::_write_record(&mut buf, &mut names, record);
+        }
+        buf
+    }
+
+    fn _write_name(buf: &mut Vec<u8>, names: &mut HashMap<String, usize>, name: &str) {
+        let name = name.trim_end_matches('.');
+        let labels: Vec<&str> = match name {
+            "" => vec![],
+            _ => name.split('.').collect(),
+        };
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if let Some(&pointer) = names.get(&suffix) {
+                buf.extend_from_slice(&(0xc000 | pointer as u16).to_be_bytes());
+                return;
+            }
+            if buf.len() <= DnsMessage::MAX_POINTER {
+                names.insert(suffix, buf.len());
+            }
+            buf.push(labels[i].len() as u8);
+            buf.extend_from_slice(labels[i].as_bytes());
+        }
+        buf.push(0);
+    }
+
+    fn _write_record(buf: &mut Vec<u8>, names: &mut HashMap<String, usize>, record: &Record) {
+        DnsMessage::_write_name(buf, names, &record.name);
+        buf.extend_from_slice(&record.rtype().to_be_bytes());
+        buf.extend_from_slice(&record.class.to_be_bytes());
+        buf.extend_from_slice(&record.ttl.to_be_bytes());
+
+        // Fill in the length after the data
+        let start = buf.len();
+        buf.extend_from_slice(&[0, 0]);
+        match &record.data {
+            RData::A(address) => buf.extend_from_slice(&address.octets()),
+            RData::Aaaa(address) => buf.extend_from_slice(&address.octets()),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => {
+                DnsMessage::_write_name(buf, names, name)
+            }
+            RData::Soa(soa) => {
+                DnsMessage::_write_name(buf, names, &soa.mname);
+                DnsMessage::_write_name(buf, names, &soa.rname);
+                for field in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    buf.extend_from_slice(&field.to_be_bytes());
+                }
+            }
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                buf.extend_from_slice(&preference.to_be_bytes());
+                DnsMessage::_write_name(buf, names, exchange);
+            }
+            RData::Txt(strings) => {
+                for string in strings {
+                    // Longer strings are split into 255-byte pieces
+                    let mut chunks = string.as_bytes().chunks(255).peekable();
+                    if chunks.peek().is_none() {
+                        buf.push(0);
+                    }
+                    for chunk in chunks {
+                        buf.push(chunk.len() as u8);
+                        buf.extend_from_slice(chunk);
+                    }
+                }
+            }
+            RData::Other { data, .. } => buf.extend_from_slice(data),
+        }
+        let length = (buf.len() - start - 2) as u16;
+        buf[start..start + 2].copy_from_slice(&length.to_be_bytes());
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    dns::{self, rcode, rtype, DnsError, DnsMessage, RData, Record, Soa, CLASS_IN},
+    socket::{SocketHandle, SocketType, Sockets},
+};
+
+// The records of a zone from a master file (RFC 1035 5). $ORIGIN, $TTL,
+// relative names, "@", blank owners and parentheses are supported.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Zone {
+    pub origin: String,
+    pub records: Vec<Record>,
+}
+
+impl Zone {
+    const DEFAULT_TTL: u32 = 3600;
+
+    pub fn load(path: &Path) -> Result<Zone, DnsError> {
+        let s =
+            fs::read_to_string(path).map_err(|e| DnsError(format!("{}: {}", path.display(), e)))?;
+        Zone::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> Result<Zone, DnsError> {
+        let mut origin: Option<String> = None;
+        let mut ttl = Zone::DEFAULT_TTL;
+        let mut owner: Option<String> = None;
+        let mut records = vec![];
+
+        for (line, blank_owner, tokens) in Zone::_entries(s)? {
+            let error = |message: String| DnsError(format!("zone: line {}: {}", line, message));
+            let mut tokens = tokens.into_iter().peekable();
+            match tokens.peek().map(String::as_str) {
+                Some("$ORIGIN") => {
+                    tokens.next();
+                    let name = tokens
+                        .next()
+                        .ok_or_else(|| error("no origin".to_string()))?;
+                    origin = Some(Zone::_name(&name, origin.as_deref()).map_err(error)?);
+                    continue;
+                }
+                Some("$TTL") => {
+                    tokens.next();
+                    ttl = tokens
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| error("invalid TTL".to_string()))?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if !blank_owner {
+                let name = tokens.next().unwrap_or_default();
+                owner = Some(Zone::_name(&name, origin.as_deref()).map_err(error)?);
+            }
+            let name = owner.clone().ok_or_else(|| error("no owner".to_string()))?;
+
+            // The TTL and the class may come in either order
+            let mut record_ttl = None;
+            let mut rtype = None;
+            for token in tokens.by_ref() {
+                match token.parse::<u32>() {
+                    Ok(t) => record_ttl = Some(t),
+                    Err(_) if token.eq_ignore_ascii_case("IN") => {}
+                    Err(_) => {
+                        rtype = Some(token.to_ascii_uppercase());
+                        break;
+                    }
+                }
+            }
+            let rtype = rtype.ok_or_else(|| error("no type".to_string()))?;
+            let rdata: Vec<String> = tokens.collect();
+            let data = Zone::_rdata(&rtype, &rdata, origin.as_deref()).map_err(error)?;
+            // The SOA sets the origin if $ORIGIN has not
+            if origin.is_none() && matches!(data, RData::Soa(_)) {
+                origin = Some(name.clone());
+            }
+            records.push(Record {
+                name,
+                class: CLASS_IN,
+                ttl: record_ttl.unwrap_or(ttl),
+                data,
+            });
+        }
+
+        let origin = origin.ok_or_else(|| DnsError("zone: no origin".to_string()))?;
+        let soas = records
+            .iter()
+            .filter(|r| r.rtype() == rtype::SOA && dns::name_eq(&r.name, &origin))
+            .count();
+        if soas != 1 {
+            return Err(DnsError(format!(
+                "zone: {} SOA records at the origin",
+                soas
+            )));
+        }
+        Ok(Zone { origin, records })
+    }
+
+    // Split the file into entries of the line, whether the owner is omitted and
+    // the tokens with the comments and the quotes removed
+    fn _entries(s: &str) -> Result<Vec<(usize, bool, Vec<String>)>, DnsError> {
+        let mut entries: Vec<(usize, bool, Vec<String>)> = vec![];
+        let mut depth = 0;
+        for (i, line) in s.lines().enumerate() {
+            let continued = depth > 0;
+            let mut tokens = vec![];
+            let mut token = String::new();
+            let mut quoted = false;
+            let mut chars = line.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if quoted => token.extend(chars.next()),
+                    '"' => {
+                        // An empty string is still a token
+                        if quoted {
+                            tokens.push(std::mem::take(&mut token));
+                        }
+                        quoted = !quoted;
+                    }
+                    _ if quoted => token.push(c),
+                    ';' => break,
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    ')' => return Err(DnsError(format!("zone: line {}: unbalanced ')'", i + 1))),
+                    c if c.is_whitespace() => {
+                        if !token.is_empty() {
+                            tokens.push(std::mem::take(&mut token));
+                        }
+                    }
+                    c => token.push(c),
+                }
+            }
+            if quoted {
+                return Err(DnsError(format!(
+                    "zone: line {}: unterminated string",
+                    i + 1
+                )));
+            }
+            if !token.is_empty() {
+                tokens.push(token);
+            }
+
+            // Continue the entry inside parentheses
+            match entries.last_mut() {
+                Some((_, _, previous)) if continued => previous.append(&mut tokens),
+                _ if tokens.is_empty() => {}
+                _ => {
+                    let blank_owner = line.starts_with(char::is_whitespace);
+                    entries.push((i + 1, blank_owner, tokens));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn _name(name: &str, origin: Option<&str>) -> Result<String, String> {
+        let name = match (name, origin) {
+            ("@", Some(origin)) => origin.to_string(),
+            (name, _) if name.ends_with('.') => name.trim_end_matches('.').to_string(),
+            (name, Some("")) => name.to_string(),
+            (name, Some(origin)) => format!("{}.{}", name, origin),
+            (name, None) => return Err(format!("relative name without origin: {}", name)),
+        };
+        dns::check_name(&name).map_err(|e| e.0)?;
+        Ok(name)
+    }
+
+    fn _rdata(rtype: &str, rdata: &[String], origin: Option<&str>) -> Result<RData, String> {
+        let field = |i: usize| {
+            rdata
+                .get(i)
+                .map(String::as_str)
+                .ok_or_else(|| format!("too few fields: type={}", rtype))
+        };
+        let number = |i: usize| {
+            field(i)?
+                .parse::<u32>()
+                .map_err(|_| format!("invalid number: {}", rdata[i]))
+        };
+        let name = |i: usize| Zone::_name(field(i)?, origin);
+
+        let data = match rtype {
+            "A" => RData::A(field(0)?.parse().map_err(|_| "invalid address")?),
+            "AAAA" => RData::Aaaa(field(0)?.parse().map_err(|_| "invalid address")?),
+            "NS" => RData::Ns(name(0)?),
+            "CNAME" => RData::Cname(name(0)?),
+            "PTR" => RData::Ptr(name(0)?),
+            "MX" => RData::Mx {
+                preference: u16::try_from(number(0)?).map_err(|_| "invalid preference")?,
+                exchange: name(1)?,
+            },
+            "TXT" if !rdata.is_empty() => RData::Txt(rdata.to_vec()),
+            "TXT" => return Err("too few fields: type=TXT".to_string()),
+            "SOA" => RData::Soa(Soa {
+                mname: name(0)?,
+                rname: name(1)?,
+                serial: number(2)?,
+                refresh: number(3)?,
+                retry: number(4)?,
+                expire: number(5)?,
+                minimum: number(6)?,
+            }),
+            _ => return Err(format!("unsupported type: {}", rtype)),
+        };
+        Ok(data)
+    }
+
+    // Whether the name is the origin or below it
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let origin = self.origin.to_ascii_lowercase();
+        origin.is_empty() || name == origin || name.ends_with(&format!(".{}", origin))
+    }
+
+    fn _soa(&self) -> &Record {
+        self.records
+            .iter()
+            .find(|r| r.rtype() == rtype::SOA && dns::name_eq(&r.name, &self.origin))
+            .expect("the zone has an SOA record")
+    }
+
+    fn _records(&self, name: &str) -> impl Iterator<Item = &Record> {
+        let name = name.to_string();
+        self.records
+            .iter()
+            .filter(move |r| dns::name_eq(&r.name, &name))
+    }
+
+    // Names with records below them exist even without records (RFC 8020)
+    fn _exists(&self, name: &str) -> bool {
+        let suffix = format!(".{}", name.trim_end_matches('.').to_ascii_lowercase());
+        self.records
+            .iter()
+            .any(|r| dns::name_eq(&r.name, name) || r.name.to_ascii_lowercase().ends_with(&suffix))
+    }
+}
+
+// Authoritative server for a zone over UDP and TCP
+#[derive(Clone)]
+pub struct DnsServer {
+    sockets: Sockets,
+    zone: Arc<Zone>,
+}
+
+impl DnsServer {
+    const MAX_CNAMES: usize = 8;
+    // RFC 7766 6.2.3
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+    const OPCODE_QUERY: u16 = 0;
+
+    pub fn new(sockets: Sockets, zone: Zone) -> DnsServer {
+        DnsServer {
+            sockets,
+            zone: Arc::new(zone),
+        }
+    }
+
+    pub fn run_udp(&self) -> Result<(), Error> {
+        let socket = self.sockets.socket(SocketType::Datagram)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DnsMessage::PORT);
+        self.sockets.bind(socket, addr)?;
+
+        let mut buf = [0u8; 65535];
+        loop {
+            let (n, src) = self.sockets.recv_from(socket, &mut buf)?;
+            let response = match self._respond(&buf[..n]) {
+                Some(response) => response,
+                None => continue,
+            };
+            let buf = DnsServer::truncate(response).to_bytes();
+            self.sockets.send_to(socket, &buf, src)?;
+        }
+    }
+
+    // Serve each connection on its own thread
+    pub fn run_tcp(&self) -> Result<(), Error> {
+        let listener = self.sockets.socket(SocketType::Stream)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DnsMessage::PORT);
+        self.sockets.bind(listener, addr)?;
+        self.sockets.listen(listener, 8)?;
+        loop {
+            let (stream, _) = self.sockets.accept(listener)?;
+            let server = self.clone();
+            thread::spawn(move || {
+                let _ = server._serve_stream(stream);
+                let _ = server.sockets.close(stream);
+            });
+        }
+    }
+
+    fn _serve_stream(&self, stream: SocketHandle) -> Result<(), Error> {
+        self.sockets
+            .set_read_timeout(stream, Some(DnsServer::IDLE_TIMEOUT))?;
+        // Messages are prefixed with the length (RFC 1035 4.2.2)
+        while let Some(length) = self._read_exact(stream, 2)? {
+            let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+            let query = match self._read_exact(stream, length)? {
+                Some(query) => query,
+                None => break,
+            };
+            let response = match self._respond(&query) {
+                Some(response) => response.to_bytes(),
+                None => continue,
+            };
+            let mut buf = (response.len() as u16).to_be_bytes().to_vec();
+            buf.extend_from_slice(&response);
+            let mut sent = 0;
+            while sent < buf.len() {
+                sent += self.sockets.send(stream, &buf[sent..])?;
+            }
+        }
+        Ok(())
+    }
+
+    // None when the peer closes the connection or stays idle
+    fn _read_exact(&self, stream: SocketHandle, length: usize) -> Result<Option<Vec<u8>>, Error> {
+        let mut buf = vec![0u8; length];
+        let mut received = 0;
+        while received < length {
+            match self.sockets.recv(stream, &mut buf[received..]) {
+                Ok(0) | Err(Errno::EAGAIN) => return Ok(None),
+                Ok(n) => received += n,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(buf))
+    }
+
+    // Replace the records with TC if the response does not fit in a datagram
+    pub(crate) fn truncate(mut response: DnsMessage) -> DnsMessage {
+        if response.to_bytes().len() > DnsMessage::UDP_SIZE {
+            response.flags |= DnsMessage::TC;
+            response.answers.clear();
+            response.authorities.clear();
+            response.additionals.clear();
+        }
+        response
+    }
+
+    fn _respond(&self, buf: &[u8]) -> Option<DnsMessage> {
+        match DnsMessage::parse(buf) {
+            Ok(query) => self.handle(&query),
+            // Tell the client if at least the header is readable
+            Err(_) if buf.len() >= 12 => {
+                let query = DnsMessage {
+                    id: u16::from_be_bytes([buf[0], buf[1]]),
+                    flags: u16::from_be_bytes([buf[2], buf[3]]),
+                    questions: vec![],
+                    answers: vec![],
+                    authorities: vec![],
+                    additionals: vec![],
+                };
+                (!query.is_response()).then(|| DnsServer::_response(&query, rcode::FORMERR))
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn _response(query: &DnsMessage, rcode: u8) -> DnsMessage {
+        DnsMessage {
+            id: query.id,
+            // Copy Opcode and RD
+            flags: DnsMessage::QR | (query.flags & 0x7900) | rcode as u16,
+            questions: query.questions.clone(),
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    // Returns the response to the query if any
+    pub fn handle(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        // Never answer responses so that two servers cannot loop
+        if query.is_response() {
+            return None;
+        }
+        if (query.flags >> 11) & 0xf != DnsServer::OPCODE_QUERY {
+            return Some(DnsServer::_response(query, rcode::NOTIMP));
+        }
+        let question = match query.questions.as_slice() {
+            [question] => question,
+            _ => return Some(DnsServer::_response(query, rcode::FORMERR)),
+        };
+        if question.class != CLASS_IN || !self.zone.contains(&question.name) {
+            return Some(DnsServer::_response(query, rcode::REFUSED));
+        }
+
+        let mut response = DnsServer::_response(query, rcode::NOERROR);
+        response.flags |= DnsMessage::AA;
+
+        // Follow the CNAMEs inside the zone
+        let mut name = question.name.clone();
+        let follow = question.rtype != rtype::CNAME && question.rtype != rtype::ANY;
+        for _ in 0..DnsServer::MAX_CNAMES {
+            let cname = match self._lookup(&name, rtype::CNAME).pop() {
+                Some(cname) if follow => cname,
+                _ => break,
+            };
+            let target = match &cname.data {
+                RData::Cname(target) => target.clone(),
+                _ => break,
+            };
+            response.answers.push(cname);
+            if !self.zone.contains(&target) {
+                return Some(response);
+            }
+            name = target;
+        }
+
+        let answers = self._lookup(&name, question.rtype);
+        if answers.is_empty() {
+            if !self.zone._exists(&name) {
+                response.flags |= rcode::NXDOMAIN as u16;
+            }
+            response.authorities.push(self._negative_soa());
+            return Some(response);
+        }
+        response.additionals = self._additionals(&answers);
+        response.answers.extend(answers);
+        Some(response)
+    }
+
+    fn _lookup(&self, name: &str, rtype: u16) -> Vec<Record> {
+        self.zone
+            ._records(name)
+            .filter(|r| rtype == rtype::ANY || r.rtype() == rtype)
+            .cloned()
+            .collect()
+    }
+
+    // The addresses of the targets of NS and MX in the zone
+    fn _additionals(&self, answers: &[Record]) -> Vec<Record> {
+        let mut additionals = vec![];
+        for record in answers {
+            let target = match &record.data {
+                RData::Ns(target) => target,
+                RData::Mx { exchange, .. } => exchange,
+                _ => continue,
+            };
+            for rtype in [rtype::A, rtype::AAAA] {
+                additionals.extend(self._lookup(target, rtype));
+            }
+        }
+        additionals
+    }
+
+    // The TTL of the SOA is the TTL of the negative answer (RFC 2308 3)
+    fn _negative_soa(&self) -> Record {
+        let mut soa = self.zone._soa().clone();
+        if let RData::Soa(data) = &soa.data {
+            soa.ttl = soa.ttl.min(data.minimum);
+        }
+        soa
+    }
+}
@@ -0,0 +1,198 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::{
+        dns::{rcode, rtype, DnsError, DnsMessage, RData, Soa},
+        dnsserver::{DnsServer, Zone},
+        eventloop::EventLoop,
+        socket::Sockets,
+    };
+
+    const ZONE: &str = r#"
+$ORIGIN lab.test.
+$TTL 3600
+@       IN  SOA ns1 hostmaster (
+                2024010101 ; serial
+                3600 600 86400 60 )
+        IN  NS  ns1
+        IN  MX  10 mail
+        IN  TXT "v=spf1 -all" "two words"
+ns1     IN  A   10.0.0.1
+mail    300 IN  A   10.0.0.2
+        IN  AAAA 2001:db8::2
+www     IN  CNAME ns1
+web     IN  CNAME www.lab.test.
+ext     IN  CNAME example.com.
+a.b     IN  A   10.0.0.3
+"#;
+
+    fn server() -> DnsServer {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        DnsServer::new(sockets, Zone::parse(ZONE).unwrap())
+    }
+
+    fn query(name: &str, rtype: u16) -> DnsMessage {
+        DnsMessage::query(0x1234, name, rtype)
+    }
+
+    #[test]
+    fn parse() {
+        let zone = Zone::parse(ZONE).unwrap();
+        assert_eq!(zone.origin, "lab.test");
+        assert_eq!(zone.records.len(), 11);
+        assert_eq!(
+            zone.records[0].data,
+            RData::Soa(Soa {
+                mname: "ns1.lab.test".to_string(),
+                rname: "hostmaster.lab.test".to_string(),
+                serial: 2024010101,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 60,
+            })
+        );
+        assert_eq!(zone.records[1].name, "lab.test");
+        assert_eq!(
+            zone.records[3].data,
+            RData::Txt(vec!["v=spf1 -all".to_string(), "two words".to_string()])
+        );
+        assert_eq!(zone.records[5].ttl, 300);
+        assert_eq!(zone.records[6].name, "mail.lab.test");
+        assert_eq!(zone.records[6].ttl, 3600);
+        assert_eq!(
+            zone.records[6].data,
+            RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2))
+        );
+        assert_eq!(zone.records[10].name, "a.b.lab.test");
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(
+            Zone::parse("$ORIGIN lab.test.\n@ IN SOA ns1 hostmaster 1 2 3 4 5\nfoo IN BAR 1\n"),
+            Err(DnsError("zone: line 3: unsupported type: BAR".to_string()))
+        );
+        assert_eq!(
+            Zone::parse("foo IN A 10.0.0.1\n"),
+            Err(DnsError(
+                "zone: line 1: relative name without origin: foo".to_string()
+            ))
+        );
+        assert_eq!(
+            Zone::parse("$ORIGIN lab.test.\nfoo IN A 10.0.0.1\n"),
+            Err(DnsError("zone: 0 SOA records at the origin".to_string()))
+        );
+    }
+
+    #[test]
+    fn answer() {
+        let response = server().handle(&query("NS1.lab.test", rtype::A)).unwrap();
+        assert_eq!(response.id, 0x1234);
+        assert!(response.is_response());
+        assert_ne!(response.flags & DnsMessage::AA, 0);
+        // RD is copied
+        assert_ne!(response.flags & DnsMessage::RD, 0);
+        assert_eq!(response.rcode(), rcode::NOERROR);
+        assert_eq!(
+            response.questions,
+            query("NS1.lab.test", rtype::A).questions
+        );
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].data,
+            RData::A(Ipv4Addr::new(10, 0, 0, 1))
+        );
+    }
+
+    #[test]
+    fn cname() {
+        let server = server();
+        let response = server.handle(&query("web.lab.test", rtype::A)).unwrap();
+        let data: Vec<RData> = response.answers.into_iter().map(|r| r.data).collect();
+        assert_eq!(
+            data,
+            vec![
+                RData::Cname("www.lab.test".to_string()),
+                RData::Cname("ns1.lab.test".to_string()),
+                RData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            ]
+        );
+
+        // The target outside the zone is left to the client
+        let response = server.handle(&query("ext.lab.test", rtype::A)).unwrap();
+        assert_eq!(response.rcode(), rcode::NOERROR);
+        assert_eq!(response.answers.len(), 1);
+
+        // Not followed for the CNAME itself
+        let response = server.handle(&query("web.lab.test", rtype::CNAME)).unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn additionals() {
+        let response = server().handle(&query("lab.test", rtype::MX)).unwrap();
+        assert_eq!(
+            response.answers[0].data,
+            RData::Mx {
+                preference: 10,
+                exchange: "mail.lab.test".to_string()
+            }
+        );
+        let types: Vec<u16> = response.additionals.iter().map(|r| r.rtype()).collect();
+        assert_eq!(types, vec![rtype::A, rtype::AAAA]);
+    }
+
+    #[test]
+    fn nxdomain() {
+        let server = server();
+        let response = server.handle(&query("nothing.lab.test", rtype::A)).unwrap();
+        assert_eq!(response.rcode(), rcode::NXDOMAIN);
+        assert!(response.answers.is_empty());
+        // The TTL is the minimum of the SOA
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.authorities[0].rtype(), rtype::SOA);
+        assert_eq!(response.authorities[0].ttl, 60);
+
+        // No data
+        for name in ["ns1.lab.test", "b.lab.test"] {
+            let response = server.handle(&query(name, rtype::AAAA)).unwrap();
+            assert_eq!(response.rcode(), rcode::NOERROR);
+            assert!(response.answers.is_empty());
+            assert_eq!(response.authorities.len(), 1);
+        }
+    }
+
+    #[test]
+    fn refused() {
+        let server = server();
+        let response = server.handle(&query("example.com", rtype::A)).unwrap();
+        assert_eq!(response.rcode(), rcode::REFUSED);
+        assert_eq!(response.flags & DnsMessage::AA, 0);
+
+        // STATUS
+        let mut status = query("lab.test", rtype::A);
+        status.flags |= 2 << 11;
+        let response = server.handle(&status).unwrap();
+        assert_eq!(response.rcode(), rcode::NOTIMP);
+
+        let mut response = query("lab.test", rtype::A);
+        response.flags |= DnsMessage::QR;
+        assert!(server.handle(&response).is_none());
+    }
+
+    #[test]
+    fn truncate() {
+        let mut response = server().handle(&query("lab.test", rtype::ANY)).unwrap();
+        assert_eq!(response.answers.len(), 4);
+        assert!(!DnsServer::truncate(response.clone()).is_truncated());
+
+        let record = response.answers[0].clone();
+        response.answers = vec![record; 20];
+        let truncated = DnsServer::truncate(response);
+        assert!(truncated.is_truncated());
+        assert!(truncated.answers.is_empty());
+    }
+}
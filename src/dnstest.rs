@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::dns::{self, rcode, rtype, DnsError, DnsMessage, RData, Record, Soa, CLASS_IN};
+
+    fn record(name: &str, data: RData) -> Record {
+        Record {
+            name: name.to_string(),
+            class: CLASS_IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        // A response to a query for the A records of example.com
+        let buf = [
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // Header
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm',
+            0x00, // Name
+            0x00, 0x01, 0x00, 0x01, // Type, Class
+            0xc0, 0x0c, // Pointer to the name
+            0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x04, // Type .. RDLENGTH
+            93, 184, 216, 34, // RDATA
+        ];
+        let message = DnsMessage::parse(&buf).unwrap();
+        assert_eq!(message.id, 0x1234);
+        assert!(message.is_response());
+        assert!(!message.is_truncated());
+        assert_eq!(message.rcode(), rcode::NOERROR);
+        assert_eq!(message.questions[0].name, "example.com");
+        assert_eq!(message.questions[0].rtype, rtype::A);
+        assert_eq!(
+            message.answers,
+            vec![record(
+                "example.com",
+                RData::A(Ipv4Addr::new(93, 184, 216, 34))
+            )]
+        );
+        assert_eq!(message.to_bytes(), buf);
+    }
+
+    #[test]
+    fn compression() {
+        let mut message = DnsMessage::query(1, "www.example.com.", rtype::A);
+        message.flags |= DnsMessage::QR;
+        message.answers = vec![
+            record(
+                "www.example.com",
+                RData::Cname("web.example.com".to_string()),
+            ),
+            record("web.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            record(
+                "web.example.com",
+                RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+            ),
+            record(
+                "1.2.0.192.in-addr.arpa",
+                RData::Ptr("web.example.com".to_string()),
+            ),
+        ];
+
+        let buf = message.to_bytes();
+        // The name of the first answer points to the question
+        assert_eq!(&buf[33..35], &[0xc0, 0x0c]);
+        // "web" followed by a pointer to "example.com"
+        assert_eq!(&buf[45..51], &[3, b'w', b'e', b'b', 0xc0, 0x10]);
+        assert_eq!(DnsMessage::parse(&buf).unwrap(), message);
+    }
+
+    #[test]
+    fn records() {
+        let mut message = DnsMessage::query(1, "example.com", rtype::ANY);
+        message.answers = vec![
+            record("example.com", RData::Ns("ns.example.com".to_string())),
+            record(
+                "example.com",
+                RData::Soa(Soa {
+                    mname: "ns.example.com".to_string(),
+                    rname: "hostmaster.example.com".to_string(),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 60,
+                }),
+            ),
+            record(
+                "example.com",
+                RData::Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".to_string(),
+                },
+            ),
+            record(
+                "example.com",
+                RData::Txt(vec!["v=spf1 -all".to_string(), "".to_string()]),
+            ),
+        ];
+        assert_eq!(DnsMessage::parse(&message.to_bytes()).unwrap(), message);
+    }
+
+    #[test]
+    fn other() {
+        let mut message = DnsMessage::query(1, "example.com", 99);
+        message.answers = vec![record(
+            "example.com",
+            RData::Other {
+                rtype: 99,
+                data: vec![1, 2, 3],
+            },
+        )];
+        let parsed = DnsMessage::parse(&message.to_bytes()).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.answers[0].rtype(), 99);
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let mut buf = DnsMessage::query(1, "example.com", rtype::A).to_bytes();
+        // The name points to itself
+        buf[12] = 0xc0;
+        buf[13] = 0x0c;
+        assert_eq!(
+            DnsMessage::parse(&buf),
+            Err(DnsError("pointer error: pointer=12".to_string()))
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        let buf = DnsMessage::query(1, "example.com", rtype::A).to_bytes();
+        assert!(DnsMessage::parse(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert!(dns::name_eq("Example.COM.", "example.com"));
+        assert!(!dns::name_eq("example.com", "example.org"));
+        assert_eq!(
+            dns::reverse_name(Ipv4Addr::new(192, 0, 2, 1)),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert!(dns::check_name("example.com.").is_ok());
+        assert!(dns::check_name("example..com").is_err());
+        assert!(dns::check_name(&"a".repeat(64)).is_err());
+    }
+}
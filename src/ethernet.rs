@@ -0,0 +1,264 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    net::Ipv4Addr,
+    str::FromStr,
+    time::Instant,
+};
+
+use crate::{
+    arp::{ArpCache, ArpOperation, ArpPacket, Neighbor},
+    router::Interface,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct EthernetError(pub String);
+
+impl fmt::Display for EthernetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ethernet: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+    pub const UNSPECIFIED: MacAddress = MacAddress([0x00; 6]);
+
+    pub fn from_slice(buf: &[u8]) -> MacAddress {
+        let mut octets = [0u8; 6];
+        octets.copy_from_slice(&buf[..6]);
+        MacAddress(octets)
+    }
+
+    // A random unicast address which is locally administered
+    pub fn random() -> MacAddress {
+        let random = RandomState::new().build_hasher().finish().to_be_bytes();
+        let mut octets = [0u8; 6];
+        octets.copy_from_slice(&random[..6]);
+        octets[0] = (octets[0] & 0xfc) | 0x02;
+        MacAddress(octets)
+    }
+
+    // RFC 1112 6.4: the low-order 23 bits of the group in 01-00-5E-00-00-00
+    pub fn multicast(group: Ipv4Addr) -> MacAddress {
+        let [_, b, c, d] = group.octets();
+        MacAddress([0x01, 0x00, 0x5e, b & 0x7f, c, d])
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = EthernetError;
+
+    // Six hexadecimal octets separated by colons
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || EthernetError(format!("invalid address: {}", s));
+        let mut octets = [0u8; 6];
+        let mut parts = s.split(':');
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or_else(error)?;
+            if part.len() != 2 {
+                return Err(error());
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| error())?;
+        }
+        if parts.next().is_some() {
+            return Err(error());
+        }
+        Ok(MacAddress(octets))
+    }
+}
+
+// The IP datagram in a frame and the frames to send back
+pub type Input = (Option<Vec<u8>>, Vec<Vec<u8>>);
+
+// The link layer of a TAP device
+pub struct Ethernet {
+    mac: MacAddress,
+    arp: ArpCache,
+}
+
+impl Ethernet {
+    pub const TYPE_IPV4: u16 = 0x0800;
+    pub const TYPE_ARP: u16 = 0x0806;
+    const HEADER_SIZE: usize = 14;
+    // Without the frame check sequence
+    const MIN_FRAME_SIZE: usize = 60;
+
+    pub fn new(mac: MacAddress) -> Ethernet {
+        Ethernet {
+            mac,
+            arp: ArpCache::new(),
+        }
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    pub fn neighbors(&self, now: Instant) -> Vec<Neighbor> {
+        self.arp.neighbors(now)
+    }
+
+    pub fn add_neighbor(&mut self, ip: Ipv4Addr, mac: MacAddress) {
+        self.arp.insert_permanent(ip, mac);
+    }
+
+    pub fn remove_neighbor(&mut self, ip: Ipv4Addr) -> bool {
+        self.arp.remove(ip)
+    }
+
+    pub fn frame(dst: MacAddress, src: MacAddress, ethertype: u16, data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&dst.0);
+        buf.extend_from_slice(&src.0);
+        buf.extend_from_slice(&ethertype.to_be_bytes());
+        buf.extend_from_slice(data);
+        // Pad short frames
+        if buf.len() < Ethernet::MIN_FRAME_SIZE {
+            buf.resize(Ethernet::MIN_FRAME_SIZE, 0);
+        }
+        buf
+    }
+
+    pub fn input(
+        &mut self,
+        buf: &[u8],
+        interface: &Interface,
+        now: Instant,
+    ) -> Result<Input, EthernetError> {
+        if buf.len() < Ethernet::HEADER_SIZE {
+            return Err(EthernetError("too short".to_string()));
+        }
+
+        let dst = MacAddress::from_slice(&buf[0..6]);
+        if dst != self.mac && !dst.is_multicast() {
+            return Err(EthernetError(format!("not for this host: dst={}", dst)));
+        }
+
+        let data = &buf[Ethernet::HEADER_SIZE..];
+        match u16::from_be_bytes([buf[12], buf[13]]) {
+            Ethernet::TYPE_IPV4 => {
+                // Remove the padding of short frames with the total length
+                let total_length = match data {
+                    [_, _, high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+                    _ => data.len(),
+                };
+                let n = total_length.min(data.len());
+                Ok((Some(data[..n].to_vec()), vec![]))
+            }
+            Ethernet::TYPE_ARP => Ok((None, self._arp(data, interface, now)?)),
+            ethertype => Err(EthernetError(format!(
+                "unsupported type: type={:#x?}",
+                ethertype
+            ))),
+        }
+    }
+
+    // RFC 826 Packet Reception
+    fn _arp(
+        &mut self,
+        buf: &[u8],
+        interface: &Interface,
+        now: Instant,
+    ) -> Result<Vec<Vec<u8>>, EthernetError> {
+        let packet = ArpPacket::parse(buf).map_err(|e| EthernetError(e.to_string()))?;
+
+        let merged = self.arp.update(packet.sender_ip, packet.sender_mac, now);
+        if interface.address.is_unspecified() || packet.target_ip != interface.address {
+            return Ok(vec![]);
+        }
+        if !merged {
+            self.arp.insert(packet.sender_ip, packet.sender_mac, now);
+        }
+
+        let mut frames = vec![];
+        if packet.operation == ArpOperation::Request {
+            let reply = ArpPacket {
+                operation: ArpOperation::Reply,
+                sender_mac: self.mac,
+                sender_ip: interface.address,
+                target_mac: packet.sender_mac,
+                target_ip: packet.sender_ip,
+            };
+            frames.push(Ethernet::frame(
+                packet.sender_mac,
+                self.mac,
+                Ethernet::TYPE_ARP,
+                &reply.to_bytes(),
+            ));
+        }
+
+        // Send the datagrams waiting for the address
+        for datagram in self.arp.take_pending(packet.sender_ip) {
+            frames.push(Ethernet::frame(
+                packet.sender_mac,
+                self.mac,
+                Ethernet::TYPE_IPV4,
+                &datagram,
+            ));
+        }
+        Ok(frames)
+    }
+
+    // Returns the frames to send the datagram to the next hop
+    pub fn output(
+        &mut self,
+        next_hop: Ipv4Addr,
+        interface: &Interface,
+        datagram: &[u8],
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let dst = if next_hop.is_broadcast() || next_hop == interface.broadcast() {
+            Some(MacAddress::BROADCAST)
+        } else if next_hop.is_multicast() {
+            Some(MacAddress::multicast(next_hop))
+        } else {
+            self.arp.lookup(next_hop, now)
+        };
+        if let Some(dst) = dst {
+            return vec![Ethernet::frame(
+                dst,
+                self.mac,
+                Ethernet::TYPE_IPV4,
+                datagram,
+            )];
+        }
+
+        // Resolve the address of the next hop
+        if !self.arp.queue(next_hop, datagram.to_vec(), now) || interface.address.is_unspecified() {
+            return vec![];
+        }
+        let request = ArpPacket {
+            operation: ArpOperation::Request,
+            sender_mac: self.mac,
+            sender_ip: interface.address,
+            target_mac: MacAddress::UNSPECIFIED,
+            target_ip: next_hop,
+        };
+        vec![Ethernet::frame(
+            MacAddress::BROADCAST,
+            self.mac,
+            Ethernet::TYPE_ARP,
+            &request.to_bytes(),
+        )]
+    }
+}
@@ -0,0 +1,187 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        arp::{ArpOperation, ArpPacket, NeighborState},
+        ethernet::{Ethernet, MacAddress},
+        router::Interface,
+    };
+
+    fn stack() -> MacAddress {
+        MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01])
+    }
+
+    fn host() -> MacAddress {
+        MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x02])
+    }
+
+    fn interface() -> Interface {
+        Interface::new(
+            Ipv4Addr::new(203, 0, 113, 2),
+            Ipv4Addr::new(255, 255, 255, 0),
+            1500,
+        )
+    }
+
+    fn request(target_ip: Ipv4Addr) -> Vec<u8> {
+        let request = ArpPacket {
+            operation: ArpOperation::Request,
+            sender_mac: host(),
+            sender_ip: Ipv4Addr::new(203, 0, 113, 1),
+            target_mac: MacAddress::UNSPECIFIED,
+            target_ip,
+        };
+        Ethernet::frame(
+            MacAddress::BROADCAST,
+            host(),
+            Ethernet::TYPE_ARP,
+            &request.to_bytes(),
+        )
+    }
+
+    #[test]
+    fn arp_reply() {
+        let mut ethernet = Ethernet::new(stack());
+        let frame = request(interface().address);
+        let (datagram, frames) = ethernet
+            .input(&frame, &interface(), Instant::now())
+            .unwrap();
+        assert_eq!(datagram, None);
+        assert_eq!(frames.len(), 1);
+
+        let reply = &frames[0];
+        assert_eq!(&reply[0..6], &host().0);
+        assert_eq!(&reply[6..12], &stack().0);
+        assert_eq!(
+            ArpPacket::parse(&reply[14..]),
+            Ok(ArpPacket {
+                operation: ArpOperation::Reply,
+                sender_mac: stack(),
+                sender_ip: interface().address,
+                target_mac: host(),
+                target_ip: Ipv4Addr::new(203, 0, 113, 1),
+            })
+        );
+    }
+
+    #[test]
+    fn arp_other_target() {
+        let mut ethernet = Ethernet::new(stack());
+        let frame = request(Ipv4Addr::new(203, 0, 113, 3));
+        let (_, frames) = ethernet
+            .input(&frame, &interface(), Instant::now())
+            .unwrap();
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn resolve() {
+        let mut ethernet = Ethernet::new(stack());
+        let now = Instant::now();
+        let next_hop = Ipv4Addr::new(203, 0, 113, 1);
+        let datagram = [0x45; 20];
+
+        // The datagram waits for the reply to the request
+        let frames = ethernet.output(next_hop, &interface(), &datagram, now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0][0..6], &MacAddress::BROADCAST.0);
+        let request = ArpPacket::parse(&frames[0][14..]).unwrap();
+        assert_eq!(request.operation, ArpOperation::Request);
+        assert_eq!(request.target_ip, next_hop);
+
+        // No request is sent again right away
+        let frames = ethernet.output(next_hop, &interface(), &datagram, now);
+        assert!(frames.is_empty());
+
+        let reply = ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: host(),
+            sender_ip: next_hop,
+            target_mac: stack(),
+            target_ip: interface().address,
+        };
+        let frame = Ethernet::frame(stack(), host(), Ethernet::TYPE_ARP, &reply.to_bytes());
+        let (_, frames) = ethernet.input(&frame, &interface(), now).unwrap();
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            assert_eq!(&frame[0..6], &host().0);
+            assert_eq!(&frame[12..14], &[0x08, 0x00]);
+            assert_eq!(&frame[14..34], &datagram);
+        }
+
+        // Resolved
+        let frames = ethernet.output(next_hop, &interface(), &datagram, now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0][0..6], &host().0);
+    }
+
+    #[test]
+    fn permanent() {
+        let mut ethernet = Ethernet::new(stack());
+        let now = Instant::now();
+        let next_hop = Ipv4Addr::new(203, 0, 113, 1);
+        let datagram = [0x45; 20];
+        let mac = MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x03]);
+        ethernet.add_neighbor(next_hop, mac);
+
+        // Not updated by ARP and never expires
+        ethernet
+            .input(&request(interface().address), &interface(), now)
+            .unwrap();
+        let later = now + Duration::from_secs(24 * 60 * 60);
+        let frames = ethernet.output(next_hop, &interface(), &datagram, later);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0][0..6], &mac.0);
+        let neighbors = ethernet.neighbors(later);
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].state, NeighborState::Permanent);
+
+        assert!(ethernet.remove_neighbor(next_hop));
+        assert!(!ethernet.remove_neighbor(next_hop));
+        let frames = ethernet.output(next_hop, &interface(), &datagram, later);
+        assert_eq!(&frames[0][0..6], &MacAddress::BROADCAST.0);
+    }
+
+    #[test]
+    fn padding() {
+        let mut ethernet = Ethernet::new(stack());
+        let mut datagram = vec![0x45, 0x00, 0x00, 0x14];
+        datagram.resize(20, 0);
+        let frame = Ethernet::frame(stack(), host(), Ethernet::TYPE_IPV4, &datagram);
+        assert_eq!(frame.len(), 60);
+
+        let (received, _) = ethernet
+            .input(&frame, &interface(), Instant::now())
+            .unwrap();
+        assert_eq!(received, Some(datagram));
+    }
+
+    #[test]
+    fn not_for_this_host() {
+        let mut ethernet = Ethernet::new(stack());
+        let frame = Ethernet::frame(host(), stack(), Ethernet::TYPE_IPV4, &[0x45; 20]);
+        assert!(ethernet
+            .input(&frame, &interface(), Instant::now())
+            .is_err());
+    }
+
+    // Sent to the mapped address without ARP
+    #[test]
+    fn multicast() {
+        let group = Ipv4Addr::new(239, 129, 2, 3);
+        assert_eq!(
+            MacAddress::multicast(group).to_string(),
+            "01:00:5e:01:02:03"
+        );
+
+        let mut ethernet = Ethernet::new(stack());
+        let frames = ethernet.output(group, &interface(), &[0x45; 20], Instant::now());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0][0..6], &[0x01, 0x00, 0x5e, 0x01, 0x02, 0x03]);
+        assert_eq!(&frames[0][12..14], &Ethernet::TYPE_IPV4.to_be_bytes());
+    }
+}
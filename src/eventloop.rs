@@ -0,0 +1,129 @@
+use std::{
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{
+            epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+        },
+        eventfd::{eventfd, EfdFlags},
+    },
+    unistd::{close, read, write},
+    Error,
+};
+
+use crate::timer::{TimerWheel, Timers};
+
+// Wakes up the event loop from other threads
+pub struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    fn new() -> Result<Waker, Error> {
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)?;
+        Ok(Waker { fd })
+    }
+
+    pub fn wake(&self) {
+        // The counter only saturates if nobody clears it, so an error can be ignored
+        let _ = write(self.fd, &1u64.to_ne_bytes());
+    }
+
+    fn clear(&self) {
+        let mut buf = [0u8; 8];
+        let _ = read(self.fd, &mut buf);
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+pub struct EventLoop {
+    epoll: RawFd,
+    waker: Arc<Waker>,
+    wheel: Arc<Mutex<TimerWheel>>,
+}
+
+impl EventLoop {
+    const WAKER: u64 = u64::MAX;
+    const MAX_EVENTS: usize = 16;
+
+    pub fn new() -> Result<EventLoop, Error> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let waker = Arc::new(Waker::new()?);
+
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, EventLoop::WAKER);
+        epoll_ctl(epoll, EpollOp::EpollCtlAdd, waker.fd, &mut event)?;
+
+        Ok(EventLoop {
+            epoll,
+            waker,
+            wheel: Arc::new(Mutex::new(TimerWheel::new(Instant::now()))),
+        })
+    }
+
+    pub fn timers(&self) -> Timers {
+        Timers::new(self.wheel.clone(), self.waker.clone())
+    }
+
+    pub fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    // Watch a file descriptor which is reported to the handler with the token
+    pub fn register(&self, fd: RawFd, token: usize) -> Result<(), Error> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, token as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event)
+    }
+
+    // `poll` runs after every iteration to send the data queued by sockets and
+    // timers
+    pub fn run<F, G>(&self, mut readable: F, mut poll: G) -> Result<(), Error>
+    where
+        F: FnMut(usize),
+        G: FnMut(),
+    {
+        let mut events = [EpollEvent::empty(); EventLoop::MAX_EVENTS];
+        loop {
+            let timeout = match self.wheel.lock().unwrap().next_timeout(Instant::now()) {
+                // Round up to avoid waking up before the timer expires
+                Some(timeout) => timeout.as_micros().div_ceil(1000) as isize,
+                None => -1,
+            };
+
+            let n = match epoll_wait(self.epoll, &mut events, timeout) {
+                Ok(n) => n,
+                Err(Errno::EINTR) => 0,
+                Err(e) => return Err(e),
+            };
+            for event in &events[..n] {
+                match event.data() {
+                    EventLoop::WAKER => self.waker.clear(),
+                    token => readable(token as usize),
+                }
+            }
+
+            // Run the callbacks without the lock so that they can schedule timers
+            let expired = self.wheel.lock().unwrap().expire(Instant::now());
+            for callback in expired {
+                callback();
+            }
+
+            poll();
+        }
+    }
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        let _ = close(self.epoll);
+    }
+}
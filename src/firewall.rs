@@ -0,0 +1,520 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddrV4},
+    str::FromStr,
+    sync::RwLock,
+};
+
+use crate::{conntrack::State, icmp::Icmp, route::Prefix, tcp::Tcp, udp::Udp};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct FirewallError(pub String);
+
+impl fmt::Display for FirewallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "firewall: {}", self.0)
+    }
+}
+
+// The points in the paths of a datagram where the rules are checked, as in
+// netfilter
+//
+// A received datagram passes prerouting, then input if it is for this host
+// or forward and postrouting if it is forwarded. A datagram sent by this host
+// passes output and postrouting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hook {
+    Prerouting,
+    Input,
+    Forward,
+    Output,
+    Postrouting,
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Hook::Prerouting => "prerouting",
+            Hook::Input => "input",
+            Hook::Forward => "forward",
+            Hook::Output => "output",
+            Hook::Postrouting => "postrouting",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Hook {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prerouting" => Ok(Hook::Prerouting),
+            "input" => Ok(Hook::Input),
+            "forward" => Ok(Hook::Forward),
+            "output" => Ok(Hook::Output),
+            "postrouting" => Ok(Hook::Postrouting),
+            _ => Err(FirewallError(format!("invalid hook: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Accept,
+    Drop,
+    // Drop and send Destination Unreachable (Communication Administratively
+    // Prohibited) back to the source, only at input and forward
+    Reject,
+    // Rewrite the source of a new connection to the address of the egress
+    // interface, only at postrouting
+    Masquerade,
+    // Rewrite the source of a new connection to the address, only at
+    // postrouting
+    Snat(Ipv4Addr),
+    // Rewrite the destination of a new connection to the address and
+    // optionally the port, only at prerouting
+    Dnat(Ipv4Addr, Option<u16>),
+}
+
+impl Action {
+    // The action translates the addresses of a connection instead of
+    // filtering datagrams
+    pub fn is_nat(&self) -> bool {
+        self._nat_hook().is_some()
+    }
+
+    fn _nat_hook(&self) -> Option<Hook> {
+        match self {
+            Action::Masquerade | Action::Snat(_) => Some(Hook::Postrouting),
+            Action::Dnat(..) => Some(Hook::Prerouting),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Accept => write!(f, "accept"),
+            Action::Drop => write!(f, "drop"),
+            Action::Reject => write!(f, "reject"),
+            Action::Masquerade => write!(f, "masquerade"),
+            Action::Snat(address) => write!(f, "snat {}", address),
+            Action::Dnat(address, None) => write!(f, "dnat {}", address),
+            Action::Dnat(address, Some(port)) => write!(f, "dnat {}:{}", address, port),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(Action::Accept),
+            "drop" => Ok(Action::Drop),
+            "reject" => Ok(Action::Reject),
+            "masquerade" => Ok(Action::Masquerade),
+            _ => {
+                let invalid = |address| FirewallError(format!("invalid address: {}", address));
+                if let Some(address) = s.strip_prefix("snat ") {
+                    return address
+                        .parse()
+                        .map(Action::Snat)
+                        .map_err(|_| invalid(address));
+                }
+                if let Some(address) = s.strip_prefix("dnat ") {
+                    if let Ok(address) = address.parse::<SocketAddrV4>() {
+                        return Ok(Action::Dnat(*address.ip(), Some(address.port())));
+                    }
+                    return address
+                        .parse()
+                        .map(|address| Action::Dnat(address, None))
+                        .map_err(|_| invalid(address));
+                }
+                Err(FirewallError(format!("invalid action: {}", s)))
+            }
+        }
+    }
+}
+
+// An inclusive range of ports, e.g. "1024-65535", or a single port
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ports {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Ports {
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl fmt::Display for Ports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+
+impl FromStr for Ports {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || FirewallError(format!("invalid ports: {}", s));
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let start = start.parse().map_err(|_| error())?;
+        let end = end.parse().map_err(|_| error())?;
+        if start > end {
+            return Err(error());
+        }
+        Ok(Ports { start, end })
+    }
+}
+
+// The TCP flags in the mask must have the bits of the value, e.g. "syn,!ack"
+// for the first segment of a connection (RFC 9293)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TcpFlags {
+    pub mask: u8,
+    pub value: u8,
+}
+
+impl TcpFlags {
+    const NAMES: [(&'static str, u8); 6] = [
+        ("fin", 0x01),
+        ("syn", 0x02),
+        ("rst", 0x04),
+        ("psh", 0x08),
+        ("ack", 0x10),
+        ("urg", 0x20),
+    ];
+
+    pub fn matches(&self, flags: u8) -> bool {
+        flags & self.mask == self.value
+    }
+}
+
+impl fmt::Display for TcpFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = TcpFlags::NAMES
+            .iter()
+            .filter(|(_, bit)| self.mask & bit != 0)
+            .map(|(name, bit)| match self.value & bit != 0 {
+                true => name.to_string(),
+                false => format!("!{}", name),
+            })
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl FromStr for TcpFlags {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = TcpFlags { mask: 0, value: 0 };
+        for name in s.split(',') {
+            let (set, name) = match name.strip_prefix('!') {
+                Some(name) => (false, name),
+                None => (true, name),
+            };
+            let (_, bit) = TcpFlags::NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or_else(|| FirewallError(format!("invalid TCP flags: {}", s)))?;
+            flags.mask |= bit;
+            if set {
+                flags.value |= bit;
+            }
+        }
+        Ok(flags)
+    }
+}
+
+// A rule of the form
+//
+//   HOOK [from PREFIX] [to PREFIX] [proto icmp|udp|tcp|N] [sport PORTS]
+//   [dport PORTS] [icmp-type N] [flags FLAGS] [state STATES]
+//   accept|drop|reject
+//
+// A rule matches a datagram if all the given fields match. STATES is a list
+// of the connection tracking states, e.g. "established,related".
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub hook: Hook,
+    pub source: Option<Prefix>,
+    pub destination: Option<Prefix>,
+    pub protocol: Option<u8>,
+    pub source_ports: Option<Ports>,
+    pub destination_ports: Option<Ports>,
+    pub icmp_type: Option<u8>,
+    pub tcp_flags: Option<TcpFlags>,
+    pub states: Option<Vec<State>>,
+    pub action: Action,
+}
+
+impl Rule {
+    pub fn new(hook: Hook, action: Action) -> Rule {
+        Rule {
+            hook,
+            source: None,
+            destination: None,
+            protocol: None,
+            source_ports: None,
+            destination_ports: None,
+            icmp_type: None,
+            tcp_flags: None,
+            states: None,
+            action,
+        }
+    }
+
+    // The datagram has been verified by IPv4 and tracked in the state
+    pub fn matches(&self, buf: &[u8], state: State) -> bool {
+        if self
+            .states
+            .as_ref()
+            .is_some_and(|states| !states.contains(&state))
+        {
+            return false;
+        }
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        if self.source.is_some_and(|prefix| !prefix.contains(src))
+            || self.destination.is_some_and(|prefix| !prefix.contains(dst))
+            || self.protocol.is_some_and(|protocol| protocol != buf[9])
+        {
+            return false;
+        }
+        if !self._has_transport() {
+            return true;
+        }
+
+        // Only the first fragment carries the header of the protocol
+        if buf[6] & 0x1f != 0 || buf[7] != 0 {
+            return false;
+        }
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        let data = &buf[ihl..];
+        let port = |i: usize| data.get(i..i + 2).map(|p| u16::from_be_bytes([p[0], p[1]]));
+        let matches = |ports: Option<Ports>, port: Option<u16>| match (ports, port) {
+            (None, _) => true,
+            (Some(ports), Some(port)) => ports.contains(port),
+            (Some(_), None) => false,
+        };
+        matches(self.source_ports, port(0))
+            && matches(self.destination_ports, port(2))
+            && self
+                .icmp_type
+                .is_none_or(|icmp_type| data.first() == Some(&icmp_type))
+            && self
+                .tcp_flags
+                .is_none_or(|flags| data.get(13).is_some_and(|&f| flags.matches(f)))
+    }
+
+    fn _has_transport(&self) -> bool {
+        self.source_ports.is_some()
+            || self.destination_ports.is_some()
+            || self.icmp_type.is_some()
+            || self.tcp_flags.is_some()
+    }
+
+    fn _protocol_name(protocol: u8) -> String {
+        match protocol {
+            Icmp::NUMBER => "icmp".to_string(),
+            Udp::NUMBER => "udp".to_string(),
+            Tcp::NUMBER => "tcp".to_string(),
+            _ => protocol.to_string(),
+        }
+    }
+
+    fn _parse_protocol(s: &str) -> Result<u8, FirewallError> {
+        match s {
+            "icmp" => Ok(Icmp::NUMBER),
+            "udp" => Ok(Udp::NUMBER),
+            "tcp" => Ok(Tcp::NUMBER),
+            _ => s
+                .parse()
+                .map_err(|_| FirewallError(format!("invalid protocol: {}", s))),
+        }
+    }
+
+    fn _validate(&self) -> Result<(), FirewallError> {
+        let ports = self.source_ports.is_some() || self.destination_ports.is_some();
+        if ports && !matches!(self.protocol, Some(Udp::NUMBER | Tcp::NUMBER)) {
+            return Err(FirewallError("ports need proto udp or tcp".to_string()));
+        }
+        if self.icmp_type.is_some() && self.protocol != Some(Icmp::NUMBER) {
+            return Err(FirewallError("icmp-type needs proto icmp".to_string()));
+        }
+        if self.tcp_flags.is_some() && self.protocol != Some(Tcp::NUMBER) {
+            return Err(FirewallError("flags need proto tcp".to_string()));
+        }
+        // Only a datagram received from another host can be answered
+        if self.action == Action::Reject && !matches!(self.hook, Hook::Input | Hook::Forward) {
+            return Err(FirewallError(format!("reject is not for {}", self.hook)));
+        }
+        match self.action._nat_hook() {
+            Some(hook) if hook != self.hook => {
+                return Err(FirewallError(format!(
+                    "{} is only for {}",
+                    self.action, hook
+                )));
+            }
+            _ => {}
+        }
+        let port = matches!(self.action, Action::Dnat(_, Some(_)));
+        if port && !matches!(self.protocol, Some(Udp::NUMBER | Tcp::NUMBER)) {
+            return Err(FirewallError(
+                "dnat to a port needs proto udp or tcp".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.hook)?;
+        if let Some(source) = self.source {
+            write!(f, " from {}", source)?;
+        }
+        if let Some(destination) = self.destination {
+            write!(f, " to {}", destination)?;
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " proto {}", Rule::_protocol_name(protocol))?;
+        }
+        if let Some(ports) = self.source_ports {
+            write!(f, " sport {}", ports)?;
+        }
+        if let Some(ports) = self.destination_ports {
+            write!(f, " dport {}", ports)?;
+        }
+        if let Some(icmp_type) = self.icmp_type {
+            write!(f, " icmp-type {}", icmp_type)?;
+        }
+        if let Some(flags) = self.tcp_flags {
+            write!(f, " flags {}", flags)?;
+        }
+        if let Some(states) = &self.states {
+            let states: Vec<String> = states.iter().map(|state| state.to_string()).collect();
+            write!(f, " state {}", states.join(","))?;
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = FirewallError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (hook, action, mut args) = match words.as_slice() {
+            [hook, args @ .., action @ ("snat" | "dnat"), address] => {
+                let action = format!("{} {}", action, address).parse()?;
+                (hook.parse()?, action, args)
+            }
+            [hook, args @ .., action] => (hook.parse()?, action.parse()?, args),
+            _ => return Err(FirewallError(format!("invalid rule: {}", s))),
+        };
+        let mut rule = Rule::new(hook, action);
+        let prefix = |s: &str| {
+            s.parse::<Prefix>()
+                .map_err(|_| FirewallError(format!("invalid prefix: {}", s)))
+        };
+        while let [key, value, rest @ ..] = args {
+            match *key {
+                "from" => rule.source = Some(prefix(value)?),
+                "to" => rule.destination = Some(prefix(value)?),
+                "proto" => rule.protocol = Some(Rule::_parse_protocol(value)?),
+                "sport" => rule.source_ports = Some(value.parse()?),
+                "dport" => rule.destination_ports = Some(value.parse()?),
+                "icmp-type" => {
+                    let icmp_type = value
+                        .parse()
+                        .map_err(|_| FirewallError(format!("invalid ICMP type: {}", value)))?;
+                    rule.icmp_type = Some(icmp_type);
+                }
+                "flags" => rule.tcp_flags = Some(value.parse()?),
+                "state" => {
+                    let states = value.split(',').map(|state| state.parse());
+                    rule.states = Some(states.collect::<Result<_, _>>()?);
+                }
+                _ => return Err(FirewallError(format!("invalid rule: {}", s))),
+            }
+            args = rest;
+        }
+        if !args.is_empty() {
+            return Err(FirewallError(format!("invalid rule: {}", s)));
+        }
+        rule._validate()?;
+        Ok(rule)
+    }
+}
+
+// The rules checked in order at each hook. The first rule which matches
+// decides the action, and a datagram which no rule matches is accepted.
+//
+// The NAT rules are checked apart from the others, like the nat table of
+// netfilter, only for the first datagram of a connection.
+#[derive(Debug, Default)]
+pub struct Firewall {
+    rules: RwLock<Vec<Rule>>,
+}
+
+impl Firewall {
+    pub fn new(rules: Vec<Rule>) -> Firewall {
+        Firewall {
+            rules: RwLock::new(rules),
+        }
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    // Append a rule after the others
+    pub fn add(&self, rule: Rule) {
+        self.rules.write().unwrap().push(rule);
+    }
+
+    // Returns false if the rule does not exist
+    pub fn delete(&self, rule: &Rule) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        match rules.iter().position(|r| r == rule) {
+            Some(i) => {
+                rules.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn check(&self, hook: Hook, buf: &[u8], state: State) -> Action {
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .find(|rule| !rule.action.is_nat() && rule.hook == hook && rule.matches(buf, state))
+            .map_or(Action::Accept, |rule| rule.action)
+    }
+
+    // The action of the first NAT rule which matches a new connection
+    pub fn nat(&self, hook: Hook, buf: &[u8], state: State) -> Option<Action> {
+        if state != State::New {
+            return None;
+        }
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .find(|rule| rule.action.is_nat() && rule.hook == hook && rule.matches(buf, state))
+            .map(|rule| rule.action)
+    }
+}
@@ -0,0 +1,256 @@
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        conntrack::State,
+        firewall::{Action, Firewall, FirewallError, Hook, Ports, Rule, TcpFlags},
+        icmp::Icmp,
+        ipv4::IPv4,
+        protocol::ProtocolError,
+        route::Prefix,
+        tcp::Tcp,
+        testutil::{datagram, echo, host, remote, router, segment, stack},
+        udp::Udp,
+    };
+
+    fn rule(s: &str) -> Rule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let r = rule("forward from 192.0.2.0/24 to 198.51.100.0/24 proto tcp sport 1024-65535 dport 22 flags syn,!ack reject");
+        assert_eq!(r.hook, Hook::Forward);
+        assert_eq!(r.source, Some(Prefix::new(Ipv4Addr::new(192, 0, 2, 0), 24)));
+        assert_eq!(r.protocol, Some(Tcp::NUMBER));
+        assert_eq!(
+            r.source_ports,
+            Some(Ports {
+                start: 1024,
+                end: 65535
+            })
+        );
+        assert_eq!(r.destination_ports, Some(Ports { start: 22, end: 22 }));
+        assert_eq!(
+            r.tcp_flags,
+            Some(TcpFlags {
+                mask: 0x12,
+                value: 0x02
+            })
+        );
+        assert_eq!(r.action, Action::Reject);
+        assert_eq!(
+            r.to_string(),
+            "forward from 192.0.2.0/24 to 198.51.100.0/24 proto tcp sport 1024-65535 dport 22 flags syn,!ack reject"
+        );
+        assert_eq!(
+            rule("input proto 1 icmp-type 8 drop").to_string(),
+            "input proto icmp icmp-type 8 drop"
+        );
+        assert_eq!(
+            rule("input state related,established accept").states,
+            Some(vec![State::Related, State::Established])
+        );
+        assert_eq!(
+            rule("input proto tcp state new flags syn drop").to_string(),
+            "input proto tcp flags syn state new drop"
+        );
+        assert_eq!(
+            rule("postrouting to 198.51.100.0/24 snat 198.51.100.10").action,
+            Action::Snat(Ipv4Addr::new(198, 51, 100, 10))
+        );
+        assert_eq!(
+            rule("prerouting proto tcp dport 8080 dnat 192.0.2.5:80").action,
+            Action::Dnat(Ipv4Addr::new(192, 0, 2, 5), Some(80))
+        );
+        assert_eq!(
+            rule("prerouting to 198.51.100.2 dnat 192.0.2.5").to_string(),
+            "prerouting to 198.51.100.2/32 dnat 192.0.2.5"
+        );
+        assert_eq!(
+            rule("postrouting proto udp masquerade").to_string(),
+            "postrouting proto udp masquerade"
+        );
+        assert_eq!(
+            rule("output accept"),
+            Rule::new(Hook::Output, Action::Accept)
+        );
+
+        let error = |s: &str| s.parse::<Rule>().unwrap_err();
+        assert_eq!(
+            error("input"),
+            FirewallError("invalid rule: input".to_string())
+        );
+        assert_eq!(
+            error("inbound drop"),
+            FirewallError("invalid hook: inbound".to_string())
+        );
+        assert_eq!(
+            error("input deny"),
+            FirewallError("invalid action: deny".to_string())
+        );
+        assert_eq!(
+            error("input from drop"),
+            FirewallError("invalid rule: input from drop".to_string())
+        );
+        assert_eq!(
+            error("input proto udp dport 53-1 drop"),
+            FirewallError("invalid ports: 53-1".to_string())
+        );
+        assert_eq!(
+            error("input proto tcp flags syn,fin,ece drop"),
+            FirewallError("invalid TCP flags: syn,fin,ece".to_string())
+        );
+        assert_eq!(
+            error("input state new,closed drop"),
+            FirewallError("invalid state: closed".to_string())
+        );
+        assert_eq!(
+            error("input dport 22 drop"),
+            FirewallError("ports need proto udp or tcp".to_string())
+        );
+        assert_eq!(
+            error("input proto udp icmp-type 8 drop"),
+            FirewallError("icmp-type needs proto icmp".to_string())
+        );
+        assert_eq!(
+            error("output proto tcp reject"),
+            FirewallError("reject is not for output".to_string())
+        );
+        assert_eq!(
+            error("forward masquerade"),
+            FirewallError("masquerade is only for postrouting".to_string())
+        );
+        assert_eq!(
+            error("postrouting dnat 192.0.2.5"),
+            FirewallError("dnat 192.0.2.5 is only for prerouting".to_string())
+        );
+        assert_eq!(
+            error("prerouting dnat 192.0.2.5:80"),
+            FirewallError("dnat to a port needs proto udp or tcp".to_string())
+        );
+        assert_eq!(
+            error("postrouting snat 198.51.100"),
+            FirewallError("invalid address: 198.51.100".to_string())
+        );
+    }
+
+    #[test]
+    fn matches() {
+        let syn = segment(host(), stack(), (40000, 22), 0x02);
+        let ack = segment(host(), stack(), (40000, 22), 0x10);
+        assert!(rule("input proto tcp dport 22 drop").matches(&syn, State::New));
+        assert!(!rule("input state established drop").matches(&syn, State::New));
+        assert!(!rule("input proto tcp dport 23-80 drop").matches(&syn, State::New));
+        assert!(!rule("input proto udp drop").matches(&syn, State::New));
+        assert!(rule("input proto tcp flags syn,!ack drop").matches(&syn, State::New));
+        assert!(!rule("input proto tcp flags syn,!ack drop").matches(&ack, State::New));
+        assert!(rule("input from 192.0.2.0/24 to 192.0.2.2 drop").matches(&syn, State::New));
+        assert!(!rule("input from 198.51.100.0/24 drop").matches(&syn, State::New));
+
+        let request = echo(host(), stack(), 8);
+        assert!(rule("input proto icmp icmp-type 8 drop").matches(&request, State::New));
+        assert!(!rule("input proto icmp icmp-type 0 drop").matches(&request, State::New));
+
+        // A later fragment has no ports
+        let mut fragment = datagram(host(), stack(), Udp::NUMBER, &[0; 8]);
+        fragment[7] = 1;
+        assert!(rule("input proto udp drop").matches(&fragment, State::Invalid));
+        assert!(!rule("input proto udp dport 0-65535 drop").matches(&fragment, State::Invalid));
+    }
+
+    #[test]
+    fn first_match() {
+        let firewall = Firewall::default();
+        let request = echo(host(), stack(), 8);
+        assert_eq!(
+            firewall.check(Hook::Input, &request, State::New),
+            Action::Accept
+        );
+
+        firewall.add(rule("input from 192.0.2.1 accept"));
+        firewall.add(rule("input proto icmp drop"));
+        assert_eq!(
+            firewall.check(Hook::Input, &request, State::New),
+            Action::Accept
+        );
+        assert_eq!(
+            firewall.check(Hook::Forward, &request, State::New),
+            Action::Accept
+        );
+        assert!(firewall.delete(&rule("input from 192.0.2.1 accept")));
+        assert!(!firewall.delete(&rule("input from 192.0.2.1 accept")));
+        assert_eq!(
+            firewall.check(Hook::Input, &request, State::New),
+            Action::Drop
+        );
+        assert_eq!(firewall.rules(), vec![rule("input proto icmp drop")]);
+    }
+
+    #[test]
+    fn input() {
+        let router = router(IPv4::new(vec![Box::new(Icmp::new())]));
+        let request = echo(host(), stack(), 8);
+        router
+            .firewall()
+            .add(rule("input proto icmp icmp-type 8 drop"));
+        assert_eq!(router.input(0, &request), Err(ProtocolError::General));
+        assert_eq!(router.ipv4_stats().in_discards.get(), 1);
+        assert_eq!(router.ipv4_stats().in_delivers.get(), 0);
+
+        // Destination Unreachable (Communication Administratively Prohibited)
+        router
+            .firewall()
+            .delete(&rule("input proto icmp icmp-type 8 drop"));
+        router
+            .firewall()
+            .add(rule("input from 192.0.2.0/24 reject"));
+        let request = echo(host(), Ipv4Addr::new(198, 51, 100, 2), 8);
+        let datagrams = router.input(0, &request).unwrap();
+        assert_eq!(datagrams.len(), 1);
+        let (index, error) = &datagrams[0];
+        assert_eq!(*index, 0);
+        assert_eq!(&error[12..20], &[192, 0, 2, 2, 192, 0, 2, 1]);
+        assert_eq!(&error[20..22], &[3, 13]);
+        assert_eq!(&error[28..], &request[..28]);
+    }
+
+    #[test]
+    fn forward() {
+        let router = router(IPv4::new(vec![Box::new(Icmp::new())]));
+        router
+            .firewall()
+            .add(rule("forward to 198.51.100.0/24 proto icmp drop"));
+        assert_eq!(
+            router.input(0, &echo(host(), remote(), 8)),
+            Err(ProtocolError::General)
+        );
+        assert_eq!(router.ipv4_stats().in_forw_datagrams.get(), 0);
+
+        // The datagram has a decremented TTL at postrouting
+        router.firewall().add(rule("postrouting proto tcp drop"));
+        assert_eq!(
+            router.input(0, &segment(host(), remote(), (40000, 80), 0x02)),
+            Err(ProtocolError::General)
+        );
+        assert_eq!(router.ipv4_stats().out_discards.get(), 1);
+        assert!(router
+            .input(0, &datagram(host(), remote(), Udp::NUMBER, &[0; 8]))
+            .is_ok());
+    }
+
+    #[test]
+    fn output() {
+        let router = router(IPv4::new(vec![Box::new(Icmp::new())]));
+        router
+            .firewall()
+            .add(rule("output to 192.0.2.1 proto icmp drop"));
+        assert_eq!(
+            router.input(0, &echo(host(), stack(), 8)),
+            Err(ProtocolError::General)
+        );
+        assert_eq!(router.ipv4_stats().in_delivers.get(), 1);
+        assert_eq!(router.ipv4_stats().out_discards.get(), 1);
+    }
+}
@@ -1,13 +1,26 @@
-use std::fmt;
+use std::{fmt, net::Ipv4Addr};
 
 use crate::{
     ipv4::IPv4Protocol,
     protocol::{get_checksum, Protocol, ProtocolError},
 };
 
-enum IcmpType {
+pub enum IcmpType {
     EchoReply = 0,
+    DestinationUnreachable = 3,
     Echo = 8,
+    TimeExceeded = 11,
+}
+
+pub enum DestinationUnreachableCode {
+    Net = 0,
+    FragmentationNeeded = 4,
+    // RFC 1812 5.2.7.1
+    AdministrativelyProhibited = 13,
+}
+
+pub enum TimeExceededCode {
+    Ttl = 0,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -23,10 +36,55 @@ impl fmt::Display for IcmpError {
 pub struct Icmp {}
 
 impl Icmp {
+    pub const NUMBER: u8 = 1;
+
     pub fn new() -> Icmp {
         Icmp {}
     }
 
+    // The internet header plus the first 64 bits of the original datagram's data
+    fn _original(buf: &[u8]) -> &[u8] {
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        &buf[..buf.len().min(ihl + 8)]
+    }
+
+    fn _error(icmp_type: IcmpType, code: u8, rest: [u8; 4], original: &[u8]) -> Vec<u8> {
+        let mut buf = vec![icmp_type as u8, code, 0, 0];
+        buf.extend_from_slice(&rest);
+        buf.extend_from_slice(Icmp::_original(original));
+
+        let checksum = get_checksum(&buf);
+        buf[2] = (checksum >> 8) as u8;
+        buf[3] = (checksum & 0xff) as u8;
+        buf
+    }
+
+    pub fn destination_unreachable(
+        code: DestinationUnreachableCode,
+        original: &[u8],
+        next_hop_mtu: u16,
+    ) -> Vec<u8> {
+        // RFC 1191
+        // The unused field is used to report the MTU of the next-hop network
+        let [high, low] = next_hop_mtu.to_be_bytes();
+        Icmp::_error(
+            IcmpType::DestinationUnreachable,
+            code as u8,
+            [0, 0, high, low],
+            original,
+        )
+    }
+
+    pub fn time_exceeded(code: TimeExceededCode, original: &[u8]) -> Vec<u8> {
+        Icmp::_error(IcmpType::TimeExceeded, code as u8, [0; 4], original)
+    }
+
+    pub fn is_error(icmp_type: u8) -> bool {
+        // Destination Unreachable, Source Quench, Redirect, Time Exceeded and
+        // Parameter Problem
+        matches!(icmp_type, 3 | 4 | 5 | 11 | 12)
+    }
+
     fn _verify_length(&self, buf: &[u8]) -> Result<(), ProtocolError> {
         // Type: 1 octet
         // Code: 1 octet
@@ -41,7 +99,7 @@ impl Icmp {
         Ok(())
     }
 
-    fn _verify_checksum(&self, buf: &Vec<u8>) -> Result<(), ProtocolError> {
+    fn _verify_checksum(&self, buf: &[u8]) -> Result<(), ProtocolError> {
         let checksum = get_checksum(buf);
         if checksum != 0 {
             return Err(IcmpError(format!("checksum error: checksum={:#x?}", checksum)).into());
@@ -50,6 +108,12 @@ impl Icmp {
     }
 }
 
+impl Default for Icmp {
+    fn default() -> Self {
+        Icmp::new()
+    }
+}
+
 impl Protocol for Icmp {
     fn reply(&self, buf: &[u8]) -> Result<Vec<u8>, ProtocolError> {
         self._verify_length(buf)?;
@@ -79,6 +143,10 @@ impl Protocol for Icmp {
 
 impl IPv4Protocol for Icmp {
     fn number(&self) -> u8 {
-        1
+        Icmp::NUMBER
+    }
+
+    fn input(&self, _src: Ipv4Addr, _dst: Ipv4Addr, buf: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        self.reply(buf)
     }
 }
@@ -0,0 +1,369 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    net::Ipv4Addr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{
+    ipv4::Datagram,
+    protocol::{get_checksum, ProtocolError},
+    socket::Sockets,
+    timer::Timers,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct IgmpError(pub String);
+
+impl fmt::Display for IgmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "igmp: {}", self.0)
+    }
+}
+
+// The version of the queriers on a link. Hosts answer in the oldest version
+// heard recently (RFC 3376 7.2.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum IgmpVersion {
+    V1,
+    V2,
+    V3,
+}
+
+#[derive(Debug)]
+struct Group {
+    // When to send the next report
+    due: Option<Instant>,
+    // The record type of the next report of IGMPv3
+    record: u8,
+    // The unsolicited reports left to send after joining
+    retransmits: u8,
+    // Whether this host sent the last report heard, to leave in IGMPv2
+    last_reporter: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // The groups joined on each interface
+    groups: BTreeMap<(usize, Ipv4Addr), Group>,
+    // Until when a querier of an older version is present on each interface
+    older: HashMap<(usize, IgmpVersion), Instant>,
+}
+
+// RFC 2236 and RFC 3376, the host side
+//
+// The groups joined by the sockets are reported on their interfaces. Reports
+// and leaves are sent when the event loop polls the protocols.
+pub struct Igmp {
+    sockets: Sockets,
+    timers: Timers,
+    state: Mutex<State>,
+}
+
+impl Igmp {
+    pub const NUMBER: u8 = 2;
+    // RFC 1112 4: every host is a member on every interface
+    pub const ALL_SYSTEMS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+    const ALL_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+    const ALL_IGMPV3_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 22);
+    // RFC 2113 Router Alert
+    pub const ROUTER_ALERT: [u8; 4] = [0x94, 0x04, 0x00, 0x00];
+
+    const QUERY: u8 = 0x11;
+    const V1_REPORT: u8 = 0x12;
+    const V2_REPORT: u8 = 0x16;
+    const LEAVE: u8 = 0x17;
+    const V3_REPORT: u8 = 0x22;
+
+    // RFC 3376 4.2.12
+    const MODE_IS_EXCLUDE: u8 = 2;
+    const CHANGE_TO_INCLUDE: u8 = 3;
+    const CHANGE_TO_EXCLUDE: u8 = 4;
+
+    // RFC 3376 8.1, 8.11 and 8.12
+    const ROBUSTNESS: u8 = 2;
+    const OLDER_QUERIER_TIMEOUT: Duration = Duration::from_secs(2 * 125 + 10);
+    const V1_MAX_RESPONSE: Duration = Duration::from_secs(10);
+
+    pub fn new(sockets: Sockets, timers: Timers) -> Igmp {
+        Igmp {
+            sockets,
+            timers,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn is_member(&self, interface: usize, group: Ipv4Addr) -> bool {
+        group == Igmp::ALL_SYSTEMS || self.sockets.is_member(interface, group)
+    }
+
+    pub fn version(&self, interface: usize, now: Instant) -> IgmpVersion {
+        Igmp::_version(&self.state.lock().unwrap().older, interface, now)
+    }
+
+    fn _version(
+        older: &HashMap<(usize, IgmpVersion), Instant>,
+        interface: usize,
+        now: Instant,
+    ) -> IgmpVersion {
+        [IgmpVersion::V1, IgmpVersion::V2]
+            .into_iter()
+            .find(|&version| {
+                older
+                    .get(&(interface, version))
+                    .is_some_and(|&until| now < until)
+            })
+            .unwrap_or(IgmpVersion::V3)
+    }
+
+    // Handle an IGMP message received on the interface. Nothing is sent back
+    // directly; the reports are sent by `poll` after random delays.
+    pub fn input(&self, interface: usize, buf: &[u8], now: Instant) -> Result<(), ProtocolError> {
+        if buf.len() < 8 {
+            return Err(IgmpError("too short".to_string()).into());
+        }
+        let checksum = get_checksum(buf);
+        if checksum != 0 {
+            return Err(IgmpError(format!("checksum error: checksum={:#x?}", checksum)).into());
+        }
+
+        let group = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+        let mut state = self.state.lock().unwrap();
+        match buf[0] {
+            Igmp::QUERY => {
+                // RFC 3376 7.1: the version is told by the length and the Max
+                // Resp Code
+                let (version, max_response) = match (buf.len(), buf[1]) {
+                    (8, 0) => (IgmpVersion::V1, Igmp::V1_MAX_RESPONSE),
+                    (8, code) => (IgmpVersion::V2, Duration::from_millis(100 * code as u64)),
+                    (12.., code) => (IgmpVersion::V3, Igmp::_max_response(code)),
+                    _ => return Err(IgmpError("invalid query".to_string()).into()),
+                };
+                if version < IgmpVersion::V3 {
+                    let until = now + Igmp::OLDER_QUERIER_TIMEOUT;
+                    state.older.insert((interface, version), until);
+                }
+                debug!("interface {}: {:?} query for {}", interface, version, group);
+                self._answer(&mut state, interface, group, max_response, now);
+            }
+            // Another member reported the group (RFC 2236 3)
+            Igmp::V1_REPORT | Igmp::V2_REPORT => {
+                let version = Igmp::_version(&state.older, interface, now);
+                if let Some(member) = state.groups.get_mut(&(interface, group)) {
+                    if version < IgmpVersion::V3 {
+                        member.due = None;
+                        member.last_reporter = false;
+                    }
+                }
+            }
+            // Sent by other hosts and only for routers
+            Igmp::LEAVE | Igmp::V3_REPORT => {}
+            igmp_type => {
+                return Err(IgmpError(format!("unknown type: type={}", igmp_type)).into());
+            }
+        }
+        Ok(())
+    }
+
+    // RFC 3376 4.1.1
+    fn _max_response(code: u8) -> Duration {
+        let tenths = match code {
+            0..=127 => code as u64,
+            _ => ((code as u64 & 0xf) | 0x10) << (((code >> 4) & 0x7) + 3),
+        };
+        Duration::from_millis(100 * tenths)
+    }
+
+    // Schedule the reports for the groups which the query asks about
+    fn _answer(
+        &self,
+        state: &mut State,
+        interface: usize,
+        group: Ipv4Addr,
+        max_response: Duration,
+        now: Instant,
+    ) {
+        let delay = Igmp::_random_delay(max_response);
+        let mut scheduled = false;
+        for ((i, g), member) in state.groups.iter_mut() {
+            // The group is unspecified in general queries
+            if *i != interface || (!group.is_unspecified() && *g != group) {
+                continue;
+            }
+            // Keep the earlier report
+            if member.due.is_some_and(|due| due <= now + delay) {
+                continue;
+            }
+            member.due = Some(now + delay);
+            if member.retransmits == 0 {
+                member.record = Igmp::MODE_IS_EXCLUDE;
+            }
+            scheduled = true;
+        }
+        if scheduled {
+            self._wake_at(delay);
+        }
+    }
+
+    // Follow the groups joined by the sockets. Returns the groups left.
+    fn _sync(&self, state: &mut State, now: Instant) -> Vec<(usize, Ipv4Addr)> {
+        let joined = self.sockets.memberships();
+        let left: Vec<(usize, Ipv4Addr)> = state
+            .groups
+            .keys()
+            .filter(|key| !joined.contains(key))
+            .copied()
+            .collect();
+        let mut leaves = vec![];
+        for key in left {
+            let member = state.groups.remove(&key).unwrap();
+            // RFC 2236 3: only the last host which reported the group leaves
+            // it in IGMPv2
+            if member.last_reporter || Igmp::_version(&state.older, key.0, now) == IgmpVersion::V3 {
+                leaves.push(key);
+            }
+        }
+        for key in joined {
+            if key.1 == Igmp::ALL_SYSTEMS || state.groups.contains_key(&key) {
+                continue;
+            }
+            // RFC 3376 5.1: report the change at once and repeat it
+            state.groups.insert(
+                key,
+                Group {
+                    due: Some(now),
+                    record: Igmp::CHANGE_TO_EXCLUDE,
+                    retransmits: Igmp::ROBUSTNESS - 1,
+                    last_reporter: false,
+                },
+            );
+        }
+        leaves
+    }
+
+    // Returns the messages to send with the interfaces
+    pub fn poll(
+        &self,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+        now: Instant,
+    ) -> Vec<Datagram> {
+        let mut state = self.state.lock().unwrap();
+        let leaves = self._sync(&mut state, now);
+
+        let mut datagrams = vec![];
+        let mut send = |interface: usize, dst: Ipv4Addr, data: Vec<u8>| {
+            // The source may be unspecified before the interface has an
+            // address (RFC 3376 4.2.13)
+            if let Some((src, _)) = source(dst, Some(interface)) {
+                datagrams.push(Datagram {
+                    src,
+                    dst,
+                    interface: Some(interface),
+                    data,
+                });
+            }
+        };
+        for (interface, group) in leaves {
+            debug!("interface {}: leaving {}", interface, group);
+            match Igmp::_version(&state.older, interface, now) {
+                IgmpVersion::V1 => {}
+                IgmpVersion::V2 => send(
+                    interface,
+                    Igmp::ALL_ROUTERS,
+                    Igmp::message(Igmp::LEAVE, group),
+                ),
+                IgmpVersion::V3 => send(
+                    interface,
+                    Igmp::ALL_IGMPV3_ROUTERS,
+                    Igmp::v3_report(&[(Igmp::CHANGE_TO_INCLUDE, group)]),
+                ),
+            }
+        }
+
+        // The records of IGMPv3 for each interface
+        let mut records: BTreeMap<usize, Vec<(u8, Ipv4Addr)>> = BTreeMap::new();
+        let mut next = None;
+        let State { groups, older } = &mut *state;
+        for (&(interface, group), member) in groups.iter_mut() {
+            let due = match member.due {
+                Some(due) if due <= now => due,
+                Some(due) => {
+                    next = Some(next.map_or(due, |next: Instant| next.min(due)));
+                    continue;
+                }
+                None => continue,
+            };
+            let version = Igmp::_version(older, interface, now);
+            match version {
+                IgmpVersion::V1 => send(interface, group, Igmp::message(Igmp::V1_REPORT, group)),
+                IgmpVersion::V2 => send(interface, group, Igmp::message(Igmp::V2_REPORT, group)),
+                IgmpVersion::V3 => records
+                    .entry(interface)
+                    .or_default()
+                    .push((member.record, group)),
+            }
+            member.last_reporter = true;
+            member.due = None;
+            if member.retransmits > 0 {
+                member.retransmits -= 1;
+                // RFC 3376 8.11 and RFC 2236 8.10: Unsolicited Report Interval
+                let interval = match version {
+                    IgmpVersion::V3 => Duration::from_secs(1),
+                    _ => Duration::from_secs(10),
+                };
+                let delay = Igmp::_random_delay(interval);
+                member.due = Some(due.max(now) + delay);
+                next = Some(next.map_or(now + delay, |next: Instant| next.min(now + delay)));
+            } else {
+                member.record = Igmp::MODE_IS_EXCLUDE;
+            }
+        }
+        for (interface, records) in records {
+            send(
+                interface,
+                Igmp::ALL_IGMPV3_ROUTERS,
+                Igmp::v3_report(&records),
+            );
+        }
+        if let Some(next) = next {
+            self._wake_at(next.saturating_duration_since(now));
+        }
+        datagrams
+    }
+
+    // Let the event loop poll again after the delay
+    fn _wake_at(&self, delay: Duration) {
+        self.timers.schedule(delay, || {});
+    }
+
+    fn _random_delay(max: Duration) -> Duration {
+        let random = RandomState::new().build_hasher().finish();
+        Duration::from_millis(random % (max.as_millis() as u64 + 1))
+    }
+
+    // A message of IGMPv1 or IGMPv2 about the group
+    pub fn message(igmp_type: u8, group: Ipv4Addr) -> Vec<u8> {
+        let mut buf = vec![igmp_type, 0, 0, 0];
+        buf.extend_from_slice(&group.octets());
+        let checksum = get_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    // RFC 3376 4.2: the groups are joined in the exclude mode without sources
+    pub fn v3_report(records: &[(u8, Ipv4Addr)]) -> Vec<u8> {
+        let mut buf = vec![Igmp::V3_REPORT, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        for (record, group) in records {
+            buf.extend_from_slice(&[*record, 0, 0, 0]);
+            buf.extend_from_slice(&group.octets());
+        }
+        let checksum = get_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+}
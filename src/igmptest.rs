@@ -0,0 +1,195 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        time::{Duration, Instant},
+    };
+
+    use nix::errno::Errno;
+
+    use crate::{
+        eventloop::EventLoop,
+        igmp::{Igmp, IgmpVersion},
+        ipv4::{Datagram, IPv4},
+        protocol::{get_checksum, ProtocolError},
+        socket::{SocketType, Sockets},
+        testutil::{self, router, stack},
+        udp::Udp,
+    };
+
+    fn group() -> Ipv4Addr {
+        Ipv4Addr::new(239, 1, 2, 3)
+    }
+
+    fn poll(igmp: &Igmp, now: Instant) -> Vec<Datagram> {
+        igmp.poll(&|_, _| Some((stack(), 1500)), now)
+    }
+
+    // A query of IGMPv3 without sources
+    fn v3_query(group: Ipv4Addr, code: u8) -> Vec<u8> {
+        let mut buf = Igmp::message(0x11, group);
+        buf[1] = code;
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+        buf[2..4].copy_from_slice(&[0, 0]);
+        let checksum = get_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    fn v2_query(group: Ipv4Addr, code: u8) -> Vec<u8> {
+        let mut buf = Igmp::message(0x11, group);
+        buf[1] = code;
+        buf[2..4].copy_from_slice(&[0, 0]);
+        let checksum = get_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn join() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let socket = sockets.socket(SocketType::Datagram).unwrap();
+        assert_eq!(
+            sockets.join_multicast(socket, stack(), 0),
+            Err(Errno::EINVAL)
+        );
+        sockets.join_multicast(socket, group(), 0).unwrap();
+        assert_eq!(
+            sockets.join_multicast(socket, group(), 0),
+            Err(Errno::EADDRINUSE)
+        );
+        assert_eq!(
+            sockets.leave_multicast(socket, group(), 1),
+            Err(Errno::EADDRNOTAVAIL)
+        );
+        let stream = sockets.socket(SocketType::Stream).unwrap();
+        assert_eq!(
+            sockets.join_multicast(stream, group(), 0),
+            Err(Errno::ENOPROTOOPT)
+        );
+        assert_eq!(sockets.memberships(), vec![(0, group())]);
+
+        // Reported at once and again within Unsolicited Report Interval
+        let igmp = Igmp::new(sockets.clone(), event_loop.timers());
+        let now = Instant::now();
+        let datagrams = poll(&igmp, now);
+        assert_eq!(datagrams.len(), 1);
+        let report = &datagrams[0];
+        assert_eq!(report.dst, Ipv4Addr::new(224, 0, 0, 22));
+        assert_eq!(report.interface, Some(0));
+        assert_eq!(report.data, Igmp::v3_report(&[(4, group())]));
+        assert!(poll(&igmp, now).is_empty());
+        assert_eq!(poll(&igmp, now + Duration::from_secs(1)).len(), 1);
+        assert!(poll(&igmp, now + Duration::from_secs(2)).is_empty());
+
+        // Closing the socket leaves the group
+        sockets.close(socket).unwrap();
+        let datagrams = poll(&igmp, now + Duration::from_secs(2));
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].data, Igmp::v3_report(&[(3, group())]));
+    }
+
+    #[test]
+    fn query() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let socket = sockets.socket(SocketType::Datagram).unwrap();
+        sockets.join_multicast(socket, group(), 0).unwrap();
+        let other = Ipv4Addr::new(239, 1, 2, 4);
+        sockets.join_multicast(socket, other, 1).unwrap();
+        let igmp = Igmp::new(sockets.clone(), event_loop.timers());
+        let now = Instant::now();
+        poll(&igmp, now);
+        poll(&igmp, now + Duration::from_secs(1));
+
+        // A general query with Max Resp Time of 1 second
+        let now = now + Duration::from_secs(2);
+        assert_eq!(
+            igmp.input(0, &v3_query(Ipv4Addr::UNSPECIFIED, 10), now),
+            Ok(())
+        );
+        let datagrams = poll(&igmp, now + Duration::from_secs(1));
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].data, Igmp::v3_report(&[(2, group())]));
+
+        // IGMPv2 queriers are answered in IGMPv2
+        assert_eq!(igmp.input(1, &v2_query(other, 10), now), Ok(()));
+        assert_eq!(igmp.version(1, now), IgmpVersion::V2);
+        assert_eq!(igmp.version(0, now), IgmpVersion::V3);
+        let datagrams = poll(&igmp, now + Duration::from_secs(1));
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].dst, other);
+        assert_eq!(datagrams[0].data, Igmp::message(0x16, other));
+
+        // A report of another member suppresses ours
+        let now = now + Duration::from_secs(2);
+        igmp.input(1, &v2_query(Ipv4Addr::UNSPECIFIED, 10), now)
+            .unwrap();
+        igmp.input(1, &Igmp::message(0x16, other), now).unwrap();
+        assert!(poll(&igmp, now + Duration::from_secs(1)).is_empty());
+
+        // Only the last reporter leaves the group
+        sockets.leave_multicast(socket, other, 1).unwrap();
+        assert!(poll(&igmp, now + Duration::from_secs(1)).is_empty());
+
+        // IGMPv3 again after Older Version Querier Present Timeout
+        assert_eq!(
+            igmp.version(1, now + Duration::from_secs(260)),
+            IgmpVersion::V3
+        );
+
+        let mut corrupted = v3_query(Ipv4Addr::UNSPECIFIED, 10);
+        corrupted[4] ^= 1;
+        assert!(matches!(
+            igmp.input(0, &corrupted, now),
+            Err(ProtocolError::Igmp(_))
+        ));
+    }
+
+    #[test]
+    fn receive() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let ipv4 = IPv4::new(vec![Box::new(Udp::new(sockets.clone()))])
+            .with_igmp(Igmp::new(sockets.clone(), event_loop.timers()));
+        let router = router(ipv4);
+
+        let host = SocketAddrV4::new(testutil::host(), 5000);
+        let dst = SocketAddrV4::new(group(), 5000);
+        let data = Udp::datagram(host, dst, b"hello");
+        let datagram = IPv4::new(vec![]).datagram(*host.ip(), group(), Udp::NUMBER, &data);
+        assert_eq!(datagram[8], 1);
+
+        // Not joined yet, and never forwarded
+        assert_eq!(router.input(0, &datagram), Err(ProtocolError::General));
+        assert_eq!(router.ipv4_stats().in_discards.get(), 1);
+        assert_eq!(router.ipv4_stats().in_forw_datagrams.get(), 0);
+
+        let socket = sockets.socket(SocketType::Datagram).unwrap();
+        sockets
+            .bind(socket, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 5000))
+            .unwrap();
+        sockets.join_multicast(socket, group(), 0).unwrap();
+        assert_eq!(router.input(1, &datagram), Err(ProtocolError::General));
+        assert_eq!(router.ipv4_stats().in_discards.get(), 2);
+
+        assert_eq!(router.input(0, &datagram), Err(ProtocolError::General));
+        assert_eq!(router.ipv4_stats().in_mcast_pkts.get(), 1);
+        let mut buf = [0u8; 16];
+        assert_eq!(sockets.recv_from(socket, &mut buf), Ok((5, host)));
+
+        // Reports are sent with TTL 1 and Router Alert
+        let datagrams = router.poll();
+        assert_eq!(datagrams.len(), 1);
+        let (index, report) = &datagrams[0];
+        assert_eq!(*index, 0);
+        assert_eq!(report[0], 0x46);
+        assert_eq!(report[8], 1);
+        assert_eq!(&report[16..20], &[224, 0, 0, 22]);
+        assert_eq!(&report[20..24], &Igmp::ROUTER_ALERT);
+        assert_eq!(get_checksum(&report[..24]), 0);
+        assert_eq!(get_checksum(&report[24..]), 0);
+        assert_eq!(router.ipv4_stats().out_mcast_pkts.get(), 1);
+    }
+}
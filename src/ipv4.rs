@@ -1,6 +1,22 @@
-use std::fmt;
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use crate::protocol::{get_checksum, Protocol, ProtocolError};
+use log::debug;
+
+use crate::{
+    icmp::Icmp,
+    igmp::Igmp,
+    protocol::{get_checksum, update_checksum, Protocol, ProtocolError},
+    raw::Raw,
+    stats::{IPv4Stats, IcmpStats},
+};
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct IPv4Error(pub String);
@@ -11,26 +27,324 @@ impl fmt::Display for IPv4Error {
     }
 }
 
-pub trait IPv4Protocol: Protocol {
+// Data waiting to be sent in a datagram
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    // The interface to send the datagram from instead of the route
+    pub interface: Option<usize>,
+    pub data: Vec<u8>,
+}
+
+pub trait IPv4Protocol: Send + Sync {
     fn number(&self) -> u8;
+
+    // Handle the data of a datagram addressed to this host and return the
+    // data to send back
+    fn input(&self, src: Ipv4Addr, dst: Ipv4Addr, buf: &[u8]) -> Result<Vec<u8>, ProtocolError>;
+
+    // Collect the data waiting to be sent. `source` selects the source address
+    // for a destination, or for an interface if one is given, with the MTU of
+    // the interface.
+    fn poll(
+        &self,
+        _source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<Datagram> {
+        vec![]
+    }
 }
 
 // RFC 791
 pub struct IPv4 {
     protocols: Vec<Box<dyn IPv4Protocol>>,
+    raw: Option<Raw>,
+    igmp: Option<Igmp>,
+    id: AtomicU16,
+    stats: Arc<IPv4Stats>,
+    // ICMP is counted here as the messages pass between IP and ICMP
+    icmp_stats: Arc<IcmpStats>,
 }
 
 impl IPv4 {
     pub fn new(protocols: Vec<Box<dyn IPv4Protocol>>) -> IPv4 {
-        IPv4 { protocols }
+        IPv4 {
+            protocols,
+            raw: None,
+            igmp: None,
+            id: AtomicU16::new(0),
+            stats: Arc::default(),
+            icmp_stats: Arc::default(),
+        }
+    }
+
+    // Deliver a copy of each datagram to raw sockets as well
+    pub fn with_raw(protocols: Vec<Box<dyn IPv4Protocol>>, raw: Raw) -> IPv4 {
+        IPv4 {
+            raw: Some(raw),
+            ..IPv4::new(protocols)
+        }
+    }
+
+    // Join multicast groups and answer the queries of the routers
+    pub fn with_igmp(self, igmp: Igmp) -> IPv4 {
+        IPv4 {
+            igmp: Some(igmp),
+            ..self
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<IPv4Stats> {
+        &self.stats
+    }
+
+    pub fn icmp_stats(&self) -> &Arc<IcmpStats> {
+        &self.icmp_stats
+    }
+
+    // Returns the datagrams to send with the interfaces if the senders chose
+    // them
+    pub fn poll(
+        &self,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<(Option<usize>, Vec<u8>)> {
+        let mut datagrams = vec![];
+        for p in &self.protocols {
+            let number = p.number();
+            for d in p.poll(source) {
+                datagrams.push((d.interface, self.datagram(d.src, d.dst, number, &d.data)));
+            }
+        }
+        if let Some(raw) = &self.raw {
+            datagrams.extend(raw.poll(self, source));
+        }
+        if let Some(igmp) = &self.igmp {
+            // RFC 3376 4: with TTL 1 and Router Alert
+            for d in igmp.poll(source, Instant::now()) {
+                let buf =
+                    self._datagram(d.src, d.dst, Igmp::NUMBER, 1, &Igmp::ROUTER_ALERT, &d.data);
+                datagrams.push((d.interface, buf));
+            }
+        }
+        datagrams
+    }
+
+    // Whether datagrams sent to the group are received on the interface
+    pub fn is_member(&self, interface: usize, group: Ipv4Addr) -> bool {
+        match &self.igmp {
+            Some(igmp) => igmp.is_member(interface, group),
+            None => group == Igmp::ALL_SYSTEMS,
+        }
+    }
+
+    // Deliver a datagram sent to a group joined on the interface. Nothing is
+    // sent back.
+    pub fn receive_multicast(&self, interface: usize, buf: &[u8]) -> Result<(), ProtocolError> {
+        self.stats.in_mcast_pkts.increment();
+        let igmp = match &self.igmp {
+            Some(igmp) if buf[9] == Igmp::NUMBER => igmp,
+            _ => return self.reply(buf).map(|_| ()),
+        };
+        self._verify_no_fragment(buf)?;
+        if let Some(raw) = &self.raw {
+            raw.input(buf);
+        }
+        self.stats.in_delivers.increment();
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        igmp.input(interface, &buf[ihl..], Instant::now())
+            .inspect_err(|e| debug!("interface {}: dropped: {}", interface, e))
     }
 }
 
 impl IPv4 {
     const MIN_HEADER_SIZE: usize = 20;
+    const DEFAULT_TTL: u8 = 64;
+
+    // Verify a datagram which is not necessarily addressed to this host
+    pub fn verify(&self, buf: &[u8]) -> Result<usize, ProtocolError> {
+        self.stats.in_receives.increment();
+        self._verify_length(buf)?;
+        self._verify_version(buf)?;
+
+        // Internet Header Length (IHL)
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        self._verify_ihl(buf, ihl)?;
+
+        self._verify_total_length(buf)?;
+        self._verify_header_checksum(&buf[..ihl])?;
+        Ok(ihl)
+    }
+
+    pub fn datagram(&self, src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, data: &[u8]) -> Vec<u8> {
+        // RFC 1112 6.1: multicasts stay on the link unless asked otherwise
+        let ttl = match dst.is_multicast() {
+            true => 1,
+            false => IPv4::DEFAULT_TTL,
+        };
+        self._datagram(src, dst, protocol, ttl, &[], data)
+    }
+
+    // The options are padded to a multiple of 4 octets by the caller
+    fn _datagram(
+        &self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        protocol: u8,
+        ttl: u8,
+        options: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let ihl = IPv4::MIN_HEADER_SIZE + options.len();
+        let total_length = (ihl + data.len()) as u16;
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        self._count_output(protocol, data);
+        if dst.is_multicast() {
+            self.stats.out_mcast_pkts.increment();
+        }
+
+        let mut buf = vec![0x40 | (ihl / 4) as u8, 0x00];
+        buf.extend_from_slice(&total_length.to_be_bytes());
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&[0x00, 0x00, ttl, protocol, 0x00, 0x00]);
+        buf.extend_from_slice(&src.octets());
+        buf.extend_from_slice(&dst.octets());
+        buf.extend_from_slice(options);
+        IPv4::_set_header_checksum(&mut buf);
+
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    // Fill in the header fields of a datagram built by a raw socket with
+    // IP_HDRINCL in the same way as Linux
+    pub fn complete_header(&self, buf: &mut [u8], src: Ipv4Addr) {
+        let total_length = buf.len() as u16;
+        buf[2..4].copy_from_slice(&total_length.to_be_bytes());
+
+        // Identification
+        if buf[4] == 0 && buf[5] == 0 {
+            let id = self.id.fetch_add(1, Ordering::Relaxed);
+            buf[4..6].copy_from_slice(&id.to_be_bytes());
+        }
+
+        // Source Address
+        if buf[12..16] == [0, 0, 0, 0] {
+            buf[12..16].copy_from_slice(&src.octets());
+        }
+        IPv4::_set_header_checksum(buf);
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        self._count_output(buf[9], buf.get(ihl..).unwrap_or_default());
+    }
+
+    fn _count_output(&self, protocol: u8, data: &[u8]) {
+        self.stats.out_requests.increment();
+        if protocol == Icmp::NUMBER {
+            self.icmp_stats.output(data);
+        }
+    }
+
+    // The pseudo header for the checksums of the upper-layer protocols
+    pub fn pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&src.octets());
+        buf.extend_from_slice(&dst.octets());
+        buf.extend_from_slice(&[0x00, protocol]);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+        buf
+    }
+
+    pub fn decrement_ttl(buf: &mut [u8]) {
+        // Time to Live and Protocol share a 16-bit word
+        let old = u16::from_be_bytes([buf[8], buf[9]]);
+        buf[8] -= 1;
+        let new = u16::from_be_bytes([buf[8], buf[9]]);
+
+        let checksum = u16::from_be_bytes([buf[10], buf[11]]);
+        let checksum = update_checksum(checksum, old, new);
+        buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    // Split a datagram into fragments which fit in the MTU
+    pub fn fragment(buf: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        let data = &buf[ihl..];
+
+        // Flags (More Fragments)
+        let more_fragments = buf[6] & 0x20 != 0;
+        // Fragment Offset
+        let offset = (((buf[6] & 0x1f) as usize) << 8) | buf[7] as usize;
+
+        let mut fragments = vec![];
+        let mut pos = 0;
+        while pos < data.len() {
+            // Only the first fragment carries all the options
+            let mut fragment = if pos == 0 {
+                buf[..ihl].to_vec()
+            } else {
+                IPv4::_copied_header(&buf[..ihl])
+            };
+            let size = ((mtu - fragment.len()) & !7).min(data.len() - pos);
+            fragment.extend_from_slice(&data[pos..pos + size]);
+            pos += size;
+
+            let total_length = fragment.len() as u16;
+            fragment[2..4].copy_from_slice(&total_length.to_be_bytes());
+
+            let mut flags_offset = (offset + (pos - size) / 8) as u16;
+            if pos < data.len() || more_fragments {
+                flags_offset |= 0x2000;
+            }
+            fragment[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+
+            IPv4::_set_header_checksum(&mut fragment);
+            fragments.push(fragment);
+        }
+        fragments
+    }
+
+    // The header with the options whose copied flag is set
+    fn _copied_header(header: &[u8]) -> Vec<u8> {
+        let mut buf = header[..IPv4::MIN_HEADER_SIZE].to_vec();
+        let mut i = IPv4::MIN_HEADER_SIZE;
+        while i < header.len() {
+            match header[i] {
+                // End of Option List
+                0 => break,
+                // No Operation
+                1 => i += 1,
+                option => {
+                    let length = header.get(i + 1).copied().unwrap_or(0) as usize;
+                    if length < 2 || i + length > header.len() {
+                        break;
+                    }
+                    if option & 0x80 != 0 {
+                        buf.extend_from_slice(&header[i..i + length]);
+                    }
+                    i += length;
+                }
+            }
+        }
+        // Pad the options with End of Option List
+        while !buf.len().is_multiple_of(4) {
+            buf.push(0);
+        }
+        buf[0] = 0x40 | (buf.len() / 4) as u8;
+        buf
+    }
+
+    fn _set_header_checksum(buf: &mut [u8]) {
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+
+        // Set the checksum field to zero before computing a checksum
+        buf[10] = 0;
+        buf[11] = 0;
+
+        let checksum = get_checksum(&buf[..ihl]);
+        buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+    }
 
     fn _verify_length(&self, buf: &[u8]) -> Result<(), ProtocolError> {
         if buf.len() < IPv4::MIN_HEADER_SIZE {
+            self.stats.in_hdr_errors.increment();
             return Err(IPv4Error("too short".to_string()).into());
         }
         Ok(())
@@ -39,13 +353,15 @@ impl IPv4 {
     fn _verify_version(&self, buf: &[u8]) -> Result<(), ProtocolError> {
         let version = buf[0] >> 4;
         if version != 4 {
+            self.stats.in_hdr_errors.increment();
             return Err(IPv4Error(format!("ip version error: version={}", version)).into());
         }
         Ok(())
     }
 
     fn _verify_ihl(&self, buf: &[u8], ihl: usize) -> Result<(), ProtocolError> {
-        if buf.len() < ihl {
+        if ihl < IPv4::MIN_HEADER_SIZE || buf.len() < ihl {
+            self.stats.in_hdr_errors.increment();
             return Err(IPv4Error(format!(
                 "header length error: ihl={}, len={}",
                 ihl,
@@ -59,6 +375,11 @@ impl IPv4 {
     fn _verify_total_length(&self, buf: &[u8]) -> Result<(), ProtocolError> {
         let total_length = ((buf[2] as usize) << 8) | buf[3] as usize;
         if buf.len() != total_length {
+            if buf.len() < total_length {
+                self.stats.in_truncated_pkts.increment();
+            } else {
+                self.stats.in_hdr_errors.increment();
+            }
             return Err(IPv4Error(format!(
                 "total length error: total length={}, len={}",
                 total_length,
@@ -73,14 +394,18 @@ impl IPv4 {
         // Flags (More Fragments)
         // Fragment Offset
         if buf[6] & 0x20 != 0 || buf[6] & 0x1f != 0 || buf[7] != 0 {
+            // Reassembly is not implemented
+            self.stats.reasm_reqds.increment();
+            self.stats.reasm_fails.increment();
             return Err(IPv4Error("fragments unsupported".to_string()).into());
         }
         Ok(())
     }
 
-    fn _verify_header_checksum(&self, header: &Vec<u8>) -> Result<(), ProtocolError> {
+    fn _verify_header_checksum(&self, header: &[u8]) -> Result<(), ProtocolError> {
         let checksum = get_checksum(header);
         if checksum != 0 {
+            self.stats.in_hdr_errors.increment();
             return Err(IPv4Error(format!(
                 "header checksum error: header checksum={:#x?}",
                 checksum
@@ -106,22 +431,53 @@ impl Protocol for IPv4 {
         let header = &mut buf[..ihl].to_vec();
         self._verify_header_checksum(header)?;
 
+        if let Some(raw) = &self.raw {
+            raw.input(buf);
+        }
+
         let protocol = buf[9];
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
         let data = &buf[ihl..];
         for p in &self.protocols {
             if protocol != p.number() {
                 continue;
             }
 
-            if let Ok(mut data) = p.reply(data) {
-                // Swap the source IP address for the destination IP address
-                for i in 12..16 {
-                    header.swap(i, i + 4);
+            self.stats.in_delivers.increment();
+            if protocol == Icmp::NUMBER {
+                self.icmp_stats.input(data);
+            }
+            let mut data = match p.input(src, dst, data) {
+                Ok(data) => data,
+                Err(ProtocolError::General) => return Err(ProtocolError::General),
+                Err(e) => {
+                    if let ProtocolError::Icmp(_) = e {
+                        self.icmp_stats.in_errors.increment();
+                    }
+                    debug!("{} > {}: dropped: {}", src, dst, e);
+                    return Err(e);
                 }
-                let mut buf = header.to_vec();
-                buf.append(&mut data);
-                return Ok(buf);
+            };
+            self._count_output(protocol, &data);
+            // Swap the source IP address for the destination IP address
+            for i in 12..16 {
+                header.swap(i, i + 4);
             }
+            let mut buf = header.to_vec();
+            buf.append(&mut data);
+
+            // The reply may differ in length
+            let total_length = buf.len() as u16;
+            buf[2..4].copy_from_slice(&total_length.to_be_bytes());
+            IPv4::_set_header_checksum(&mut buf);
+            return Ok(buf);
+        }
+        if self.raw.is_some() {
+            self.stats.in_delivers.increment();
+        } else {
+            self.stats.in_unknown_protos.increment();
+            debug!("{} > {}: dropped: unknown protocol {}", src, dst, protocol);
         }
         Err(ProtocolError::General)
     }
@@ -1,23 +1,48 @@
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
     use crate::{
         ipv4::{IPv4, IPv4Error, IPv4Protocol},
         protocol::{Protocol, ProtocolError},
+        udp::UdpError,
     };
 
     struct TestProtocol {}
 
-    impl Protocol for TestProtocol {
-        fn reply(&self, buf: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    impl IPv4Protocol for TestProtocol {
+        fn number(&self) -> u8 {
+            // RFC 3692
+            // 0xfd
+            253
+        }
+
+        fn input(
+            &self,
+            _src: Ipv4Addr,
+            _dst: Ipv4Addr,
+            buf: &[u8],
+        ) -> Result<Vec<u8>, ProtocolError> {
             Ok(buf.to_vec())
         }
     }
 
-    impl IPv4Protocol for TestProtocol {
+    struct DroppingProtocol {}
+
+    impl IPv4Protocol for DroppingProtocol {
         fn number(&self) -> u8 {
             // RFC 3692
-            // 0xfd
-            253
+            // 0xfe
+            254
+        }
+
+        fn input(
+            &self,
+            _src: Ipv4Addr,
+            _dst: Ipv4Addr,
+            _buf: &[u8],
+        ) -> Result<Vec<u8>, ProtocolError> {
+            Err(UdpError("checksum error".to_string()).into())
         }
     }
 
@@ -238,4 +263,16 @@ mod tests {
             Err(IPv4Error("header checksum error: header checksum=0x1".to_string()).into()),
         );
     }
+
+    #[test]
+    fn dropped() {
+        let ipv4 = IPv4::new(vec![Box::new(DroppingProtocol {})]);
+        let src = Ipv4Addr::new(192, 0, 2, 1);
+        let dst = Ipv4Addr::new(192, 0, 2, 2);
+        let buf = ipv4.datagram(src, dst, 254, &[0x00]);
+        let error = ipv4.reply(&buf).unwrap_err();
+        assert_eq!(error, UdpError("checksum error".to_string()).into());
+        assert_eq!(error.to_string(), "udp: checksum error");
+        assert_eq!(ProtocolError::General.to_string(), "no reply");
+    }
 }
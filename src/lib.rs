@@ -0,0 +1,63 @@
+pub mod arp;
+#[cfg(feature = "tokio")]
+pub mod asyncio;
+#[cfg(feature = "tokio")]
+mod asynciotest;
+pub mod config;
+mod configtest;
+pub mod conntrack;
+mod conntracktest;
+pub mod control;
+mod controltest;
+pub mod device;
+pub mod dhcp;
+pub mod dhcpclient;
+pub mod dhcpserver;
+mod dhcpservertest;
+mod dhcptest;
+pub mod dissect;
+mod dissecttest;
+pub mod dns;
+pub mod dnsserver;
+mod dnsservertest;
+mod dnstest;
+pub mod ethernet;
+mod ethernettest;
+pub mod eventloop;
+pub mod firewall;
+mod firewalltest;
+pub mod icmp;
+mod icmptest;
+pub mod igmp;
+mod igmptest;
+pub mod ipv4;
+mod ipv4test;
+pub mod metrics;
+mod metricstest;
+pub mod pcapng;
+mod pcapngtest;
+pub mod ping;
+mod pingtest;
+pub mod protocol;
+pub mod raw;
+mod rawtest;
+pub mod resolver;
+mod resolvertest;
+pub mod route;
+pub mod router;
+mod routertest;
+mod routetest;
+pub mod services;
+mod servicestest;
+pub mod socket;
+pub mod stats;
+mod statstest;
+pub mod tcp;
+mod tcptest;
+#[cfg(test)]
+mod testutil;
+pub mod timer;
+mod timertest;
+pub mod tuntap;
+pub mod udp;
+mod udptest;
@@ -1,30 +1,509 @@
-use ipv4::IPv4;
-use tuntap::{TunTap, TunTapFlag};
+use std::{
+    env, fmt,
+    net::{Ipv4Addr, TcpListener},
+    path::PathBuf,
+    process,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::{icmp::Icmp, protocol::Protocol};
+use clap::{value_parser, ArgAction, Args, Parser, Subcommand};
+use env_logger::Env;
+use log::{info, warn};
 
-mod icmp;
-mod icmptest;
-mod ipv4;
-mod ipv4test;
-mod protocol;
-mod tuntap;
+use pareiodon::{
+    config::{Address, Config, ConfigError, DeviceType, MetricsConfig, ProtocolName},
+    control::Control,
+    device::Device,
+    dhcpclient::DhcpClient,
+    dhcpserver::DhcpServer,
+    dissect::{Dissector, Verbosity},
+    dnsserver::{DnsServer, Zone},
+    ethernet::{Ethernet, MacAddress},
+    eventloop::EventLoop,
+    icmp::Icmp,
+    igmp::Igmp,
+    ipv4::{IPv4, IPv4Protocol},
+    metrics::Exporter,
+    pcapng::{self, Capture, Direction},
+    ping::Ping,
+    raw::Raw,
+    resolver::Resolver,
+    route::{Prefix, Route, RouteError, RoutingTable},
+    router::{Interface, Router},
+    services::Services,
+    socket::Sockets,
+    stats::Stats,
+    tcp::Tcp,
+    tuntap::{TunTap, TunTapFlag},
+    udp::Udp,
+};
 
-fn main() {
-    let tun = TunTap::new(TunTapFlag::Tun).unwrap();
-    let icmp = Box::new(Icmp::new());
-    let ipv4 = IPv4::new(vec![icmp]);
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Parser)]
+#[command(
+    name = "pareiodon",
+    version,
+    about = "A TCP/IP stack on TUN/TAP devices"
+)]
+struct Cli {
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Count,
+        global = true,
+        help = "Print the datagrams received, with the fields for -vv"
+    )]
+    verbose: u8,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Run the stack described by the configuration file (default)")]
+    Run(RunArgs),
+    #[command(about = "Send echo requests from the stack on a device")]
+    Ping(PingArgs),
+    #[command(about = "Print or record the packets on a device without answering them")]
+    Capture(CaptureArgs),
+}
 
-    loop {
-        let mut buf = [0u8; 65535];
-        let n = tun.read(&mut buf).unwrap();
-        let buf = &mut buf[0..n];
+#[derive(Args)]
+struct RunArgs {
+    #[arg(short, long, default_value = Config::DEFAULT_PATH, help = "The configuration file")]
+    config: PathBuf,
+    #[arg(
+        long,
+        help = "Attach to the persistent devices without configuring them"
+    )]
+    no_configure: bool,
+}
+
+// A single device for ping and capture
+#[derive(Args)]
+struct DeviceArgs {
+    #[arg(short, long, default_value = "tun0", help = "The name of the device")]
+    device: String,
+    #[arg(long, help = "Use a TAP device instead of a TUN device")]
+    tap: bool,
+    #[arg(
+        long,
+        default_value = "192.0.2.1/24",
+        value_parser = parse_prefix,
+        help = "The address of the host side"
+    )]
+    host: Prefix,
+    #[arg(long, help = "Attach to a persistent device without configuring it")]
+    no_configure: bool,
+}
 
-        // Debug
-        println!("{:x?}", buf);
+impl DeviceArgs {
+    fn open(&self) -> TunTap {
+        let flag = match self.tap {
+            true => TunTapFlag::Tap,
+            false => TunTapFlag::Tun,
+        };
+        let tuntap = match self.no_configure {
+            true => TunTap::attach(flag, &self.device),
+            false => TunTap::new(flag, &self.device, self.host.address, self.host.netmask),
+        };
+        tuntap.unwrap_or_else(|e| exit(format!("{}: {}", self.device, e)))
+    }
+}
+
+#[derive(Args)]
+struct PingArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+    #[arg(
+        short,
+        long,
+        default_value = "192.0.2.2/24",
+        value_parser = parse_prefix,
+        help = "The address of the stack"
+    )]
+    address: Prefix,
+    #[arg(
+        short,
+        long,
+        default_value_t = 4,
+        value_parser = value_parser!(u16).range(1..),
+        help = "The number of requests"
+    )]
+    count: u16,
+    #[arg(short, long, default_value_t = Ping::DEFAULT_SIZE, help = "The octets of data in a request")]
+    size: usize,
+    #[arg(help = "The address to send the requests to")]
+    destination: Ipv4Addr,
+}
+
+#[derive(Args)]
+struct CaptureArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+    #[arg(short, long, help = "Stop after the number of packets")]
+    count: Option<usize>,
+    #[arg(short, long, help = "Write the packets to a pcapng file or a FIFO")]
+    write: Option<PathBuf>,
+}
+
+fn parse_prefix(s: &str) -> Result<Prefix, String> {
+    s.parse().map_err(|e: RouteError| e.0)
+}
+
+fn send(devices: &[Device], router: &Router, index: usize, buf: &[u8]) {
+    let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+    let next_hop = router.next_hop(dst);
+    // A device which fails, e.g. while its link is down, does not stop the
+    // others
+    if let Err(e) = devices[index].send(next_hop, &router.interface(index), buf) {
+        warn!("device {}: send failed: {}", index, e);
+    }
+}
 
-        if let Ok(mut buf) = ipv4.reply(buf) {
-            tun.write(&mut buf).unwrap();
+fn exit(message: impl fmt::Display) -> ! {
+    eprintln!("pareiodon: {}", message);
+    process::exit(1);
+}
+
+// RUST_LOG=pareiodon::tcp=debug shows the state transitions of TCP
+fn init_logging(level: &str) {
+    env_logger::Builder::from_env(Env::default().default_filter_or(level)).init();
+}
+
+// -v prints a line for each datagram received and -vv prints the fields
+fn verbosity(verbose: u8) -> Verbosity {
+    match verbose {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Summary,
+        _ => Verbosity::Tree,
+    }
+}
+
+// Pass the datagrams between the devices and the stack until the process
+// exits
+fn serve(event_loop: &EventLoop, devices: &[Device], router: &Router, verbosity: Verbosity) {
+    let dissector = Dissector::new();
+    for (i, device) in devices.iter().enumerate() {
+        event_loop.register(device.fd(), i).unwrap();
+    }
+
+    event_loop
+        .run(
+            |i| {
+                let buf = match devices[i].receive(&router.interface(i)) {
+                    Ok(Some(buf)) => buf,
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!("device {}: receive failed: {}", i, e);
+                        return;
+                    }
+                };
+
+                if let Some(s) = dissector.format(&buf, verbosity) {
+                    println!("{}", s);
+                }
+
+                if let Ok(datagrams) = router.input(i, &buf) {
+                    for (j, buf) in datagrams {
+                        send(devices, router, j, &buf);
+                    }
+                }
+            },
+            || {
+                for (j, buf) in router.poll() {
+                    send(devices, router, j, &buf);
+                }
+            },
+        )
+        .unwrap();
+}
+
+fn run(args: RunArgs, verbosity: Verbosity) {
+    let mut config = Config::load(&args.config).unwrap_or_else(|e| exit(e));
+    if args.no_configure {
+        for device in &mut config.devices {
+            device.configure = false;
         }
     }
+    init_logging(&config.logging.level);
+
+    let mut devices: Vec<Device> = config
+        .devices
+        .iter()
+        .map(|device| {
+            let flag = match device.device_type {
+                DeviceType::Tun => TunTapFlag::Tun,
+                DeviceType::Tap => TunTapFlag::Tap,
+            };
+            let (address, netmask) = device
+                .host
+                .map_or((Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED), |host| {
+                    (host.address, host.netmask)
+                });
+            let tuntap = match device.configure {
+                true => TunTap::new(flag, &device.name, address, netmask),
+                false => TunTap::attach(flag, &device.name),
+            };
+            let tuntap = tuntap.unwrap_or_else(|e| exit(format!("{}: {}", device.name, e)));
+            match device.device_type {
+                DeviceType::Tun => Device::tun(tuntap),
+                DeviceType::Tap => {
+                    Device::tap(tuntap, device.mac.unwrap_or_else(MacAddress::random))
+                }
+            }
+        })
+        .collect();
+    let names: Vec<String> = config
+        .devices
+        .iter()
+        .map(|device| device.name.clone())
+        .collect();
+    // Capture the packets into a file or a FIFO
+    let capture = env::var_os("PAREIODON_CAPTURE")
+        .map(PathBuf::from)
+        .or_else(|| config.capture.as_ref().map(|capture| capture.path.clone()));
+    if let Some(path) = capture {
+        let capture = Arc::new(
+            Capture::create(&path).unwrap_or_else(|e| exit(format!("{}: {}", path.display(), e))),
+        );
+        info!("capturing the packets to {}", path.display());
+        for (device, name) in devices.iter_mut().zip(&names) {
+            device.set_capture(capture.clone(), name);
+        }
+    }
+
+    let event_loop = EventLoop::new().unwrap();
+    let sockets = Sockets::new(event_loop.waker());
+
+    let mut protocols: Vec<Box<dyn IPv4Protocol>> = vec![];
+    if config.has_protocol(ProtocolName::Icmp) {
+        protocols.push(Box::new(Icmp::new()));
+    }
+    if config.has_protocol(ProtocolName::Udp) {
+        protocols.push(Box::new(Udp::new(sockets.clone())));
+    }
+    if config.has_protocol(ProtocolName::Tcp) {
+        protocols.push(Box::new(Tcp::new(sockets.clone(), event_loop.timers())));
+    }
+    let mut ipv4 = match config.has_protocol(ProtocolName::Raw) {
+        true => IPv4::with_raw(protocols, Raw::new(sockets.clone())),
+        false => IPv4::new(protocols),
+    };
+    if config.has_protocol(ProtocolName::Igmp) {
+        ipv4 = ipv4.with_igmp(Igmp::new(sockets.clone(), event_loop.timers()));
+    }
+    let router = Arc::new(Router::new(
+        ipv4,
+        config.interfaces(),
+        config.routing_table(),
+    ));
+    for rule in &config.firewall.rules {
+        router.firewall().add(rule.clone());
+    }
+
+    let mut dhcp_clients = vec![];
+    for (i, device) in config.devices.iter().enumerate() {
+        if device.address == Some(Address::Dhcp) {
+            let dhcp = DhcpClient::new(
+                sockets.clone(),
+                router.clone(),
+                i,
+                devices[i].mac().unwrap(),
+            );
+            dhcp_clients.push(dhcp.clone());
+            thread::spawn(move || dhcp.run().unwrap());
+        }
+    }
+
+    // The nameservers in the file, or else those of the first DHCP lease
+    let resolver = match &config.resolver {
+        Some(resolver) => Some(Resolver::new(sockets.clone(), resolver.nameservers.clone())),
+        None => dhcp_clients
+            .into_iter()
+            .next()
+            .map(|dhcp| Resolver::from_dhcp(sockets.clone(), dhcp)),
+    };
+
+    if let Some(dhcp) = config.dhcp_server() {
+        let dhcp = DhcpServer::new(sockets.clone(), router.clone(), dhcp)
+            .unwrap_or_else(|e| exit(format!("DHCP server: {}", e)));
+        thread::spawn(move || dhcp.run().unwrap());
+    }
+
+    if let Some(dns) = &config.services.dns {
+        let zone = Zone::load(&dns.zone).unwrap_or_else(|e| exit(e));
+        let dns = DnsServer::new(sockets.clone(), zone);
+        let dns_tcp = dns.clone();
+        thread::spawn(move || dns.run_udp().unwrap());
+        thread::spawn(move || dns_tcp.run_tcp().unwrap());
+    }
+
+    // Export the statistics on a loopback socket of the host, e.g.
+    // 127.0.0.1:9464
+    let metrics = match env::var("PAREIODON_METRICS") {
+        Ok(addr) => Some(
+            addr.parse()
+                .map_err(|_| ConfigError(format!("metrics: invalid address: {}", addr)))
+                .and_then(MetricsConfig::new)
+                .unwrap_or_else(|e| exit(e)),
+        ),
+        Err(_) => config.metrics.clone(),
+    };
+    if let Some(MetricsConfig { listen: addr }) = metrics {
+        let listener = TcpListener::bind(addr).unwrap_or_else(|e| exit(format!("{}: {}", addr, e)));
+        let devices = names
+            .iter()
+            .zip(&devices)
+            .map(|(name, device)| (name.clone(), device.stats().clone()))
+            .collect();
+        let stats = Stats::new(&router, &sockets);
+        let exporter = Exporter::new(stats, sockets.clone(), devices);
+        info!("serving the metrics on http://{}/metrics", addr);
+        thread::spawn(move || exporter.serve(listener).unwrap());
+    }
+
+    // Show and change the state of the stack with pareiodon-ctl
+    let devices: Arc<[Device]> = Arc::from(devices);
+    let path = env::var_os("PAREIODON_CONTROL")
+        .map(PathBuf::from)
+        .or_else(|| config.control.as_ref().map(|control| control.path.clone()))
+        .unwrap_or_else(|| PathBuf::from(Control::DEFAULT_PATH));
+    let listener = Control::bind(&path).unwrap_or_else(|e| exit(e));
+    let mut control = Control::new(sockets.clone(), router.clone(), devices.clone(), names);
+    if let Some(resolver) = resolver {
+        control = control.with_resolver(resolver);
+    }
+    let control = Arc::new(control);
+    thread::spawn(move || control.serve(listener).unwrap());
+
+    Services::new(sockets, config.services.builtin.clone()).start();
+
+    serve(&event_loop, &devices, &router, verbosity);
+}
+
+fn ping(args: PingArgs, verbosity: Verbosity) {
+    init_logging("info");
+    let tuntap = args.device.open();
+    let device = match args.device.tap {
+        true => Device::tap(tuntap, MacAddress::random()),
+        false => Device::tun(tuntap),
+    };
+
+    let event_loop = EventLoop::new().unwrap();
+    let sockets = Sockets::new(event_loop.waker());
+    let ipv4 = IPv4::with_raw(vec![Box::new(Icmp::new())], Raw::new(sockets.clone()));
+    let Prefix { address, netmask } = args.address;
+    let interface = Interface::new(address, netmask, 1500);
+    // Through the host side to the other networks
+    let mut routes = RoutingTable::new();
+    let default = Ipv4Addr::UNSPECIFIED;
+    routes.add(Route::via(default, default, args.device.host.address, 0));
+    let router = Router::new(ipv4, vec![interface], routes);
+
+    thread::spawn(move || {
+        let mut ping = Ping::new(sockets, args.destination, args.size).unwrap_or_else(|e| exit(e));
+        println!("PING {} {} data bytes", args.destination, args.size);
+        let mut received = 0;
+        for sequence in 1..=args.count {
+            let sent = Instant::now();
+            ping.request(sequence).unwrap_or_else(|e| exit(e));
+            // Wait for the replies until the next request
+            while let Some(reply) = ping
+                .reply(PING_INTERVAL.saturating_sub(sent.elapsed()))
+                .unwrap_or_else(|e| exit(e))
+            {
+                println!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+                    reply.size,
+                    reply.from,
+                    reply.sequence,
+                    reply.ttl,
+                    reply.rtt.as_secs_f64() * 1000.0
+                );
+                received += 1;
+            }
+        }
+        println!("\n--- {} ping statistics ---", args.destination);
+        println!(
+            "{} packets transmitted, {} received, {}% packet loss",
+            args.count,
+            received,
+            (args.count - received) as u32 * 100 / args.count as u32
+        );
+        process::exit(if received == 0 { 1 } else { 0 });
+    });
+
+    serve(&event_loop, &[device], &router, verbosity);
+}
+
+fn capture(args: CaptureArgs, verbose: u8) {
+    init_logging("info");
+    let tuntap = args.device.open();
+    let linktype = match args.device.tap {
+        true => pcapng::LINKTYPE_ETHERNET,
+        false => pcapng::LINKTYPE_RAW,
+    };
+    let capture = args.write.as_ref().map(|path| {
+        let capture =
+            Capture::create(path).unwrap_or_else(|e| exit(format!("{}: {}", path.display(), e)));
+        let id = capture.add_interface(&args.device.device, linktype);
+        (capture, id)
+    });
+    // Print a line for each packet unless they are written to a file
+    let verbosity = match (verbose, &capture) {
+        (0, Some(_)) => Verbosity::Quiet,
+        (0 | 1, _) => Verbosity::Summary,
+        _ => Verbosity::Tree,
+    };
+    let dissector = Dissector::new();
+
+    let mut buf = vec![0u8; 65536];
+    let mut packets = 0;
+    while args.count.is_none_or(|count| packets < count) {
+        let n = tuntap
+            .read(&mut buf)
+            .unwrap_or_else(|e| exit(format!("{}: {}", args.device.device, e)));
+        let frame = &buf[..n];
+        if let Some((capture, id)) = &capture {
+            capture.packet(*id, Direction::Inbound, frame, SystemTime::now());
+        }
+        packets += 1;
+        if verbosity == Verbosity::Quiet {
+            continue;
+        }
+        // Only the IP datagrams are dissected on a TAP device
+        let datagram = match frame {
+            [_, _, _, _, _, _, _, _, _, _, _, _, high, low, data @ ..] if args.device.tap => {
+                match u16::from_be_bytes([*high, *low]) {
+                    Ethernet::TYPE_IPV4 => Ok(data),
+                    ethertype => Err(ethertype),
+                }
+            }
+            _ => Ok(frame),
+        };
+        match datagram {
+            Ok(datagram) => println!("{}", dissector.format(datagram, verbosity).unwrap()),
+            Err(ethertype) => println!("Ethernet type={:#06x} len={}", ethertype, n),
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let verbose = cli.verbose;
+    let command = cli.command.unwrap_or_else(|| {
+        Command::Run(RunArgs {
+            config: PathBuf::from(Config::DEFAULT_PATH),
+            no_configure: false,
+        })
+    });
+    match command {
+        Command::Run(args) => run(args, verbosity(verbose)),
+        Command::Ping(args) => ping(args, verbosity(verbose)),
+        Command::Capture(args) => capture(args, verbose),
+    }
 }
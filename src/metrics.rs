@@ -0,0 +1,212 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::{
+    socket::Sockets,
+    stats::{DeviceStats, Stats},
+};
+
+// Exports the statistics in the OpenMetrics text format
+// (https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md)
+//
+// The endpoint is served on a socket of the host, not through the stack, so
+// that scraping does not disturb the counters.
+#[derive(Clone)]
+pub struct Exporter {
+    stats: Stats,
+    sockets: Sockets,
+    devices: Vec<(String, Arc<DeviceStats>)>,
+}
+
+impl Exporter {
+    pub const CONTENT_TYPE: &'static str =
+        "application/openmetrics-text; version=1.0.0; charset=utf-8";
+    const PATH: &'static str = "/metrics";
+    const PREFIX: &'static str = "pareiodon";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(
+        stats: Stats,
+        sockets: Sockets,
+        devices: Vec<(String, Arc<DeviceStats>)>,
+    ) -> Exporter {
+        Exporter {
+            stats,
+            sockets,
+            devices,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let layers = [
+            ("ipv4", "ipSystemStats", self.stats.ipv4.counters()),
+            ("icmp", "icmpStats", self.stats.icmp.counters()),
+            ("udp", "udp", self.stats.udp.counters()),
+            ("tcp", "tcp", self.stats.tcp.counters()),
+        ];
+        for (layer, prefix, counters) in layers {
+            for (object, value) in counters {
+                let name = format!("{}_{}", layer, Exporter::_snake(&object[prefix.len()..]));
+                Exporter::_family(
+                    &mut out,
+                    &name,
+                    "counter",
+                    object,
+                    &[(String::new(), value)],
+                );
+            }
+        }
+
+        // icmpMsgStatsTable, only for the types seen so far
+        for (direction, types) in [
+            ("in", &self.stats.icmp.in_types),
+            ("out", &self.stats.icmp.out_types),
+        ] {
+            let samples: Vec<(String, u64)> = types
+                .iter()
+                .enumerate()
+                .filter(|(_, counter)| counter.get() != 0)
+                .map(|(icmp_type, counter)| (format!("type=\"{}\"", icmp_type), counter.get()))
+                .collect();
+            let name = format!("icmp_msg_{}_pkts", direction);
+            let help = format!(
+                "icmpMsgStats{}Pkts",
+                if direction == "in" { "In" } else { "Out" }
+            );
+            Exporter::_family(&mut out, &name, "counter", &help, &samples);
+        }
+
+        if let Some((_, stats)) = self.devices.first() {
+            for (i, (counter, _)) in stats.counters().into_iter().enumerate() {
+                let samples: Vec<(String, u64)> = self
+                    .devices
+                    .iter()
+                    .map(|(device, stats)| {
+                        (format!("device=\"{}\"", device), stats.counters()[i].1)
+                    })
+                    .collect();
+                let name = format!("device_{}", counter);
+                let help = format!("The {} of the TUN/TAP device", counter.replace('_', " "));
+                Exporter::_family(&mut out, &name, "counter", &help, &samples);
+            }
+        }
+
+        let gauges = self.sockets.gauges();
+        let samples = vec![
+            ("type=\"udp\"".to_string(), gauges.udp as u64),
+            (
+                "type=\"tcp\"".to_string(),
+                gauges.tcp.iter().map(|(_, n)| *n as u64).sum(),
+            ),
+            ("type=\"raw\"".to_string(), gauges.raw as u64),
+        ];
+        Exporter::_family(&mut out, "sockets", "gauge", "The open sockets", &samples);
+        let samples: Vec<(String, u64)> = gauges
+            .tcp
+            .iter()
+            .map(|(state, n)| {
+                (
+                    format!("state=\"{}\"", Exporter::_snake(&format!("{:?}", state))),
+                    *n as u64,
+                )
+            })
+            .collect();
+        Exporter::_family(
+            &mut out,
+            "tcp_connections",
+            "gauge",
+            "The TCP sockets in each state",
+            &samples,
+        );
+
+        out.push_str("# EOF\n");
+        out
+    }
+
+    // Serve the metrics until the listener fails
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept()?;
+            let exporter = self.clone();
+            thread::spawn(move || {
+                if let Err(e) = exporter._respond(stream) {
+                    debug!("{}: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    fn _respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(Exporter::TIMEOUT))?;
+        let mut request = vec![];
+        let mut buf = [0u8; 1024];
+        // Only the request line is used but the headers are read to the end
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf)?;
+            if n == 0 || request.len() > 8192 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let mut words = request.split_whitespace();
+        let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+        let (status, content_type, body) = match (method, path) {
+            ("GET" | "HEAD", Exporter::PATH) => ("200 OK", Exporter::CONTENT_TYPE, self.render()),
+            (_, Exporter::PATH) => ("405 Method Not Allowed", "text/plain", String::new()),
+            _ => ("404 Not Found", "text/plain", String::new()),
+        };
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        );
+        if method != "HEAD" {
+            response.push_str(&body);
+        }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn _family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+        let name = format!("{}_{}", Exporter::PREFIX, name);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        // Counters are exposed with the _total suffix
+        let suffix = if kind == "counter" { "_total" } else { "" };
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                let _ = writeln!(out, "{}{} {}", name, suffix, value);
+            } else {
+                let _ = writeln!(out, "{}{}{{{}}} {}", name, suffix, labels, value);
+            }
+        }
+    }
+
+    // InHdrErrors to in_hdr_errors, OutFragOKs to out_frag_oks
+    fn _snake(s: &str) -> String {
+        let mut snake = String::new();
+        let mut previous: Option<char> = None;
+        for c in s.chars() {
+            if c.is_ascii_uppercase()
+                && previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit())
+            {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+            previous = Some(c);
+        }
+        snake
+    }
+}
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{Ipv4Addr, TcpListener, TcpStream},
+        sync::Arc,
+        thread,
+    };
+
+    use crate::{
+        eventloop::EventLoop,
+        icmp::Icmp,
+        ipv4::IPv4,
+        metrics::Exporter,
+        route::RoutingTable,
+        router::{Interface, Router},
+        socket::{SocketType, Sockets},
+        stats::{DeviceStats, Stats},
+    };
+
+    fn exporter() -> (Exporter, Stats, Arc<DeviceStats>) {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let interfaces = vec![Interface::new(
+            Ipv4Addr::new(192, 0, 2, 2),
+            Ipv4Addr::new(255, 255, 255, 0),
+            1500,
+        )];
+        let ipv4 = IPv4::new(vec![Box::new(Icmp::new())]);
+        let router = Router::new(ipv4, interfaces, RoutingTable::new());
+        let stats = Stats::new(&router, &sockets);
+        sockets.socket(SocketType::Datagram).unwrap();
+        sockets.socket(SocketType::Stream).unwrap();
+
+        let device = Arc::new(DeviceStats::default());
+        let devices = vec![("tun0".to_string(), device.clone())];
+        (
+            Exporter::new(stats.clone(), sockets, devices),
+            stats,
+            device,
+        )
+    }
+
+    #[test]
+    fn render() {
+        let (exporter, stats, device) = exporter();
+        stats.ipv4.in_hdr_errors.add(3);
+        stats.ipv4.out_frag_oks.increment();
+        stats.icmp.in_types[8].increment();
+        device.in_octets.add(84);
+
+        let text = exporter.render();
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "# TYPE pareiodon_ipv4_in_hdr_errors counter",
+            "# HELP pareiodon_ipv4_in_hdr_errors ipSystemStatsInHdrErrors",
+            "pareiodon_ipv4_in_hdr_errors_total 3",
+            "pareiodon_ipv4_out_frag_oks_total 1",
+            "pareiodon_icmp_in_msgs_total 0",
+            "pareiodon_icmp_msg_in_pkts_total{type=\"8\"} 1",
+            "pareiodon_udp_no_ports_total 0",
+            "pareiodon_tcp_retrans_segs_total 0",
+            "pareiodon_device_in_octets_total{device=\"tun0\"} 84",
+            "# TYPE pareiodon_sockets gauge",
+            "pareiodon_sockets{type=\"udp\"} 1",
+            "pareiodon_sockets{type=\"tcp\"} 1",
+            "pareiodon_tcp_connections{state=\"closed\"} 1",
+            "pareiodon_tcp_connections{state=\"syn_sent\"} 0",
+        ] {
+            assert!(lines.contains(&line), "{}", line);
+        }
+        assert!(!text.contains("type=\"0\""));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+
+    fn get(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serve() {
+        let (exporter, _, _) = exporter();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || exporter.serve(listener));
+
+        let response = get(addr, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(&format!("Content-Type: {}\r\n", Exporter::CONTENT_TYPE)));
+        assert!(response.ends_with("# EOF\n"));
+
+        let response = get(addr, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get(addr, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+
+// Link types (https://www.tcpdump.org/linktypes.html)
+pub const LINKTYPE_ETHERNET: u16 = 1;
+pub const LINKTYPE_RAW: u16 = 101;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Inbound = 1,
+    Outbound = 2,
+}
+
+struct Writer {
+    out: Box<dyn Write + Send>,
+    interfaces: u32,
+    // Stop writing once the reader has gone away
+    failed: bool,
+}
+
+// Writes the packets in the pcapng format
+// (https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html)
+pub struct Capture {
+    writer: Mutex<Writer>,
+}
+
+impl Capture {
+    const SECTION_HEADER: u32 = 0x0a0d0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 0x00000001;
+    const ENHANCED_PACKET: u32 = 0x00000006;
+    const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+    // Options
+    const OPT_ENDOFOPT: u16 = 0;
+    const IF_NAME: u16 = 2;
+    const EPB_FLAGS: u16 = 2;
+
+    // Write the section header
+    pub fn new(mut out: Box<dyn Write + Send>) -> io::Result<Capture> {
+        let mut body = vec![];
+        body.extend_from_slice(&Capture::BYTE_ORDER_MAGIC.to_le_bytes());
+        // Version 1.0
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // The section length is not specified
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        out.write_all(&Capture::_block(Capture::SECTION_HEADER, &body))?;
+        out.flush()?;
+        Ok(Capture {
+            writer: Mutex::new(Writer {
+                out,
+                interfaces: 0,
+                failed: false,
+            }),
+        })
+    }
+
+    // A regular file or a FIFO
+    pub fn create(path: &Path) -> io::Result<Capture> {
+        Capture::new(Box::new(File::create(path)?))
+    }
+
+    // Returns the ID of the interface for the packets
+    pub fn add_interface(&self, name: &str, linktype: u16) -> u32 {
+        let mut body = vec![];
+        body.extend_from_slice(&linktype.to_le_bytes());
+        // Reserved
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // No limit on the snapshot length
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend(Capture::_option(Capture::IF_NAME, name.as_bytes()));
+        body.extend(Capture::_option(Capture::OPT_ENDOFOPT, &[]));
+
+        let mut writer = self.writer.lock().unwrap();
+        let id = writer.interfaces;
+        writer.interfaces += 1;
+        Capture::_write(
+            &mut writer,
+            &Capture::_block(Capture::INTERFACE_DESCRIPTION, &body),
+        );
+        id
+    }
+
+    pub fn packet(&self, interface: u32, direction: Direction, data: &[u8], time: SystemTime) {
+        // Microseconds, the default resolution
+        let timestamp = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut body = vec![];
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        // Captured and original lengths
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len().next_multiple_of(4), 0);
+        body.extend(Capture::_option(
+            Capture::EPB_FLAGS,
+            &(direction as u32).to_le_bytes(),
+        ));
+        body.extend(Capture::_option(Capture::OPT_ENDOFOPT, &[]));
+
+        let mut writer = self.writer.lock().unwrap();
+        Capture::_write(
+            &mut writer,
+            &Capture::_block(Capture::ENHANCED_PACKET, &body),
+        );
+    }
+
+    fn _write(writer: &mut Writer, block: &[u8]) {
+        if writer.failed {
+            return;
+        }
+        // Flush each block so that readers of a FIFO see the packets at once
+        if let Err(e) = writer.out.write_all(block).and_then(|_| writer.out.flush()) {
+            error!("failed to write the capture: {}", e);
+            writer.failed = true;
+        }
+    }
+
+    // The body is followed by the total length again
+    fn _block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut block = vec![];
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    // Values are padded to 32 bits
+    fn _option(code: u16, value: &[u8]) -> Vec<u8> {
+        let mut option = vec![];
+        option.extend_from_slice(&code.to_le_bytes());
+        option.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        option.extend_from_slice(value);
+        option.resize(option.len().next_multiple_of(4), 0);
+        option
+    }
+}
@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::pcapng::{self, Capture, Direction};
+
+    // Keeps what has been written for the test
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn u32_at(buf: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes(buf[i..i + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn section_header() {
+        let buffer = Buffer::default();
+        let _capture = Capture::new(Box::new(buffer.clone())).unwrap();
+        let buf = buffer.0.lock().unwrap();
+        assert_eq!(
+            *buf,
+            [
+                0x0a, 0x0d, 0x0d, 0x0a, 0x1c, 0x00, 0x00, 0x00, // Type, Length
+                0x4d, 0x3c, 0x2b, 0x1a, 0x01, 0x00, 0x00, 0x00, // Magic, Version
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // Section Length
+                0x1c, 0x00, 0x00, 0x00, // Length
+            ]
+        );
+    }
+
+    #[test]
+    fn packets() {
+        let buffer = Buffer::default();
+        let capture = Capture::new(Box::new(buffer.clone())).unwrap();
+        assert_eq!(capture.add_interface("tun0", pcapng::LINKTYPE_RAW), 0);
+        assert_eq!(capture.add_interface("tap0", pcapng::LINKTYPE_ETHERNET), 1);
+        let time = UNIX_EPOCH + Duration::from_micros(0x1_0000_0002);
+        capture.packet(1, Direction::Outbound, &[1, 2, 3, 4, 5], time);
+
+        let buf = buffer.0.lock().unwrap();
+        // Interface Description Blocks after the Section Header Block
+        let idb = &buf[28..];
+        assert_eq!(u32_at(idb, 0), 1);
+        assert_eq!(u32_at(idb, 4), 32);
+        assert_eq!(&idb[8..10], &[101, 0]);
+        // if_name
+        assert_eq!(&idb[16..24], &[2, 0, 4, 0, b't', b'u', b'n', b'0']);
+        assert_eq!(u32_at(idb, 28), 32);
+        assert_eq!(&buf[60 + 8..60 + 10], &[1, 0]);
+
+        let epb = &buf[92..];
+        assert_eq!(u32_at(epb, 0), 6);
+        let length = u32_at(epb, 4) as usize;
+        assert_eq!(length, 52);
+        assert_eq!(epb.len(), length);
+        assert_eq!(u32_at(epb, 8), 1);
+        // Timestamp
+        assert_eq!(u32_at(epb, 12), 1);
+        assert_eq!(u32_at(epb, 16), 2);
+        // Captured and original lengths
+        assert_eq!(u32_at(epb, 20), 5);
+        assert_eq!(u32_at(epb, 24), 5);
+        // The data padded to 32 bits
+        assert_eq!(&epb[28..36], &[1, 2, 3, 4, 5, 0, 0, 0]);
+        // epb_flags (outbound)
+        assert_eq!(&epb[36..44], &[2, 0, 4, 0, 2, 0, 0, 0]);
+        assert_eq!(u32_at(epb, 48), 52);
+    }
+}
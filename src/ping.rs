@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    process,
+    time::{Duration, Instant},
+};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    icmp::{Icmp, IcmpType},
+    protocol::get_checksum,
+    socket::{SocketHandle, SocketType, Sockets},
+};
+
+// An echo reply to one of the requests
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EchoReply {
+    pub from: Ipv4Addr,
+    // The octets of the ICMP message
+    pub size: usize,
+    pub sequence: u16,
+    pub ttl: u8,
+    pub rtt: Duration,
+}
+
+// Sends echo requests and receives the replies on a raw socket (RFC 792)
+pub struct Ping {
+    sockets: Sockets,
+    handle: SocketHandle,
+    destination: Ipv4Addr,
+    identifier: u16,
+    // The data after the header of the ICMP message
+    size: usize,
+    sent: HashMap<u16, Instant>,
+}
+
+impl Ping {
+    pub const DEFAULT_SIZE: usize = 56;
+    const HEADER_SIZE: usize = 8;
+
+    pub fn new(sockets: Sockets, destination: Ipv4Addr, size: usize) -> Result<Ping, Error> {
+        let handle = sockets.socket(SocketType::Raw(Icmp::NUMBER))?;
+        Ok(Ping {
+            sockets,
+            handle,
+            destination,
+            identifier: process::id() as u16,
+            size,
+            sent: HashMap::new(),
+        })
+    }
+
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    pub fn request(&mut self, sequence: u16) -> Result<(), Error> {
+        let mut buf = vec![IcmpType::Echo as u8, 0, 0, 0];
+        buf.extend_from_slice(&self.identifier.to_be_bytes());
+        buf.extend_from_slice(&sequence.to_be_bytes());
+        buf.extend((0..self.size).map(|i| i as u8));
+        let checksum = get_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        self.sockets
+            .send_to(self.handle, &buf, SocketAddrV4::new(self.destination, 0))?;
+        self.sent.insert(sequence, Instant::now());
+        Ok(())
+    }
+
+    // Wait for a reply until the timeout. Other ICMP messages, e.g. the
+    // requests of other hosts, are skipped.
+    pub fn reply(&mut self, timeout: Duration) -> Result<Option<EchoReply>, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.sockets
+                .set_read_timeout(self.handle, Some(remaining))?;
+            let n = match self.sockets.recv(self.handle, &mut buf) {
+                Ok(n) => n,
+                Err(Errno::EAGAIN) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if let Some(reply) = self._parse(&buf[..n]) {
+                return Ok(Some(reply));
+            }
+        }
+    }
+
+    // The datagram starts with the IP header on a raw socket
+    fn _parse(&mut self, buf: &[u8]) -> Option<EchoReply> {
+        let ihl = 4 * (*buf.first()? & 0xf) as usize;
+        let data = buf.get(ihl..)?;
+        if data.len() < Ping::HEADER_SIZE
+            || data[0] != IcmpType::EchoReply as u8
+            || get_checksum(data) != 0
+            || u16::from_be_bytes([data[4], data[5]]) != self.identifier
+        {
+            return None;
+        }
+        let sequence = u16::from_be_bytes([data[6], data[7]]);
+        let sent = self.sent.remove(&sequence)?;
+        Some(EchoReply {
+            from: Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]),
+            size: data.len(),
+            sequence,
+            ttl: buf[8],
+            rtt: sent.elapsed(),
+        })
+    }
+}
+
+impl Drop for Ping {
+    fn drop(&mut self) {
+        let _ = self.sockets.close(self.handle);
+    }
+}
@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        eventloop::EventLoop,
+        icmp::Icmp,
+        ipv4::IPv4,
+        ping::Ping,
+        protocol::get_checksum,
+        raw::Raw,
+        socket::Sockets,
+        testutil::{datagram, host, router, stack},
+    };
+
+    // The echo reply of the host to the request from the stack
+    fn echo_reply(request: &[u8], identifier: u16) -> Vec<u8> {
+        let mut icmp = request[20..].to_vec();
+        icmp[0] = 0;
+        icmp[2..4].copy_from_slice(&[0, 0]);
+        icmp[4..6].copy_from_slice(&identifier.to_be_bytes());
+        let checksum = get_checksum(&icmp);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+        datagram(host(), stack(), Icmp::NUMBER, &icmp)
+    }
+
+    #[test]
+    fn ping() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let ipv4 = IPv4::with_raw(vec![Box::new(Icmp::new())], Raw::new(sockets.clone()));
+        let router = router(ipv4);
+
+        let mut ping = Ping::new(sockets.clone(), host(), Ping::DEFAULT_SIZE).unwrap();
+        ping.request(1).unwrap();
+        let datagrams = router.poll();
+        assert_eq!(datagrams.len(), 1);
+        let (interface, request) = &datagrams[0];
+        assert_eq!(*interface, 0);
+        assert_eq!(request.len(), 20 + 8 + Ping::DEFAULT_SIZE);
+        // Echo, sequence number 1
+        assert_eq!(request[20], 8);
+        assert_eq!(&request[26..28], &[0, 1]);
+
+        // The reply to another process is skipped
+        let other = echo_reply(request, ping.identifier().wrapping_add(1));
+        assert!(router.input(0, &other).is_err());
+        assert_eq!(ping.reply(Duration::from_millis(10)), Ok(None));
+
+        let reply = echo_reply(request, ping.identifier());
+        assert!(router.input(0, &reply).is_err());
+        let reply = ping.reply(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(reply.from, host());
+        assert_eq!(reply.sequence, 1);
+        assert_eq!(reply.size, 8 + Ping::DEFAULT_SIZE);
+        assert_eq!(reply.ttl, 64);
+
+        // No duplicate replies
+        let reply = echo_reply(request, ping.identifier());
+        assert!(router.input(0, &reply).is_err());
+        assert_eq!(ping.reply(Duration::from_millis(10)), Ok(None));
+    }
+}
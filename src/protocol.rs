@@ -1,4 +1,6 @@
-use crate::{icmp::IcmpError, ipv4::IPv4Error};
+use std::fmt;
+
+use crate::{icmp::IcmpError, igmp::IgmpError, ipv4::IPv4Error, tcp::TcpError, udp::UdpError};
 
 pub trait Protocol {
     fn reply(&self, buf: &[u8]) -> Result<Vec<u8>, ProtocolError>;
@@ -8,9 +10,26 @@ pub trait Protocol {
 pub enum ProtocolError {
     IPv4(IPv4Error),
     Icmp(IcmpError),
+    Igmp(IgmpError),
+    Udp(UdpError),
+    Tcp(TcpError),
     General,
 }
 
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::IPv4(e) => e.fmt(f),
+            ProtocolError::Icmp(e) => e.fmt(f),
+            ProtocolError::Igmp(e) => e.fmt(f),
+            ProtocolError::Udp(e) => e.fmt(f),
+            ProtocolError::Tcp(e) => e.fmt(f),
+            // Nothing to reply, e.g. no socket is bound
+            ProtocolError::General => write!(f, "no reply"),
+        }
+    }
+}
+
 impl From<IPv4Error> for ProtocolError {
     fn from(e: IPv4Error) -> Self {
         Self::IPv4(e)
@@ -23,10 +42,30 @@ impl From<IcmpError> for ProtocolError {
     }
 }
 
-pub fn get_checksum(buf: &Vec<u8>) -> u16 {
+impl From<IgmpError> for ProtocolError {
+    fn from(e: IgmpError) -> Self {
+        Self::Igmp(e)
+    }
+}
+
+impl From<UdpError> for ProtocolError {
+    fn from(e: UdpError) -> Self {
+        Self::Udp(e)
+    }
+}
+
+impl From<TcpError> for ProtocolError {
+    fn from(e: TcpError) -> Self {
+        Self::Tcp(e)
+    }
+}
+
+pub fn get_checksum(buf: &[u8]) -> u16 {
     let mut checksum: u32 = 0;
     for i in (0..buf.len()).step_by(2) {
-        checksum += ((buf[i] as u32) << 8) + buf[i + 1] as u32;
+        // Pad the last octet with zero if the length is odd
+        let low = buf.get(i + 1).copied().unwrap_or(0);
+        checksum += ((buf[i] as u32) << 8) + low as u32;
     }
     while (checksum >> 16) != 0 {
         checksum = (checksum & 0xffff) + (checksum >> 16);
@@ -35,3 +74,13 @@ pub fn get_checksum(buf: &Vec<u8>) -> u16 {
     checksum = !checksum & 0xffff;
     checksum as u16
 }
+
+// RFC 1624
+// HC' = ~(~HC + ~m + m')
+pub fn update_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = (!checksum as u32) + (!old as u32) + new as u32;
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
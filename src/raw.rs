@@ -0,0 +1,153 @@
+use std::{collections::VecDeque, net::Ipv4Addr};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    ipv4::IPv4,
+    socket::{Socket, Sockets},
+};
+
+pub(crate) struct RawPcb {
+    pub(crate) protocol: u8,
+    pub(crate) local: Ipv4Addr,
+    pub(crate) remote: Option<Ipv4Addr>,
+    // SO_BINDTODEVICE
+    pub(crate) interface: Option<usize>,
+    // IP_HDRINCL: the data to send starts with the IP header
+    pub(crate) header_included: bool,
+    received: VecDeque<(Ipv4Addr, Vec<u8>)>,
+    pending: VecDeque<(Ipv4Addr, Vec<u8>)>,
+}
+
+impl RawPcb {
+    // The number of datagrams queued for receiving
+    const QUEUE_SIZE: usize = 64;
+    const MAX_DATA_SIZE: usize = 65535 - 20;
+    const MAX_DATAGRAM_SIZE: usize = 65535;
+
+    pub(crate) fn new(protocol: u8) -> RawPcb {
+        RawPcb {
+            protocol,
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: None,
+            interface: None,
+            header_included: false,
+            received: VecDeque::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    // The octets waiting to be received and sent
+    pub(crate) fn queues(&self) -> (usize, usize) {
+        let len = |queue: &VecDeque<(Ipv4Addr, Vec<u8>)>| queue.iter().map(|(_, d)| d.len()).sum();
+        (len(&self.received), len(&self.pending))
+    }
+
+    fn _accepts(&self, protocol: u8, src: Ipv4Addr, dst: Ipv4Addr) -> bool {
+        self.protocol == protocol
+            && (self.local.is_unspecified() || self.local == dst)
+            && self.remote.is_none_or(|remote| remote == src)
+    }
+
+    pub(crate) fn send_to(&mut self, buf: &[u8], addr: Ipv4Addr) -> Result<(), Error> {
+        if !self.header_included {
+            if buf.len() > RawPcb::MAX_DATA_SIZE {
+                return Err(Errno::EMSGSIZE);
+            }
+            self.pending.push_back((addr, buf.to_vec()));
+            return Ok(());
+        }
+
+        if buf.len() > RawPcb::MAX_DATAGRAM_SIZE {
+            return Err(Errno::EMSGSIZE);
+        }
+        // Version, Internet Header Length (IHL)
+        let ihl = 4 * (buf.first().copied().unwrap_or(0) & 0xf) as usize;
+        if buf.len() < 20 || buf[0] >> 4 != 4 || ihl < 20 || buf.len() < ihl {
+            return Err(Errno::EINVAL);
+        }
+        // The destination in the header takes precedence like Linux
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        self.pending.push_back((dst, buf.to_vec()));
+        Ok(())
+    }
+
+    // Datagrams longer than the buffer are truncated
+    pub(crate) fn recv_from(&mut self, buf: &mut [u8]) -> Option<(usize, Ipv4Addr)> {
+        let (addr, data) = self.received.pop_front()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Some((n, addr))
+    }
+}
+
+// Raw sockets receive whole datagrams including the header for their
+// protocol numbers, whether the stack handles the protocols or not. They send
+// data with a header built by the stack, or complete datagrams with
+// IP_HDRINCL.
+pub struct Raw {
+    sockets: Sockets,
+}
+
+impl Raw {
+    pub fn new(sockets: Sockets) -> Raw {
+        Raw { sockets }
+    }
+
+    pub(crate) fn input(&self, buf: &[u8]) {
+        let protocol = buf[9];
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+
+        let mut table = self.sockets.lock();
+        let mut delivered = false;
+        for (_, socket) in table.iter_mut() {
+            let pcb = match socket {
+                Socket::Raw(pcb) if pcb._accepts(protocol, src, dst) => pcb,
+                _ => continue,
+            };
+            if pcb.received.len() < RawPcb::QUEUE_SIZE {
+                pcb.received.push_back((src, buf.to_vec()));
+                delivered = true;
+            }
+        }
+        if delivered {
+            self.sockets.notify();
+        }
+    }
+
+    // Returns whole datagrams ready to be routed
+    pub(crate) fn poll(
+        &self,
+        ipv4: &IPv4,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<(Option<usize>, Vec<u8>)> {
+        let mut datagrams = vec![];
+        let mut table = self.sockets.lock();
+        for (_, socket) in table.iter_mut() {
+            let pcb = match socket {
+                Socket::Raw(pcb) => pcb,
+                _ => continue,
+            };
+
+            while let Some((dst, mut data)) = pcb.pending.pop_front() {
+                let src = match pcb.local {
+                    ip if ip.is_unspecified() => match source(dst, pcb.interface) {
+                        Some((ip, _)) => ip,
+                        // No route to the destination
+                        None => continue,
+                    },
+                    ip => ip,
+                };
+                if pcb.header_included {
+                    ipv4.complete_header(&mut data, src);
+                    datagrams.push((pcb.interface, data));
+                } else {
+                    let data = ipv4.datagram(src, dst, pcb.protocol, &data);
+                    datagrams.push((pcb.interface, data));
+                }
+            }
+        }
+        datagrams
+    }
+}
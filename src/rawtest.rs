@@ -0,0 +1,108 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use crate::{
+        eventloop::EventLoop,
+        ipv4::IPv4,
+        protocol::{get_checksum, Protocol, ProtocolError},
+        raw::Raw,
+        socket::{SocketType, Sockets},
+        testutil::{host, remote, stack},
+    };
+
+    fn source(_: Ipv4Addr, _: Option<usize>) -> Option<(Ipv4Addr, usize)> {
+        Some((stack(), 1500))
+    }
+
+    #[test]
+    fn receive() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        // RFC 3692
+        let socket = sockets.socket(SocketType::Raw(253)).unwrap();
+        let ipv4 = IPv4::with_raw(vec![], Raw::new(sockets.clone()));
+
+        let buf = ipv4.datagram(host(), stack(), 253, b"hello");
+        // No protocol in the stack handles the datagram
+        assert_eq!(ipv4.reply(&buf), Err(ProtocolError::General));
+
+        let mut data = [0u8; 64];
+        let (n, addr) = sockets.recv_from(socket, &mut data).unwrap();
+        assert_eq!(&data[..n], &buf[..]);
+        assert_eq!(addr, SocketAddrV4::new(host(), 0));
+    }
+
+    #[test]
+    fn send() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let socket = sockets.socket(SocketType::Raw(253)).unwrap();
+        let ipv4 = IPv4::with_raw(vec![], Raw::new(sockets.clone()));
+
+        let addr = SocketAddrV4::new(host(), 0);
+        assert_eq!(sockets.send_to(socket, b"hello", addr), Ok(5));
+
+        let datagrams = ipv4.poll(&source);
+        assert_eq!(datagrams.len(), 1);
+        let (interface, buf) = &datagrams[0];
+        assert_eq!(*interface, None);
+        assert_eq!(buf[9], 253);
+        assert_eq!(&buf[12..16], &stack().octets());
+        assert_eq!(&buf[16..20], &host().octets());
+        assert_eq!(&buf[20..], b"hello");
+        assert_eq!(get_checksum(&buf[..20]), 0);
+    }
+
+    #[test]
+    fn header_included() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let socket = sockets.socket(SocketType::Raw(253)).unwrap();
+        sockets.set_header_included(socket, true).unwrap();
+        let ipv4 = IPv4::with_raw(vec![], Raw::new(sockets.clone()));
+
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x00, // Total Length
+            0x00, 0x00, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x10, // Time to Live
+            0xfd, // Protocol
+            0x00, 0x00, // Header Checksum
+            0x00, 0x00, 0x00, 0x00, // Source Address
+            0xc0, 0x00, 0x02, 0x01, // Destination Address
+            0x68, 0x65, 0x6c, 0x6c, 0x6f, // Data
+        ];
+        // The destination in the header is used
+        let addr = SocketAddrV4::new(remote(), 0);
+        assert_eq!(sockets.send_to(socket, &buf, addr), Ok(buf.len()));
+
+        let datagrams = ipv4.poll(&source);
+        assert_eq!(datagrams.len(), 1);
+        let (_, datagram) = &datagrams[0];
+        // Total Length
+        assert_eq!(&datagram[2..4], &[0x00, 0x19]);
+        // Time to Live
+        assert_eq!(datagram[8], 0x10);
+        assert_eq!(&datagram[12..16], &stack().octets());
+        assert_eq!(&datagram[16..20], &host().octets());
+        assert_eq!(&datagram[20..], b"hello");
+        assert_eq!(get_checksum(&datagram[..20]), 0);
+    }
+
+    #[test]
+    fn header_included_invalid() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let socket = sockets.socket(SocketType::Raw(253)).unwrap();
+        sockets.set_header_included(socket, true).unwrap();
+
+        let addr = SocketAddrV4::new(host(), 0);
+        assert!(sockets.send_to(socket, b"hello", addr).is_err());
+
+        let udp = sockets.socket(SocketType::Datagram).unwrap();
+        assert!(sockets.set_header_included(udp, true).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    dhcpclient::DhcpClient,
+    dns::{self, rcode, rtype, DnsError, DnsMessage, RData, Record},
+    socket::{SocketHandle, SocketType, Sockets},
+};
+
+// Where the addresses of the nameservers come from
+enum Nameservers {
+    Static(Vec<Ipv4Addr>),
+    Dhcp(DhcpClient),
+}
+
+// Answers kept until their TTL expires
+#[derive(Default)]
+pub(crate) struct Cache {
+    entries: HashMap<(String, u16), (Instant, Vec<Record>)>,
+}
+
+impl Cache {
+    const MAX_ENTRIES: usize = 1024;
+
+    // The records with the TTLs which remain
+    pub(crate) fn get(&mut self, name: &str, rtype: u16, now: Instant) -> Option<Vec<Record>> {
+        let key = (name.to_ascii_lowercase(), rtype);
+        let (expires, records) = self.entries.get(&key)?;
+        if *expires <= now {
+            self.entries.remove(&key);
+            return None;
+        }
+        let ttl = (*expires - now).as_secs() as u32;
+        Some(
+            records
+                .iter()
+                .map(|r| Record {
+                    ttl: r.ttl.min(ttl),
+                    ..r.clone()
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, rtype: u16, records: Vec<Record>, now: Instant) {
+        let ttl = match records.iter().map(|r| r.ttl).min() {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+        if self.entries.len() >= Cache::MAX_ENTRIES {
+            self.entries.retain(|_, (expires, _)| *expires > now);
+            if self.entries.len() >= Cache::MAX_ENTRIES {
+                return;
+            }
+        }
+        let expires = now + Duration::from_secs(ttl as u64);
+        self.entries
+            .insert((name.to_ascii_lowercase(), rtype), (expires, records));
+    }
+}
+
+// Follow the CNAMEs from the name in the answers. Returns the records of the
+// type and the name at the end of the chain.
+pub(crate) fn answers(message: &DnsMessage, name: &str, rtype: u16) -> (Vec<Record>, String) {
+    let mut chain = vec![];
+    let mut name = name.to_string();
+    for _ in 0..Resolver::MAX_CNAMES {
+        let records: Vec<Record> = message
+            .answers
+            .iter()
+            .filter(|r| r.rtype() == rtype && dns::name_eq(&r.name, &name))
+            .cloned()
+            .collect();
+        if !records.is_empty() {
+            chain.extend(records);
+            return (chain, name);
+        }
+        let cname = message
+            .answers
+            .iter()
+            .find(|r| r.rtype() == rtype::CNAME && dns::name_eq(&r.name, &name));
+        match cname {
+            Some(
+                record @ Record {
+                    data: RData::Cname(target),
+                    ..
+                },
+            ) => {
+                chain.push(record.clone());
+                name = target.clone();
+            }
+            _ => break,
+        }
+    }
+    (chain, name)
+}
+
+// Stub resolver (RFC 1123 6.1.3.1) which asks the nameservers to recurse
+pub struct Resolver {
+    sockets: Sockets,
+    nameservers: Nameservers,
+    cache: Mutex<Cache>,
+}
+
+impl Resolver {
+    // The same defaults as resolv.conf(5)
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const ATTEMPTS: usize = 2;
+    const MAX_CNAMES: usize = 8;
+
+    pub fn new(sockets: Sockets, nameservers: Vec<Ipv4Addr>) -> Resolver {
+        Resolver {
+            sockets,
+            nameservers: Nameservers::Static(nameservers),
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    // Use the nameservers in the current lease
+    pub fn from_dhcp(sockets: Sockets, client: DhcpClient) -> Resolver {
+        Resolver {
+            sockets,
+            nameservers: Nameservers::Dhcp(client),
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    pub fn nameservers(&self) -> Vec<Ipv4Addr> {
+        match &self.nameservers {
+            Nameservers::Static(nameservers) => nameservers.clone(),
+            Nameservers::Dhcp(client) => client.lease().map(|l| l.dns).unwrap_or_default(),
+        }
+    }
+
+    pub fn lookup_ipv4(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        let records = self.query(name, rtype::A)?;
+        Ok(records
+            .iter()
+            .filter_map(|r| match r.data {
+                RData::A(address) => Some(address),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub fn lookup_ipv6(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        let records = self.query(name, rtype::AAAA)?;
+        Ok(records
+            .iter()
+            .filter_map(|r| match r.data {
+                RData::Aaaa(address) => Some(address),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // The names of the address from the in-addr.arpa domain
+    pub fn lookup_ptr(&self, address: Ipv4Addr) -> Result<Vec<String>, DnsError> {
+        let records = self.query(&dns::reverse_name(address), rtype::PTR)?;
+        Ok(records
+            .into_iter()
+            .filter_map(|r| match r.data {
+                RData::Ptr(name) => Some(name),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // The records of the type including the CNAMEs which lead to them
+    pub fn query(&self, name: &str, rtype: u16) -> Result<Vec<Record>, DnsError> {
+        dns::check_name(name)?;
+        let name = name.trim_end_matches('.');
+        if let Some(records) = self.cache.lock().unwrap().get(name, rtype, Instant::now()) {
+            return Ok(records);
+        }
+
+        let mut chain = vec![];
+        let mut target = name.to_string();
+        for _ in 0..Resolver::MAX_CNAMES {
+            let response = self._exchange(&DnsMessage::query(Resolver::_id(), &target, rtype))?;
+            if response.rcode() == rcode::NXDOMAIN {
+                return Err(DnsError(format!("name not found: {}", target)));
+            }
+            let (records, end) = answers(&response, &target, rtype);
+            let resolved = records.last().is_some_and(|r| r.rtype() == rtype);
+            chain.extend(records);
+            // The server has not followed the CNAME for us
+            if !resolved && !dns::name_eq(&end, &target) {
+                target = end;
+                continue;
+            }
+            if resolved {
+                let mut cache = self.cache.lock().unwrap();
+                cache.insert(name, rtype, chain.clone(), Instant::now());
+            }
+            return Ok(chain);
+        }
+        Err(DnsError(format!("too many CNAMEs: {}", name)))
+    }
+
+    fn _id() -> u16 {
+        RandomState::new().build_hasher().finish() as u16
+    }
+
+    fn _error(e: Error) -> DnsError {
+        DnsError(e.to_string())
+    }
+
+    // Try each nameserver in turn over UDP and retry over TCP if the answer
+    // does not fit (RFC 7766)
+    fn _exchange(&self, query: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let nameservers = self.nameservers();
+        if nameservers.is_empty() {
+            return Err(DnsError("no nameservers".to_string()));
+        }
+        let socket = self
+            .sockets
+            .socket(SocketType::Datagram)
+            .map_err(Resolver::_error)?;
+        let result = self._exchange_udp(socket, query, &nameservers);
+        let _ = self.sockets.close(socket);
+
+        let (response, nameserver) = result?;
+        if response.is_truncated() {
+            return self._exchange_tcp(query, nameserver);
+        }
+        Ok(response)
+    }
+
+    fn _exchange_udp(
+        &self,
+        socket: SocketHandle,
+        query: &DnsMessage,
+        nameservers: &[Ipv4Addr],
+    ) -> Result<(DnsMessage, Ipv4Addr), DnsError> {
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+        self.sockets.bind(socket, addr).map_err(Resolver::_error)?;
+
+        let buf = query.to_bytes();
+        let mut error = DnsError("timed out".to_string());
+        for _ in 0..Resolver::ATTEMPTS {
+            for nameserver in nameservers {
+                let dst = SocketAddrV4::new(*nameserver, DnsMessage::PORT);
+                self.sockets
+                    .send_to(socket, &buf, dst)
+                    .map_err(Resolver::_error)?;
+                let response = match self._receive(socket, query, dst)? {
+                    Some(response) => response,
+                    None => continue,
+                };
+                match response.rcode() {
+                    rcode::NOERROR | rcode::NXDOMAIN => return Ok((response, *nameserver)),
+                    // Another nameserver may answer
+                    code => {
+                        error = DnsError(format!(
+                            "server failure: nameserver={}, rcode={}",
+                            nameserver, code
+                        ))
+                    }
+                }
+            }
+        }
+        Err(error)
+    }
+
+    // Wait for the response to the query from the nameserver
+    fn _receive(
+        &self,
+        socket: SocketHandle,
+        query: &DnsMessage,
+        nameserver: SocketAddrV4,
+    ) -> Result<Option<DnsMessage>, DnsError> {
+        let deadline = Instant::now() + Resolver::TIMEOUT;
+        let mut buf = [0u8; DnsMessage::UDP_SIZE];
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(None);
+            }
+            self.sockets
+                .set_read_timeout(socket, Some(timeout))
+                .map_err(Resolver::_error)?;
+            let (n, src) = match self.sockets.recv_from(socket, &mut buf) {
+                Ok(received) => received,
+                Err(Errno::EAGAIN) => return Ok(None),
+                Err(e) => return Err(Resolver::_error(e)),
+            };
+            if src != nameserver {
+                continue;
+            }
+            match DnsMessage::parse(&buf[..n]) {
+                Ok(response) if Resolver::_matches(query, &response) => return Ok(Some(response)),
+                _ => continue,
+            }
+        }
+    }
+
+    // RFC 5452 9.1
+    fn _matches(query: &DnsMessage, response: &DnsMessage) -> bool {
+        response.is_response()
+            && response.id == query.id
+            && response.questions.len() == query.questions.len()
+            && response
+                .questions
+                .iter()
+                .zip(&query.questions)
+                .all(|(a, b)| a.rtype == b.rtype && dns::name_eq(&a.name, &b.name))
+    }
+
+    fn _exchange_tcp(
+        &self,
+        query: &DnsMessage,
+        nameserver: Ipv4Addr,
+    ) -> Result<DnsMessage, DnsError> {
+        let socket = self
+            .sockets
+            .socket(SocketType::Stream)
+            .map_err(Resolver::_error)?;
+        let result = self._exchange_stream(socket, query, nameserver);
+        let _ = self.sockets.close(socket);
+        result
+    }
+
+    fn _exchange_stream(
+        &self,
+        socket: SocketHandle,
+        query: &DnsMessage,
+        nameserver: Ipv4Addr,
+    ) -> Result<DnsMessage, DnsError> {
+        let dst = SocketAddrV4::new(nameserver, DnsMessage::PORT);
+        self.sockets
+            .connect(socket, dst)
+            .map_err(Resolver::_error)?;
+        self.sockets
+            .set_read_timeout(socket, Some(Resolver::TIMEOUT))
+            .map_err(Resolver::_error)?;
+
+        // Messages are prefixed with the length (RFC 1035 4.2.2)
+        let buf = query.to_bytes();
+        let mut message = (buf.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(&buf);
+        let mut sent = 0;
+        while sent < message.len() {
+            sent += self
+                .sockets
+                .send(socket, &message[sent..])
+                .map_err(Resolver::_error)?;
+        }
+
+        let length = self._read_exact(socket, 2)?;
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        let buf = self._read_exact(socket, length)?;
+        let response = DnsMessage::parse(&buf)?;
+        if !Resolver::_matches(query, &response) {
+            return Err(DnsError("unexpected response".to_string()));
+        }
+        Ok(response)
+    }
+
+    fn _read_exact(&self, socket: SocketHandle, length: usize) -> Result<Vec<u8>, DnsError> {
+        let mut buf = vec![0u8; length];
+        let mut received = 0;
+        while received < buf.len() {
+            match self.sockets.recv(socket, &mut buf[received..]) {
+                Ok(0) => return Err(DnsError("connection closed".to_string())),
+                Ok(n) => received += n,
+                Err(Errno::EAGAIN) => return Err(DnsError("timed out".to_string())),
+                Err(e) => return Err(Resolver::_error(e)),
+            }
+        }
+        Ok(buf)
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        dns::{rtype, DnsError, DnsMessage, RData, Record, CLASS_IN},
+        eventloop::EventLoop,
+        resolver::{self, Cache, Resolver},
+        socket::Sockets,
+    };
+
+    fn record(name: &str, ttl: u32, data: RData) -> Record {
+        Record {
+            name: name.to_string(),
+            class: CLASS_IN,
+            ttl,
+            data,
+        }
+    }
+
+    fn a(name: &str, ttl: u32) -> Record {
+        record(name, ttl, RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        record(name, 300, RData::Cname(target.to_string()))
+    }
+
+    #[test]
+    fn answers() {
+        let mut message = DnsMessage::query(1, "www.example.com", rtype::A);
+        message.answers = vec![
+            a("web.example.com", 60),
+            cname("www.example.com", "Web.example.com"),
+            a("other.example.com", 60),
+        ];
+        let (records, end) = resolver::answers(&message, "www.example.com", rtype::A);
+        assert_eq!(
+            records,
+            vec![
+                cname("www.example.com", "Web.example.com"),
+                a("web.example.com", 60)
+            ]
+        );
+        assert_eq!(end, "Web.example.com");
+    }
+
+    #[test]
+    fn answers_without_target() {
+        let mut message = DnsMessage::query(1, "www.example.com", rtype::A);
+        message.answers = vec![cname("www.example.com", "web.example.org")];
+        let (records, end) = resolver::answers(&message, "www.example.com", rtype::A);
+        assert_eq!(records, vec![cname("www.example.com", "web.example.org")]);
+        assert_eq!(end, "web.example.org");
+    }
+
+    #[test]
+    fn cache() {
+        let mut cache = Cache::default();
+        let now = Instant::now();
+        cache.insert(
+            "Example.com",
+            rtype::A,
+            vec![a("example.com", 300), a("example.com", 60)],
+            now,
+        );
+        // Records without TTL are not kept
+        cache.insert("example.org", rtype::A, vec![a("example.org", 0)], now);
+
+        let later = now + Duration::from_secs(10);
+        let records = cache.get("example.COM", rtype::A, later).unwrap();
+        assert_eq!(records, vec![a("example.com", 50), a("example.com", 50)]);
+        assert!(cache.get("example.com", rtype::AAAA, later).is_none());
+        assert!(cache.get("example.org", rtype::A, later).is_none());
+
+        let expired = now + Duration::from_secs(60);
+        assert!(cache.get("example.com", rtype::A, expired).is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let event_loop = EventLoop::new().unwrap();
+        let resolver = Resolver::new(Sockets::new(event_loop.waker()), vec![]);
+        assert_eq!(
+            resolver.lookup_ipv4("example.com"),
+            Err(DnsError("no nameservers".to_string()))
+        );
+        assert_eq!(
+            resolver.lookup_ipv4("example..com"),
+            Err(DnsError("invalid name: example..com".to_string()))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::{fmt, net::Ipv4Addr, str::FromStr};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct RouteError(pub String);
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "route: {}", self.0)
+    }
+}
+
+// An address with the length of the network prefix, e.g. 192.0.2.2/24
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Prefix {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+}
+
+impl Prefix {
+    pub fn new(address: Ipv4Addr, len: u32) -> Prefix {
+        let netmask = u32::MAX.checked_shl(32 - len.min(32)).unwrap_or(0);
+        Prefix {
+            address,
+            netmask: Ipv4Addr::from(netmask),
+        }
+    }
+
+    pub fn length(&self) -> u32 {
+        u32::from(self.netmask).count_ones()
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) & u32::from(self.netmask))
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & u32::from(self.netmask) == u32::from(self.network())
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length())
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = RouteError;
+
+    // "default" for 0.0.0.0/0 and an address without the length for /32
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "default" {
+            return Ok(Prefix::new(Ipv4Addr::UNSPECIFIED, 0));
+        }
+        let error = || RouteError(format!("invalid prefix: {}", s));
+        let (address, len) = s.split_once('/').unwrap_or((s, "32"));
+        let len: u32 = len.parse().map_err(|_| error())?;
+        if len > 32 {
+            return Err(error());
+        }
+        Ok(Prefix::new(address.parse().map_err(|_| error())?, len))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub destination: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    // None for directly connected networks
+    pub gateway: Option<Ipv4Addr>,
+    pub interface: usize,
+}
+
+impl Route {
+    pub fn new(destination: Ipv4Addr, netmask: Ipv4Addr, interface: usize) -> Route {
+        Route {
+            destination,
+            netmask,
+            gateway: None,
+            interface,
+        }
+    }
+
+    // A route through a gateway on the interface
+    pub fn via(
+        destination: Ipv4Addr,
+        netmask: Ipv4Addr,
+        gateway: Ipv4Addr,
+        interface: usize,
+    ) -> Route {
+        Route {
+            gateway: Some(gateway),
+            ..Route::new(destination, netmask, interface)
+        }
+    }
+
+    // Routes are the same if they have the same network, e.g. 192.0.2.2/24 of
+    // a directly connected network and 192.0.2.0/24
+    fn _same(&self, other: &Route) -> bool {
+        self.netmask == other.netmask
+            && self._matches(other.destination)
+            && self.gateway == other.gateway
+            && self.interface == other.interface
+    }
+
+    fn _matches(&self, dst: Ipv4Addr) -> bool {
+        let netmask = u32::from(self.netmask);
+        u32::from(dst) & netmask == u32::from(self.destination) & netmask
+    }
+}
+
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable { routes: vec![] }
+    }
+
+    pub fn add(&mut self, route: Route) {
+        self.routes.push(route);
+    }
+
+    // Returns false if the same route exists
+    pub fn insert(&mut self, route: Route) -> bool {
+        if self.routes.iter().any(|r| r._same(&route)) {
+            return false;
+        }
+        self.routes.push(route);
+        true
+    }
+
+    // Remove the route. Returns false if it does not exist.
+    pub fn delete(&mut self, route: &Route) -> bool {
+        let n = self.routes.len();
+        self.routes.retain(|r| !r._same(route));
+        self.routes.len() != n
+    }
+
+    // Remove all the routes through the interface
+    pub fn remove(&mut self, interface: usize) {
+        self.routes.retain(|route| route.interface != interface);
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    // Longest prefix match
+    pub fn lookup(&self, dst: Ipv4Addr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route._matches(dst))
+            .max_by_key(|route| u32::from(route.netmask).count_ones())
+    }
+}
+
+impl Default for RoutingTable {
+    fn default() -> Self {
+        RoutingTable::new()
+    }
+}
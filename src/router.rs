@@ -0,0 +1,513 @@
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use log::debug;
+
+use crate::{
+    conntrack::{Conntrack, State, Tracked},
+    firewall::{Action, Firewall, Hook},
+    icmp::{DestinationUnreachableCode, Icmp, TimeExceededCode},
+    ipv4::IPv4,
+    protocol::{Protocol, ProtocolError},
+    route::{Route, RoutingTable},
+    stats::{IPv4Stats, IcmpStats},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Interface {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub mtu: usize,
+}
+
+impl Interface {
+    pub fn new(address: Ipv4Addr, netmask: Ipv4Addr, mtu: usize) -> Interface {
+        Interface {
+            address,
+            netmask,
+            mtu,
+        }
+    }
+
+    // An interface without an address, e.g. waiting for DHCP
+    pub fn unconfigured(mtu: usize) -> Interface {
+        Interface::new(Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, mtu)
+    }
+
+    fn _is_configured(&self) -> bool {
+        !self.address.is_unspecified()
+    }
+
+    // The subnet-directed broadcast address
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !u32::from(self.netmask))
+    }
+}
+
+// The datagrams to send with the indexes of the egress interfaces
+type Datagrams = Vec<(usize, Vec<u8>)>;
+
+// RFC 1812
+//
+// The interfaces and the routes can be changed at runtime, e.g. by DHCP.
+pub struct Router {
+    ipv4: IPv4,
+    interfaces: RwLock<Vec<Interface>>,
+    routes: RwLock<RoutingTable>,
+    firewall: Firewall,
+    conntrack: Conntrack,
+}
+
+impl Router {
+    pub fn new(ipv4: IPv4, interfaces: Vec<Interface>, mut routes: RoutingTable) -> Router {
+        // Directly connected networks
+        for (i, interface) in interfaces.iter().enumerate() {
+            if interface._is_configured() {
+                routes.add(Route::new(interface.address, interface.netmask, i));
+            }
+        }
+        Router {
+            ipv4,
+            interfaces: RwLock::new(interfaces),
+            routes: RwLock::new(routes),
+            firewall: Firewall::default(),
+            conntrack: Conntrack::new(),
+        }
+    }
+
+    pub fn ipv4_stats(&self) -> &Arc<IPv4Stats> {
+        self.ipv4.stats()
+    }
+
+    pub fn icmp_stats(&self) -> &Arc<IcmpStats> {
+        self.ipv4.icmp_stats()
+    }
+
+    pub fn firewall(&self) -> &Firewall {
+        &self.firewall
+    }
+
+    pub fn conntrack(&self) -> &Conntrack {
+        &self.conntrack
+    }
+
+    pub fn interface(&self, index: usize) -> Interface {
+        self.interfaces.read().unwrap()[index]
+    }
+
+    // Replace the configuration of the interface. Only the route of the
+    // directly connected network follows the address; the other routes
+    // through the interface are kept.
+    pub fn set_interface(&self, index: usize, interface: Interface) {
+        let mut routes = self.routes.write().unwrap();
+        let mut interfaces = self.interfaces.write().unwrap();
+        let old = interfaces[index];
+        if old._is_configured() {
+            routes.delete(&Route::new(old.address, old.netmask, index));
+        }
+        if interface._is_configured() {
+            routes.insert(Route::new(interface.address, interface.netmask, index));
+        }
+        interfaces[index] = interface;
+    }
+
+    pub fn interfaces(&self) -> Vec<Interface> {
+        self.interfaces.read().unwrap().clone()
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        self.routes.read().unwrap().routes().to_vec()
+    }
+
+    // Returns false if the same route exists
+    pub fn add_route(&self, route: Route) -> bool {
+        self.routes.write().unwrap().insert(route)
+    }
+
+    // Returns false if the route does not exist
+    pub fn remove_route(&self, route: &Route) -> bool {
+        self.routes.write().unwrap().delete(route)
+    }
+
+    // The address to resolve the link-layer address for
+    pub fn next_hop(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        if dst.is_broadcast() || dst.is_multicast() {
+            return dst;
+        }
+        let routes = self.routes.read().unwrap();
+        routes.lookup(dst).and_then(|r| r.gateway).unwrap_or(dst)
+    }
+
+    fn _is_broadcast(&self, index: usize, dst: Ipv4Addr) -> bool {
+        let interface = self.interface(index);
+        dst.is_broadcast() || (interface._is_configured() && dst == interface.broadcast())
+    }
+
+    // Returns the datagrams to send with the indexes of the egress interfaces
+    pub fn input(&self, index: usize, buf: &[u8]) -> Result<Vec<(usize, Vec<u8>)>, ProtocolError> {
+        if let Err(e) = self.ipv4.verify(buf) {
+            debug!("interface {}: dropped: {}", index, e);
+            return Err(e);
+        }
+        let tracked = match self.conntrack.follow(buf, Instant::now()) {
+            Some(tracked) => tracked,
+            None => {
+                debug!("interface {}: dropped: conntrack table full", index);
+                self.ipv4.stats().in_discards.increment();
+                return Err(ProtocolError::General);
+            }
+        };
+        let state = tracked.state;
+        if let Some(result) = self._filter(Hook::Prerouting, buf, &tracked) {
+            return result;
+        }
+        if let Some(Action::Dnat(address, port)) = self.firewall.nat(Hook::Prerouting, buf, state) {
+            if !self.conntrack.bind_destination(&tracked, address, port) {
+                debug!("{}: translated destination in use", address);
+                self.conntrack.discard(&tracked);
+                self.ipv4.stats().in_discards.increment();
+                return Err(ProtocolError::General);
+            }
+        }
+        // Translated before routing, e.g. replies to the inside
+        let mut buf = buf.to_vec();
+        self.conntrack.translate_destination(&tracked, &mut buf);
+        let buf = &buf[..];
+
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        // Broadcasts are delivered but never answered
+        if self._is_broadcast(index, dst) {
+            if self.firewall.check(Hook::Input, buf, state) == Action::Accept {
+                let _ = self.ipv4.reply(buf);
+            } else {
+                self.conntrack.discard(&tracked);
+                self.ipv4.stats().in_discards.increment();
+            }
+            return Err(ProtocolError::General);
+        }
+        // RFC 1112: delivered only for the groups joined on the interface
+        // and never forwarded
+        if dst.is_multicast() {
+            if !self.ipv4.is_member(index, dst) {
+                debug!("interface {}: not a member of {}", index, dst);
+                self.conntrack.discard(&tracked);
+                self.ipv4.stats().in_discards.increment();
+            } else if self.firewall.check(Hook::Input, buf, state) == Action::Accept {
+                let _ = self.ipv4.receive_multicast(index, buf);
+            } else {
+                self.conntrack.discard(&tracked);
+                self.ipv4.stats().in_discards.increment();
+            }
+            return Err(ProtocolError::General);
+        }
+
+        let local = self
+            .interfaces
+            .read()
+            .unwrap()
+            .iter()
+            .any(|i| i.address == dst);
+        if local {
+            if let Some(result) = self._filter(Hook::Input, buf, &tracked) {
+                return result;
+            }
+            let reply = match self.ipv4.reply(buf) {
+                Ok(reply) => reply,
+                // Delivered without a reply
+                Err(ProtocolError::General) => return Err(ProtocolError::General),
+                Err(e) => {
+                    self.conntrack.discard(&tracked);
+                    return Err(e);
+                }
+            };
+            let accepted = self._accept_output(&reply);
+            // Answered only with an error, e.g. Port Unreachable
+            if Router::_is_icmp_error(&reply) {
+                self.conntrack.discard(&tracked);
+            }
+            if accepted.is_none() {
+                return Err(ProtocolError::General);
+            }
+            return Ok(vec![(index, reply)]);
+        }
+        self._forward(index, buf, tracked)
+    }
+
+    // Returns the datagrams sent by the protocols with the indexes of the
+    // egress interfaces
+    pub fn poll(&self) -> Vec<(usize, Vec<u8>)> {
+        let source = |dst, interface| self._source(dst, interface);
+        let mut datagrams = vec![];
+        for (interface, buf) in self.ipv4.poll(&source) {
+            if self._accept_output(&buf).is_none() {
+                continue;
+            }
+            let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+            let index = match interface {
+                Some(index) => index,
+                None => match self.routes.read().unwrap().lookup(dst) {
+                    Some(route) => route.interface,
+                    None => {
+                        self.ipv4.stats().out_no_routes.increment();
+                        continue;
+                    }
+                },
+            };
+
+            let mtu = self.interface(index).mtu;
+            if buf.len() <= mtu {
+                datagrams.push((index, buf));
+            } else {
+                // Flags (Don't Fragment) may be set by raw sockets
+                let fragments = self._fragment(&buf, mtu);
+                datagrams.extend(fragments.into_iter().map(|fragment| (index, fragment)));
+            }
+        }
+        datagrams
+    }
+
+    // The address and the MTU of the interface to send datagrams to the
+    // destination. The address is unspecified if the interface is not
+    // configured yet.
+    fn _source(&self, dst: Ipv4Addr, interface: Option<usize>) -> Option<(Ipv4Addr, usize)> {
+        let index = match interface {
+            Some(index) => index,
+            None => self.routes.read().unwrap().lookup(dst)?.interface,
+        };
+        self.interfaces
+            .read()
+            .unwrap()
+            .get(index)
+            .map(|interface| (interface.address, interface.mtu))
+    }
+
+    // Fragments of a datagram larger than the MTU, or nothing if the Don't
+    // Fragment flag is set
+    fn _fragment(&self, buf: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        let stats = self.ipv4.stats();
+        stats.out_frag_reqds.increment();
+        if buf[6] & 0x40 != 0 {
+            stats.out_frag_fails.increment();
+            return vec![];
+        }
+        let fragments = IPv4::fragment(buf, mtu);
+        stats.out_frag_oks.increment();
+        stats.out_frag_creates.add(fragments.len() as u64);
+        fragments
+    }
+
+    // RFC 1812 5.3.7
+    fn _is_martian(dst: Ipv4Addr) -> bool {
+        dst.octets()[0] == 0 || dst.is_loopback() || (dst.octets()[0] >= 240 && !dst.is_broadcast())
+    }
+
+    fn _forward(
+        &self,
+        ingress: usize,
+        buf: &[u8],
+        tracked: Tracked,
+    ) -> Result<Vec<(usize, Vec<u8>)>, ProtocolError> {
+        let state = tracked.state;
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        if Router::_is_martian(dst) {
+            debug!("{} > {}: invalid destination", src, dst);
+            self.conntrack.discard(&tracked);
+            self.ipv4.stats().in_addr_errors.increment();
+            return Err(ProtocolError::General);
+        }
+        // Time to Live
+        if buf[8] <= 1 {
+            debug!("{} > {}: time exceeded", src, dst);
+            let icmp = Icmp::time_exceeded(TimeExceededCode::Ttl, buf);
+            return self._error(buf, &icmp, &tracked);
+        }
+
+        let route = self.routes.read().unwrap().lookup(dst).map(|r| r.interface);
+        let index = match route {
+            Some(index) => index,
+            None => {
+                debug!("{} > {}: no route", src, dst);
+                self.ipv4.stats().in_no_routes.increment();
+                let icmp = Icmp::destination_unreachable(DestinationUnreachableCode::Net, buf, 0);
+                return self._error(buf, &icmp, &tracked);
+            }
+        };
+
+        if let Some(result) = self._filter(Hook::Forward, buf, &tracked) {
+            return result;
+        }
+
+        self.ipv4.stats().in_forw_datagrams.increment();
+        let mtu = self.interface(index).mtu;
+        // Flags (Don't Fragment)
+        if buf.len() > mtu && buf[6] & 0x40 != 0 {
+            self.ipv4.stats().out_frag_reqds.increment();
+            self.ipv4.stats().out_frag_fails.increment();
+            debug!("{} > {}: fragmentation needed for MTU {}", src, dst, mtu);
+            let icmp = Icmp::destination_unreachable(
+                DestinationUnreachableCode::FragmentationNeeded,
+                buf,
+                mtu as u16,
+            );
+            return self._error(buf, &icmp, &tracked);
+        }
+
+        let mut buf = buf.to_vec();
+        IPv4::decrement_ttl(&mut buf);
+        if let Some(result) = self._filter(Hook::Postrouting, &buf, &tracked) {
+            return result;
+        }
+        let nat = match self.firewall.nat(Hook::Postrouting, &buf, state) {
+            // Hairpinning: the replies from a redirected connection back to
+            // the same link have to come through the router
+            None if state == State::New
+                && index == ingress
+                && self.conntrack.is_redirected(&tracked) =>
+            {
+                Some(Action::Masquerade)
+            }
+            nat => nat,
+        };
+        if let Some(action) = nat {
+            let address = match action {
+                Action::Snat(address) => address,
+                _ => self.interface(index).address,
+            };
+            if !self.conntrack.bind_source(&tracked, address) {
+                debug!("{} > {}: no port to translate to", src, dst);
+                self.conntrack.discard(&tracked);
+                self.ipv4.stats().out_discards.increment();
+                return Err(ProtocolError::General);
+            }
+        }
+        self.conntrack.translate_source(&tracked, &mut buf);
+        self.ipv4.stats().out_forw_datagrams.increment();
+        if buf.len() <= mtu {
+            return Ok(vec![(index, buf)]);
+        }
+        Ok(self
+            ._fragment(&buf, mtu)
+            .into_iter()
+            .map(|fragment| (index, fragment))
+            .collect())
+    }
+
+    // Check the rules for a received datagram at the hook. Returns the
+    // result of the input unless the datagram is accepted.
+    fn _filter(
+        &self,
+        hook: Hook,
+        buf: &[u8],
+        tracked: &Tracked,
+    ) -> Option<Result<Datagrams, ProtocolError>> {
+        let action = self.firewall.check(hook, buf, tracked.state);
+        if action == Action::Accept {
+            return None;
+        }
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+        debug!("{} > {}: {} at {}", src, dst, action, hook);
+        match hook {
+            Hook::Postrouting => self.ipv4.stats().out_discards.increment(),
+            _ => self.ipv4.stats().in_discards.increment(),
+        }
+        if action == Action::Reject {
+            let icmp = Icmp::destination_unreachable(
+                DestinationUnreachableCode::AdministrativelyProhibited,
+                buf,
+                0,
+            );
+            return Some(self._error(buf, &icmp, tracked));
+        }
+        self.conntrack.discard(tracked);
+        Some(Err(ProtocolError::General))
+    }
+
+    // Track a datagram sent by this host and check the rules at output and
+    // postrouting. Returns the tracked datagram if it is accepted.
+    fn _accept_output(&self, buf: &[u8]) -> Option<Tracked> {
+        let accepted = self
+            .conntrack
+            .follow(buf, Instant::now())
+            .filter(|tracked| {
+                let accept = [Hook::Output, Hook::Postrouting]
+                    .iter()
+                    .all(|&hook| self.firewall.check(hook, buf, tracked.state) == Action::Accept);
+                if !accept {
+                    self.conntrack.discard(tracked);
+                }
+                accept
+            });
+        if accepted.is_none() {
+            let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+            let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+            debug!("{} > {}: dropped at output", src, dst);
+            self.ipv4.stats().out_discards.increment();
+        }
+        accepted
+    }
+
+    // Answer a datagram only with an ICMP error. The connection created by
+    // the datagram is discarded once the error has been translated.
+    fn _error(
+        &self,
+        original: &[u8],
+        icmp: &[u8],
+        tracked: &Tracked,
+    ) -> Result<Vec<(usize, Vec<u8>)>, ProtocolError> {
+        let result = self._send_error(original, icmp);
+        self.conntrack.discard(tracked);
+        result
+    }
+
+    // Send an ICMP error message back to the source of the original datagram.
+    // The error is related to the connection of the datagram, and the
+    // translation of the datagram is reversed in the error, as in netfilter,
+    // so the source sees the addresses and ports which it sent to.
+    fn _send_error(
+        &self,
+        original: &[u8],
+        icmp: &[u8],
+    ) -> Result<Vec<(usize, Vec<u8>)>, ProtocolError> {
+        if !self._should_send_error(original) {
+            return Err(ProtocolError::General);
+        }
+
+        let src = Ipv4Addr::new(original[12], original[13], original[14], original[15]);
+        let route = self.routes.read().unwrap().lookup(src).map(|r| r.interface);
+        let index = route.ok_or(ProtocolError::General)?;
+        let address = self.interface(index).address;
+        let mut datagram = self.ipv4.datagram(address, src, Icmp::NUMBER, icmp);
+        let tracked = self
+            ._accept_output(&datagram)
+            .ok_or(ProtocolError::General)?;
+        self.conntrack
+            .translate_destination(&tracked, &mut datagram);
+        self.conntrack.translate_source(&tracked, &mut datagram);
+        Ok(vec![(index, datagram)])
+    }
+
+    fn _is_icmp_error(buf: &[u8]) -> bool {
+        let ihl = 4 * (buf[0] & 0xf) as usize;
+        buf[9] == Icmp::NUMBER && buf.len() > ihl && Icmp::is_error(buf[ihl])
+    }
+
+    // RFC 1122 3.2.2
+    fn _should_send_error(&self, buf: &[u8]) -> bool {
+        // Fragment Offset
+        if buf[6] & 0x1f != 0 || buf[7] != 0 {
+            return false;
+        }
+
+        if Router::_is_icmp_error(buf) {
+            return false;
+        }
+
+        let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+        !(src.is_unspecified() || src.is_broadcast() || src.is_multicast())
+    }
+}
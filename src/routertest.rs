@@ -0,0 +1,389 @@
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        icmp::Icmp,
+        ipv4::IPv4,
+        protocol::{get_checksum, ProtocolError},
+        route::{Route, RoutingTable},
+        router::{Interface, Router},
+        testutil::router_with_mtu,
+    };
+
+    fn router(mtu: usize) -> Router {
+        router_with_mtu(IPv4::new(vec![Box::new(Icmp::new())]), mtu)
+    }
+
+    #[test]
+    fn local() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0x49, 0x36, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xc0, 0x00, 0x02, 0x02, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(1500);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![(
+                0,
+                vec![
+                    0x45, // Version, IHL
+                    0x00, // Type of Service
+                    0x00, 0x54, // Total Length
+                    0x6d, 0x6f, // Identification
+                    0x40, 0x00, // Flags, Fragment Offset
+                    0x40, // Time to Live
+                    0x01, // Protocol
+                    0x49, 0x36, // Header Checksum
+                    0xc0, 0x00, 0x02, 0x02, // Source Address
+                    0xc0, 0x00, 0x02, 0x01, // Destination Address
+                    0x00, 0x00, 0x87, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00,
+                    0x00, 0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11,
+                    0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+                    0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b,
+                    0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36,
+                    0x37, // Data
+                ]
+            ),])
+        );
+    }
+
+    #[test]
+    fn forward() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0xe1, 0x03, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xc6, 0x33, 0x64, 0x01, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(1500);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![(
+                1,
+                vec![
+                    0x45, // Version, IHL
+                    0x00, // Type of Service
+                    0x00, 0x54, // Total Length
+                    0x6d, 0x6f, // Identification
+                    0x40, 0x00, // Flags, Fragment Offset
+                    0x3f, // Time to Live
+                    0x01, // Protocol
+                    0xe2, 0x03, // Header Checksum
+                    0xc0, 0x00, 0x02, 0x01, // Source Address
+                    0xc6, 0x33, 0x64, 0x01, // Destination Address
+                    0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00,
+                    0x00, 0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11,
+                    0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+                    0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b,
+                    0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36,
+                    0x37, // Data
+                ]
+            ),])
+        );
+    }
+
+    #[test]
+    fn time_exceeded() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x01, // Time to Live
+            0x01, // Protocol
+            0x20, 0x04, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xc6, 0x33, 0x64, 0x01, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(1500);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![(
+                0,
+                vec![
+                    0x45, // Version, IHL
+                    0x00, // Type of Service
+                    0x00, 0x38, // Total Length
+                    0x00, 0x00, // Identification
+                    0x00, 0x00, // Flags, Fragment Offset
+                    0x40, // Time to Live
+                    0x01, // Protocol
+                    0xf6, 0xc1, // Header Checksum
+                    0xc0, 0x00, 0x02, 0x02, // Source Address
+                    0xc0, 0x00, 0x02, 0x01, // Destination Address
+                    0x0b, 0x00, 0x6d, 0x79, 0x00, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x54, 0x6d,
+                    0x6f, 0x40, 0x00, 0x01, 0x01, 0x20, 0x04, 0xc0, 0x00, 0x02, 0x01, 0xc6, 0x33,
+                    0x64, 0x01, 0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, // Data
+                ]
+            ),])
+        );
+    }
+
+    #[test]
+    fn no_route() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0xcf, 0x36, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xcb, 0x00, 0x71, 0x01, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(1500);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![(
+                0,
+                vec![
+                    0x45, // Version, IHL
+                    0x00, // Type of Service
+                    0x00, 0x38, // Total Length
+                    0x00, 0x00, // Identification
+                    0x00, 0x00, // Flags, Fragment Offset
+                    0x40, // Time to Live
+                    0x01, // Protocol
+                    0xf6, 0xc1, // Header Checksum
+                    0xc0, 0x00, 0x02, 0x02, // Source Address
+                    0xc0, 0x00, 0x02, 0x01, // Destination Address
+                    0x03, 0x00, 0x75, 0x79, 0x00, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x54, 0x6d,
+                    0x6f, 0x40, 0x00, 0x40, 0x01, 0xcf, 0x36, 0xc0, 0x00, 0x02, 0x01, 0xcb, 0x00,
+                    0x71, 0x01, 0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, // Data
+                ]
+            ),])
+        );
+    }
+
+    #[test]
+    fn fragmentation_needed() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0xe1, 0x03, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xc6, 0x33, 0x64, 0x01, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(68);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![(
+                0,
+                vec![
+                    0x45, // Version, IHL
+                    0x00, // Type of Service
+                    0x00, 0x38, // Total Length
+                    0x00, 0x00, // Identification
+                    0x00, 0x00, // Flags, Fragment Offset
+                    0x40, // Time to Live
+                    0x01, // Protocol
+                    0xf6, 0xc1, // Header Checksum
+                    0xc0, 0x00, 0x02, 0x02, // Source Address
+                    0xc0, 0x00, 0x02, 0x01, // Destination Address
+                    0x03, 0x04, 0x75, 0x31, 0x00, 0x00, 0x00, 0x44, 0x45, 0x00, 0x00, 0x54, 0x6d,
+                    0x6f, 0x40, 0x00, 0x40, 0x01, 0xe1, 0x03, 0xc0, 0x00, 0x02, 0x01, 0xc6, 0x33,
+                    0x64, 0x01, 0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, // Data
+                ]
+            ),])
+        );
+    }
+
+    #[test]
+    fn fragment() {
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x54, // Total Length
+            0x6d, 0x6f, // Identification
+            0x00, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0x21, 0x04, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xc6, 0x33, 0x64, 0x01, // Destination Address
+            0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63, 0x00, 0x00,
+            0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13,
+            0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21,
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+            0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, // Data
+        ];
+        let router = router(68);
+        let datagrams = router.input(0, &buf);
+        assert_eq!(
+            datagrams,
+            Ok(vec![
+                (
+                    1,
+                    vec![
+                        0x45, // Version, IHL
+                        0x00, // Type of Service
+                        0x00, 0x44, // Total Length
+                        0x6d, 0x6f, // Identification
+                        0x20, 0x00, // Flags, Fragment Offset
+                        0x3f, // Time to Live
+                        0x01, // Protocol
+                        0x02, 0x14, // Header Checksum
+                        0xc0, 0x00, 0x02, 0x01, // Source Address
+                        0xc6, 0x33, 0x64, 0x01, // Destination Address
+                        0x08, 0x00, 0x7f, 0x57, 0x00, 0x2d, 0x00, 0x02, 0xf3, 0x89, 0x8d, 0x63,
+                        0x00, 0x00, 0x00, 0x00, 0x35, 0xb9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+                        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
+                        0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26,
+                        0x27, // Data
+                    ]
+                ),
+                (
+                    1,
+                    vec![
+                        0x45, // Version, IHL
+                        0x00, // Type of Service
+                        0x00, 0x24, // Total Length
+                        0x6d, 0x6f, // Identification
+                        0x00, 0x06, // Flags, Fragment Offset
+                        0x3f, // Time to Live
+                        0x01, // Protocol
+                        0x22, 0x2e, // Header Checksum
+                        0xc0, 0x00, 0x02, 0x01, // Source Address
+                        0xc6, 0x33, 0x64, 0x01, // Destination Address
+                        0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33,
+                        0x34, 0x35, 0x36, 0x37, // Data
+                    ]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn broadcast() {
+        let router = router(1500);
+        // An echo request to the limited broadcast address
+        let buf = [
+            0x45, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x1c, // Total Length
+            0x00, 0x01, // Identification
+            0x00, 0x00, // Flags, Fragment Offset
+            0x40, // Time to Live
+            0x01, // Protocol
+            0xb8, 0xdf, // Header Checksum
+            0xc0, 0x00, 0x02, 0x01, // Source Address
+            0xff, 0xff, 0xff, 0xff, // Destination Address
+            0x08, 0x00, 0xf7, 0xff, 0x00, 0x00, 0x00, 0x00, // Data
+        ];
+        assert_eq!(router.input(0, &buf), Err(ProtocolError::General));
+    }
+
+    #[test]
+    fn set_interface() {
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let interfaces = vec![
+            Interface::new(Ipv4Addr::new(192, 0, 2, 2), netmask, 1500),
+            Interface::unconfigured(1500),
+        ];
+        let ipv4 = IPv4::new(vec![Box::new(Icmp::new())]);
+        let router = Router::new(ipv4, interfaces, RoutingTable::new());
+
+        let gateway = Ipv4Addr::new(203, 0, 113, 1);
+        let dst = Ipv4Addr::new(198, 51, 100, 1);
+        assert_eq!(router.next_hop(dst), dst);
+
+        let address = Ipv4Addr::new(203, 0, 113, 2);
+        router.set_interface(1, Interface::new(address, netmask, 1400));
+        let any = Ipv4Addr::UNSPECIFIED;
+        router.add_route(Route::via(any, any, gateway, 1));
+        assert_eq!(router.interface(1).address, address);
+        assert_eq!(router.next_hop(dst), gateway);
+        assert_eq!(router.next_hop(gateway), gateway);
+        assert_eq!(router.next_hop(Ipv4Addr::BROADCAST), Ipv4Addr::BROADCAST);
+
+        // Only the route of the connected network follows the address
+        let static_route = Route::via(dst, netmask, gateway, 1);
+        router.add_route(static_route.clone());
+        let renumbered = Ipv4Addr::new(203, 0, 113, 3);
+        router.set_interface(1, Interface::new(renumbered, netmask, 1400));
+        router.set_interface(1, Interface::unconfigured(1500));
+        assert_eq!(
+            router.routes(),
+            vec![
+                Route::new(Ipv4Addr::new(192, 0, 2, 2), netmask, 0),
+                Route::via(any, any, gateway, 1),
+                static_route,
+            ]
+        );
+    }
+
+    #[test]
+    fn short_header() {
+        let mut buf = vec![
+            0x42, // Version, IHL
+            0x00, // Type of Service
+            0x00, 0x1c, // Total Length
+            0x00, 0x00, // Identification
+            0x40, 0x00, // Flags, Fragment Offset
+            0x08, 0x01, 0x00, 0x00, 0xc0, 0x00, 0x02, 0x01, 0xc0, 0x00, 0x02, 0x02, 0x08, 0x00,
+            0xf7, 0xff, 0x00, 0x00, 0x00, 0x00,
+        ];
+        // The Identification makes the checksum of the 8 octets valid
+        let checksum = get_checksum(&buf[..8]);
+        buf[4..6].copy_from_slice(&checksum.to_be_bytes());
+        assert_eq!(get_checksum(&buf[..8]), 0);
+
+        let router = router(1500);
+        assert!(matches!(router.input(0, &buf), Err(ProtocolError::IPv4(_))));
+        assert_eq!(router.ipv4_stats().in_hdr_errors.get(), 1);
+        assert_eq!(router.ipv4_stats().in_forw_datagrams.get(), 0);
+    }
+}
@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::route::{Route, RoutingTable};
+
+    #[test]
+    fn longest_prefix_match() {
+        let mut routes = RoutingTable::new();
+        routes.add(Route::new(
+            Ipv4Addr::new(0, 0, 0, 0),
+            Ipv4Addr::new(0, 0, 0, 0),
+            0,
+        ));
+        routes.add(Route::new(
+            Ipv4Addr::new(198, 51, 100, 0),
+            Ipv4Addr::new(255, 255, 255, 0),
+            1,
+        ));
+        routes.add(Route::new(
+            Ipv4Addr::new(198, 51, 100, 128),
+            Ipv4Addr::new(255, 255, 255, 128),
+            2,
+        ));
+
+        let route = routes.lookup(Ipv4Addr::new(198, 51, 100, 1));
+        assert_eq!(route.map(|r| r.interface), Some(1));
+
+        let route = routes.lookup(Ipv4Addr::new(198, 51, 100, 129));
+        assert_eq!(route.map(|r| r.interface), Some(2));
+
+        let route = routes.lookup(Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(route.map(|r| r.interface), Some(0));
+    }
+
+    #[test]
+    fn no_route() {
+        let mut routes = RoutingTable::new();
+        routes.add(Route::new(
+            Ipv4Addr::new(198, 51, 100, 0),
+            Ipv4Addr::new(255, 255, 255, 0),
+            1,
+        ));
+
+        let route = routes.lookup(Ipv4Addr::new(203, 0, 113, 1));
+        assert!(route.is_none());
+    }
+
+    #[test]
+    fn remove() {
+        let mut routes = RoutingTable::new();
+        routes.add(Route::new(
+            Ipv4Addr::new(198, 51, 100, 0),
+            Ipv4Addr::new(255, 255, 255, 0),
+            1,
+        ));
+        routes.add(Route::via(
+            Ipv4Addr::new(0, 0, 0, 0),
+            Ipv4Addr::new(0, 0, 0, 0),
+            Ipv4Addr::new(198, 51, 100, 1),
+            1,
+        ));
+
+        let route = routes.lookup(Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(
+            route.and_then(|r| r.gateway),
+            Some(Ipv4Addr::new(198, 51, 100, 1))
+        );
+
+        routes.remove(1);
+        assert!(routes.lookup(Ipv4Addr::new(198, 51, 100, 1)).is_none());
+    }
+
+    #[test]
+    fn insert_delete() {
+        let mut routes = RoutingTable::new();
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        assert!(routes.insert(Route::new(Ipv4Addr::new(192, 0, 2, 2), netmask, 0)));
+        assert!(!routes.insert(Route::new(Ipv4Addr::new(192, 0, 2, 0), netmask, 0)));
+        assert!(routes.insert(Route::new(Ipv4Addr::new(192, 0, 2, 0), netmask, 1)));
+
+        // The directly connected network by the address of the network
+        assert!(routes.delete(&Route::new(Ipv4Addr::new(192, 0, 2, 0), netmask, 0)));
+        assert!(!routes.delete(&Route::new(Ipv4Addr::new(192, 0, 2, 0), netmask, 0)));
+        assert_eq!(routes.routes().len(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    thread,
+};
+
+use nix::Error;
+use serde::Deserialize;
+
+use crate::socket::{SocketHandle, SocketType, Sockets};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    // RFC 862
+    Echo,
+    // RFC 863
+    Discard,
+    // RFC 864
+    Chargen,
+}
+
+impl Service {
+    pub fn port(self) -> u16 {
+        match self {
+            Service::Echo => 7,
+            Service::Discard => 9,
+            Service::Chargen => 19,
+        }
+    }
+
+    // The reply to a datagram if any
+    pub fn reply(self, data: &[u8], chargen: &mut Chargen) -> Option<Vec<u8>> {
+        match self {
+            Service::Echo => Some(data.to_vec()),
+            Service::Discard => None,
+            Service::Chargen => Some(chargen.lines(Chargen::DATAGRAM_LINES)),
+        }
+    }
+}
+
+// The rotating pattern of the printable ASCII characters (RFC 864)
+#[derive(Default)]
+pub struct Chargen {
+    line: usize,
+}
+
+impl Chargen {
+    const LINE_LENGTH: usize = 72;
+    // Keep datagrams within 512 bytes
+    const DATAGRAM_LINES: usize = 6;
+
+    pub fn lines(&mut self, n: usize) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(n * (Chargen::LINE_LENGTH + 2));
+        for _ in 0..n {
+            // From ' ' to '~'
+            buf.extend((0..Chargen::LINE_LENGTH).map(|i| b' ' + ((self.line + i) % 95) as u8));
+            buf.extend_from_slice(b"\r\n");
+            self.line = (self.line + 1) % 95;
+        }
+        buf
+    }
+}
+
+// Simple services over UDP and TCP for exercising the transport layer
+pub struct Services {
+    sockets: Sockets,
+    services: Vec<Service>,
+}
+
+impl Services {
+    // The lines sent at once over TCP
+    const STREAM_LINES: usize = 16;
+
+    pub fn new(sockets: Sockets, services: Vec<Service>) -> Services {
+        Services { sockets, services }
+    }
+
+    // Serve each enabled service on its own threads
+    pub fn start(&self) {
+        for &service in &self.services {
+            let sockets = self.sockets.clone();
+            thread::spawn(move || Services::run_udp(&sockets, service).unwrap());
+            let sockets = self.sockets.clone();
+            thread::spawn(move || Services::run_tcp(&sockets, service).unwrap());
+        }
+    }
+
+    pub fn run_udp(sockets: &Sockets, service: Service) -> Result<(), Error> {
+        let socket = sockets.socket(SocketType::Datagram)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, service.port());
+        sockets.bind(socket, addr)?;
+
+        let mut chargen = Chargen::default();
+        let mut buf = [0u8; 65535];
+        loop {
+            let (n, src) = sockets.recv_from(socket, &mut buf)?;
+            if let Some(reply) = service.reply(&buf[..n], &mut chargen) {
+                sockets.send_to(socket, &reply, src)?;
+            }
+        }
+    }
+
+    pub fn run_tcp(sockets: &Sockets, service: Service) -> Result<(), Error> {
+        let listener = sockets.socket(SocketType::Stream)?;
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, service.port());
+        sockets.bind(listener, addr)?;
+        sockets.listen(listener, 8)?;
+        loop {
+            let (stream, _) = sockets.accept(listener)?;
+            let sockets = sockets.clone();
+            thread::spawn(move || {
+                let _ = Services::_serve_stream(&sockets, service, stream);
+                let _ = sockets.close(stream);
+            });
+        }
+    }
+
+    // Until the client closes the connection
+    fn _serve_stream(
+        sockets: &Sockets,
+        service: Service,
+        stream: SocketHandle,
+    ) -> Result<(), Error> {
+        let mut buf = [0u8; 1024];
+        match service {
+            Service::Echo | Service::Discard => loop {
+                let n = sockets.recv(stream, &mut buf)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                if service == Service::Echo {
+                    Services::_send_all(sockets, stream, &buf[..n])?;
+                }
+            },
+            // The data from the client is ignored
+            Service::Chargen => {
+                let mut chargen = Chargen::default();
+                loop {
+                    Services::_send_all(sockets, stream, &chargen.lines(Services::STREAM_LINES))?;
+                }
+            }
+        }
+    }
+
+    fn _send_all(sockets: &Sockets, stream: SocketHandle, buf: &[u8]) -> Result<(), Error> {
+        let mut sent = 0;
+        while sent < buf.len() {
+            sent += sockets.send(stream, &buf[sent..])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod tests {
+    use crate::services::{Chargen, Service};
+
+    #[test]
+    fn echo() {
+        let mut chargen = Chargen::default();
+        assert_eq!(Service::Echo.port(), 7);
+        assert_eq!(
+            Service::Echo.reply(b"hello", &mut chargen),
+            Some(b"hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn discard() {
+        let mut chargen = Chargen::default();
+        assert_eq!(Service::Discard.port(), 9);
+        assert_eq!(Service::Discard.reply(b"hello", &mut chargen), None);
+    }
+
+    #[test]
+    fn chargen() {
+        let mut chargen = Chargen::default();
+        let buf = chargen.lines(2);
+        assert_eq!(buf.len(), 2 * 74);
+        assert!(buf.starts_with(b" !\"#$%&'()*+,-./0123456789"));
+        assert_eq!(&buf[70..74], b"fg\r\n");
+        // Each line starts one character later
+        assert!(buf[74..].starts_with(b"!\"#$%&"));
+
+        // The pattern wraps around after 95 lines
+        let buf = chargen.lines(93);
+        assert!(buf[92 * 74..].starts_with(b"~ !\"#"));
+        assert_eq!(chargen.lines(1), Chargen::default().lines(1));
+    }
+
+    #[test]
+    fn chargen_datagram() {
+        let mut chargen = Chargen::default();
+        assert_eq!(Service::Chargen.port(), 19);
+        let reply = Service::Chargen.reply(b"", &mut chargen).unwrap();
+        assert!(reply.len() <= 512);
+        let reply = Service::Chargen.reply(b"", &mut chargen).unwrap();
+        // The next datagram continues the pattern
+        assert!(reply.starts_with(b"&'()"));
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet},
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task,
+    time::{Duration, Instant},
+};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    eventloop::Waker,
+    raw::RawPcb,
+    stats::{TcpStats, UdpStats},
+    tcp::{TcpPcb, TcpState},
+    udp::UdpPcb,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketType {
+    // UDP
+    Datagram,
+    // TCP
+    Stream,
+    // IP with the protocol number
+    Raw(u8),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct SocketHandle(usize);
+
+// A socket shown by netstat or ss
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SocketInfo {
+    pub handle: SocketHandle,
+    pub socket_type: SocketType,
+    // The port is 0 for raw sockets
+    pub local: SocketAddrV4,
+    pub remote: Option<SocketAddrV4>,
+    // TCP only
+    pub state: Option<TcpState>,
+    pub recv_queue: usize,
+    pub send_queue: usize,
+}
+
+// The number of the open sockets
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct SocketGauges {
+    pub udp: usize,
+    pub raw: usize,
+    // For each state including the listening sockets
+    pub tcp: Vec<(TcpState, usize)>,
+}
+
+pub(crate) enum Socket {
+    Udp(UdpPcb),
+    Tcp(TcpPcb),
+    Raw(RawPcb),
+}
+
+pub(crate) struct SocketTable {
+    sockets: BTreeMap<SocketHandle, Socket>,
+    // SO_RCVTIMEO
+    read_timeouts: BTreeMap<SocketHandle, Duration>,
+    // O_NONBLOCK
+    nonblocking: BTreeSet<SocketHandle>,
+    // Handles are never reused so that a stale handle cannot refer to
+    // another socket
+    next: usize,
+}
+
+impl SocketTable {
+    // RFC 6335
+    const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+    fn new() -> SocketTable {
+        SocketTable {
+            sockets: BTreeMap::new(),
+            read_timeouts: BTreeMap::new(),
+            nonblocking: BTreeSet::new(),
+            next: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, socket: Socket) -> SocketHandle {
+        let handle = SocketHandle(self.next);
+        self.next += 1;
+        self.sockets.insert(handle, socket);
+        handle
+    }
+
+    pub(crate) fn remove(&mut self, handle: SocketHandle) {
+        self.sockets.remove(&handle);
+        self.read_timeouts.remove(&handle);
+        self.nonblocking.remove(&handle);
+    }
+
+    pub(crate) fn get_mut(&mut self, handle: SocketHandle) -> Result<&mut Socket, Error> {
+        self.sockets.get_mut(&handle).ok_or(Errno::EBADF)
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (SocketHandle, &mut Socket)> {
+        self.sockets
+            .iter_mut()
+            .map(|(handle, socket)| (*handle, socket))
+    }
+
+    pub(crate) fn tcp_mut(&mut self, handle: SocketHandle) -> Option<&mut TcpPcb> {
+        match self.sockets.get_mut(&handle) {
+            Some(Socket::Tcp(pcb)) => Some(pcb),
+            _ => None,
+        }
+    }
+
+    // The local addresses of the sockets which can receive datagrams or
+    // connections by themselves
+    fn _bound(&self, stream: bool) -> impl Iterator<Item = SocketAddrV4> + '_ {
+        self.sockets
+            .values()
+            .filter_map(move |socket| match socket {
+                Socket::Udp(pcb) if !stream && pcb.local.port() != 0 => Some(pcb.local),
+                Socket::Tcp(pcb) if stream && pcb.local.port() != 0 && pcb.parent.is_none() => {
+                    Some(pcb.local)
+                }
+                _ => None,
+            })
+    }
+
+    fn _in_use(&self, stream: bool, addr: SocketAddrV4) -> bool {
+        self._bound(stream).any(|local| {
+            local.port() == addr.port()
+                && (local.ip().is_unspecified()
+                    || addr.ip().is_unspecified()
+                    || local.ip() == addr.ip())
+        })
+    }
+
+    fn _ephemeral_port(&self, stream: bool) -> Result<u16, Error> {
+        let start = *SocketTable::EPHEMERAL_PORTS.start();
+        let count = SocketTable::EPHEMERAL_PORTS.len();
+
+        // Start from a random port to make the ports hard to guess
+        let offset = RandomState::new().build_hasher().finish() as usize;
+        (0..count)
+            .map(|i| start + ((offset + i) % count) as u16)
+            .find(|port| !self._in_use(stream, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, *port)))
+            .ok_or(Errno::EADDRINUSE)
+    }
+
+    // Bind the socket to an ephemeral port unless it is bound already
+    pub(crate) fn bind_ephemeral(&mut self, handle: SocketHandle) -> Result<(), Error> {
+        let stream = match self.get_mut(handle)? {
+            Socket::Udp(pcb) if pcb.local.port() == 0 => false,
+            Socket::Tcp(pcb) if pcb.local.port() == 0 => true,
+            _ => return Ok(()),
+        };
+        let port = self._ephemeral_port(stream)?;
+        match self.get_mut(handle)? {
+            Socket::Udp(pcb) => pcb.local.set_port(port),
+            Socket::Tcp(pcb) => pcb.local.set_port(port),
+            Socket::Raw(_) => {}
+        }
+        Ok(())
+    }
+}
+
+struct Inner {
+    table: Mutex<SocketTable>,
+    cond: Condvar,
+    // The async tasks waiting for the non-blocking sockets
+    tasks: Mutex<Vec<task::Waker>>,
+    waker: Arc<Waker>,
+    udp_stats: Arc<UdpStats>,
+    tcp_stats: Arc<TcpStats>,
+}
+
+// BSD-like sockets on top of the stack
+//
+// The socket calls block the calling thread while the event loop sends and
+// receives the data.
+#[derive(Clone)]
+pub struct Sockets {
+    inner: Arc<Inner>,
+}
+
+impl Sockets {
+    // IP_MAX_MEMBERSHIPS of Linux
+    const MAX_MEMBERSHIPS: usize = 20;
+
+    pub fn new(waker: Arc<Waker>) -> Sockets {
+        Sockets {
+            inner: Arc::new(Inner {
+                table: Mutex::new(SocketTable::new()),
+                cond: Condvar::new(),
+                tasks: Mutex::new(vec![]),
+                waker,
+                udp_stats: Arc::default(),
+                tcp_stats: Arc::default(),
+            }),
+        }
+    }
+
+    pub fn udp_stats(&self) -> &Arc<UdpStats> {
+        &self.inner.udp_stats
+    }
+
+    pub fn tcp_stats(&self) -> &Arc<TcpStats> {
+        &self.inner.tcp_stats
+    }
+
+    pub fn list(&self) -> Vec<SocketInfo> {
+        let mut table = self.lock();
+        table
+            .iter_mut()
+            .map(|(handle, socket)| {
+                let (socket_type, local, remote, state, (recv_queue, send_queue)) = match socket {
+                    Socket::Udp(pcb) => (
+                        SocketType::Datagram,
+                        pcb.local,
+                        pcb.remote,
+                        None,
+                        pcb.queues(),
+                    ),
+                    Socket::Tcp(pcb) => {
+                        let remote = match pcb.state {
+                            TcpState::Closed | TcpState::Listen => None,
+                            _ => Some(pcb.remote),
+                        };
+                        (
+                            SocketType::Stream,
+                            pcb.local,
+                            remote,
+                            Some(pcb.state),
+                            pcb.queues(),
+                        )
+                    }
+                    Socket::Raw(pcb) => (
+                        SocketType::Raw(pcb.protocol),
+                        SocketAddrV4::new(pcb.local, 0),
+                        pcb.remote.map(|remote| SocketAddrV4::new(remote, 0)),
+                        None,
+                        pcb.queues(),
+                    ),
+                };
+                SocketInfo {
+                    handle,
+                    socket_type,
+                    local,
+                    remote,
+                    state,
+                    recv_queue,
+                    send_queue,
+                }
+            })
+            .collect()
+    }
+
+    pub fn gauges(&self) -> SocketGauges {
+        let mut gauges = SocketGauges {
+            tcp: TcpState::ALL.iter().map(|&state| (state, 0)).collect(),
+            ..SocketGauges::default()
+        };
+        let mut table = self.lock();
+        for (_, socket) in table.iter_mut() {
+            match socket {
+                Socket::Udp(_) => gauges.udp += 1,
+                Socket::Raw(_) => gauges.raw += 1,
+                Socket::Tcp(pcb) => {
+                    if let Some((_, n)) = gauges.tcp.iter_mut().find(|(s, _)| *s == pcb.state) {
+                        *n += 1;
+                    }
+                }
+            }
+        }
+        gauges
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, SocketTable> {
+        self.inner.table.lock().unwrap()
+    }
+
+    // Wake up the threads blocked in socket calls and the tasks watching
+    // the sockets
+    pub(crate) fn notify(&self) {
+        self.inner.cond.notify_all();
+        for waker in self.inner.tasks.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+
+    // Wake up the task at the next change of the sockets. Call this before
+    // trying a non-blocking call so that a change in between is not missed.
+    pub fn watch(&self, waker: &task::Waker) {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        if !tasks.iter().any(|task| task.will_wake(waker)) {
+            tasks.push(waker.clone());
+        }
+    }
+
+    fn _wait<'a>(&self, guard: MutexGuard<'a, SocketTable>) -> MutexGuard<'a, SocketTable> {
+        self.inner.cond.wait(guard).unwrap()
+    }
+
+    // Returns None if the deadline has passed
+    fn _wait_until<'a>(
+        &self,
+        guard: MutexGuard<'a, SocketTable>,
+        deadline: Option<Instant>,
+    ) -> Option<MutexGuard<'a, SocketTable>> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return Some(self._wait(guard)),
+        };
+        let timeout = deadline.checked_duration_since(Instant::now())?;
+        let (guard, _) = self.inner.cond.wait_timeout(guard, timeout).unwrap();
+        Some(guard)
+    }
+
+    // Let the event loop send the data
+    fn _wake(&self) {
+        self.inner.waker.wake();
+    }
+
+    pub fn socket(&self, socket_type: SocketType) -> Result<SocketHandle, Error> {
+        let socket = match socket_type {
+            SocketType::Datagram => Socket::Udp(UdpPcb::new()),
+            SocketType::Stream => Socket::Tcp(TcpPcb::new(self.tcp_stats().clone())),
+            SocketType::Raw(protocol) => Socket::Raw(RawPcb::new(protocol)),
+        };
+        Ok(self.lock().insert(socket))
+    }
+
+    pub fn bind(&self, handle: SocketHandle, addr: SocketAddrV4) -> Result<(), Error> {
+        let mut table = self.lock();
+        let stream = match table.get_mut(handle)? {
+            Socket::Udp(pcb) if pcb.local.port() == 0 => false,
+            Socket::Tcp(pcb) if pcb.local.port() == 0 => true,
+            Socket::Raw(pcb) => {
+                pcb.local = *addr.ip();
+                return Ok(());
+            }
+            _ => return Err(Errno::EINVAL),
+        };
+
+        let addr = if addr.port() == 0 {
+            SocketAddrV4::new(*addr.ip(), table._ephemeral_port(stream)?)
+        } else if table._in_use(stream, addr) {
+            return Err(Errno::EADDRINUSE);
+        } else {
+            addr
+        };
+        match table.get_mut(handle)? {
+            Socket::Udp(pcb) => pcb.local = addr,
+            Socket::Tcp(pcb) => pcb.local = addr,
+            Socket::Raw(_) => {}
+        }
+        Ok(())
+    }
+
+    pub fn connect(&self, handle: SocketHandle, addr: SocketAddrV4) -> Result<(), Error> {
+        let mut table = self.lock();
+        table.bind_ephemeral(handle)?;
+        match table.get_mut(handle)? {
+            Socket::Udp(pcb) => {
+                pcb.remote = Some(addr);
+                return Ok(());
+            }
+            Socket::Raw(pcb) => {
+                pcb.remote = Some(*addr.ip());
+                return Ok(());
+            }
+            Socket::Tcp(pcb) => match pcb.state {
+                // Called again on a non-blocking socket
+                TcpState::SynSent | TcpState::SynReceived => return Err(Errno::EALREADY),
+                TcpState::Closed if pcb.error.is_some() => return Err(pcb.error.take().unwrap()),
+                _ => pcb.connect(addr)?,
+            },
+        }
+        self._wake();
+
+        // Wait for the three-way handshake
+        let nonblocking = table.nonblocking.contains(&handle);
+        loop {
+            let pcb = table.tcp_mut(handle).ok_or(Errno::EBADF)?;
+            match pcb.state {
+                TcpState::SynSent | TcpState::SynReceived if nonblocking => {
+                    return Err(Errno::EINPROGRESS)
+                }
+                TcpState::SynSent | TcpState::SynReceived => table = self._wait(table),
+                TcpState::Closed => return Err(pcb.error.take().unwrap_or(Errno::ECONNREFUSED)),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    pub fn listen(&self, handle: SocketHandle, backlog: usize) -> Result<(), Error> {
+        let mut table = self.lock();
+        table.bind_ephemeral(handle)?;
+        match table.get_mut(handle)? {
+            Socket::Tcp(pcb) => pcb.listen(backlog),
+            _ => Err(Errno::EOPNOTSUPP),
+        }
+    }
+
+    pub fn accept(&self, handle: SocketHandle) -> Result<(SocketHandle, SocketAddrV4), Error> {
+        let mut table = self.lock();
+        loop {
+            let listener = match table.get_mut(handle)? {
+                Socket::Tcp(pcb) if pcb.state == TcpState::Listen => pcb,
+                Socket::Tcp(_) => return Err(Errno::EINVAL),
+                _ => return Err(Errno::EOPNOTSUPP),
+            };
+
+            match listener.backlog.pop_front() {
+                Some(child) => {
+                    // The connection may have been reset before being accepted
+                    if let Some(pcb) = table.tcp_mut(child) {
+                        if pcb.state != TcpState::Closed {
+                            pcb.parent = None;
+                            pcb.closed = false;
+                            return Ok((child, pcb.remote));
+                        }
+                    }
+                }
+                None if table.nonblocking.contains(&handle) => return Err(Errno::EAGAIN),
+                None => table = self._wait(table),
+            }
+        }
+    }
+
+    pub fn send(&self, handle: SocketHandle, buf: &[u8]) -> Result<usize, Error> {
+        let mut table = self.lock();
+        let remote = match table.get_mut(handle)? {
+            Socket::Udp(pcb) => pcb.remote.ok_or(Errno::EDESTADDRREQ)?,
+            Socket::Raw(pcb) => {
+                let remote = pcb.remote.ok_or(Errno::EDESTADDRREQ)?;
+                SocketAddrV4::new(remote, 0)
+            }
+            Socket::Tcp(_) => {
+                drop(table);
+                return self._send_stream(handle, buf);
+            }
+        };
+        drop(table);
+        self.send_to(handle, buf, remote)
+    }
+
+    fn _send_stream(&self, handle: SocketHandle, buf: &[u8]) -> Result<usize, Error> {
+        let mut table = self.lock();
+        loop {
+            let pcb = table.tcp_mut(handle).ok_or(Errno::EBADF)?;
+            match pcb.send(buf)? {
+                // Wait until the send buffer has room
+                0 if !buf.is_empty() => match table.nonblocking.contains(&handle) {
+                    true => return Err(Errno::EAGAIN),
+                    false => table = self._wait(table),
+                },
+                n => {
+                    self._wake();
+                    return Ok(n);
+                }
+            }
+        }
+    }
+
+    pub fn send_to(
+        &self,
+        handle: SocketHandle,
+        buf: &[u8],
+        addr: SocketAddrV4,
+    ) -> Result<usize, Error> {
+        let mut table = self.lock();
+        table.bind_ephemeral(handle)?;
+        match table.get_mut(handle)? {
+            Socket::Udp(pcb) => pcb.send_to(buf, addr)?,
+            Socket::Raw(pcb) => pcb.send_to(buf, *addr.ip())?,
+            Socket::Tcp(_) => return Err(Errno::EISCONN),
+        }
+        self._wake();
+        Ok(buf.len())
+    }
+
+    pub fn recv(&self, handle: SocketHandle, buf: &mut [u8]) -> Result<usize, Error> {
+        self.recv_from(handle, buf).map(|(n, _)| n)
+    }
+
+    pub fn recv_from(
+        &self,
+        handle: SocketHandle,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddrV4), Error> {
+        let mut table = self.lock();
+        let timeout = table.read_timeouts.get(&handle).copied();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let nonblocking = table.nonblocking.contains(&handle);
+        loop {
+            let received = match table.get_mut(handle)? {
+                Socket::Udp(pcb) => pcb.recv_from(buf),
+                Socket::Raw(pcb) => pcb
+                    .recv_from(buf)
+                    .map(|(n, addr)| (n, SocketAddrV4::new(addr, 0))),
+                Socket::Tcp(pcb) => {
+                    let received = pcb.recv(buf)?.map(|n| (n, pcb.remote));
+                    if received.is_some() {
+                        // The receive window may have opened
+                        self._wake();
+                    }
+                    received
+                }
+            };
+            match received {
+                Some(received) => return Ok(received),
+                None if nonblocking => return Err(Errno::EAGAIN),
+                None => match self._wait_until(table, deadline) {
+                    Some(guard) => table = guard,
+                    None => return Err(Errno::EAGAIN),
+                },
+            }
+        }
+    }
+
+    // SO_RCVTIMEO: recv fails with EAGAIN after the timeout. None blocks
+    // forever.
+    pub fn set_read_timeout(
+        &self,
+        handle: SocketHandle,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        let mut table = self.lock();
+        table.get_mut(handle)?;
+        match timeout {
+            Some(timeout) => table.read_timeouts.insert(handle, timeout),
+            None => table.read_timeouts.remove(&handle),
+        };
+        Ok(())
+    }
+
+    // O_NONBLOCK: the calls fail with EAGAIN instead of waiting, and connect
+    // fails with EINPROGRESS and then EALREADY until the connection is
+    // established
+    pub fn set_nonblocking(&self, handle: SocketHandle, nonblocking: bool) -> Result<(), Error> {
+        let mut table = self.lock();
+        table.get_mut(handle)?;
+        match nonblocking {
+            true => table.nonblocking.insert(handle),
+            false => table.nonblocking.remove(&handle),
+        };
+        Ok(())
+    }
+
+    // SO_BINDTODEVICE: send datagrams from the interface regardless of the
+    // routes, e.g. broadcasts before the interface has an address
+    pub fn bind_to_device(
+        &self,
+        handle: SocketHandle,
+        interface: Option<usize>,
+    ) -> Result<(), Error> {
+        match self.lock().get_mut(handle)? {
+            Socket::Udp(pcb) => pcb.interface = interface,
+            Socket::Raw(pcb) => pcb.interface = interface,
+            Socket::Tcp(_) => return Err(Errno::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    // IP_ADD_MEMBERSHIP: receive the datagrams sent to the group on the
+    // interface
+    pub fn join_multicast(
+        &self,
+        handle: SocketHandle,
+        group: Ipv4Addr,
+        interface: usize,
+    ) -> Result<(), Error> {
+        if !group.is_multicast() {
+            return Err(Errno::EINVAL);
+        }
+        match self.lock().get_mut(handle)? {
+            Socket::Udp(pcb) => {
+                if pcb.groups.contains(&(group, interface)) {
+                    return Err(Errno::EADDRINUSE);
+                }
+                if pcb.groups.len() >= Sockets::MAX_MEMBERSHIPS {
+                    return Err(Errno::ENOBUFS);
+                }
+                pcb.groups.push((group, interface));
+            }
+            _ => return Err(Errno::ENOPROTOOPT),
+        }
+        // Let IGMP report the group
+        self._wake();
+        Ok(())
+    }
+
+    // IP_DROP_MEMBERSHIP
+    pub fn leave_multicast(
+        &self,
+        handle: SocketHandle,
+        group: Ipv4Addr,
+        interface: usize,
+    ) -> Result<(), Error> {
+        match self.lock().get_mut(handle)? {
+            Socket::Udp(pcb) => {
+                let n = pcb.groups.len();
+                pcb.groups.retain(|&g| g != (group, interface));
+                if pcb.groups.len() == n {
+                    return Err(Errno::EADDRNOTAVAIL);
+                }
+            }
+            _ => return Err(Errno::ENOPROTOOPT),
+        }
+        self._wake();
+        Ok(())
+    }
+
+    // The groups joined by any socket with the interfaces, like `ip maddr`
+    pub fn memberships(&self) -> Vec<(usize, Ipv4Addr)> {
+        let mut table = self.lock();
+        let mut memberships: Vec<(usize, Ipv4Addr)> = table
+            .iter_mut()
+            .flat_map(|(_, socket)| match socket {
+                Socket::Udp(pcb) => pcb.groups.clone(),
+                _ => vec![],
+            })
+            .map(|(group, interface)| (interface, group))
+            .collect();
+        memberships.sort();
+        memberships.dedup();
+        memberships
+    }
+
+    pub fn is_member(&self, interface: usize, group: Ipv4Addr) -> bool {
+        self.memberships().contains(&(interface, group))
+    }
+
+    // IP_HDRINCL
+    pub fn set_header_included(&self, handle: SocketHandle, enabled: bool) -> Result<(), Error> {
+        match self.lock().get_mut(handle)? {
+            Socket::Raw(pcb) => {
+                pcb.header_included = enabled;
+                Ok(())
+            }
+            _ => Err(Errno::ENOPROTOOPT),
+        }
+    }
+
+    pub fn local_addr(&self, handle: SocketHandle) -> Result<SocketAddrV4, Error> {
+        match self.lock().get_mut(handle)? {
+            Socket::Udp(pcb) => Ok(pcb.local),
+            Socket::Tcp(pcb) => Ok(pcb.local),
+            Socket::Raw(pcb) => Ok(SocketAddrV4::new(pcb.local, 0)),
+        }
+    }
+
+    pub fn close(&self, handle: SocketHandle) -> Result<(), Error> {
+        let mut table = self.lock();
+        let release = match table.get_mut(handle)? {
+            Socket::Tcp(pcb) => pcb.close(),
+            _ => true,
+        };
+        if release {
+            table.remove(handle);
+            // Drop the connections which have not been accepted yet
+            let children: Vec<SocketHandle> = table
+                .iter_mut()
+                .filter_map(|(child, socket)| match socket {
+                    Socket::Tcp(pcb) if pcb.parent == Some(handle) => Some(child),
+                    _ => None,
+                })
+                .collect();
+            for child in children {
+                table.remove(child);
+            }
+        }
+        self._wake();
+        self.notify();
+        Ok(())
+    }
+}
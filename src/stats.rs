@@ -0,0 +1,226 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::{router::Router, socket::Sockets};
+
+// A counter which only increases and wraps around like Counter64 of SNMP
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// RFC 4293 ipSystemStatsTable for IPv4
+#[derive(Debug, Default)]
+pub struct IPv4Stats {
+    pub in_receives: Counter,
+    pub in_hdr_errors: Counter,
+    pub in_no_routes: Counter,
+    pub in_addr_errors: Counter,
+    pub in_unknown_protos: Counter,
+    pub in_truncated_pkts: Counter,
+    pub in_forw_datagrams: Counter,
+    pub reasm_reqds: Counter,
+    pub reasm_fails: Counter,
+    // Dropped by the firewall or sent to groups which are not joined
+    pub in_discards: Counter,
+    pub in_delivers: Counter,
+    pub out_requests: Counter,
+    pub out_no_routes: Counter,
+    pub out_forw_datagrams: Counter,
+    pub out_discards: Counter,
+    pub out_frag_reqds: Counter,
+    pub out_frag_oks: Counter,
+    pub out_frag_fails: Counter,
+    pub out_frag_creates: Counter,
+    pub in_mcast_pkts: Counter,
+    pub out_mcast_pkts: Counter,
+}
+
+impl IPv4Stats {
+    // The counters with the names of the MIB objects
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("ipSystemStatsInReceives", self.in_receives.get()),
+            ("ipSystemStatsInHdrErrors", self.in_hdr_errors.get()),
+            ("ipSystemStatsInNoRoutes", self.in_no_routes.get()),
+            ("ipSystemStatsInAddrErrors", self.in_addr_errors.get()),
+            ("ipSystemStatsInUnknownProtos", self.in_unknown_protos.get()),
+            ("ipSystemStatsInTruncatedPkts", self.in_truncated_pkts.get()),
+            ("ipSystemStatsInForwDatagrams", self.in_forw_datagrams.get()),
+            ("ipSystemStatsReasmReqds", self.reasm_reqds.get()),
+            ("ipSystemStatsReasmFails", self.reasm_fails.get()),
+            ("ipSystemStatsInDiscards", self.in_discards.get()),
+            ("ipSystemStatsInDelivers", self.in_delivers.get()),
+            ("ipSystemStatsOutRequests", self.out_requests.get()),
+            ("ipSystemStatsOutNoRoutes", self.out_no_routes.get()),
+            (
+                "ipSystemStatsOutForwDatagrams",
+                self.out_forw_datagrams.get(),
+            ),
+            ("ipSystemStatsOutDiscards", self.out_discards.get()),
+            ("ipSystemStatsOutFragReqds", self.out_frag_reqds.get()),
+            ("ipSystemStatsOutFragOKs", self.out_frag_oks.get()),
+            ("ipSystemStatsOutFragFails", self.out_frag_fails.get()),
+            ("ipSystemStatsOutFragCreates", self.out_frag_creates.get()),
+            ("ipSystemStatsInMcastPkts", self.in_mcast_pkts.get()),
+            ("ipSystemStatsOutMcastPkts", self.out_mcast_pkts.get()),
+        ]
+    }
+}
+
+// RFC 4293 icmpStatsTable and icmpMsgStatsTable for IPv4
+#[derive(Debug)]
+pub struct IcmpStats {
+    pub in_msgs: Counter,
+    pub in_errors: Counter,
+    pub out_msgs: Counter,
+    // Indexed by the ICMP type
+    pub in_types: [Counter; 256],
+    pub out_types: [Counter; 256],
+}
+
+impl IcmpStats {
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("icmpStatsInMsgs", self.in_msgs.get()),
+            ("icmpStatsInErrors", self.in_errors.get()),
+            ("icmpStatsOutMsgs", self.out_msgs.get()),
+        ]
+    }
+
+    pub(crate) fn input(&self, buf: &[u8]) {
+        self.in_msgs.increment();
+        if let Some(&icmp_type) = buf.first() {
+            self.in_types[icmp_type as usize].increment();
+        }
+    }
+
+    pub(crate) fn output(&self, buf: &[u8]) {
+        self.out_msgs.increment();
+        if let Some(&icmp_type) = buf.first() {
+            self.out_types[icmp_type as usize].increment();
+        }
+    }
+}
+
+impl Default for IcmpStats {
+    fn default() -> Self {
+        IcmpStats {
+            in_msgs: Counter::default(),
+            in_errors: Counter::default(),
+            out_msgs: Counter::default(),
+            in_types: std::array::from_fn(|_| Counter::default()),
+            out_types: std::array::from_fn(|_| Counter::default()),
+        }
+    }
+}
+
+// RFC 4113
+#[derive(Debug, Default)]
+pub struct UdpStats {
+    pub in_datagrams: Counter,
+    pub no_ports: Counter,
+    pub in_errors: Counter,
+    pub out_datagrams: Counter,
+}
+
+impl UdpStats {
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("udpInDatagrams", self.in_datagrams.get()),
+            ("udpNoPorts", self.no_ports.get()),
+            ("udpInErrors", self.in_errors.get()),
+            ("udpOutDatagrams", self.out_datagrams.get()),
+        ]
+    }
+}
+
+// RFC 4022
+#[derive(Debug, Default)]
+pub struct TcpStats {
+    pub active_opens: Counter,
+    pub passive_opens: Counter,
+    pub attempt_fails: Counter,
+    pub estab_resets: Counter,
+    pub in_segs: Counter,
+    pub out_segs: Counter,
+    pub retrans_segs: Counter,
+    pub in_errs: Counter,
+    pub out_rsts: Counter,
+}
+
+impl TcpStats {
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("tcpActiveOpens", self.active_opens.get()),
+            ("tcpPassiveOpens", self.passive_opens.get()),
+            ("tcpAttemptFails", self.attempt_fails.get()),
+            ("tcpEstabResets", self.estab_resets.get()),
+            ("tcpInSegs", self.in_segs.get()),
+            ("tcpOutSegs", self.out_segs.get()),
+            ("tcpRetransSegs", self.retrans_segs.get()),
+            ("tcpInErrs", self.in_errs.get()),
+            ("tcpOutRsts", self.out_rsts.get()),
+        ]
+    }
+}
+
+// The packets read from and written to a TUN/TAP device
+#[derive(Debug, Default)]
+pub struct DeviceStats {
+    pub in_packets: Counter,
+    pub in_octets: Counter,
+    // Frames which are not for this host, cannot be parsed or cannot be read
+    pub in_discards: Counter,
+    pub out_packets: Counter,
+    pub out_octets: Counter,
+    // Packets which cannot be written, e.g. while the link is down
+    pub out_discards: Counter,
+}
+
+impl DeviceStats {
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("in_packets", self.in_packets.get()),
+            ("in_octets", self.in_octets.get()),
+            ("in_discards", self.in_discards.get()),
+            ("out_packets", self.out_packets.get()),
+            ("out_octets", self.out_octets.get()),
+            ("out_discards", self.out_discards.get()),
+        ]
+    }
+}
+
+// The counters of all the layers, updated while the stack runs
+#[derive(Clone, Debug)]
+pub struct Stats {
+    pub ipv4: Arc<IPv4Stats>,
+    pub icmp: Arc<IcmpStats>,
+    pub udp: Arc<UdpStats>,
+    pub tcp: Arc<TcpStats>,
+}
+
+impl Stats {
+    pub fn new(router: &Router, sockets: &Sockets) -> Stats {
+        Stats {
+            ipv4: router.ipv4_stats().clone(),
+            icmp: router.icmp_stats().clone(),
+            udp: sockets.udp_stats().clone(),
+            tcp: sockets.tcp_stats().clone(),
+        }
+    }
+}
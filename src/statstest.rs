@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use crate::{
+        eventloop::EventLoop,
+        icmp::Icmp,
+        ipv4::IPv4,
+        router::Router,
+        socket::Sockets,
+        stats::Stats,
+        tcp::Tcp,
+        testutil::{self, echo, host, stack},
+        udp::Udp,
+    };
+
+    fn router(sockets: &Sockets, event_loop: &EventLoop) -> Router {
+        testutil::router(IPv4::new(vec![
+            Box::new(Icmp::new()),
+            Box::new(Udp::new(sockets.clone())),
+            Box::new(Tcp::new(sockets.clone(), event_loop.timers())),
+        ]))
+    }
+
+    #[test]
+    fn ipv4_icmp() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let router = router(&sockets, &event_loop);
+        let stats = Stats::new(&router, &sockets);
+        let ipv4 = IPv4::new(vec![]);
+
+        let buf = echo(host(), stack(), 8);
+        assert!(router.input(0, &buf).is_ok());
+        assert_eq!(stats.ipv4.in_receives.get(), 1);
+        assert_eq!(stats.ipv4.in_delivers.get(), 1);
+        assert_eq!(stats.ipv4.out_requests.get(), 1);
+        assert_eq!(stats.icmp.in_msgs.get(), 1);
+        assert_eq!(stats.icmp.in_types[8].get(), 1);
+        assert_eq!(stats.icmp.out_types[0].get(), 1);
+
+        // Header Checksum
+        let mut bad = buf.clone();
+        bad[10] ^= 0xff;
+        assert!(router.input(0, &bad).is_err());
+        assert_eq!(stats.ipv4.in_hdr_errors.get(), 1);
+
+        // Total Length
+        assert!(router.input(0, &buf[..buf.len() - 1]).is_err());
+        assert_eq!(stats.ipv4.in_truncated_pkts.get(), 1);
+
+        // ICMP Checksum
+        let mut bad = echo(host(), stack(), 8);
+        bad[22] ^= 0xff;
+        assert!(router.input(0, &bad).is_err());
+        assert_eq!(stats.icmp.in_msgs.get(), 2);
+        assert_eq!(stats.icmp.in_errors.get(), 1);
+
+        let unknown = ipv4.datagram(host(), stack(), 253, &[]);
+        assert!(router.input(0, &unknown).is_err());
+        assert_eq!(stats.ipv4.in_unknown_protos.get(), 1);
+
+        let martian = echo(host(), Ipv4Addr::LOCALHOST, 8);
+        assert!(router.input(0, &martian).is_err());
+        assert_eq!(stats.ipv4.in_addr_errors.get(), 1);
+
+        // Destination Unreachable
+        let unreachable = ipv4.datagram(host(), Ipv4Addr::new(203, 0, 113, 1), 253, &[]);
+        assert!(router.input(0, &unreachable).is_ok());
+        assert_eq!(stats.ipv4.in_no_routes.get(), 1);
+        assert_eq!(stats.icmp.out_msgs.get(), 2);
+        assert_eq!(stats.icmp.out_types[3].get(), 1);
+        assert_eq!(stats.ipv4.in_receives.get(), 7);
+    }
+
+    #[test]
+    fn udp_tcp() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let router = router(&sockets, &event_loop);
+        let stats = Stats::new(&router, &sockets);
+        let ipv4 = IPv4::new(vec![]);
+        let src = SocketAddrV4::new(host(), 40000);
+        let dst = SocketAddrV4::new(stack(), 7);
+
+        let udp = Udp::datagram(src, dst, b"hello");
+        let buf = ipv4.datagram(host(), stack(), Udp::NUMBER, &udp);
+        assert!(router.input(0, &buf).is_err());
+        assert_eq!(stats.udp.no_ports.get(), 1);
+
+        let mut bad = udp.clone();
+        bad[6] ^= 0xff;
+        let buf = ipv4.datagram(host(), stack(), Udp::NUMBER, &bad);
+        assert!(router.input(0, &buf).is_err());
+        assert_eq!(stats.udp.in_errors.get(), 1);
+
+        // SYN to a closed port
+        let syn = Tcp::segment(src, dst, 1, 0, 0x02, 65535, None, &[]);
+        let buf = ipv4.datagram(host(), stack(), Tcp::NUMBER, &syn);
+        assert!(router.input(0, &buf).is_ok());
+        assert_eq!(
+            stats.tcp.counters(),
+            vec![
+                ("tcpActiveOpens", 0),
+                ("tcpPassiveOpens", 0),
+                ("tcpAttemptFails", 0),
+                ("tcpEstabResets", 0),
+                ("tcpInSegs", 1),
+                ("tcpOutSegs", 1),
+                ("tcpRetransSegs", 0),
+                ("tcpInErrs", 0),
+                ("tcpOutRsts", 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,994 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use nix::{errno::Errno, Error};
+
+use crate::{
+    ipv4::{Datagram, IPv4, IPv4Protocol},
+    protocol::{get_checksum, ProtocolError},
+    socket::{Socket, SocketHandle, SocketTable, Sockets},
+    stats::TcpStats,
+    timer::Timers,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TcpError(pub String);
+
+impl fmt::Display for TcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tcp: {}", self.0)
+    }
+}
+
+// Control Bits
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+// Sequence numbers wrap around
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    pub const ALL: [TcpState; 11] = [
+        TcpState::Closed,
+        TcpState::Listen,
+        TcpState::SynSent,
+        TcpState::SynReceived,
+        TcpState::Established,
+        TcpState::FinWait1,
+        TcpState::FinWait2,
+        TcpState::CloseWait,
+        TcpState::Closing,
+        TcpState::LastAck,
+        TcpState::TimeWait,
+    ];
+}
+
+struct Header {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    // Maximum Segment Size option
+    mss: Option<u16>,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Header {
+        let data_offset = 4 * (buf[12] >> 4) as usize;
+        Header {
+            src_port: u16::from_be_bytes([buf[0], buf[1]]),
+            dst_port: u16::from_be_bytes([buf[2], buf[3]]),
+            seq: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            ack: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            flags: buf[13] & 0x3f,
+            window: u16::from_be_bytes([buf[14], buf[15]]),
+            mss: Header::_mss(&buf[Tcp::HEADER_SIZE..data_offset]),
+        }
+    }
+
+    fn _mss(options: &[u8]) -> Option<u16> {
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                // End of Option List
+                0 => break,
+                // No-Operation
+                1 => i += 1,
+                kind => {
+                    let length = *options.get(i + 1)? as usize;
+                    if length < 2 || i + length > options.len() {
+                        return None;
+                    }
+                    // Maximum Segment Size
+                    if kind == 2 && length == 4 {
+                        return Some(u16::from_be_bytes([options[i + 2], options[i + 3]]));
+                    }
+                    i += length;
+                }
+            }
+        }
+        None
+    }
+}
+
+// A segment in the retransmission queue
+struct Segment {
+    seq: u32,
+    flags: u8,
+    data: Vec<u8>,
+    first_sent: Option<Instant>,
+    // None until the segment is (re)transmitted
+    sent: Option<Instant>,
+    rto: Duration,
+}
+
+impl Segment {
+    fn new(seq: u32, flags: u8, data: Vec<u8>) -> Segment {
+        Segment {
+            seq,
+            flags,
+            data,
+            first_sent: None,
+            sent: None,
+            rto: Tcp::INITIAL_RTO,
+        }
+    }
+
+    // SYN and FIN occupy a sequence number
+    fn len(&self) -> u32 {
+        self.data.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+pub(crate) struct TcpPcb {
+    pub(crate) state: TcpState,
+    pub(crate) local: SocketAddrV4,
+    pub(crate) remote: SocketAddrV4,
+    // The listening socket of a connection which is not accepted yet
+    pub(crate) parent: Option<SocketHandle>,
+    pub(crate) backlog: VecDeque<SocketHandle>,
+    backlog_size: usize,
+    pub(crate) error: Option<Errno>,
+    // The application closed the socket
+    pub(crate) closed: bool,
+
+    // Send Sequence Variables
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    // Receive Sequence Variables
+    rcv_nxt: u32,
+
+    // The MSS option of the peer
+    mss: usize,
+    // The MTU of the interface to the peer
+    mtu: usize,
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+    retransmission: VecDeque<Segment>,
+    fin_pending: bool,
+    ack_pending: bool,
+    time_wait: Option<Instant>,
+    stats: Arc<TcpStats>,
+}
+
+impl TcpPcb {
+    const BUFFER_SIZE: usize = 65535;
+
+    pub(crate) fn new(stats: Arc<TcpStats>) -> TcpPcb {
+        TcpPcb {
+            state: TcpState::Closed,
+            local: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            remote: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            parent: None,
+            backlog: VecDeque::new(),
+            backlog_size: 0,
+            error: None,
+            closed: false,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            rcv_nxt: 0,
+            mss: Tcp::DEFAULT_MSS,
+            mtu: Tcp::DEFAULT_MTU,
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            retransmission: VecDeque::new(),
+            fin_pending: false,
+            ack_pending: false,
+            time_wait: None,
+            stats,
+        }
+    }
+
+    // The octets not read yet, and the octets not sent or acknowledged yet
+    pub(crate) fn queues(&self) -> (usize, usize) {
+        let unacknowledged: usize = self.retransmission.iter().map(|s| s.data.len()).sum();
+        (
+            self.recv_buffer.len(),
+            self.send_buffer.len() + unacknowledged,
+        )
+    }
+
+    // The largest segment to send, which fits in the MTU as well
+    fn _send_mss(&self) -> usize {
+        self.mss.min(self.mtu.saturating_sub(Tcp::HEADERS_SIZE))
+    }
+
+    fn _window(&self) -> u32 {
+        (TcpPcb::BUFFER_SIZE - self.recv_buffer.len()) as u32
+    }
+
+    fn _set_state(&mut self, state: TcpState) {
+        if self.state != state {
+            debug!(
+                "{} > {}: {:?} -> {:?}",
+                self.local, self.remote, self.state, state
+            );
+        }
+        // RFC 4022 counts the transitions
+        match (self.state, state) {
+            (TcpState::Closed, TcpState::SynSent) => self.stats.active_opens.increment(),
+            // The connection is forked from the listening socket
+            (TcpState::Closed, TcpState::SynReceived) => self.stats.passive_opens.increment(),
+            (TcpState::SynSent | TcpState::SynReceived, TcpState::Closed) => {
+                self.stats.attempt_fails.increment()
+            }
+            (TcpState::Established | TcpState::CloseWait, TcpState::Closed) => {
+                self.stats.estab_resets.increment()
+            }
+            _ => {}
+        }
+        self.state = state;
+    }
+
+    fn _open(&mut self, flags: u8) {
+        // RFC 6528 recommends unpredictable initial sequence numbers
+        self.iss = RandomState::new().build_hasher().finish() as u32;
+        self.snd_una = self.iss;
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.retransmission
+            .push_back(Segment::new(self.iss, flags, vec![]));
+    }
+
+    pub(crate) fn connect(&mut self, remote: SocketAddrV4) -> Result<(), Error> {
+        if self.state != TcpState::Closed {
+            return Err(Errno::EISCONN);
+        }
+        self.remote = remote;
+        self._open(SYN);
+        self._set_state(TcpState::SynSent);
+        Ok(())
+    }
+
+    pub(crate) fn listen(&mut self, backlog: usize) -> Result<(), Error> {
+        if !matches!(self.state, TcpState::Closed | TcpState::Listen) {
+            return Err(Errno::EISCONN);
+        }
+        self.backlog_size = backlog.max(1);
+        self._set_state(TcpState::Listen);
+        Ok(())
+    }
+
+    // Returns 0 if the send buffer is full
+    pub(crate) fn send(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.state {
+            TcpState::Established | TcpState::CloseWait if !self.fin_pending => {}
+            TcpState::SynSent | TcpState::SynReceived => return Ok(0),
+            TcpState::Closed | TcpState::Listen => return Err(Errno::ENOTCONN),
+            _ => return Err(Errno::EPIPE),
+        }
+
+        let n = buf.len().min(TcpPcb::BUFFER_SIZE - self.send_buffer.len());
+        self.send_buffer.extend(&buf[..n]);
+        Ok(n)
+    }
+
+    // Returns None if no data has arrived yet and Some(0) at the end of the
+    // stream
+    pub(crate) fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        if !self.recv_buffer.is_empty() {
+            let before = self._window();
+            let n = buf.len().min(self.recv_buffer.len());
+            for (i, b) in self.recv_buffer.drain(..n).enumerate() {
+                buf[i] = b;
+            }
+            // Tell the peer that the window has opened
+            if before < self.mss as u32 {
+                self.ack_pending = true;
+            }
+            return Ok(Some(n));
+        }
+
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.state {
+            TcpState::Listen => Err(Errno::ENOTCONN),
+            TcpState::SynSent
+            | TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::FinWait1
+            | TcpState::FinWait2 => Ok(None),
+            // The peer has sent FIN
+            _ => Ok(Some(0)),
+        }
+    }
+
+    // Returns true if the socket can be released right away
+    pub(crate) fn close(&mut self) -> bool {
+        self.closed = true;
+        match self.state {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => true,
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                self.fin_pending = true;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn _abort(&mut self, error: Errno) {
+        self._set_state(TcpState::Closed);
+        self.error = Some(error);
+        self.send_buffer.clear();
+        self.retransmission.clear();
+    }
+
+    // Remove the segments which are acknowledged entirely
+    fn _acknowledge(&mut self, ack: u32) {
+        while let Some(segment) = self.retransmission.front() {
+            if !seq_le(segment.seq.wrapping_add(segment.len()), ack) {
+                break;
+            }
+            self.retransmission.pop_front();
+        }
+    }
+
+    fn _fin_acked(&self) -> bool {
+        self.snd_una == self.snd_nxt && self.send_buffer.is_empty()
+    }
+
+    fn _acceptable(&self, seq: u32, len: u32) -> bool {
+        let window = self._window();
+        let in_window =
+            |n: u32| seq_le(self.rcv_nxt, n) && seq_lt(n, self.rcv_nxt.wrapping_add(window));
+        match (len, window) {
+            (0, 0) => seq == self.rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+        }
+    }
+
+    fn _update_window(&mut self, header: &Header) {
+        if seq_lt(self.snd_wl1, header.seq)
+            || (self.snd_wl1 == header.seq && seq_le(self.snd_wl2, header.ack))
+        {
+            self.snd_wnd = header.window as u32;
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = header.ack;
+        }
+    }
+
+    // SYN-SENT
+    fn _syn_sent(&mut self, header: &Header) -> Result<(), ProtocolError> {
+        let acceptable = header.flags & ACK != 0
+            && seq_lt(self.iss, header.ack)
+            && seq_le(header.ack, self.snd_nxt);
+        if header.flags & ACK != 0 && !acceptable {
+            return Err(TcpError("unacceptable ack".to_string()).into());
+        }
+
+        if header.flags & RST != 0 {
+            if acceptable {
+                self._abort(Errno::ECONNREFUSED);
+            }
+            return Err(ProtocolError::General);
+        }
+
+        if header.flags & SYN == 0 {
+            return Err(ProtocolError::General);
+        }
+        self.rcv_nxt = header.seq.wrapping_add(1);
+        self.mss = header.mss.map_or(Tcp::DEFAULT_MSS, |mss| mss as usize);
+
+        if acceptable {
+            self.snd_una = header.ack;
+            self._acknowledge(header.ack);
+            self.snd_wnd = header.window as u32;
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = header.ack;
+            self._set_state(TcpState::Established);
+            self.ack_pending = true;
+        } else {
+            // Simultaneous open: retransmit the SYN with ACK
+            self._set_state(TcpState::SynReceived);
+            if let Some(segment) = self.retransmission.front_mut() {
+                segment.sent = None;
+            }
+        }
+        Err(ProtocolError::General)
+    }
+
+    // The states other than CLOSED, LISTEN and SYN-SENT
+    fn _synchronized(
+        &mut self,
+        header: &Header,
+        data: &[u8],
+        now: Instant,
+    ) -> Result<(), ProtocolError> {
+        let len =
+            data.len() as u32 + (header.flags & SYN != 0) as u32 + (header.flags & FIN != 0) as u32;
+        // RFC 793 p.69: an unacceptable segment is only acknowledged, e.g. a
+        // SYN retransmitted in SYN-RECEIVED after the SYN, ACK was lost
+        if !self._acceptable(header.seq, len) {
+            debug!(
+                "{} > {}: unacceptable segment: seq={}",
+                self.remote, self.local, header.seq
+            );
+            if header.flags & RST == 0 {
+                self.ack_pending = true;
+            }
+            return Err(ProtocolError::General);
+        }
+
+        if header.flags & RST != 0 {
+            let error = match self.state {
+                TcpState::SynReceived => Errno::ECONNREFUSED,
+                _ => Errno::ECONNRESET,
+            };
+            self._abort(error);
+            return Err(ProtocolError::General);
+        }
+
+        if header.flags & SYN != 0 {
+            self._abort(Errno::ECONNRESET);
+            return Err(TcpError("syn in window".to_string()).into());
+        }
+
+        if header.flags & ACK == 0 {
+            return Err(ProtocolError::General);
+        }
+
+        if self.state == TcpState::SynReceived {
+            if !(seq_lt(self.snd_una, header.ack) && seq_le(header.ack, self.snd_nxt)) {
+                return Err(TcpError("unacceptable ack".to_string()).into());
+            }
+            self._set_state(TcpState::Established);
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = header.ack;
+            self.snd_wnd = header.window as u32;
+        }
+
+        if seq_lt(self.snd_una, header.ack) && seq_le(header.ack, self.snd_nxt) {
+            self.snd_una = header.ack;
+            self._acknowledge(header.ack);
+        } else if seq_lt(self.snd_nxt, header.ack) {
+            self.ack_pending = true;
+            return Err(TcpError("ack for unsent data".to_string()).into());
+        }
+        if seq_le(self.snd_una, header.ack) {
+            self._update_window(header);
+        }
+
+        match self.state {
+            TcpState::FinWait1 if self._fin_acked() => self._set_state(TcpState::FinWait2),
+            TcpState::Closing if self._fin_acked() => {
+                self._set_state(TcpState::TimeWait);
+                self.time_wait = Some(now);
+            }
+            TcpState::LastAck if self._fin_acked() => {
+                self._set_state(TcpState::Closed);
+                return Err(ProtocolError::General);
+            }
+            TcpState::TimeWait => {
+                self.ack_pending = true;
+                self.time_wait = Some(now);
+            }
+            _ => {}
+        }
+
+        // Segment Text
+        if !data.is_empty()
+            && matches!(
+                self.state,
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+            )
+        {
+            // Skip the data received already
+            let offset = self.rcv_nxt.wrapping_sub(header.seq) as usize;
+            if offset < data.len() {
+                let n = (data.len() - offset).min(self._window() as usize);
+                self.recv_buffer.extend(&data[offset..offset + n]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(n as u32);
+            }
+            self.ack_pending = true;
+        }
+
+        // Only the FIN right after the received data is processed
+        if header.flags & FIN != 0 && header.seq.wrapping_add(data.len() as u32) == self.rcv_nxt {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            match self.state {
+                TcpState::Established => self._set_state(TcpState::CloseWait),
+                TcpState::FinWait1 if self._fin_acked() => {
+                    self._set_state(TcpState::TimeWait);
+                    self.time_wait = Some(now);
+                }
+                TcpState::FinWait1 => self._set_state(TcpState::Closing),
+                TcpState::FinWait2 => {
+                    self._set_state(TcpState::TimeWait);
+                    self.time_wait = Some(now);
+                }
+                _ => {}
+            }
+        }
+        Err(ProtocolError::General)
+    }
+
+    fn _segment(&self, seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        // The MSS which fits in the MTU of the interface
+        let mss = if flags & SYN != 0 {
+            let mss = self.mtu.saturating_sub(Tcp::HEADERS_SIZE);
+            Some(mss.min(u16::MAX as usize) as u16)
+        } else {
+            None
+        };
+        let window = self._window().min(u16::MAX as u32) as u16;
+        Tcp::segment(
+            self.local,
+            self.remote,
+            seq,
+            self.rcv_nxt,
+            flags,
+            window,
+            mss,
+            data,
+        )
+    }
+
+    fn _poll(
+        &mut self,
+        now: Instant,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<Datagram> {
+        if matches!(self.state, TcpState::Closed | TcpState::Listen) {
+            return vec![];
+        }
+
+        match source(*self.remote.ip(), None) {
+            Some((ip, mtu)) => {
+                if self.local.ip().is_unspecified() {
+                    self.local.set_ip(ip);
+                }
+                self.mtu = mtu;
+            }
+            None if self.local.ip().is_unspecified() => {
+                self._abort(Errno::ENETUNREACH);
+                return vec![];
+            }
+            None => {}
+        }
+
+        if matches!(self.state, TcpState::Established | TcpState::CloseWait) {
+            // Segmentize the data within the send window
+            let mut in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
+            while !self.send_buffer.is_empty() {
+                let n = self
+                    ._send_mss()
+                    .min(self.snd_wnd.saturating_sub(in_flight) as usize)
+                    .min(self.send_buffer.len());
+                if n == 0 {
+                    break;
+                }
+                let data: Vec<u8> = self.send_buffer.drain(..n).collect();
+                self.retransmission
+                    .push_back(Segment::new(self.snd_nxt, PSH, data));
+                self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+                in_flight += n as u32;
+            }
+
+            if self.fin_pending && self.send_buffer.is_empty() {
+                self.retransmission
+                    .push_back(Segment::new(self.snd_nxt, FIN, vec![]));
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                self.fin_pending = false;
+                let state = match self.state {
+                    TcpState::Established => TcpState::FinWait1,
+                    _ => TcpState::LastAck,
+                };
+                self._set_state(state);
+            }
+        }
+
+        let mut segments = vec![];
+        for i in 0..self.retransmission.len() {
+            let segment = &self.retransmission[i];
+            if segment.sent.is_some() {
+                continue;
+            }
+            let flags = match self.state {
+                TcpState::SynSent => segment.flags,
+                _ => segment.flags | ACK,
+            };
+            segments.push(self._segment(segment.seq, flags, &segment.data));
+
+            let segment = &mut self.retransmission[i];
+            if segment.first_sent.is_some() {
+                self.stats.retrans_segs.increment();
+            }
+            segment.first_sent.get_or_insert(now);
+            segment.sent = Some(now);
+        }
+
+        if self.ack_pending && segments.is_empty() && self.state != TcpState::SynSent {
+            segments.push(self._segment(self.snd_nxt, ACK, &[]));
+        }
+        self.ack_pending = false;
+        self.stats.out_segs.add(segments.len() as u64);
+
+        segments
+            .into_iter()
+            .map(|data| Datagram {
+                src: *self.local.ip(),
+                dst: *self.remote.ip(),
+                interface: None,
+                data,
+            })
+            .collect()
+    }
+
+    // Returns true if the socket can be released
+    fn _tick(&mut self, now: Instant) -> bool {
+        let mut timed_out = false;
+        for segment in self.retransmission.iter_mut() {
+            let (first_sent, sent) = match (segment.first_sent, segment.sent) {
+                (Some(first_sent), Some(sent)) => (first_sent, sent),
+                _ => continue,
+            };
+            if now.duration_since(first_sent) >= Tcp::USER_TIMEOUT {
+                timed_out = true;
+                break;
+            }
+            if now.duration_since(sent) >= segment.rto {
+                // Exponential backoff
+                segment.sent = None;
+                segment.rto = (segment.rto * 2).min(Tcp::MAX_RTO);
+            }
+        }
+        if timed_out {
+            self._abort(Errno::ETIMEDOUT);
+        }
+
+        if let Some(time_wait) = self.time_wait {
+            if self.state == TcpState::TimeWait && now.duration_since(time_wait) >= 2 * Tcp::MSL {
+                self._set_state(TcpState::Closed);
+            }
+        }
+
+        self.closed && self.state == TcpState::Closed
+    }
+}
+
+// RFC 793
+pub struct Tcp {
+    sockets: Sockets,
+}
+
+impl Tcp {
+    pub const NUMBER: u8 = 6;
+    const HEADER_SIZE: usize = 20;
+    // RFC 879: the MSS without the option and the MTU which every host
+    // accepts
+    const DEFAULT_MSS: usize = 536;
+    const DEFAULT_MTU: usize = 576;
+    // The IPv4 and TCP headers without options
+    const HEADERS_SIZE: usize = 40;
+    const TICK: Duration = Duration::from_millis(100);
+    // RFC 6298
+    const INITIAL_RTO: Duration = Duration::from_secs(1);
+    const MAX_RTO: Duration = Duration::from_secs(60);
+    const USER_TIMEOUT: Duration = Duration::from_secs(120);
+    // Maximum Segment Lifetime
+    const MSL: Duration = Duration::from_secs(30);
+
+    pub fn new(sockets: Sockets, timers: Timers) -> Tcp {
+        Tcp::_schedule_tick(timers, sockets.clone());
+        Tcp { sockets }
+    }
+
+    fn _schedule_tick(timers: Timers, sockets: Sockets) {
+        let next = timers.clone();
+        timers.schedule(Tcp::TICK, move || {
+            Tcp::tick(&sockets, Instant::now());
+            Tcp::_schedule_tick(next, sockets);
+        });
+    }
+
+    // Handle retransmission timeouts and TIME-WAIT
+    pub(crate) fn tick(sockets: &Sockets, now: Instant) {
+        let mut table = sockets.lock();
+        let released: Vec<SocketHandle> = table
+            .iter_mut()
+            .filter_map(|(handle, socket)| match socket {
+                Socket::Tcp(pcb) => pcb._tick(now).then_some(handle),
+                _ => None,
+            })
+            .collect();
+        for handle in released {
+            table.remove(handle);
+        }
+        sockets.notify();
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn segment(
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: u16,
+        mss: Option<u16>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let options = match mss {
+            // Maximum Segment Size
+            Some(mss) => {
+                let [high, low] = mss.to_be_bytes();
+                vec![2, 4, high, low]
+            }
+            None => vec![],
+        };
+        let data_offset = ((Tcp::HEADER_SIZE + options.len()) / 4) as u8;
+
+        let mut buf = vec![];
+        buf.extend_from_slice(&src.port().to_be_bytes());
+        buf.extend_from_slice(&dst.port().to_be_bytes());
+        buf.extend_from_slice(&seq.to_be_bytes());
+        buf.extend_from_slice(&ack.to_be_bytes());
+        buf.extend_from_slice(&[data_offset << 4, flags]);
+        buf.extend_from_slice(&window.to_be_bytes());
+        // Checksum, Urgent Pointer
+        buf.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        buf.extend_from_slice(&options);
+        buf.extend_from_slice(data);
+
+        let mut pseudo = IPv4::pseudo_header(*src.ip(), *dst.ip(), Tcp::NUMBER, buf.len());
+        pseudo.extend_from_slice(&buf);
+        let checksum = get_checksum(&pseudo);
+        buf[16..18].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    fn _verify_length(&self, buf: &[u8]) -> Result<(), ProtocolError> {
+        if buf.len() < Tcp::HEADER_SIZE {
+            return Err(TcpError("too short".to_string()).into());
+        }
+
+        let data_offset = 4 * (buf[12] >> 4) as usize;
+        if data_offset < Tcp::HEADER_SIZE || buf.len() < data_offset {
+            return Err(TcpError(format!(
+                "data offset error: data offset={}, len={}",
+                data_offset,
+                buf.len()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn _verify_checksum(
+        &self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        buf: &[u8],
+    ) -> Result<(), ProtocolError> {
+        let mut pseudo = IPv4::pseudo_header(src, dst, Tcp::NUMBER, buf.len());
+        pseudo.extend_from_slice(buf);
+        let checksum = get_checksum(&pseudo);
+        if checksum != 0 {
+            return Err(TcpError(format!("checksum error: checksum={:#x?}", checksum)).into());
+        }
+        Ok(())
+    }
+
+    // A reset for a segment which does not belong to any connection
+    fn _reset(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        header: &Header,
+        len: u32,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        if header.flags & RST != 0 {
+            return Err(ProtocolError::General);
+        }
+        let stats = self.sockets.tcp_stats();
+        stats.out_segs.increment();
+        stats.out_rsts.increment();
+        let segment = if header.flags & ACK != 0 {
+            Tcp::segment(local, remote, header.ack, 0, RST, 0, None, &[])
+        } else {
+            let ack = header.seq.wrapping_add(len);
+            Tcp::segment(local, remote, 0, ack, RST | ACK, 0, None, &[])
+        };
+        Ok(segment)
+    }
+
+    fn _find(
+        table: &mut SocketTable,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+    ) -> Option<SocketHandle> {
+        let mut listener = None;
+        for (handle, socket) in table.iter_mut() {
+            let pcb = match socket {
+                Socket::Tcp(pcb) => pcb,
+                _ => continue,
+            };
+            match pcb.state {
+                TcpState::Closed => {}
+                TcpState::Listen => {
+                    if pcb.local.port() == local.port()
+                        && (pcb.local.ip().is_unspecified() || pcb.local.ip() == local.ip())
+                    {
+                        listener = Some(handle);
+                    }
+                }
+                _ => {
+                    if pcb.local == local && pcb.remote == remote {
+                        return Some(handle);
+                    }
+                }
+            }
+        }
+        listener
+    }
+
+    // LISTEN
+    fn _listen(
+        &self,
+        table: &mut SocketTable,
+        handle: SocketHandle,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        header: &Header,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        if header.flags & RST != 0 {
+            return Err(ProtocolError::General);
+        }
+        if header.flags & ACK != 0 {
+            return self._reset(local, remote, header, 0);
+        }
+        if header.flags & SYN == 0 {
+            return Err(ProtocolError::General);
+        }
+
+        let pending = table
+            .iter_mut()
+            .filter(|(_, socket)| matches!(socket, Socket::Tcp(pcb) if pcb.parent == Some(handle)))
+            .count();
+        let listener = table.tcp_mut(handle).ok_or(ProtocolError::General)?;
+        if pending >= listener.backlog_size {
+            return Err(TcpError(format!("backlog full: port={}", local.port())).into());
+        }
+
+        let mut pcb = TcpPcb::new(listener.stats.clone());
+        pcb.local = local;
+        pcb.remote = remote;
+        pcb.parent = Some(handle);
+        // Released unless it is accepted
+        pcb.closed = true;
+        pcb.rcv_nxt = header.seq.wrapping_add(1);
+        pcb.mss = header.mss.map_or(Tcp::DEFAULT_MSS, |mss| mss as usize);
+        pcb._open(SYN);
+        pcb._set_state(TcpState::SynReceived);
+        table.insert(Socket::Tcp(pcb));
+        Err(ProtocolError::General)
+    }
+}
+
+impl IPv4Protocol for Tcp {
+    fn number(&self) -> u8 {
+        Tcp::NUMBER
+    }
+
+    fn input(&self, src: Ipv4Addr, dst: Ipv4Addr, buf: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        let stats = self.sockets.tcp_stats();
+        stats.in_segs.increment();
+        if let Err(e) = self
+            ._verify_length(buf)
+            .and_then(|_| self._verify_checksum(src, dst, buf))
+        {
+            stats.in_errs.increment();
+            return Err(e);
+        }
+
+        let header = Header::parse(buf);
+        let data = &buf[4 * (buf[12] >> 4) as usize..];
+        let local = SocketAddrV4::new(dst, header.dst_port);
+        let remote = SocketAddrV4::new(src, header.src_port);
+
+        let mut table = self.sockets.lock();
+        let handle = match Tcp::_find(&mut table, local, remote) {
+            Some(handle) => handle,
+            None => {
+                // CLOSED
+                let len = data.len() as u32
+                    + (header.flags & SYN != 0) as u32
+                    + (header.flags & FIN != 0) as u32;
+                return self._reset(local, remote, &header, len);
+            }
+        };
+
+        let pcb = table.tcp_mut(handle).ok_or(ProtocolError::General)?;
+        let result = match pcb.state {
+            TcpState::Listen => self._listen(&mut table, handle, local, remote, &header),
+            TcpState::SynSent => match pcb._syn_sent(&header) {
+                Err(ProtocolError::Tcp(_)) => self._reset(local, remote, &header, 0),
+                result => result.map(|_| vec![]),
+            },
+            _ => {
+                let was_syn_received = pcb.state == TcpState::SynReceived;
+                let result = pcb._synchronized(&header, data, Instant::now());
+                let established = pcb.state == TcpState::Established;
+                let parent = pcb.parent;
+
+                // Queue the connection for accept()
+                if was_syn_received && established {
+                    if let Some(listener) = parent.and_then(|parent| table.tcp_mut(parent)) {
+                        listener.backlog.push_back(handle);
+                    }
+                }
+                // An unacceptable ACK or a SYN in the window is reset
+                if was_syn_received && matches!(result, Err(ProtocolError::Tcp(_))) {
+                    self._reset(local, remote, &header, 0)
+                } else {
+                    result.map(|_| vec![])
+                }
+            }
+        };
+        self.sockets.notify();
+        result
+    }
+
+    fn poll(
+        &self,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<Datagram> {
+        let now = Instant::now();
+        let mut datagrams = vec![];
+        let mut table = self.sockets.lock();
+        for (_, socket) in table.iter_mut() {
+            if let Socket::Tcp(pcb) = socket {
+                datagrams.extend(pcb._poll(now, source));
+            }
+        }
+        self.sockets.notify();
+        datagrams
+    }
+}
@@ -0,0 +1,211 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddrV4;
+
+    use crate::{
+        eventloop::EventLoop,
+        ipv4::{Datagram, IPv4Protocol},
+        protocol::ProtocolError,
+        socket::{SocketType, Sockets},
+        tcp::Tcp,
+        testutil::{host, stack},
+    };
+
+    // Control Bits
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const PSH: u8 = 0x08;
+    const ACK: u8 = 0x10;
+
+    fn host_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(host(), 5000)
+    }
+
+    fn stack_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(stack(), 80)
+    }
+
+    fn segment(seq: u32, ack: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        Tcp::segment(
+            host_addr(),
+            stack_addr(),
+            seq,
+            ack,
+            flags,
+            65535,
+            None,
+            data,
+        )
+    }
+
+    fn seq(buf: &[u8]) -> u32 {
+        u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]])
+    }
+
+    fn ack(buf: &[u8]) -> u32 {
+        u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]])
+    }
+
+    fn poll(tcp: &Tcp) -> Vec<Datagram> {
+        let source = |_, _| Some((stack(), 1500));
+        tcp.poll(&source)
+    }
+
+    #[test]
+    fn passive_open() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets.clone(), event_loop.timers());
+
+        let listener = sockets.socket(SocketType::Stream).unwrap();
+        sockets.bind(listener, stack_addr()).unwrap();
+        sockets.listen(listener, 1).unwrap();
+
+        // SYN
+        let buf = segment(1000, 0, SYN, &[]);
+        assert_eq!(
+            tcp.input(host(), stack(), &buf),
+            Err(ProtocolError::General)
+        );
+
+        // SYN, ACK
+        let datagrams = poll(&tcp);
+        assert_eq!(datagrams.len(), 1);
+        let reply = &datagrams[0].data;
+        assert_eq!(datagrams[0].dst, host());
+        assert_eq!(reply[13], SYN | ACK);
+        assert_eq!(ack(reply), 1001);
+        // Maximum Segment Size
+        assert_eq!(reply[12] >> 4, 6);
+        assert_eq!(&reply[20..24], &[0x02, 0x04, 0x05, 0xb4]);
+        let iss = seq(reply);
+
+        // ACK
+        let buf = segment(1001, iss.wrapping_add(1), ACK, &[]);
+        assert_eq!(
+            tcp.input(host(), stack(), &buf),
+            Err(ProtocolError::General)
+        );
+        let (stream, addr) = sockets.accept(listener).unwrap();
+        assert_eq!(addr, host_addr());
+
+        // Data
+        let buf = segment(1001, iss.wrapping_add(1), PSH | ACK, b"hello");
+        assert_eq!(
+            tcp.input(host(), stack(), &buf),
+            Err(ProtocolError::General)
+        );
+        let mut data = [0u8; 16];
+        let n = sockets.recv(stream, &mut data).unwrap();
+        assert_eq!(&data[..n], b"hello");
+
+        let datagrams = poll(&tcp);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].data[13], ACK);
+        assert_eq!(ack(&datagrams[0].data), 1006);
+
+        // Send back the data
+        assert_eq!(sockets.send(stream, b"world"), Ok(5));
+        let datagrams = poll(&tcp);
+        assert_eq!(datagrams.len(), 1);
+        let reply = &datagrams[0].data;
+        assert_eq!(reply[13], PSH | ACK);
+        assert_eq!(seq(reply), iss.wrapping_add(1));
+        assert_eq!(&reply[20..], b"world");
+    }
+
+    #[test]
+    fn mss() {
+        // The option of the peer and the MTU of the interface
+        for (option, mtu, expected) in [
+            (Some(1460), 1500, 1460),
+            (None, 1500, 536),
+            (Some(1460), 576, 536),
+        ] {
+            let event_loop = EventLoop::new().unwrap();
+            let sockets = Sockets::new(event_loop.waker());
+            let tcp = Tcp::new(sockets.clone(), event_loop.timers());
+            let source = |_, _| Some((stack(), mtu));
+            let listener = sockets.socket(SocketType::Stream).unwrap();
+            sockets.bind(listener, stack_addr()).unwrap();
+            sockets.listen(listener, 1).unwrap();
+
+            let buf = Tcp::segment(host_addr(), stack_addr(), 1000, 0, SYN, 65535, option, &[]);
+            tcp.input(host(), stack(), &buf).unwrap_err();
+            let datagrams = tcp.poll(&source);
+            // The MSS for the MTU
+            let reply = &datagrams[0].data;
+            assert_eq!(&reply[22..24], &(mtu as u16 - 40).to_be_bytes());
+            let buf = segment(1001, seq(reply).wrapping_add(1), ACK, &[]);
+            tcp.input(host(), stack(), &buf).unwrap_err();
+            let (stream, _) = sockets.accept(listener).unwrap();
+
+            assert_eq!(sockets.send(stream, &[0; 2000]), Ok(2000));
+            let datagrams = tcp.poll(&source);
+            assert_eq!(datagrams[0].data.len(), 20 + expected);
+            let sent: usize = datagrams.iter().map(|d| d.data.len() - 20).sum();
+            assert_eq!(sent, 2000);
+        }
+    }
+
+    #[test]
+    fn retransmitted_syn() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets.clone(), event_loop.timers());
+        let listener = sockets.socket(SocketType::Stream).unwrap();
+        sockets.bind(listener, stack_addr()).unwrap();
+        sockets.listen(listener, 1).unwrap();
+
+        let buf = segment(1000, 0, SYN, &[]);
+        tcp.input(host(), stack(), &buf).unwrap_err();
+        let iss = seq(&poll(&tcp)[0].data);
+
+        // The SYN, ACK is lost and the host sends the SYN again, which is
+        // acknowledged without a reset
+        assert_eq!(
+            tcp.input(host(), stack(), &buf),
+            Err(ProtocolError::General)
+        );
+        let datagrams = poll(&tcp);
+        assert_eq!(datagrams.len(), 1);
+        let reply = &datagrams[0].data;
+        assert_eq!(reply[13], ACK);
+        assert_eq!(seq(reply), iss.wrapping_add(1));
+        assert_eq!(ack(reply), 1001);
+
+        let buf = segment(1001, iss.wrapping_add(1), ACK, &[]);
+        tcp.input(host(), stack(), &buf).unwrap_err();
+        let (_, addr) = sockets.accept(listener).unwrap();
+        assert_eq!(addr, host_addr());
+    }
+
+    #[test]
+    fn reset() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets, event_loop.timers());
+
+        // No socket listens on the port
+        let buf = segment(1000, 0, SYN, &[]);
+        let reply = tcp.input(host(), stack(), &buf).unwrap();
+        assert_eq!(reply[13], RST | ACK);
+        assert_eq!(seq(&reply), 0);
+        assert_eq!(ack(&reply), 1001);
+        assert_eq!(&reply[0..4], &[0x00, 0x50, 0x13, 0x88]);
+    }
+
+    #[test]
+    fn checksum_error() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let tcp = Tcp::new(sockets, event_loop.timers());
+
+        let mut buf = segment(1000, 0, SYN, &[]);
+        buf[4] ^= 0xff;
+        assert!(matches!(
+            tcp.input(host(), stack(), &buf),
+            Err(ProtocolError::Tcp(_))
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+// The fixtures shared by the tests of the router and the protocols
+//
+// Interface 0 is 192.0.2.2/24 with the host 192.0.2.1 and interface 1 is
+// 198.51.100.2/24 with a remote host 198.51.100.1.
+
+use std::net::Ipv4Addr;
+
+use crate::{
+    icmp::Icmp,
+    ipv4::IPv4,
+    protocol::get_checksum,
+    route::RoutingTable,
+    router::{Interface, Router},
+    tcp::Tcp,
+    udp::Udp,
+};
+
+pub(crate) fn host() -> Ipv4Addr {
+    Ipv4Addr::new(192, 0, 2, 1)
+}
+
+pub(crate) fn stack() -> Ipv4Addr {
+    Ipv4Addr::new(192, 0, 2, 2)
+}
+
+pub(crate) fn remote() -> Ipv4Addr {
+    Ipv4Addr::new(198, 51, 100, 1)
+}
+
+pub(crate) fn router(ipv4: IPv4) -> Router {
+    router_with_mtu(ipv4, 1500)
+}
+
+// The MTU is of interface 1
+pub(crate) fn router_with_mtu(ipv4: IPv4, mtu: usize) -> Router {
+    let netmask = Ipv4Addr::new(255, 255, 255, 0);
+    let interfaces = vec![
+        Interface::new(stack(), netmask, 1500),
+        Interface::new(Ipv4Addr::new(198, 51, 100, 2), netmask, mtu),
+    ];
+    Router::new(ipv4, interfaces, RoutingTable::new())
+}
+
+pub(crate) fn datagram(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, data: &[u8]) -> Vec<u8> {
+    IPv4::new(vec![]).datagram(src, dst, protocol, data)
+}
+
+// An echo request or reply with the identifier 0x1234
+pub(crate) fn echo(src: Ipv4Addr, dst: Ipv4Addr, icmp_type: u8) -> Vec<u8> {
+    let mut icmp = vec![icmp_type, 0, 0, 0, 0x12, 0x34, 0, 1];
+    let checksum = get_checksum(&icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+    datagram(src, dst, Icmp::NUMBER, &icmp)
+}
+
+// A TCP segment with the flags; the checksum is not set
+pub(crate) fn segment(src: Ipv4Addr, dst: Ipv4Addr, ports: (u16, u16), flags: u8) -> Vec<u8> {
+    let mut tcp = vec![0; 20];
+    tcp[0..2].copy_from_slice(&ports.0.to_be_bytes());
+    tcp[2..4].copy_from_slice(&ports.1.to_be_bytes());
+    tcp[12] = 0x50;
+    tcp[13] = flags;
+    datagram(src, dst, Tcp::NUMBER, &tcp)
+}
+
+// An empty UDP datagram from port 40000 to 53 without a checksum
+pub(crate) fn udp(src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+    datagram(src, dst, Udp::NUMBER, &[0x9c, 0x40, 0, 53, 0, 8, 0, 0])
+}
+
+// Input a datagram which the router sends on as one datagram
+pub(crate) fn forward(router: &Router, index: usize, buf: &[u8]) -> (usize, Vec<u8>) {
+    let mut datagrams = router.input(index, buf).unwrap();
+    assert_eq!(datagrams.len(), 1);
+    datagrams.remove(0)
+}
+
+// The checksums of the header and the data are valid
+pub(crate) fn assert_checksums(buf: &[u8]) {
+    assert_eq!(get_checksum(&buf[..20]), 0);
+    let src = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
+    let dst = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
+    let mut data = match buf[9] {
+        Icmp::NUMBER => vec![],
+        protocol => IPv4::pseudo_header(src, dst, protocol, buf.len() - 20),
+    };
+    data.extend_from_slice(&buf[20..]);
+    assert_eq!(get_checksum(&data), 0);
+}
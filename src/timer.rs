@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::eventloop::Waker;
+
+pub type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TimerId(u64);
+
+struct Timer {
+    id: TimerId,
+    // The tick at which the timer expires
+    expires: u64,
+    callback: Callback,
+}
+
+// Hashed timing wheel
+pub struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    // The slot of each pending timer
+    pending: HashMap<TimerId, usize>,
+    start: Instant,
+    tick: u64,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    pub const TICK: Duration = Duration::from_millis(10);
+    const SLOTS: usize = 256;
+
+    pub fn new(start: Instant) -> TimerWheel {
+        TimerWheel {
+            slots: (0..TimerWheel::SLOTS).map(|_| vec![]).collect(),
+            pending: HashMap::new(),
+            start,
+            tick: 0,
+            next_id: 0,
+        }
+    }
+
+    fn _ticks(&self, time: Instant, round_up: bool) -> u64 {
+        let elapsed = time.saturating_duration_since(self.start).as_nanos();
+        let tick = TimerWheel::TICK.as_nanos();
+        if round_up {
+            elapsed.div_ceil(tick) as u64
+        } else {
+            (elapsed / tick) as u64
+        }
+    }
+
+    pub fn schedule(&mut self, now: Instant, delay: Duration, callback: Callback) -> TimerId {
+        // Round up so that a timer never expires early
+        let expires = self._ticks(now + delay, true).max(self.tick + 1);
+        let slot = (expires % TimerWheel::SLOTS as u64) as usize;
+
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        self.slots[slot].push(Timer {
+            id,
+            expires,
+            callback,
+        });
+        self.pending.insert(id, slot);
+        id
+    }
+
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match self.pending.remove(&id) {
+            Some(slot) => {
+                self.slots[slot].retain(|timer| timer.id != id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns the callbacks of the expired timers in the order of expiry
+    pub fn expire(&mut self, now: Instant) -> Vec<Callback> {
+        let target = self._ticks(now, false);
+        if target <= self.tick {
+            return vec![];
+        }
+
+        // Each slot needs to be visited at most once
+        let ticks = (target - self.tick).min(TimerWheel::SLOTS as u64);
+        let mut expired = vec![];
+        for i in 1..=ticks {
+            let slot = ((self.tick + i) % TimerWheel::SLOTS as u64) as usize;
+            let (fired, remaining) = self.slots[slot]
+                .drain(..)
+                .partition(|timer| timer.expires <= target);
+            self.slots[slot] = remaining;
+            expired.extend(fired);
+        }
+        self.tick = target;
+
+        expired.sort_by_key(|timer: &Timer| (timer.expires, timer.id.0));
+        expired
+            .into_iter()
+            .map(|timer| {
+                self.pending.remove(&timer.id);
+                timer.callback
+            })
+            .collect()
+    }
+
+    // Returns how long to wait until the next timer expires
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let expires = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|timer| timer.expires)
+            .min()?;
+        let deadline =
+            self.start + Duration::from_nanos(TimerWheel::TICK.as_nanos() as u64 * expires);
+        Some(deadline.saturating_duration_since(now))
+    }
+}
+
+// A handle for protocol modules to schedule and cancel timers
+#[derive(Clone)]
+pub struct Timers {
+    wheel: Arc<Mutex<TimerWheel>>,
+    waker: Arc<Waker>,
+}
+
+impl Timers {
+    pub fn new(wheel: Arc<Mutex<TimerWheel>>, waker: Arc<Waker>) -> Timers {
+        Timers { wheel, waker }
+    }
+
+    pub fn schedule<F>(&self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let id = self
+            .wheel
+            .lock()
+            .unwrap()
+            .schedule(Instant::now(), delay, Box::new(callback));
+
+        // The event loop may be waiting without a timeout
+        self.waker.wake();
+        id
+    }
+
+    pub fn cancel(&self, id: TimerId) -> bool {
+        self.wheel.lock().unwrap().cancel(id)
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use crate::timer::{Callback, TimerWheel};
+
+    fn record(fired: &Arc<Mutex<Vec<u32>>>, n: u32) -> Callback {
+        let fired = fired.clone();
+        Box::new(move || fired.lock().unwrap().push(n))
+    }
+
+    fn run(callbacks: Vec<Callback>) {
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    #[test]
+    fn expire() {
+        let start = Instant::now();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let mut wheel = TimerWheel::new(start);
+        wheel.schedule(start, Duration::from_millis(30), record(&fired, 1));
+
+        run(wheel.expire(start + Duration::from_millis(20)));
+        assert_eq!(*fired.lock().unwrap(), vec![]);
+
+        run(wheel.expire(start + Duration::from_millis(30)));
+        assert_eq!(*fired.lock().unwrap(), vec![1]);
+
+        // A timer expires only once
+        run(wheel.expire(start + Duration::from_millis(40)));
+        assert_eq!(*fired.lock().unwrap(), vec![1]);
+        assert_eq!(wheel.next_timeout(start), None);
+    }
+
+    #[test]
+    fn order() {
+        let start = Instant::now();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let mut wheel = TimerWheel::new(start);
+        wheel.schedule(start, Duration::from_millis(50), record(&fired, 1));
+        wheel.schedule(start, Duration::from_millis(10), record(&fired, 2));
+        wheel.schedule(start, Duration::from_millis(30), record(&fired, 3));
+
+        run(wheel.expire(start + Duration::from_millis(100)));
+        assert_eq!(*fired.lock().unwrap(), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn cancel() {
+        let start = Instant::now();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let mut wheel = TimerWheel::new(start);
+        let id = wheel.schedule(start, Duration::from_millis(10), record(&fired, 1));
+        wheel.schedule(start, Duration::from_millis(20), record(&fired, 2));
+
+        assert!(wheel.cancel(id));
+        assert!(!wheel.cancel(id));
+
+        run(wheel.expire(start + Duration::from_millis(100)));
+        assert_eq!(*fired.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn multiple_rotations() {
+        let start = Instant::now();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let mut wheel = TimerWheel::new(start);
+        // 256 slots of 10 ms make a rotation of 2.56 s
+        wheel.schedule(start, Duration::from_millis(5000), record(&fired, 1));
+        wheel.schedule(start, Duration::from_millis(2570), record(&fired, 2));
+
+        run(wheel.expire(start + Duration::from_millis(2560)));
+        assert_eq!(*fired.lock().unwrap(), vec![]);
+
+        run(wheel.expire(start + Duration::from_millis(2570)));
+        assert_eq!(*fired.lock().unwrap(), vec![2]);
+
+        run(wheel.expire(start + Duration::from_millis(4990)));
+        assert_eq!(*fired.lock().unwrap(), vec![2]);
+
+        run(wheel.expire(start + Duration::from_millis(10000)));
+        assert_eq!(*fired.lock().unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn next_timeout() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        assert_eq!(wheel.next_timeout(start), None);
+
+        wheel.schedule(start, Duration::from_millis(100), Box::new(|| {}));
+        wheel.schedule(start, Duration::from_millis(40), Box::new(|| {}));
+        assert_eq!(
+            wheel.next_timeout(start + Duration::from_millis(10)),
+            Some(Duration::from_millis(30))
+        );
+    }
+}
@@ -1,8 +1,15 @@
-use std::{mem, os::raw::c_char};
+use std::{
+    mem,
+    net::Ipv4Addr,
+    os::{
+        raw::c_char,
+        unix::io::{AsRawFd, RawFd},
+    },
+};
 
 use nix::{
     errno::Errno,
-    fcntl::{open, OFlag},
+    fcntl::{fcntl, open, FcntlArg, OFlag},
     ioctl_write_int, ioctl_write_ptr_bad, libc,
     sys::{
         ioctl::ioctl_param_type,
@@ -20,7 +27,6 @@ ioctl_write_ptr_bad!(siocsifflags, libc::SIOCSIFFLAGS, libc::ifreq);
 
 pub enum TunTapFlag {
     Tun,
-    #[allow(dead_code)]
     Tap,
 }
 
@@ -29,17 +35,25 @@ pub struct TunTap {
 }
 
 impl TunTap {
-    pub fn new(flag: TunTapFlag) -> Result<TunTap, Error> {
-        let fd = open("/dev/net/tun", OFlag::O_RDWR, Mode::empty())?;
+    pub fn new(
+        flag: TunTapFlag,
+        name: &str,
+        address: Ipv4Addr,
+        netmask: Ipv4Addr,
+    ) -> Result<TunTap, Error> {
+        let tuntap = TunTap::attach(flag, name)?;
+        TunTap::_configure(name, address, netmask)?;
+        Ok(tuntap)
+    }
 
-        let mut ifr_name: [c_char; libc::IF_NAMESIZE] = [0; libc::IF_NAMESIZE];
-        let name = match flag {
-            TunTapFlag::Tun => b"tun0\0",
-            TunTapFlag::Tap => b"tap0\0",
-        };
-        ifr_name[..name.len()].copy_from_slice(&name.map(|c| c as i8)[..]);
+    // Attach to the device without configuring it. A persistent device created
+    // beforehand, e.g. by `ip tuntap add tun0 mode tun user $USER`, can be
+    // attached to without privileges.
+    pub fn attach(flag: TunTapFlag, name: &str) -> Result<TunTap, Error> {
+        let ifr_name = TunTap::_name(name)?;
+        let fd = open("/dev/net/tun", OFlag::O_RDWR, Mode::empty())?;
 
-        // Create a interface
+        // Create the interface unless it exists
         let flag = match flag {
             TunTapFlag::Tun => libc::IFF_TUN,
             TunTapFlag::Tap => libc::IFF_TAP,
@@ -49,6 +63,24 @@ impl TunTap {
         let ifreq = libc::ifreq { ifr_name, ifr_ifru };
         unsafe { tunsetiff(fd, &ifreq as *const libc::ifreq as ioctl_param_type) }?;
 
+        Ok(TunTap { fd })
+    }
+
+    fn _name(name: &str) -> Result<[c_char; libc::IF_NAMESIZE], Error> {
+        // The name must be terminated by a null character
+        if name.len() >= libc::IF_NAMESIZE {
+            return Err(Errno::EINVAL);
+        }
+        let mut ifr_name: [c_char; libc::IF_NAMESIZE] = [0; libc::IF_NAMESIZE];
+        for (i, c) in name.bytes().enumerate() {
+            ifr_name[i] = c as c_char;
+        }
+        Ok(ifr_name)
+    }
+
+    // Assign the address of the host side and bring the interface up
+    fn _configure(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> Result<(), Error> {
+        let ifr_name = TunTap::_name(name)?;
         let sock = socket(
             AddressFamily::Inet,
             SockType::Datagram,
@@ -56,19 +88,24 @@ impl TunTap {
             None,
         )?;
 
-        // Assign 192.0.2.1 to the interface
-        let ifru_addr = SockaddrIn::new(192, 0, 2, 1, 0);
-        let ifru_addr = unsafe { mem::transmute(ifru_addr) };
-        let ifr_ifru = libc::__c_anonymous_ifr_ifru { ifru_addr };
-        let ifreq = libc::ifreq { ifr_name, ifr_ifru };
-        unsafe { siocsifaddr(sock, &ifreq) }?;
+        // The address may be left to DHCP on the host side
+        if !address.is_unspecified() {
+            // Assign the address to the interface
+            let [a, b, c, d] = address.octets();
+            let ifru_addr = SockaddrIn::new(a, b, c, d, 0);
+            let ifru_addr = unsafe { mem::transmute::<SockaddrIn, libc::sockaddr>(ifru_addr) };
+            let ifr_ifru = libc::__c_anonymous_ifr_ifru { ifru_addr };
+            let ifreq = libc::ifreq { ifr_name, ifr_ifru };
+            unsafe { siocsifaddr(sock, &ifreq) }?;
 
-        // Set the network mask for the interface to 255.255.255.0 (/24)
-        let ifru_addr = SockaddrIn::new(255, 255, 255, 0, 0);
-        let ifru_addr = unsafe { mem::transmute(ifru_addr) };
-        let ifr_ifru = libc::__c_anonymous_ifr_ifru { ifru_addr };
-        let ifreq = libc::ifreq { ifr_name, ifr_ifru };
-        unsafe { siocsifnetmask(sock, &ifreq) }?;
+            // Set the network mask for the interface
+            let [a, b, c, d] = netmask.octets();
+            let ifru_addr = SockaddrIn::new(a, b, c, d, 0);
+            let ifru_addr = unsafe { mem::transmute::<SockaddrIn, libc::sockaddr>(ifru_addr) };
+            let ifr_ifru = libc::__c_anonymous_ifr_ifru { ifru_addr };
+            let ifreq = libc::ifreq { ifr_name, ifr_ifru };
+            unsafe { siocsifnetmask(sock, &ifreq) }?;
+        }
 
         // Make the state of the interface up
         let ifru_flags = libc::IFF_UP as i16;
@@ -76,14 +113,31 @@ impl TunTap {
         let ifreq = libc::ifreq { ifr_name, ifr_ifru };
         unsafe { siocsifflags(sock, &ifreq) }?;
 
-        Ok(TunTap { fd })
+        Ok(())
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    // Reads and writes fail with EAGAIN instead of blocking
+    pub fn set_nonblocking(&self) -> Result<(), Error> {
+        let flags = OFlag::from_bits_truncate(fcntl(self.fd, FcntlArg::F_GETFL)?);
+        fcntl(self.fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok(())
     }
 
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
         read(self.fd, buf)
     }
 
-    pub fn write(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
         write(self.fd, buf)
     }
 }
+
+impl AsRawFd for TunTap {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
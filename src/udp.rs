@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::{Ipv4Addr, SocketAddrV4},
+};
+
+use nix::{errno::Errno, Error};
+
+use crate::{
+    ipv4::{Datagram, IPv4, IPv4Protocol},
+    protocol::{get_checksum, ProtocolError},
+    socket::{Socket, Sockets},
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct UdpError(pub String);
+
+impl fmt::Display for UdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "udp: {}", self.0)
+    }
+}
+
+pub(crate) struct UdpPcb {
+    pub(crate) local: SocketAddrV4,
+    pub(crate) remote: Option<SocketAddrV4>,
+    // SO_BINDTODEVICE
+    pub(crate) interface: Option<usize>,
+    // IP_ADD_MEMBERSHIP: the groups with the interfaces
+    pub(crate) groups: Vec<(Ipv4Addr, usize)>,
+    received: VecDeque<(SocketAddrV4, Vec<u8>)>,
+    pending: VecDeque<(SocketAddrV4, Vec<u8>)>,
+}
+
+impl UdpPcb {
+    // The number of datagrams queued for receiving
+    const QUEUE_SIZE: usize = 64;
+    const MAX_DATA_SIZE: usize = 65535 - 20 - 8;
+
+    pub(crate) fn new() -> UdpPcb {
+        UdpPcb {
+            local: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            remote: None,
+            interface: None,
+            groups: vec![],
+            received: VecDeque::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn _accepts(&self, src: SocketAddrV4, dst: SocketAddrV4) -> bool {
+        self.local.port() == dst.port()
+            && (self.local.ip().is_unspecified() || self.local.ip() == dst.ip())
+            && self.remote.is_none_or(|remote| remote == src)
+    }
+
+    pub(crate) fn send_to(&mut self, buf: &[u8], addr: SocketAddrV4) -> Result<(), Error> {
+        if buf.len() > UdpPcb::MAX_DATA_SIZE {
+            return Err(Errno::EMSGSIZE);
+        }
+        self.pending.push_back((addr, buf.to_vec()));
+        Ok(())
+    }
+
+    // The octets waiting to be received and sent
+    pub(crate) fn queues(&self) -> (usize, usize) {
+        let len =
+            |queue: &VecDeque<(SocketAddrV4, Vec<u8>)>| queue.iter().map(|(_, d)| d.len()).sum();
+        (len(&self.received), len(&self.pending))
+    }
+
+    // Datagrams longer than the buffer are truncated
+    pub(crate) fn recv_from(&mut self, buf: &mut [u8]) -> Option<(usize, SocketAddrV4)> {
+        let (addr, data) = self.received.pop_front()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Some((n, addr))
+    }
+}
+
+// RFC 768
+pub struct Udp {
+    sockets: Sockets,
+}
+
+impl Udp {
+    pub const NUMBER: u8 = 17;
+    const HEADER_SIZE: usize = 8;
+
+    pub fn new(sockets: Sockets) -> Udp {
+        Udp { sockets }
+    }
+
+    fn _verify_length(&self, buf: &[u8]) -> Result<(), ProtocolError> {
+        if buf.len() < Udp::HEADER_SIZE {
+            return Err(UdpError("too short".to_string()).into());
+        }
+
+        let length = u16::from_be_bytes([buf[4], buf[5]]) as usize;
+        if buf.len() != length {
+            return Err(UdpError(format!(
+                "length error: length={}, len={}",
+                length,
+                buf.len()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn _verify_checksum(
+        &self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        buf: &[u8],
+    ) -> Result<(), ProtocolError> {
+        // The sender did not compute the checksum
+        if buf[6] == 0 && buf[7] == 0 {
+            return Ok(());
+        }
+
+        let mut pseudo = IPv4::pseudo_header(src, dst, Udp::NUMBER, buf.len());
+        pseudo.extend_from_slice(buf);
+        let checksum = get_checksum(&pseudo);
+        if checksum != 0 {
+            return Err(UdpError(format!("checksum error: checksum={:#x?}", checksum)).into());
+        }
+        Ok(())
+    }
+
+    pub fn datagram(src: SocketAddrV4, dst: SocketAddrV4, data: &[u8]) -> Vec<u8> {
+        let length = (Udp::HEADER_SIZE + data.len()) as u16;
+
+        let mut buf = vec![];
+        buf.extend_from_slice(&src.port().to_be_bytes());
+        buf.extend_from_slice(&dst.port().to_be_bytes());
+        buf.extend_from_slice(&length.to_be_bytes());
+        buf.extend_from_slice(&[0x00, 0x00]);
+        buf.extend_from_slice(data);
+
+        let mut pseudo = IPv4::pseudo_header(*src.ip(), *dst.ip(), Udp::NUMBER, buf.len());
+        pseudo.extend_from_slice(&buf);
+        let checksum = match get_checksum(&pseudo) {
+            // Zero means that the checksum is not computed
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        buf[6..8].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+}
+
+impl IPv4Protocol for Udp {
+    fn number(&self) -> u8 {
+        Udp::NUMBER
+    }
+
+    fn input(&self, src: Ipv4Addr, dst: Ipv4Addr, buf: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        let stats = self.sockets.udp_stats();
+        if let Err(e) = self
+            ._verify_length(buf)
+            .and_then(|_| self._verify_checksum(src, dst, buf))
+        {
+            stats.in_errors.increment();
+            return Err(e);
+        }
+
+        let src = SocketAddrV4::new(src, u16::from_be_bytes([buf[0], buf[1]]));
+        let dst = SocketAddrV4::new(dst, u16::from_be_bytes([buf[2], buf[3]]));
+        let data = &buf[Udp::HEADER_SIZE..];
+
+        let mut table = self.sockets.lock();
+        let pcb = table.iter_mut().find_map(|(_, socket)| match socket {
+            Socket::Udp(pcb) if pcb._accepts(src, dst) => Some(pcb),
+            _ => None,
+        });
+        let pcb = match pcb {
+            Some(pcb) => pcb,
+            None => {
+                stats.no_ports.increment();
+                return Err(UdpError(format!("no socket: port={}", dst.port())).into());
+            }
+        };
+
+        if pcb.received.len() >= UdpPcb::QUEUE_SIZE {
+            stats.in_errors.increment();
+            return Err(UdpError(format!("queue full: port={}", dst.port())).into());
+        }
+        pcb.received.push_back((src, data.to_vec()));
+        stats.in_datagrams.increment();
+        self.sockets.notify();
+
+        // Nothing to reply
+        Err(ProtocolError::General)
+    }
+
+    fn poll(
+        &self,
+        source: &dyn Fn(Ipv4Addr, Option<usize>) -> Option<(Ipv4Addr, usize)>,
+    ) -> Vec<Datagram> {
+        let mut datagrams = vec![];
+        let mut table = self.sockets.lock();
+        for (_, socket) in table.iter_mut() {
+            let pcb = match socket {
+                Socket::Udp(pcb) => pcb,
+                _ => continue,
+            };
+
+            while let Some((dst, data)) = pcb.pending.pop_front() {
+                let src = match pcb.local.ip() {
+                    ip if ip.is_unspecified() => match source(*dst.ip(), pcb.interface) {
+                        Some((ip, _)) => ip,
+                        // No route to the destination
+                        None => continue,
+                    },
+                    ip => *ip,
+                };
+                let src = SocketAddrV4::new(src, pcb.local.port());
+                self.sockets.udp_stats().out_datagrams.increment();
+                datagrams.push(Datagram {
+                    src: *src.ip(),
+                    dst: *dst.ip(),
+                    interface: pcb.interface,
+                    data: Udp::datagram(src, dst, &data),
+                });
+            }
+        }
+        datagrams
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddrV4;
+
+    use crate::{
+        eventloop::EventLoop,
+        ipv4::{IPv4, IPv4Protocol},
+        protocol::{get_checksum, ProtocolError},
+        socket::{SocketType, Sockets},
+        testutil::{host, stack},
+        udp::{Udp, UdpError},
+    };
+
+    fn host_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(host(), 5000)
+    }
+
+    fn stack_addr() -> SocketAddrV4 {
+        SocketAddrV4::new(stack(), 7)
+    }
+
+    #[test]
+    fn recv_from() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let udp = Udp::new(sockets.clone());
+
+        let socket = sockets.socket(SocketType::Datagram).unwrap();
+        sockets.bind(socket, stack_addr()).unwrap();
+
+        let buf = Udp::datagram(host_addr(), stack_addr(), b"hello");
+        assert_eq!(
+            udp.input(host(), stack(), &buf),
+            Err(ProtocolError::General)
+        );
+
+        let mut data = [0u8; 16];
+        let (n, addr) = sockets.recv_from(socket, &mut data).unwrap();
+        assert_eq!(&data[..n], b"hello");
+        assert_eq!(addr, host_addr());
+    }
+
+    #[test]
+    fn no_socket() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let udp = Udp::new(sockets);
+
+        let buf = Udp::datagram(host_addr(), stack_addr(), b"hello");
+        assert_eq!(
+            udp.input(host(), stack(), &buf),
+            Err(UdpError("no socket: port=7".to_string()).into())
+        );
+    }
+
+    #[test]
+    fn checksum_error() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let udp = Udp::new(sockets);
+
+        let mut buf = Udp::datagram(host_addr(), stack_addr(), b"hello");
+        buf[8] ^= 0xff;
+        assert!(matches!(
+            udp.input(host(), stack(), &buf),
+            Err(ProtocolError::Udp(_))
+        ));
+    }
+
+    #[test]
+    fn send_to() {
+        let event_loop = EventLoop::new().unwrap();
+        let sockets = Sockets::new(event_loop.waker());
+        let udp = Udp::new(sockets.clone());
+
+        let socket = sockets.socket(SocketType::Datagram).unwrap();
+        assert_eq!(sockets.send_to(socket, b"hello", host_addr()), Ok(5));
+
+        let source = |_, _| Some((stack(), 1500));
+        let datagrams = udp.poll(&source);
+        assert_eq!(datagrams.len(), 1);
+
+        let datagram = &datagrams[0];
+        assert_eq!(datagram.src, stack());
+        assert_eq!(datagram.dst, host());
+        // Bound to an ephemeral port
+        let port = u16::from_be_bytes([datagram.data[0], datagram.data[1]]);
+        assert_eq!(sockets.local_addr(socket).unwrap().port(), port);
+        assert!(port >= 49152);
+        assert_eq!(&datagram.data[2..6], &[0x13, 0x88, 0x00, 0x0d]);
+        assert_eq!(&datagram.data[8..], b"hello");
+
+        let mut pseudo =
+            IPv4::pseudo_header(datagram.src, datagram.dst, Udp::NUMBER, datagram.data.len());
+        pseudo.extend_from_slice(&datagram.data);
+        assert_eq!(get_checksum(&pseudo), 0);
+    }
+}